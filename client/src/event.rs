@@ -0,0 +1,151 @@
+//! Decoding of the events, logged base64 encoded after their discriminator as `Program data:`.
+//! Events of an earlier `EVENT_SCHEMA_VERSION` do not decode with the current layouts.
+
+use anchor_lang::{prelude::*, Discriminator};
+use pawn_shop::{
+    FeesWithdrawn, LoanCancelled, LoanRepaid, LoanRequested, LoanUnderwritten, PawnSeized,
+    PoolLiquidityDeposited, PoolLiquidityWithdrawn, PoolPawnWithdrawn, ReferralFeesClaimed,
+};
+
+const PROGRAM_DATA_LOG_PREFIX: &str = "Program data: ";
+
+pub enum PawnShopEvent {
+    LoanRequested(LoanRequested),
+    LoanUnderwritten(LoanUnderwritten),
+    LoanRepaid(LoanRepaid),
+    LoanCancelled(LoanCancelled),
+    PawnSeized(PawnSeized),
+    FeesWithdrawn(FeesWithdrawn),
+    ReferralFeesClaimed(ReferralFeesClaimed),
+    PoolLiquidityDeposited(PoolLiquidityDeposited),
+    PoolLiquidityWithdrawn(PoolLiquidityWithdrawn),
+    PoolPawnWithdrawn(PoolPawnWithdrawn),
+}
+
+fn deserialize<T: AnchorDeserialize>(data: &[u8]) -> Result<T> {
+    T::deserialize(&mut &data[..])
+        .map_err(|_| error!(anchor_lang::error::ErrorCode::AccountDidNotDeserialize))
+}
+
+impl PawnShopEvent {
+    /// Decodes an event from its discriminator and borsh encoding, none if the discriminator is
+    /// not the one of an event of the program.
+    pub fn decode(data: &[u8]) -> Result<Option<Self>> {
+        if data.len() < 8 {
+            return Ok(None);
+        }
+        let (discriminator, data) = data.split_at(8);
+
+        let event = if discriminator == LoanRequested::discriminator() {
+            Self::LoanRequested(deserialize(data)?)
+        } else if discriminator == LoanUnderwritten::discriminator() {
+            Self::LoanUnderwritten(deserialize(data)?)
+        } else if discriminator == LoanRepaid::discriminator() {
+            Self::LoanRepaid(deserialize(data)?)
+        } else if discriminator == LoanCancelled::discriminator() {
+            Self::LoanCancelled(deserialize(data)?)
+        } else if discriminator == PawnSeized::discriminator() {
+            Self::PawnSeized(deserialize(data)?)
+        } else if discriminator == FeesWithdrawn::discriminator() {
+            Self::FeesWithdrawn(deserialize(data)?)
+        } else if discriminator == ReferralFeesClaimed::discriminator() {
+            Self::ReferralFeesClaimed(deserialize(data)?)
+        } else if discriminator == PoolLiquidityDeposited::discriminator() {
+            Self::PoolLiquidityDeposited(deserialize(data)?)
+        } else if discriminator == PoolLiquidityWithdrawn::discriminator() {
+            Self::PoolLiquidityWithdrawn(deserialize(data)?)
+        } else if discriminator == PoolPawnWithdrawn::discriminator() {
+            Self::PoolPawnWithdrawn(deserialize(data)?)
+        } else {
+            return Ok(None);
+        };
+
+        Ok(Some(event))
+    }
+
+    /// Decodes the events logged by a transaction, in their order. Data logged by other programs
+    /// is skipped as long as it does not start with the discriminator of an event of the program.
+    pub fn decode_logs(logs: &[String]) -> Result<Vec<Self>> {
+        let mut events = vec![];
+        for log in logs {
+            let encoded = match log.strip_prefix(PROGRAM_DATA_LOG_PREFIX) {
+                Some(encoded) => encoded,
+                None => continue,
+            };
+            let data = match base64::decode(encoded) {
+                Ok(data) => data,
+                Err(_) => continue,
+            };
+            if let Some(event) = Self::decode(&data)? {
+                events.push(event);
+            }
+        }
+
+        Ok(events)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anchor_lang::Event;
+    use pawn_shop::EVENT_SCHEMA_VERSION;
+
+    #[test]
+    fn events_are_decoded_from_the_logs() {
+        let mint = Pubkey::new_unique();
+        let withdrawn = FeesWithdrawn {
+            schema_version: EVENT_SCHEMA_VERSION,
+            mint,
+            amount: 42,
+            recipient: Pubkey::new_unique(),
+            recipient_payment_account: Pubkey::new_unique(),
+            timestamp: 1_650_000_000,
+        };
+        let deposited = PoolLiquidityDeposited {
+            schema_version: EVENT_SCHEMA_VERSION,
+            lending_pool_address: Pubkey::new_unique(),
+            depositor: Pubkey::new_unique(),
+            amount: 1_000,
+            shares: 900,
+            timestamp: 1_650_000_100,
+        };
+        let logs = vec![
+            format!("Program {} invoke [1]", pawn_shop::ID),
+            format!("Program data: {}", base64::encode(withdrawn.data())),
+            "Program data: bm90IGFuIGV2ZW50".to_string(),
+            format!("Program data: {}", base64::encode(deposited.data())),
+            format!("Program {} success", pawn_shop::ID),
+        ];
+
+        let events = PawnShopEvent::decode_logs(&logs).unwrap();
+        assert_eq!(2, events.len());
+        match &events[0] {
+            PawnShopEvent::FeesWithdrawn(event) => {
+                assert_eq!(mint, event.mint);
+                assert_eq!(42, event.amount);
+            }
+            _ => panic!("expected FeesWithdrawn"),
+        }
+        match &events[1] {
+            PawnShopEvent::PoolLiquidityDeposited(event) => assert_eq!(900, event.shares),
+            _ => panic!("expected PoolLiquidityDeposited"),
+        }
+    }
+
+    #[test]
+    fn truncated_event_is_an_error() {
+        let data = FeesWithdrawn {
+            schema_version: EVENT_SCHEMA_VERSION,
+            mint: Pubkey::new_unique(),
+            amount: 42,
+            recipient: Pubkey::new_unique(),
+            recipient_payment_account: Pubkey::new_unique(),
+            timestamp: 1_650_000_000,
+        }
+        .data();
+
+        assert!(PawnShopEvent::decode(&data[..data.len() - 1]).is_err());
+        assert!(PawnShopEvent::decode(&data[..4]).unwrap().is_none());
+    }
+}
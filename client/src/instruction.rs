@@ -0,0 +1,1232 @@
+//! Instruction builders, deriving the program accounts and the payment accounts of the loan mint.
+//! Builders of instructions on an existing loan take the decoded pawn loan, and fail with the
+//! error of the program when the loan cannot be the target of the instruction.
+
+use anchor_lang::{
+    prelude::*,
+    solana_program::{instruction::Instruction, sysvar},
+    system_program, InstructionData,
+};
+use anchor_spl::{associated_token::get_associated_token_address, token};
+use mpl_token_metadata::state::Creator;
+use pawn_shop::{
+    fee_collector, native_mint, CreatorRoyalty, ErrorCode, FeeSplitRecipient, FeeTier,
+    LenderDelegateLimits, LoanTerms, LoanToValueGuard, PawnLoanView, UnderwritingPolicy,
+};
+
+use crate::pda::*;
+
+fn instruction(
+    accounts: impl ToAccountMetas,
+    remaining_accounts: Vec<AccountMeta>,
+    data: impl InstructionData,
+) -> Instruction {
+    let mut accounts = accounts.to_account_metas(None);
+    accounts.extend(remaining_accounts);
+
+    Instruction {
+        program_id: pawn_shop::ID,
+        accounts,
+        data: data.data(),
+    }
+}
+
+/// Statistics of the loan mint, the protocol statistics standing in for loans without terms.
+fn mint_stats(mint: Option<&Pubkey>) -> Pubkey {
+    match mint {
+        Some(mint) => find_mint_stats_address(mint).0,
+        None => find_protocol_stats_address().0,
+    }
+}
+
+fn desired_terms(pawn_loan: &PawnLoanView) -> Result<&LoanTerms> {
+    pawn_loan
+        .desired_terms
+        .as_ref()
+        .ok_or_else(|| ErrorCode::InvalidLoanStatus.into())
+}
+
+fn terms(pawn_loan: &PawnLoanView) -> Result<&LoanTerms> {
+    pawn_loan
+        .terms
+        .as_ref()
+        .ok_or_else(|| ErrorCode::InvalidLoanStatus.into())
+}
+
+/// The pawn metadata and the registered collection, checked while the collection registry is
+/// enabled, then the registered referrer of a referred loan.
+fn loan_request_remaining_accounts(
+    pawn_mint: &Pubkey,
+    collection: Option<&Pubkey>,
+    referrer: Option<&Pubkey>,
+) -> Vec<AccountMeta> {
+    let mut remaining_accounts = match collection {
+        Some(collection) => vec![
+            AccountMeta::new_readonly(find_metadata_address(pawn_mint).0, false),
+            AccountMeta::new_readonly(find_registered_collection_address(collection).0, false),
+        ],
+        None => vec![],
+    };
+    remaining_accounts.extend(registered_referrer(referrer));
+
+    remaining_accounts
+}
+
+/// Registered referrer, expected last in the remaining accounts of referred loans.
+fn registered_referrer(referrer: Option<&Pubkey>) -> Option<AccountMeta> {
+    referrer.map(|referrer| {
+        AccountMeta::new_readonly(find_registered_referrer_address(referrer).0, false)
+    })
+}
+
+/// Requests a loan at the address derived from the base keypair, which signs with the borrower.
+/// The collection is the verified collection of the pawn, required while the collection registry
+/// is enabled. The referrer must be registered.
+pub fn request_loan(
+    base: &Pubkey,
+    borrower: &Pubkey,
+    pawn_token_account: &Pubkey,
+    pawn_mint: &Pubkey,
+    desired_terms: Option<LoanTerms>,
+    collection: Option<&Pubkey>,
+    referrer: Option<Pubkey>,
+) -> Instruction {
+    instruction(
+        pawn_shop::accounts::RequestLoan {
+            base: *base,
+            pawn_loan: find_pawn_loan_address_for_base(base).0,
+            borrower: *borrower,
+            pawn_token_account: *pawn_token_account,
+            pawn_mint: *pawn_mint,
+            edition: find_edition_address(pawn_mint).0,
+            collection_registry: find_collection_registry_address().0,
+            protocol_stats: find_protocol_stats_address().0,
+            mint_stats: mint_stats(desired_terms.as_ref().map(|terms| &terms.mint)),
+            token_program: token::ID,
+            mpl_token_metadata_program: mpl_token_metadata::ID,
+            system_program: system_program::ID,
+        },
+        loan_request_remaining_accounts(pawn_mint, collection, referrer.as_ref()),
+        pawn_shop::instruction::RequestLoan {
+            desired_terms,
+            referrer,
+        },
+    )
+}
+
+/// Requests a loan at the address derived from the pawn, the borrower and the nonce.
+pub fn request_loan_for_pawn(
+    borrower: &Pubkey,
+    pawn_token_account: &Pubkey,
+    pawn_mint: &Pubkey,
+    nonce: u64,
+    desired_terms: Option<LoanTerms>,
+    collection: Option<&Pubkey>,
+    referrer: Option<Pubkey>,
+) -> Instruction {
+    instruction(
+        pawn_shop::accounts::RequestLoanForPawn {
+            pawn_loan: find_pawn_loan_address(pawn_mint, borrower, nonce).0,
+            borrower: *borrower,
+            pawn_token_account: *pawn_token_account,
+            pawn_mint: *pawn_mint,
+            edition: find_edition_address(pawn_mint).0,
+            collection_registry: find_collection_registry_address().0,
+            protocol_stats: find_protocol_stats_address().0,
+            mint_stats: mint_stats(desired_terms.as_ref().map(|terms| &terms.mint)),
+            token_program: token::ID,
+            mpl_token_metadata_program: mpl_token_metadata::ID,
+            system_program: system_program::ID,
+        },
+        loan_request_remaining_accounts(pawn_mint, collection, referrer.as_ref()),
+        pawn_shop::instruction::RequestLoanForPawn {
+            nonce,
+            desired_terms,
+            referrer,
+        },
+    )
+}
+
+/// Underwrites the open loan at its desired terms. The loan to value guard is checked against the
+/// price feed given with it. With `track_lender_volume`, the lender volume account of the lender
+/// in the loan mint counts the principal towards the fee tiers. The referrer must be registered.
+pub fn underwrite_loan(
+    pawn_loan_address: &Pubkey,
+    pawn_loan: &PawnLoanView,
+    lender: &Pubkey,
+    loan_to_value_guard: Option<(LoanToValueGuard, Pubkey)>,
+    referrer: Option<Pubkey>,
+    track_lender_volume: bool,
+) -> Result<Instruction> {
+    let terms = desired_terms(pawn_loan)?;
+    let admin = find_admin_address().0;
+
+    let mut remaining_accounts = vec![];
+    if let Some((_, price_feed)) = loan_to_value_guard {
+        remaining_accounts.push(AccountMeta::new_readonly(price_feed, false));
+        remaining_accounts.push(AccountMeta::new_readonly(
+            find_metadata_address(&pawn_loan.pawn_mint).0,
+            false,
+        ));
+    }
+    if track_lender_volume {
+        remaining_accounts.push(AccountMeta::new(
+            find_lender_volume_address(lender, &terms.mint).0,
+            false,
+        ));
+    }
+    remaining_accounts.extend(registered_referrer(referrer.as_ref()));
+
+    Ok(instruction(
+        pawn_shop::accounts::UnderwriteLoan {
+            pawn_loan: *pawn_loan_address,
+            lender: *lender,
+            lender_payment_account: find_payment_account(lender, &terms.mint),
+            borrower_payment_account: find_payment_account(&pawn_loan.borrower, &terms.mint),
+            fee_schedule: find_fee_schedule_address(&terms.mint).0,
+            admin,
+            admin_payment_account: find_payment_account(&admin, &terms.mint),
+            fee_ledger: find_fee_ledger_address(&terms.mint).0,
+            borrower_stats: find_borrower_stats_address(&pawn_loan.borrower).0,
+            lender_stats: find_lender_stats_address(lender).0,
+            protocol_stats: find_protocol_stats_address().0,
+            mint_stats: mint_stats(Some(&terms.mint)),
+            token_program: token::ID,
+            system_program: system_program::ID,
+        },
+        remaining_accounts,
+        pawn_shop::instruction::UnderwriteLoan {
+            expected_terms: *terms,
+            expected_pawn_mint: pawn_loan.pawn_mint,
+            loan_to_value_guard: loan_to_value_guard.map(|(guard, _)| guard),
+            referrer,
+        },
+    ))
+}
+
+/// Creates the lender delegate. The lender approves it as spl delegate of its payment account,
+/// up to the total principal of the limits, in the same transaction.
+pub fn approve_lender_delegate(
+    lender: &Pubkey,
+    delegate: &Pubkey,
+    limits: LenderDelegateLimits,
+) -> Instruction {
+    instruction(
+        pawn_shop::accounts::ApproveLenderDelegate {
+            lender_delegate: find_lender_delegate_address(lender, delegate).0,
+            lender: *lender,
+            delegate: *delegate,
+            system_program: system_program::ID,
+        },
+        vec![],
+        pawn_shop::instruction::ApproveLenderDelegate { limits },
+    )
+}
+
+pub fn revoke_lender_delegate(lender: &Pubkey, delegate: &Pubkey) -> Instruction {
+    instruction(
+        pawn_shop::accounts::RevokeLenderDelegate {
+            lender_delegate: find_lender_delegate_address(lender, delegate).0,
+            lender: *lender,
+        },
+        vec![],
+        pawn_shop::instruction::RevokeLenderDelegate {},
+    )
+}
+
+/// Underwrites the open loan on behalf of the lender, from the associated token account of the
+/// lender. Delegates only lend spl mints, sol loans are rejected.
+pub fn underwrite_loan_with_delegate(
+    pawn_loan_address: &Pubkey,
+    pawn_loan: &PawnLoanView,
+    lender: &Pubkey,
+    delegate: &Pubkey,
+    track_lender_volume: bool,
+) -> Result<Instruction> {
+    let terms = desired_terms(pawn_loan)?;
+    if terms.mint == native_mint::ID {
+        return Err(ErrorCode::DelegateMintNotAllowed.into());
+    }
+    let admin = find_admin_address().0;
+
+    let mut remaining_accounts = vec![];
+    if track_lender_volume {
+        remaining_accounts.push(AccountMeta::new(
+            find_lender_volume_address(lender, &terms.mint).0,
+            false,
+        ));
+    }
+
+    Ok(instruction(
+        pawn_shop::accounts::UnderwriteLoanWithDelegate {
+            pawn_loan: *pawn_loan_address,
+            lender_delegate: find_lender_delegate_address(lender, delegate).0,
+            delegate: *delegate,
+            lender: *lender,
+            lender_payment_account: get_associated_token_address(lender, &terms.mint),
+            borrower_payment_account: get_associated_token_address(
+                &pawn_loan.borrower,
+                &terms.mint,
+            ),
+            fee_schedule: find_fee_schedule_address(&terms.mint).0,
+            admin,
+            admin_payment_account: get_associated_token_address(&admin, &terms.mint),
+            fee_ledger: find_fee_ledger_address(&terms.mint).0,
+            borrower_stats: find_borrower_stats_address(&pawn_loan.borrower).0,
+            lender_stats: find_lender_stats_address(lender).0,
+            protocol_stats: find_protocol_stats_address().0,
+            mint_stats: mint_stats(Some(&terms.mint)),
+            token_program: token::ID,
+            system_program: system_program::ID,
+        },
+        remaining_accounts,
+        pawn_shop::instruction::UnderwriteLoanWithDelegate {
+            expected_terms: *terms,
+            expected_pawn_mint: pawn_loan.pawn_mint,
+        },
+    ))
+}
+
+pub fn create_price_feed(
+    authority: &Pubkey,
+    collection: &Pubkey,
+    quote_mint: &Pubkey,
+) -> Instruction {
+    instruction(
+        pawn_shop::accounts::CreatePriceFeed {
+            price_feed: find_price_feed_address(authority, collection, quote_mint).0,
+            authority: *authority,
+            system_program: system_program::ID,
+        },
+        vec![],
+        pawn_shop::instruction::CreatePriceFeed {
+            collection: *collection,
+            quote_mint: *quote_mint,
+        },
+    )
+}
+
+pub fn update_price_feed(
+    price_feed: &Pubkey,
+    authority: &Pubkey,
+    price: u64,
+    confidence: u64,
+) -> Instruction {
+    instruction(
+        pawn_shop::accounts::UpdatePriceFeed {
+            price_feed: *price_feed,
+            authority: *authority,
+        },
+        vec![],
+        pawn_shop::instruction::UpdatePriceFeed { price, confidence },
+    )
+}
+
+pub fn init_collection_registry(enabled: bool) -> Instruction {
+    instruction(
+        pawn_shop::accounts::InitCollectionRegistry {
+            collection_registry: find_collection_registry_address().0,
+            fee_collector: fee_collector::ID,
+            system_program: system_program::ID,
+        },
+        vec![],
+        pawn_shop::instruction::InitCollectionRegistry { enabled },
+    )
+}
+
+pub fn set_collection_registry_enabled(enabled: bool) -> Instruction {
+    instruction(
+        pawn_shop::accounts::SetCollectionRegistryEnabled {
+            collection_registry: find_collection_registry_address().0,
+            fee_collector: fee_collector::ID,
+        },
+        vec![],
+        pawn_shop::instruction::SetCollectionRegistryEnabled { enabled },
+    )
+}
+
+pub fn register_collection(
+    collection: &Pubkey,
+    mint: &Pubkey,
+    max_principal_amount: u64,
+    max_duration: i64,
+) -> Instruction {
+    instruction(
+        pawn_shop::accounts::RegisterCollection {
+            registered_collection: find_registered_collection_address(collection).0,
+            fee_collector: fee_collector::ID,
+            system_program: system_program::ID,
+        },
+        vec![],
+        pawn_shop::instruction::RegisterCollection {
+            collection: *collection,
+            mint: *mint,
+            max_principal_amount,
+            max_duration,
+        },
+    )
+}
+
+pub fn set_collection_royalty(
+    collection: &Pubkey,
+    creator_royalty: Option<CreatorRoyalty>,
+) -> Instruction {
+    instruction(
+        pawn_shop::accounts::SetCollectionRoyalty {
+            registered_collection: find_registered_collection_address(collection).0,
+            fee_collector: fee_collector::ID,
+        },
+        vec![],
+        pawn_shop::instruction::SetCollectionRoyalty { creator_royalty },
+    )
+}
+
+pub fn unregister_collection(collection: &Pubkey) -> Instruction {
+    instruction(
+        pawn_shop::accounts::UnregisterCollection {
+            registered_collection: find_registered_collection_address(collection).0,
+            fee_collector: fee_collector::ID,
+        },
+        vec![],
+        pawn_shop::instruction::UnregisterCollection {},
+    )
+}
+
+pub fn create_fee_schedule(mint: &Pubkey, tiers: Vec<FeeTier>) -> Instruction {
+    instruction(
+        pawn_shop::accounts::CreateFeeSchedule {
+            fee_schedule: find_fee_schedule_address(mint).0,
+            fee_collector: fee_collector::ID,
+            system_program: system_program::ID,
+        },
+        vec![],
+        pawn_shop::instruction::CreateFeeSchedule { mint: *mint, tiers },
+    )
+}
+
+fn update_fee_schedule_accounts(mint: &Pubkey) -> pawn_shop::accounts::UpdateFeeSchedule {
+    pawn_shop::accounts::UpdateFeeSchedule {
+        fee_schedule: find_fee_schedule_address(mint).0,
+        fee_collector: fee_collector::ID,
+    }
+}
+
+pub fn update_fee_schedule(mint: &Pubkey, tiers: Vec<FeeTier>) -> Instruction {
+    instruction(
+        update_fee_schedule_accounts(mint),
+        vec![],
+        pawn_shop::instruction::UpdateFeeSchedule { tiers },
+    )
+}
+
+pub fn set_origination_fee(mint: &Pubkey, origination_fee_bps: u64) -> Instruction {
+    instruction(
+        update_fee_schedule_accounts(mint),
+        vec![],
+        pawn_shop::instruction::SetOriginationFee {
+            origination_fee_bps,
+        },
+    )
+}
+
+pub fn set_default_fee(mint: &Pubkey, default_fee_bps: u64) -> Instruction {
+    instruction(
+        update_fee_schedule_accounts(mint),
+        vec![],
+        pawn_shop::instruction::SetDefaultFee { default_fee_bps },
+    )
+}
+
+pub fn create_lender_volume(lender: &Pubkey, mint: &Pubkey) -> Instruction {
+    instruction(
+        pawn_shop::accounts::CreateLenderVolume {
+            lender_volume: find_lender_volume_address(lender, mint).0,
+            lender: *lender,
+            system_program: system_program::ID,
+        },
+        vec![],
+        pawn_shop::instruction::CreateLenderVolume { mint: *mint },
+    )
+}
+
+pub fn migrate_loan(pawn_loan_address: &Pubkey, payer: &Pubkey) -> Instruction {
+    instruction(
+        pawn_shop::accounts::MigrateLoan {
+            pawn_loan: *pawn_loan_address,
+            payer: *payer,
+            system_program: system_program::ID,
+        },
+        vec![],
+        pawn_shop::instruction::MigrateLoan {},
+    )
+}
+
+pub fn init_protocol_stats() -> Instruction {
+    instruction(
+        pawn_shop::accounts::InitProtocolStats {
+            protocol_stats: find_protocol_stats_address().0,
+            fee_collector: fee_collector::ID,
+            system_program: system_program::ID,
+        },
+        vec![],
+        pawn_shop::instruction::InitProtocolStats {},
+    )
+}
+
+pub fn init_referral_config(referral_fee_share_bps: u64) -> Instruction {
+    instruction(
+        pawn_shop::accounts::InitReferralConfig {
+            referral_config: find_referral_config_address().0,
+            fee_collector: fee_collector::ID,
+            system_program: system_program::ID,
+        },
+        vec![],
+        pawn_shop::instruction::InitReferralConfig {
+            referral_fee_share_bps,
+        },
+    )
+}
+
+pub fn set_referral_fee_share(referral_fee_share_bps: u64) -> Instruction {
+    instruction(
+        pawn_shop::accounts::SetReferralFeeShare {
+            referral_config: find_referral_config_address().0,
+            fee_collector: fee_collector::ID,
+        },
+        vec![],
+        pawn_shop::instruction::SetReferralFeeShare {
+            referral_fee_share_bps,
+        },
+    )
+}
+
+pub fn register_referrer(referrer: &Pubkey) -> Instruction {
+    instruction(
+        pawn_shop::accounts::RegisterReferrer {
+            registered_referrer: find_registered_referrer_address(referrer).0,
+            fee_collector: fee_collector::ID,
+            system_program: system_program::ID,
+        },
+        vec![],
+        pawn_shop::instruction::RegisterReferrer {
+            referrer: *referrer,
+        },
+    )
+}
+
+pub fn unregister_referrer(referrer: &Pubkey) -> Instruction {
+    instruction(
+        pawn_shop::accounts::UnregisterReferrer {
+            registered_referrer: find_registered_referrer_address(referrer).0,
+            fee_collector: fee_collector::ID,
+        },
+        vec![],
+        pawn_shop::instruction::UnregisterReferrer {},
+    )
+}
+
+/// Claims the referral fees earned in the mint into the payment account of the referrer.
+pub fn claim_referral_fees(referrer: &Pubkey, mint: &Pubkey) -> Instruction {
+    let admin = find_admin_address().0;
+
+    instruction(
+        pawn_shop::accounts::ClaimReferralFees {
+            referral_earnings: find_referral_earnings_address(referrer, mint).0,
+            referrer: *referrer,
+            referrer_payment_account: find_payment_account(referrer, mint),
+            admin,
+            admin_payment_account: find_payment_account(&admin, mint),
+            fee_ledger: find_fee_ledger_address(mint).0,
+            token_program: token::ID,
+            system_program: system_program::ID,
+        },
+        vec![],
+        pawn_shop::instruction::ClaimReferralFees {},
+    )
+}
+
+/// Referral earnings of the referrers then payment accounts of the creator royalty recipients, in
+/// the order the program credits and pays them. Creators are the ones listed in the pawn metadata,
+/// ignored when the royalty goes to a payout address.
+fn repayment_remaining_accounts(
+    pawn_loan: &PawnLoanView,
+    mint: &Pubkey,
+    creators: &[Creator],
+) -> Vec<AccountMeta> {
+    let mut remaining_accounts = [pawn_loan.borrower_referrer, pawn_loan.lender_referrer]
+        .iter()
+        .flatten()
+        .map(|referrer| AccountMeta::new(find_referral_earnings_address(referrer, mint).0, false))
+        .collect::<Vec<AccountMeta>>();
+
+    match pawn_loan.creator_royalty {
+        Some(CreatorRoyalty {
+            payout_address: Some(payout_address),
+            ..
+        }) => remaining_accounts.push(AccountMeta::new(
+            find_payment_account(&payout_address, mint),
+            false,
+        )),
+        Some(CreatorRoyalty {
+            payout_address: None,
+            ..
+        }) => {
+            remaining_accounts.push(AccountMeta::new_readonly(
+                find_metadata_address(&pawn_loan.pawn_mint).0,
+                false,
+            ));
+            remaining_accounts.extend(creators.iter().filter(|creator| creator.share != 0).map(
+                |creator| AccountMeta::new(find_payment_account(&creator.address, mint), false),
+            ));
+        }
+        None => (),
+    }
+
+    remaining_accounts
+}
+
+/// Repays the loan and thaws the pawn. The lender payment account defaults to the payment
+/// account of the lender wallet, loans funded by a lending pool are repaid into its vault.
+pub fn repay_loan(
+    pawn_loan_address: &Pubkey,
+    pawn_loan: &PawnLoanView,
+    lender_payment_account: Option<Pubkey>,
+    creators: &[Creator],
+) -> Result<Instruction> {
+    let terms = terms(pawn_loan)?;
+    let admin = find_admin_address().0;
+
+    Ok(instruction(
+        pawn_shop::accounts::RepayLoan {
+            pawn_loan: *pawn_loan_address,
+            pawn_token_account: pawn_loan.pawn_token_account,
+            pawn_mint: pawn_loan.pawn_mint,
+            edition: find_edition_address(&pawn_loan.pawn_mint).0,
+            borrower: pawn_loan.borrower,
+            borrower_payment_account: find_payment_account(&pawn_loan.borrower, &terms.mint),
+            lender: pawn_loan.lender,
+            lender_payment_account: lender_payment_account
+                .unwrap_or_else(|| find_payment_account(&pawn_loan.lender, &terms.mint)),
+            admin,
+            admin_payment_account: find_payment_account(&admin, &terms.mint),
+            referral_config: find_referral_config_address().0,
+            fee_ledger: find_fee_ledger_address(&terms.mint).0,
+            protocol_stats: find_protocol_stats_address().0,
+            mint_stats: mint_stats(Some(&terms.mint)),
+            borrower_stats: find_borrower_stats_address(&pawn_loan.borrower).0,
+            lender_stats: find_lender_stats_address(&pawn_loan.lender).0,
+            token_program: token::ID,
+            mpl_token_metadata_program: mpl_token_metadata::ID,
+            system_program: system_program::ID,
+        },
+        repayment_remaining_accounts(pawn_loan, &terms.mint, creators),
+        pawn_shop::instruction::RepayLoan {},
+    ))
+}
+
+pub fn cancel_loan(pawn_loan_address: &Pubkey, pawn_loan: &PawnLoanView) -> Instruction {
+    instruction(
+        pawn_shop::accounts::CancelLoan {
+            pawn_loan: *pawn_loan_address,
+            borrower: pawn_loan.borrower,
+            pawn_token_account: pawn_loan.pawn_token_account,
+            pawn_mint: pawn_loan.pawn_mint,
+            edition: find_edition_address(&pawn_loan.pawn_mint).0,
+            protocol_stats: find_protocol_stats_address().0,
+            mint_stats: mint_stats(pawn_loan.desired_terms.as_ref().map(|terms| &terms.mint)),
+            token_program: token::ID,
+            mpl_token_metadata_program: mpl_token_metadata::ID,
+        },
+        vec![],
+        pawn_shop::instruction::CancelLoan {},
+    )
+}
+
+/// Seizes the pawn of the defaulted loan into the associated token account of the lender, which
+/// pays the default fee of the loan mint.
+pub fn seize_pawn(pawn_loan_address: &Pubkey, pawn_loan: &PawnLoanView) -> Result<Instruction> {
+    let terms = terms(pawn_loan)?;
+    let admin = find_admin_address().0;
+
+    Ok(instruction(
+        pawn_shop::accounts::SeizePawn {
+            pawn_loan: *pawn_loan_address,
+            pawn_token_account: pawn_loan.pawn_token_account,
+            pawn_mint: pawn_loan.pawn_mint,
+            edition: find_edition_address(&pawn_loan.pawn_mint).0,
+            lender: pawn_loan.lender,
+            lender_pawn_token_account: get_associated_token_address(
+                &pawn_loan.lender,
+                &pawn_loan.pawn_mint,
+            ),
+            lender_payment_account: find_payment_account(&pawn_loan.lender, &terms.mint),
+            fee_schedule: find_fee_schedule_address(&terms.mint).0,
+            admin,
+            admin_payment_account: find_payment_account(&admin, &terms.mint),
+            fee_ledger: find_fee_ledger_address(&terms.mint).0,
+            protocol_stats: find_protocol_stats_address().0,
+            mint_stats: mint_stats(Some(&terms.mint)),
+            borrower_stats: find_borrower_stats_address(&pawn_loan.borrower).0,
+            lender_stats: find_lender_stats_address(&pawn_loan.lender).0,
+            token_program: token::ID,
+            mpl_token_metadata_program: mpl_token_metadata::ID,
+            system_program: system_program::ID,
+        },
+        vec![],
+        pawn_shop::instruction::SeizePawn {},
+    ))
+}
+
+/// Sets the payoff quote as return data, to be read from a simulation. Quotes can also be computed
+/// off-chain with `quote::quote_payoff`. The pawn metadata is passed when the creator royalty goes
+/// to the creators of the pawn.
+pub fn quote_payoff(
+    pawn_loan_address: &Pubkey,
+    pawn_loan: &PawnLoanView,
+    timestamp: Option<i64>,
+) -> Instruction {
+    let remaining_accounts = match pawn_loan.creator_royalty {
+        Some(CreatorRoyalty {
+            payout_address: None,
+            ..
+        }) => vec![AccountMeta::new_readonly(
+            find_metadata_address(&pawn_loan.pawn_mint).0,
+            false,
+        )],
+        _ => vec![],
+    };
+
+    instruction(
+        pawn_shop::accounts::QuotePayoff {
+            pawn_loan: *pawn_loan_address,
+        },
+        remaining_accounts,
+        pawn_shop::instruction::QuotePayoff { timestamp },
+    )
+}
+
+/// Withdraws the admin fees of the mint to the fee collector, or to the recipients of the fee split
+/// in their order when one is configured.
+pub fn withdraw_admin_fees(mint: &Pubkey, fee_split_recipients: &[Pubkey]) -> Instruction {
+    let admin = find_admin_address().0;
+
+    instruction(
+        pawn_shop::accounts::WithdrawAdminFees {
+            fee_collector: fee_collector::ID,
+            fee_collector_payment_account: find_payment_account(&fee_collector::ID, mint),
+            admin,
+            admin_payment_account: find_payment_account(&admin, mint),
+            fee_split: find_fee_split_address().0,
+            fee_ledger: find_fee_ledger_address(mint).0,
+            token_program: token::ID,
+            system_program: system_program::ID,
+        },
+        fee_split_recipients
+            .iter()
+            .map(|recipient| AccountMeta::new(find_payment_account(recipient, mint), false))
+            .collect(),
+        pawn_shop::instruction::WithdrawAdminFees {},
+    )
+}
+
+pub fn init_fee_split(recipients: Vec<FeeSplitRecipient>) -> Instruction {
+    instruction(
+        pawn_shop::accounts::InitFeeSplit {
+            fee_split: find_fee_split_address().0,
+            fee_collector: fee_collector::ID,
+            system_program: system_program::ID,
+        },
+        vec![],
+        pawn_shop::instruction::InitFeeSplit { recipients },
+    )
+}
+
+pub fn update_fee_split(recipients: Vec<FeeSplitRecipient>) -> Instruction {
+    instruction(
+        pawn_shop::accounts::UpdateFeeSplit {
+            fee_split: find_fee_split_address().0,
+            fee_collector: fee_collector::ID,
+        },
+        vec![],
+        pawn_shop::instruction::UpdateFeeSplit { recipients },
+    )
+}
+
+/// Sweeps the admin fees of the spl mints into the associated token accounts of the fee collector.
+/// Fails while the fee split pays other recipients. Each mint adds a triple of remaining accounts:
+/// the admin token account, the fee collector token account and the fee ledger of the mint, which
+/// keeps the referral fees not claimed yet in the admin pda. Sol fees are withdrawn on their own,
+/// the native mint is rejected.
+pub fn sweep_admin_fees(mints: &[Pubkey]) -> Result<Instruction> {
+    if mints.contains(&native_mint::ID) {
+        return Err(ErrorCode::InvalidFeeSweepAccounts.into());
+    }
+    let admin = find_admin_address().0;
+
+    Ok(instruction(
+        pawn_shop::accounts::SweepAdminFees {
+            fee_collector: fee_collector::ID,
+            admin,
+            fee_split: find_fee_split_address().0,
+            token_program: token::ID,
+            system_program: system_program::ID,
+        },
+        mints
+            .iter()
+            .flat_map(|mint| {
+                vec![
+                    AccountMeta::new(get_associated_token_address(&admin, mint), false),
+                    AccountMeta::new(
+                        get_associated_token_address(&fee_collector::ID, mint),
+                        false,
+                    ),
+                    AccountMeta::new(find_fee_ledger_address(mint).0, false),
+                ]
+            })
+            .collect(),
+        pawn_shop::instruction::SweepAdminFees {},
+    ))
+}
+
+/// Creates the lending pool at the address derived from the base keypair, which signs with the
+/// manager.
+pub fn create_lending_pool(base: &Pubkey, manager: &Pubkey, mint: &Pubkey) -> Instruction {
+    let lending_pool = find_lending_pool_address(base).0;
+
+    instruction(
+        pawn_shop::accounts::CreateLendingPool {
+            base: *base,
+            lending_pool,
+            manager: *manager,
+            mint: *mint,
+            vault: find_pool_vault_address(&lending_pool).0,
+            lp_mint: find_pool_lp_mint_address(&lending_pool).0,
+            token_program: token::ID,
+            system_program: system_program::ID,
+            rent: sysvar::rent::ID,
+        },
+        vec![],
+        pawn_shop::instruction::CreateLendingPool {},
+    )
+}
+
+/// Deposits from the associated token account of the depositor in the pool mint, the shares being
+/// minted to its associated token account of the lp mint.
+pub fn deposit_pool_liquidity(
+    lending_pool: &Pubkey,
+    depositor: &Pubkey,
+    mint: &Pubkey,
+    amount: u64,
+) -> Instruction {
+    let lp_mint = find_pool_lp_mint_address(lending_pool).0;
+
+    instruction(
+        pawn_shop::accounts::DepositPoolLiquidity {
+            lending_pool: *lending_pool,
+            vault: find_pool_vault_address(lending_pool).0,
+            lp_mint,
+            depositor: *depositor,
+            depositor_payment_account: get_associated_token_address(depositor, mint),
+            depositor_lp_token_account: get_associated_token_address(depositor, &lp_mint),
+            locked_shares_account: get_associated_token_address(lending_pool, &lp_mint),
+            token_program: token::ID,
+        },
+        vec![],
+        pawn_shop::instruction::DepositPoolLiquidity { amount },
+    )
+}
+
+pub fn withdraw_pool_liquidity(
+    lending_pool: &Pubkey,
+    depositor: &Pubkey,
+    mint: &Pubkey,
+    shares: u64,
+) -> Instruction {
+    let lp_mint = find_pool_lp_mint_address(lending_pool).0;
+
+    instruction(
+        pawn_shop::accounts::WithdrawPoolLiquidity {
+            lending_pool: *lending_pool,
+            vault: find_pool_vault_address(lending_pool).0,
+            lp_mint,
+            depositor: *depositor,
+            depositor_payment_account: get_associated_token_address(depositor, mint),
+            depositor_lp_token_account: get_associated_token_address(depositor, &lp_mint),
+            token_program: token::ID,
+        },
+        vec![],
+        pawn_shop::instruction::WithdrawPoolLiquidity { shares },
+    )
+}
+
+pub fn underwrite_loan_from_pool(
+    pawn_loan_address: &Pubkey,
+    pawn_loan: &PawnLoanView,
+    lending_pool: &Pubkey,
+    manager: &Pubkey,
+) -> Result<Instruction> {
+    let terms = desired_terms(pawn_loan)?;
+    let admin = find_admin_address().0;
+
+    Ok(instruction(
+        pawn_shop::accounts::UnderwriteLoanFromPool {
+            pawn_loan: *pawn_loan_address,
+            lending_pool: *lending_pool,
+            manager: *manager,
+            vault: find_pool_vault_address(lending_pool).0,
+            borrower_payment_account: get_associated_token_address(
+                &pawn_loan.borrower,
+                &terms.mint,
+            ),
+            fee_schedule: find_fee_schedule_address(&terms.mint).0,
+            admin,
+            admin_payment_account: get_associated_token_address(&admin, &terms.mint),
+            fee_ledger: find_fee_ledger_address(&terms.mint).0,
+            borrower_stats: find_borrower_stats_address(&pawn_loan.borrower).0,
+            lender_stats: find_lender_stats_address(lending_pool).0,
+            protocol_stats: find_protocol_stats_address().0,
+            mint_stats: mint_stats(Some(&terms.mint)),
+            token_program: token::ID,
+            system_program: system_program::ID,
+        },
+        vec![],
+        pawn_shop::instruction::UnderwriteLoanFromPool {
+            expected_terms: *terms,
+            expected_pawn_mint: pawn_loan.pawn_mint,
+        },
+    ))
+}
+
+pub fn set_pool_underwriting_policy(
+    lending_pool: &Pubkey,
+    manager: &Pubkey,
+    underwriting_policy: Option<UnderwritingPolicy>,
+) -> Instruction {
+    instruction(
+        pawn_shop::accounts::SetPoolUnderwritingPolicy {
+            lending_pool: *lending_pool,
+            manager: *manager,
+        },
+        vec![],
+        pawn_shop::instruction::SetPoolUnderwritingPolicy {
+            underwriting_policy,
+        },
+    )
+}
+
+pub fn add_pool_collection(
+    lending_pool: &Pubkey,
+    manager: &Pubkey,
+    collection: &Pubkey,
+    max_principal_amount: u64,
+) -> Instruction {
+    instruction(
+        pawn_shop::accounts::AddPoolCollection {
+            lending_pool: *lending_pool,
+            pool_collection: find_pool_collection_address(lending_pool, collection).0,
+            manager: *manager,
+            system_program: system_program::ID,
+        },
+        vec![],
+        pawn_shop::instruction::AddPoolCollection {
+            collection: *collection,
+            max_principal_amount,
+        },
+    )
+}
+
+pub fn remove_pool_collection(
+    lending_pool: &Pubkey,
+    manager: &Pubkey,
+    collection: &Pubkey,
+) -> Instruction {
+    instruction(
+        pawn_shop::accounts::RemovePoolCollection {
+            lending_pool: *lending_pool,
+            pool_collection: find_pool_collection_address(lending_pool, collection).0,
+            manager: *manager,
+        },
+        vec![],
+        pawn_shop::instruction::RemovePoolCollection {},
+    )
+}
+
+/// Underwrites the open loan from the pool without the manager, the pawn belonging to a collection
+/// accepted by the pool. The payer funds the fee ledger of the loan mint if it is created.
+pub fn underwrite_from_pool(
+    pawn_loan_address: &Pubkey,
+    pawn_loan: &PawnLoanView,
+    lending_pool: &Pubkey,
+    collection: &Pubkey,
+    payer: &Pubkey,
+) -> Result<Instruction> {
+    let terms = desired_terms(pawn_loan)?;
+    let admin = find_admin_address().0;
+
+    Ok(instruction(
+        pawn_shop::accounts::UnderwriteFromPool {
+            pawn_loan: *pawn_loan_address,
+            lending_pool: *lending_pool,
+            pool_collection: find_pool_collection_address(lending_pool, collection).0,
+            pawn_metadata: find_metadata_address(&pawn_loan.pawn_mint).0,
+            vault: find_pool_vault_address(lending_pool).0,
+            borrower_payment_account: get_associated_token_address(
+                &pawn_loan.borrower,
+                &terms.mint,
+            ),
+            fee_schedule: find_fee_schedule_address(&terms.mint).0,
+            admin,
+            admin_payment_account: get_associated_token_address(&admin, &terms.mint),
+            fee_ledger: find_fee_ledger_address(&terms.mint).0,
+            payer: *payer,
+            borrower_stats: find_borrower_stats_address(&pawn_loan.borrower).0,
+            lender_stats: find_lender_stats_address(lending_pool).0,
+            protocol_stats: find_protocol_stats_address().0,
+            mint_stats: mint_stats(Some(&terms.mint)),
+            token_program: token::ID,
+            system_program: system_program::ID,
+        },
+        vec![],
+        pawn_shop::instruction::UnderwriteFromPool {},
+    ))
+}
+
+/// Seizes the pawn of the defaulted loan into the associated token account of the lending pool
+/// that funded it. The payer funds the loan history of the parties if it is created.
+pub fn seize_pawn_for_pool(
+    pawn_loan_address: &Pubkey,
+    pawn_loan: &PawnLoanView,
+    payer: &Pubkey,
+) -> Result<Instruction> {
+    let terms = terms(pawn_loan)?;
+
+    Ok(instruction(
+        pawn_shop::accounts::SeizePawnForPool {
+            pawn_loan: *pawn_loan_address,
+            lending_pool: pawn_loan.lender,
+            pawn_token_account: pawn_loan.pawn_token_account,
+            pawn_mint: pawn_loan.pawn_mint,
+            edition: find_edition_address(&pawn_loan.pawn_mint).0,
+            pool_pawn_token_account: get_associated_token_address(
+                &pawn_loan.lender,
+                &pawn_loan.pawn_mint,
+            ),
+            protocol_stats: find_protocol_stats_address().0,
+            mint_stats: mint_stats(Some(&terms.mint)),
+            payer: *payer,
+            borrower_stats: find_borrower_stats_address(&pawn_loan.borrower).0,
+            lender_stats: find_lender_stats_address(&pawn_loan.lender).0,
+            token_program: token::ID,
+            mpl_token_metadata_program: mpl_token_metadata::ID,
+            system_program: system_program::ID,
+        },
+        vec![],
+        pawn_shop::instruction::SeizePawnForPool {},
+    ))
+}
+
+/// Withdraws a pawn seized by the pool from the associated token account of the pool to the one of
+/// the manager.
+pub fn withdraw_pool_pawn(
+    lending_pool: &Pubkey,
+    manager: &Pubkey,
+    pawn_mint: &Pubkey,
+) -> Instruction {
+    instruction(
+        pawn_shop::accounts::WithdrawPoolPawn {
+            lending_pool: *lending_pool,
+            manager: *manager,
+            pool_pawn_token_account: get_associated_token_address(lending_pool, pawn_mint),
+            manager_pawn_token_account: get_associated_token_address(manager, pawn_mint),
+            token_program: token::ID,
+        },
+        vec![],
+        pawn_shop::instruction::WithdrawPoolPawn {},
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pawn_shop::{InterestModel, LoanStatus, PAWN_LOAN_VERSION};
+
+    fn active_loan(mint: Pubkey) -> PawnLoanView {
+        let terms = LoanTerms {
+            principal_amount: 1_000_000_000,
+            mint,
+            interest_model: InterestModel::AnnualPercentageRate {
+                annual_percentage_rate_bps: 3_500,
+            },
+            duration: 7 * 24 * 60 * 60,
+            minimum_period_ratio_bps: None,
+        };
+
+        PawnLoanView {
+            version: PAWN_LOAN_VERSION,
+            base: Pubkey::new_unique(),
+            bump: 255,
+            borrower: Pubkey::new_unique(),
+            pawn_token_account: Pubkey::new_unique(),
+            pawn_mint: Pubkey::new_unique(),
+            status: LoanStatus::Active,
+            lender: Pubkey::new_unique(),
+            desired_terms: Some(terms),
+            terms: Some(terms),
+            creation_time: 1_650_000_000,
+            start_time: 1_650_000_100,
+            end_time: 0,
+            creator_royalty: None,
+            borrower_referrer: None,
+            lender_referrer: None,
+            admin_fee_bps: 200,
+            seed_nonce: None,
+        }
+    }
+
+    fn keys(instruction: &Instruction) -> Vec<Pubkey> {
+        instruction
+            .accounts
+            .iter()
+            .map(|account| account.pubkey)
+            .collect()
+    }
+
+    #[test]
+    fn sol_loans_are_paid_from_and_to_wallets() {
+        let pawn_loan = active_loan(native_mint::ID);
+        let admin = find_admin_address().0;
+
+        let repay = repay_loan(&Pubkey::new_unique(), &pawn_loan, None, &[]).unwrap();
+        let accounts = keys(&repay);
+        // borrower, borrower payment account, lender, lender payment account, admin, admin payment
+        // account
+        assert_eq!(
+            vec![
+                pawn_loan.borrower,
+                pawn_loan.borrower,
+                pawn_loan.lender,
+                pawn_loan.lender,
+                admin,
+                admin
+            ],
+            accounts[4..10]
+        );
+        assert_eq!(19, accounts.len());
+
+        let mint = Pubkey::new_unique();
+        let pawn_loan = active_loan(mint);
+        let repay = repay_loan(&Pubkey::new_unique(), &pawn_loan, None, &[]).unwrap();
+        assert_eq!(
+            get_associated_token_address(&pawn_loan.borrower, &mint),
+            repay.accounts[5].pubkey
+        );
+        assert_eq!(
+            get_associated_token_address(&admin, &mint),
+            repay.accounts[9].pubkey
+        );
+    }
+
+    #[test]
+    fn repayment_credits_the_referrers_before_paying_the_creators() {
+        let mut pawn_loan = active_loan(native_mint::ID);
+        let lender_referrer = Pubkey::new_unique();
+        pawn_loan.lender_referrer = Some(lender_referrer);
+        pawn_loan.creator_royalty = Some(CreatorRoyalty {
+            royalty_bps: 500,
+            payout_address: None,
+        });
+        let creators = [
+            Creator {
+                address: Pubkey::new_unique(),
+                verified: true,
+                share: 0,
+            },
+            Creator {
+                address: Pubkey::new_unique(),
+                verified: false,
+                share: 100,
+            },
+        ];
+
+        let repay = repay_loan(&Pubkey::new_unique(), &pawn_loan, None, &creators).unwrap();
+        assert_eq!(
+            vec![
+                find_referral_earnings_address(&lender_referrer, &native_mint::ID).0,
+                find_metadata_address(&pawn_loan.pawn_mint).0,
+                creators[1].address
+            ],
+            keys(&repay)[19..]
+        );
+        assert!(!repay.accounts[20].is_writable);
+
+        let payout_address = Pubkey::new_unique();
+        pawn_loan.creator_royalty = Some(CreatorRoyalty {
+            royalty_bps: 500,
+            payout_address: Some(payout_address),
+        });
+        let repay = repay_loan(&Pubkey::new_unique(), &pawn_loan, None, &creators).unwrap();
+        assert_eq!(
+            vec![
+                find_referral_earnings_address(&lender_referrer, &native_mint::ID).0,
+                payout_address
+            ],
+            keys(&repay)[19..]
+        );
+    }
+
+    #[test]
+    fn loans_without_terms_are_rejected() {
+        let mut pawn_loan = active_loan(native_mint::ID);
+        pawn_loan.status = LoanStatus::Open;
+        pawn_loan.terms = None;
+
+        assert!(repay_loan(&Pubkey::new_unique(), &pawn_loan, None, &[]).is_err());
+        assert!(seize_pawn(&Pubkey::new_unique(), &pawn_loan).is_err());
+    }
+
+    #[test]
+    fn open_loans_are_underwritten_from_their_desired_terms() {
+        let mut pawn_loan = active_loan(native_mint::ID);
+        pawn_loan.status = LoanStatus::Open;
+        pawn_loan.terms = None;
+
+        assert!(underwrite_loan(
+            &Pubkey::new_unique(),
+            &pawn_loan,
+            &Pubkey::new_unique(),
+            None,
+            None,
+            false
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn native_mint_is_rejected_without_token_accounts() {
+        let pawn_loan = active_loan(native_mint::ID);
+        assert!(underwrite_loan_with_delegate(
+            &Pubkey::new_unique(),
+            &pawn_loan,
+            &Pubkey::new_unique(),
+            &Pubkey::new_unique(),
+            false
+        )
+        .is_err());
+        assert!(sweep_admin_fees(&[Pubkey::new_unique(), native_mint::ID]).is_err());
+
+        let mint = Pubkey::new_unique();
+        assert!(underwrite_loan_with_delegate(
+            &Pubkey::new_unique(),
+            &active_loan(mint),
+            &Pubkey::new_unique(),
+            &Pubkey::new_unique(),
+            false
+        )
+        .is_ok());
+        let sweep = sweep_admin_fees(&[mint]).unwrap();
+        assert_eq!(
+            get_associated_token_address(&find_admin_address().0, &mint),
+            sweep.accounts[5].pubkey
+        );
+    }
+}
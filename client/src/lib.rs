@@ -0,0 +1,13 @@
+//! Rust client of the pawn shop program: account addresses, instruction builders, decoding of the
+//! pawn loans and of the events, and the loan math of the program for quotes.
+//! Build with the `mainnet` feature to address the mainnet fee collector.
+
+pub mod event;
+pub mod instruction;
+pub mod pda;
+pub mod quote;
+pub mod state;
+
+pub use event::PawnShopEvent;
+pub use pawn_shop;
+pub use state::decode_pawn_loan;
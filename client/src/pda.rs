@@ -0,0 +1,227 @@
+//! Addresses of the program accounts, derived with the seeds of the account constraints.
+
+use anchor_lang::prelude::*;
+use anchor_spl::associated_token::get_associated_token_address;
+use mpl_token_metadata::state::{EDITION, PREFIX};
+use pawn_shop::native_mint;
+
+/// Pawn loan requested with a base keypair, `[base, "pawn_loan"]`
+pub fn find_pawn_loan_address_for_base(base: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[base.as_ref(), b"pawn_loan".as_ref()], &pawn_shop::ID)
+}
+
+/// Pawn loan requested on the pawn, `[pawn_mint, borrower, nonce, "pawn_loan"]`
+pub fn find_pawn_loan_address(pawn_mint: &Pubkey, borrower: &Pubkey, nonce: u64) -> (Pubkey, u8) {
+    pawn_shop::find_pawn_loan_address(pawn_mint, borrower, nonce)
+}
+
+/// Holds the admin fees, in lamports or in its spl token accounts
+pub fn find_admin_address() -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"admin".as_ref()], &pawn_shop::ID)
+}
+
+pub fn find_collection_registry_address() -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"collection_registry".as_ref()], &pawn_shop::ID)
+}
+
+pub fn find_registered_collection_address(collection: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[b"registered_collection".as_ref(), collection.as_ref()],
+        &pawn_shop::ID,
+    )
+}
+
+pub fn find_referral_config_address() -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"referral_config".as_ref()], &pawn_shop::ID)
+}
+
+pub fn find_registered_referrer_address(referrer: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[b"registered_referrer".as_ref(), referrer.as_ref()],
+        &pawn_shop::ID,
+    )
+}
+
+/// Referral fees earned by the referrer in the mint, held by the admin pda until claimed
+pub fn find_referral_earnings_address(referrer: &Pubkey, mint: &Pubkey) -> (Pubkey, u8) {
+    pawn_shop::find_referral_earnings_address(referrer, mint)
+}
+
+pub fn find_fee_split_address() -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"fee_split".as_ref()], &pawn_shop::ID)
+}
+
+pub fn find_fee_schedule_address(mint: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[mint.as_ref(), b"fee_schedule".as_ref()], &pawn_shop::ID)
+}
+
+pub fn find_fee_ledger_address(mint: &Pubkey) -> (Pubkey, u8) {
+    pawn_shop::find_fee_ledger_address(mint)
+}
+
+pub fn find_lender_volume_address(lender: &Pubkey, mint: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[lender.as_ref(), mint.as_ref(), b"lender_volume".as_ref()],
+        &pawn_shop::ID,
+    )
+}
+
+pub fn find_lender_delegate_address(lender: &Pubkey, delegate: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[
+            lender.as_ref(),
+            delegate.as_ref(),
+            b"lender_delegate".as_ref(),
+        ],
+        &pawn_shop::ID,
+    )
+}
+
+pub fn find_price_feed_address(
+    authority: &Pubkey,
+    collection: &Pubkey,
+    quote_mint: &Pubkey,
+) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[
+            authority.as_ref(),
+            collection.as_ref(),
+            quote_mint.as_ref(),
+            b"price_feed".as_ref(),
+        ],
+        &pawn_shop::ID,
+    )
+}
+
+pub fn find_protocol_stats_address() -> (Pubkey, u8) {
+    pawn_shop::find_protocol_stats_address()
+}
+
+pub fn find_mint_stats_address(mint: &Pubkey) -> (Pubkey, u8) {
+    pawn_shop::find_mint_stats_address(mint)
+}
+
+pub fn find_borrower_stats_address(borrower: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[borrower.as_ref(), b"borrower_stats".as_ref()],
+        &pawn_shop::ID,
+    )
+}
+
+/// Loan history of a lender wallet or lending pool
+pub fn find_lender_stats_address(lender: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[lender.as_ref(), b"lender_stats".as_ref()], &pawn_shop::ID)
+}
+
+/// Lending pool created with a base keypair, `[base, "lending_pool"]`
+pub fn find_lending_pool_address(base: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[base.as_ref(), b"lending_pool".as_ref()], &pawn_shop::ID)
+}
+
+pub fn find_pool_vault_address(lending_pool: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[lending_pool.as_ref(), b"vault".as_ref()], &pawn_shop::ID)
+}
+
+pub fn find_pool_lp_mint_address(lending_pool: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[lending_pool.as_ref(), b"lp_mint".as_ref()],
+        &pawn_shop::ID,
+    )
+}
+
+pub fn find_pool_collection_address(lending_pool: &Pubkey, collection: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[
+            lending_pool.as_ref(),
+            collection.as_ref(),
+            b"pool_collection".as_ref(),
+        ],
+        &pawn_shop::ID,
+    )
+}
+
+/// Metaplex metadata of the mint
+pub fn find_metadata_address(mint: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[
+            PREFIX.as_bytes(),
+            mpl_token_metadata::ID.as_ref(),
+            mint.as_ref(),
+        ],
+        &mpl_token_metadata::ID,
+    )
+}
+
+/// Metaplex master edition of the mint, freezing and thawing the pawn
+pub fn find_edition_address(mint: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[
+            PREFIX.as_bytes(),
+            mpl_token_metadata::ID.as_ref(),
+            mint.as_ref(),
+            EDITION.as_bytes(),
+        ],
+        &mpl_token_metadata::ID,
+    )
+}
+
+/// Account paying or receiving amounts of the mint on behalf of the owner: the owner wallet itself
+/// for the native mint, its associated token account for spl mints.
+pub fn find_payment_account(owner: &Pubkey, mint: &Pubkey) -> Pubkey {
+    if *mint == native_mint::ID {
+        *owner
+    } else {
+        get_associated_token_address(owner, mint)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pawn_loan_addresses_match_the_program_seeds() {
+        let base = Pubkey::new_unique();
+        let (address, bump) = find_pawn_loan_address_for_base(&base);
+        let seeds = pawn_shop::PawnLoanSeeds::Base { base, bump: [bump] };
+        assert_eq!(
+            address,
+            Pubkey::create_program_address(&seeds.signer_seeds(), &pawn_shop::ID).unwrap()
+        );
+
+        let (pawn_mint, borrower) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let (address, bump) = find_pawn_loan_address(&pawn_mint, &borrower, 2);
+        let seeds = pawn_shop::PawnLoanSeeds::PawnMint {
+            pawn_mint,
+            borrower,
+            nonce: 2u64.to_le_bytes(),
+            bump: [bump],
+        };
+        assert_eq!(
+            address,
+            Pubkey::create_program_address(&seeds.signer_seeds(), &pawn_shop::ID).unwrap()
+        );
+        assert_ne!(address, find_pawn_loan_address(&pawn_mint, &borrower, 3).0);
+    }
+
+    #[test]
+    fn fee_schedule_address_matches_the_program() {
+        let mint = Pubkey::new_unique();
+        assert_eq!(
+            pawn_shop::find_fee_schedule_address(&mint),
+            find_fee_schedule_address(&mint).0
+        );
+    }
+
+    #[test]
+    fn payment_account_is_the_wallet_for_sol() {
+        let owner = Pubkey::new_unique();
+        assert_eq!(owner, find_payment_account(&owner, &native_mint::ID));
+
+        let mint = Pubkey::new_unique();
+        assert_eq!(
+            get_associated_token_address(&owner, &mint),
+            find_payment_account(&owner, &mint)
+        );
+    }
+}
@@ -0,0 +1,120 @@
+//! Loan math of the program, for quotes matching what the program charges.
+
+use anchor_lang::prelude::*;
+use mpl_token_metadata::state::Creator;
+pub use pawn_shop::{
+    compute_admin_fee, compute_creator_royalty, compute_default_fee, compute_interest_due,
+    compute_nominal_annual_percentage_rate_bps, compute_origination_fee, compute_payoff_amount,
+    compute_payoff_quote, compute_referral_fee, compute_shares_for_deposit,
+    compute_withdrawal_amount, creator_royalty_recipients, split_creator_royalty,
+    split_payoff_quote, InterestModel, LoanTerms, PayoffQuote,
+};
+use pawn_shop::{ErrorCode, PawnLoanView};
+
+/// Payoff of the underwritten loan when repaid at the timestamp, as `quote_payoff` returns it.
+/// Creators are the ones listed in the pawn metadata, ignored when the royalty goes to a payout
+/// address.
+pub fn quote_payoff(
+    pawn_loan: &PawnLoanView,
+    timestamp: i64,
+    creators: &[Creator],
+) -> Result<PayoffQuote> {
+    let terms = pawn_loan.terms.ok_or(ErrorCode::InvalidLoanStatus)?;
+    if timestamp < pawn_loan.start_time {
+        return Err(ErrorCode::InvalidQuoteTimestamp.into());
+    }
+
+    let quote = compute_payoff_quote(
+        &terms,
+        pawn_loan.start_time,
+        timestamp,
+        pawn_loan.admin_fee_bps,
+        pawn_loan
+            .creator_royalty
+            .map_or(0, |creator_royalty| creator_royalty.royalty_bps),
+    )?;
+
+    Ok(split_payoff_quote(quote, pawn_loan.creator_royalty.as_ref(), creators)?.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pawn_shop::{CreatorRoyalty, LoanStatus, PawnLoan, PAWN_LOAN_VERSION};
+
+    #[test]
+    fn quote_matches_the_payoff_of_the_loan() {
+        let terms = LoanTerms {
+            principal_amount: 1_000_000_000,
+            mint: Pubkey::new_unique(),
+            interest_model: InterestModel::FixedInterest {
+                interest_amount: 100_000_000,
+            },
+            duration: 7 * 24 * 60 * 60,
+            minimum_period_ratio_bps: None,
+        };
+        let pawn_loan = PawnLoanView {
+            version: PAWN_LOAN_VERSION,
+            base: Pubkey::new_unique(),
+            bump: 255,
+            borrower: Pubkey::new_unique(),
+            pawn_token_account: Pubkey::new_unique(),
+            pawn_mint: Pubkey::new_unique(),
+            status: LoanStatus::Active,
+            lender: Pubkey::new_unique(),
+            desired_terms: Some(terms),
+            terms: Some(terms),
+            creation_time: 1_650_000_000,
+            start_time: 1_650_000_100,
+            end_time: 0,
+            creator_royalty: Some(CreatorRoyalty {
+                royalty_bps: 500,
+                payout_address: None,
+            }),
+            borrower_referrer: None,
+            lender_referrer: None,
+            admin_fee_bps: 200,
+            seed_nonce: None,
+        };
+
+        let creators = [Creator {
+            address: Pubkey::new_unique(),
+            verified: true,
+            share: 100,
+        }];
+
+        let quote = quote_payoff(&pawn_loan, 1_650_000_200, &creators).unwrap();
+        assert_eq!(100_000_000, quote.interest_due);
+        assert_eq!(2_000_000, quote.admin_fee);
+        assert_eq!(5_000_000, quote.creator_royalty);
+        assert_eq!(1_093_000_000, quote.payoff_amount);
+        assert_eq!(1_100_000_000, quote.total_repayment_amount);
+        // The program computes the same quote from the account
+        let on_chain = PawnLoan::from(&pawn_loan);
+        assert_eq!(
+            quote,
+            compute_payoff_quote(
+                &terms,
+                1_650_000_100,
+                1_650_000_200,
+                on_chain.admin_fee_bps,
+                on_chain.creator_royalty_bps(),
+            )
+            .unwrap()
+        );
+
+        // The lender gets the royalty of pawns without creators
+        let quote = quote_payoff(&pawn_loan, 1_650_000_200, &[]).unwrap();
+        assert_eq!(0, quote.creator_royalty);
+        assert_eq!(1_098_000_000, quote.payoff_amount);
+
+        // Quotes before the start of the loan are rejected like by the program
+        assert!(quote_payoff(&pawn_loan, 1_650_000_099, &creators).is_err());
+
+        let open = PawnLoanView {
+            terms: None,
+            ..pawn_loan
+        };
+        assert!(quote_payoff(&open, 1_650_000_200, &creators).is_err());
+    }
+}
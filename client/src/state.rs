@@ -0,0 +1,93 @@
+//! Decoding of the pawn loan accounts, of the current zero-copy layout or of an earlier one.
+
+use anchor_lang::{prelude::*, Discriminator};
+use pawn_shop::{decode_outdated_pawn_loan, ErrorCode, PawnLoan, PawnLoanView, PAWN_LOAN_VERSION};
+
+/// Decodes the account data of a pawn loan. Loans not migrated yet are decoded as they will read
+/// once migrated.
+pub fn decode_pawn_loan(data: &[u8]) -> Result<PawnLoanView> {
+    if is_outdated(data) {
+        return decode_outdated_pawn_loan(data);
+    }
+    if data[..8] != PawnLoan::discriminator() {
+        return Err(ErrorCode::InvalidPawnLoanLayout.into());
+    }
+
+    let pawn_loan: &PawnLoan = bytemuck::try_from_bytes(&data[8..])
+        .map_err(|_| error!(anchor_lang::error::ErrorCode::AccountDidNotDeserialize))?;
+    pawn_loan.view()
+}
+
+/// Whether the pawn loan has to be migrated before any other instruction.
+pub fn is_outdated(data: &[u8]) -> bool {
+    data.len() != PawnLoan::space() || data[8] != PAWN_LOAN_VERSION
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pawn_shop::{InterestModel, LoanStatus, LoanTerms, BORSH_PAWN_LOAN_VERSION};
+
+    fn view() -> PawnLoanView {
+        PawnLoanView {
+            version: PAWN_LOAN_VERSION,
+            base: Pubkey::default(),
+            bump: 253,
+            borrower: Pubkey::new_unique(),
+            pawn_token_account: Pubkey::new_unique(),
+            pawn_mint: Pubkey::new_unique(),
+            status: LoanStatus::Open,
+            lender: Pubkey::default(),
+            desired_terms: Some(LoanTerms {
+                principal_amount: 1_000_000_000,
+                mint: Pubkey::new_unique(),
+                interest_model: InterestModel::AnnualPercentageRate {
+                    annual_percentage_rate_bps: 3_500,
+                },
+                duration: 7 * 24 * 60 * 60,
+                minimum_period_ratio_bps: None,
+            }),
+            terms: None,
+            creation_time: 1_650_000_000,
+            start_time: 0,
+            end_time: 0,
+            creator_royalty: None,
+            borrower_referrer: Some(Pubkey::new_unique()),
+            lender_referrer: None,
+            admin_fee_bps: 200,
+            seed_nonce: Some(1),
+        }
+    }
+
+    #[test]
+    fn zero_copy_pawn_loan_is_decoded() {
+        let view = view();
+        let mut data = PawnLoan::discriminator().to_vec();
+        data.extend_from_slice(bytemuck::bytes_of(&PawnLoan::from(&view)));
+
+        assert!(!is_outdated(&data));
+        assert!(decode_pawn_loan(&data).unwrap() == view);
+
+        data[..8].copy_from_slice(&[0; 8]);
+        assert!(decode_pawn_loan(&data).is_err());
+    }
+
+    #[test]
+    fn borsh_pawn_loan_is_decoded_as_migrated() {
+        let view = PawnLoanView {
+            seed_nonce: None,
+            ..view()
+        };
+        let mut data = PawnLoan::discriminator().to_vec();
+        PawnLoanView {
+            version: BORSH_PAWN_LOAN_VERSION,
+            ..view
+        }
+        .serialize(&mut data)
+        .unwrap();
+        data.resize(PawnLoan::space(), 0);
+
+        assert!(is_outdated(&data));
+        assert!(decode_pawn_loan(&data).unwrap() == view);
+    }
+}
@@ -0,0 +1,215 @@
+//! Lender delegates: a lender authorizes a delegate key, e.g. a lending bot, to underwrite
+//! loans on their behalf within limits. The lender approves the delegate pda as spl delegate
+//! of their payment token accounts, so funds can only be spent through the program.
+
+use anchor_lang::prelude::*;
+use anchor_spl::token::{Token, TokenAccount};
+use vipers::prelude::*;
+
+use crate::{native_mint, ErrorCode, LoanTerms, PawnLoan, PAWN_LOAN_VERSION};
+
+pub const MAX_DELEGATE_ALLOWED_MINTS: usize = 8;
+
+#[derive(Clone, AnchorSerialize, AnchorDeserialize, PartialEq)]
+pub struct LenderDelegateLimits {
+    pub max_principal_per_loan: u64,
+    /// Cap on the cumulative principal underwritten by the delegate
+    pub max_total_principal: u64,
+    pub allowed_mints: Vec<Pubkey>,
+    pub expiry: i64,
+}
+
+impl LenderDelegateLimits {
+    fn space() -> usize {
+        8 + 8 + 4 + 32 * MAX_DELEGATE_ALLOWED_MINTS + 8
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        invariant!(
+            self.allowed_mints.len() <= MAX_DELEGATE_ALLOWED_MINTS,
+            InvalidDelegateLimits
+        );
+        // Native SOL loans are paid from the lender wallet, which cannot be delegated
+        invariant!(
+            !self.allowed_mints.contains(&native_mint::ID),
+            InvalidDelegateLimits
+        );
+
+        Ok(())
+    }
+}
+
+#[account]
+pub struct LenderDelegate {
+    pub lender: Pubkey,
+    pub delegate: Pubkey,
+    pub bump: u8,
+    pub limits: LenderDelegateLimits,
+    pub total_principal_underwritten: u64,
+}
+
+impl LenderDelegate {
+    pub fn space() -> usize {
+        8 + 32 + 32 + 1 + LenderDelegateLimits::space() + 8
+    }
+
+    /// Checks the terms are within the delegate limits and records their principal.
+    pub fn record_underwriting(&mut self, terms: &LoanTerms, unix_timestamp: i64) -> Result<()> {
+        invariant!(unix_timestamp < self.limits.expiry, DelegateExpired);
+        invariant!(
+            self.limits.allowed_mints.contains(&terms.mint),
+            DelegateMintNotAllowed
+        );
+        invariant!(
+            terms.principal_amount <= self.limits.max_principal_per_loan,
+            DelegateLimitExceeded
+        );
+
+        let total_principal_underwritten = self
+            .total_principal_underwritten
+            .checked_add(terms.principal_amount)
+            .ok_or(ErrorCode::CalculationError)?;
+        invariant!(
+            total_principal_underwritten <= self.limits.max_total_principal,
+            DelegateLimitExceeded
+        );
+        self.total_principal_underwritten = total_principal_underwritten;
+
+        Ok(())
+    }
+}
+
+#[derive(Accounts)]
+pub struct ApproveLenderDelegate<'info> {
+    #[account(init, seeds = [lender.key.as_ref(), delegate.key.as_ref(), b"lender_delegate".as_ref()], bump, payer = lender, space = LenderDelegate::space())]
+    pub lender_delegate: Account<'info, LenderDelegate>,
+    #[account(mut)]
+    pub lender: Signer<'info>,
+    /// CHECK: Any key the lender trusts to underwrite on their behalf
+    pub delegate: UncheckedAccount<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RevokeLenderDelegate<'info> {
+    #[account(mut, has_one = lender, close = lender)]
+    pub lender_delegate: Account<'info, LenderDelegate>,
+    #[account(mut)]
+    pub lender: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(expected_terms: LoanTerms)]
+pub struct UnderwriteLoanWithDelegate<'info> {
+    #[account(mut, constraint = pawn_loan.to_account_info().data_len() == PawnLoan::space() @ ErrorCode::PawnLoanNotMigrated, constraint = pawn_loan.load()?.version == PAWN_LOAN_VERSION @ ErrorCode::PawnLoanNotMigrated)]
+    pub pawn_loan: AccountLoader<'info, PawnLoan>,
+    #[account(mut, has_one = lender, has_one = delegate)]
+    pub lender_delegate: Account<'info, LenderDelegate>,
+    /// Pays the rent of the fee ledger if created
+    #[account(mut)]
+    pub delegate: Signer<'info>,
+    /// CHECK: Lender on whose behalf the delegate underwrites, checked by the lender delegate
+    pub lender: UncheckedAccount<'info>,
+    /// Sends the principal, must have approved the lender delegate as spl delegate
+    #[account(mut)]
+    pub lender_payment_account: Account<'info, TokenAccount>,
+    #[account(mut)]
+    pub borrower_payment_account: Account<'info, TokenAccount>,
+    /// CHECK: Fee schedule of the loan mint, empty if the mint has none
+    #[account(seeds = [expected_terms.mint.as_ref(), b"fee_schedule".as_ref()], bump)]
+    pub fee_schedule: UncheckedAccount<'info>,
+    #[account(seeds = [b"admin"], bump)]
+    pub admin: SystemAccount<'info>,
+    /// CHECK: Receives the origination fee, a spl token account owned by the admin pda
+    #[account(mut)]
+    pub admin_payment_account: UncheckedAccount<'info>,
+    /// CHECK: Fee ledger of the loan mint, created if empty
+    #[account(mut, seeds = [expected_terms.mint.as_ref(), b"fee_ledger".as_ref()], bump)]
+    pub fee_ledger: UncheckedAccount<'info>,
+    /// CHECK: Loan history of the borrower, created if empty
+    #[account(mut, seeds = [pawn_loan.load()?.borrower.as_ref(), b"borrower_stats".as_ref()], bump)]
+    pub borrower_stats: UncheckedAccount<'info>,
+    /// CHECK: Loan history of the lender, created if empty
+    #[account(mut, seeds = [lender.key().as_ref(), b"lender_stats".as_ref()], bump)]
+    pub lender_stats: UncheckedAccount<'info>,
+    /// CHECK: Protocol statistics, skipped until created
+    #[account(mut, seeds = [b"protocol_stats".as_ref()], bump)]
+    pub protocol_stats: UncheckedAccount<'info>,
+    /// CHECK: Statistics of the loan mint, skipped if empty. Address checked in the handler
+    #[account(mut)]
+    pub mint_stats: UncheckedAccount<'info>,
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::InterestModel;
+
+    fn lender_delegate(mint: Pubkey) -> LenderDelegate {
+        LenderDelegate {
+            lender: Pubkey::new_unique(),
+            delegate: Pubkey::new_unique(),
+            bump: 255,
+            limits: LenderDelegateLimits {
+                max_principal_per_loan: 100,
+                max_total_principal: 150,
+                allowed_mints: vec![mint],
+                expiry: 1_000,
+            },
+            total_principal_underwritten: 0,
+        }
+    }
+
+    fn terms(mint: Pubkey, principal_amount: u64) -> LoanTerms {
+        LoanTerms {
+            principal_amount,
+            mint,
+            interest_model: InterestModel::AnnualPercentageRate {
+                annual_percentage_rate_bps: 1_000,
+            },
+            duration: 24 * 60 * 60,
+            minimum_period_ratio_bps: None,
+        }
+    }
+
+    #[test]
+    fn record_underwriting_enforces_limits() {
+        let mint = Pubkey::new_unique();
+        let mut lender_delegate = lender_delegate(mint);
+
+        // Above the per loan cap
+        assert!(lender_delegate
+            .record_underwriting(&terms(mint, 101), 0)
+            .is_err());
+        // Mint not allowed
+        assert!(lender_delegate
+            .record_underwriting(&terms(Pubkey::new_unique(), 10), 0)
+            .is_err());
+        // Expired
+        assert!(lender_delegate
+            .record_underwriting(&terms(mint, 10), 1_000)
+            .is_err());
+
+        assert!(lender_delegate
+            .record_underwriting(&terms(mint, 100), 0)
+            .is_ok());
+        assert_eq!(100, lender_delegate.total_principal_underwritten);
+
+        // Above the total cap
+        assert!(lender_delegate
+            .record_underwriting(&terms(mint, 51), 0)
+            .is_err());
+        assert!(lender_delegate
+            .record_underwriting(&terms(mint, 50), 0)
+            .is_ok());
+        assert_eq!(150, lender_delegate.total_principal_underwritten);
+    }
+
+    #[test]
+    fn limits_cannot_allow_native_mint() {
+        let limits = lender_delegate(native_mint::ID).limits;
+        assert!(limits.validate().is_err());
+    }
+}
@@ -0,0 +1,129 @@
+//! Fee ledger: cumulative admin fees collected and withdrawn per loan mint, so that the protocol
+//! revenue can be reported by reading accounts instead of replaying transactions.
+
+use anchor_lang::prelude::*;
+use vipers::prelude::*;
+
+use crate::{create_pda_account, ErrorCode};
+
+#[account]
+#[derive(Default)]
+pub struct FeeLedger {
+    pub mint: Pubkey,
+    pub bump: u8,
+    /// Admin fees on repayments net of referral fees, origination fees and default fees
+    pub total_collected: u64,
+    /// Admin fees withdrawn or swept out of the admin pda
+    pub total_withdrawn: u64,
+    /// Loans repaid in the mint
+    pub loan_count: u64,
+    /// Referral fees held by the admin pda until claimed by the referrers, never withdrawn
+    pub referral_fees_owed: u64,
+}
+
+impl FeeLedger {
+    pub fn space() -> usize {
+        8 + 32 + 1 + 8 + 8 + 8 + 8
+    }
+
+    pub fn record_collected(&mut self, amount: u64) -> Result<()> {
+        self.total_collected = unwrap_int!(self.total_collected.checked_add(amount));
+        Ok(())
+    }
+
+    pub fn record_repayment(&mut self, admin_fee: u64) -> Result<()> {
+        self.record_collected(admin_fee)?;
+        self.loan_count = unwrap_int!(self.loan_count.checked_add(1));
+        Ok(())
+    }
+
+    pub fn record_withdrawn(&mut self, amount: u64) -> Result<()> {
+        self.total_withdrawn = unwrap_int!(self.total_withdrawn.checked_add(amount));
+        Ok(())
+    }
+
+    pub fn record_referral_fees(&mut self, amount: u64) -> Result<()> {
+        self.referral_fees_owed = unwrap_int!(self.referral_fees_owed.checked_add(amount));
+        Ok(())
+    }
+
+    pub fn record_referral_claim(&mut self, amount: u64) -> Result<()> {
+        self.referral_fees_owed = unwrap_int!(self.referral_fees_owed.checked_sub(amount));
+        Ok(())
+    }
+
+    /// Part of the admin balance in the mint that can be withdrawn, the referral fees owed
+    /// staying in the admin pda.
+    pub fn withdrawable_amount(&self, balance: u64) -> u64 {
+        balance.saturating_sub(self.referral_fees_owed)
+    }
+}
+
+pub fn find_fee_ledger_address(mint: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[mint.as_ref(), b"fee_ledger".as_ref()], &crate::ID)
+}
+
+/// Loads the fee ledger of the mint, creating it at the expense of the payer on the first fee
+/// collected or withdrawn in the mint. The caller persists the changes with `exit`.
+pub fn load_or_create_fee_ledger<'info>(
+    fee_ledger_info: &AccountInfo<'info>,
+    mint: &Pubkey,
+    payer: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+) -> Result<Account<'info, FeeLedger>> {
+    let (address, bump) = find_fee_ledger_address(mint);
+    assert_keys_eq!(address, *fee_ledger_info.key, InvalidFeeLedger);
+
+    if !fee_ledger_info.data_is_empty() {
+        return Ok(Account::try_from(fee_ledger_info)?);
+    }
+
+    create_pda_account(
+        fee_ledger_info,
+        FeeLedger::space(),
+        &[&[mint.as_ref(), b"fee_ledger".as_ref(), &[bump]]],
+        payer,
+        system_program,
+    )?;
+
+    let mut fee_ledger: Account<FeeLedger> = Account::try_from_unchecked(fee_ledger_info)?;
+    fee_ledger.mint = *mint;
+    fee_ledger.bump = bump;
+
+    Ok(fee_ledger)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fee_ledger_accumulates() {
+        let mut fee_ledger = FeeLedger::default();
+
+        fee_ledger.record_repayment(200).unwrap();
+        fee_ledger.record_repayment(0).unwrap();
+        fee_ledger.record_collected(50).unwrap();
+        fee_ledger.record_withdrawn(150).unwrap();
+
+        assert_eq!(250, fee_ledger.total_collected);
+        assert_eq!(150, fee_ledger.total_withdrawn);
+        assert_eq!(2, fee_ledger.loan_count);
+
+        fee_ledger.total_collected = u64::MAX;
+        assert!(fee_ledger.record_collected(1).is_err());
+    }
+
+    #[test]
+    fn referral_fees_owed_are_not_withdrawable() {
+        let mut fee_ledger = FeeLedger::default();
+
+        fee_ledger.record_referral_fees(100).unwrap();
+        assert_eq!(400, fee_ledger.withdrawable_amount(500));
+        assert_eq!(0, fee_ledger.withdrawable_amount(50));
+
+        fee_ledger.record_referral_claim(100).unwrap();
+        assert_eq!(500, fee_ledger.withdrawable_amount(500));
+        assert!(fee_ledger.record_referral_claim(1).is_err());
+    }
+}
@@ -0,0 +1,168 @@
+//! Fee split: admin fees withdrawn are distributed between several treasury recipients.
+
+use anchor_lang::prelude::*;
+use vipers::prelude::*;
+
+use crate::{fee_collector, math::mul_div_floor, ErrorCode};
+
+pub const MAX_FEE_SPLIT_RECIPIENTS: usize = 8;
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq)]
+pub struct FeeSplitRecipient {
+    pub recipient: Pubkey,
+    pub bps: u64,
+}
+
+impl FeeSplitRecipient {
+    pub fn space() -> usize {
+        32 + 8
+    }
+}
+
+#[account]
+pub struct FeeSplit {
+    pub bump: u8,
+    pub recipients: Vec<FeeSplitRecipient>,
+}
+
+impl FeeSplit {
+    pub fn space() -> usize {
+        8 + 1 + 4 + MAX_FEE_SPLIT_RECIPIENTS * FeeSplitRecipient::space()
+    }
+
+    pub fn validate_recipients(recipients: &[FeeSplitRecipient]) -> Result<()> {
+        invariant!(
+            !recipients.is_empty() && recipients.len() <= MAX_FEE_SPLIT_RECIPIENTS,
+            InvalidFeeSplit
+        );
+        let total_bps = recipients
+            .iter()
+            .try_fold(0u64, |total, recipient| total.checked_add(recipient.bps));
+        invariant!(total_bps == Some(10_000), InvalidFeeSplit);
+
+        Ok(())
+    }
+
+    /// Splits the amount between the recipients according to their bps.
+    /// The rounding dust goes to the first recipient so that the whole amount is distributed.
+    pub fn split(&self, amount: u64) -> Option<Vec<(Pubkey, u64)>> {
+        let mut split = self
+            .recipients
+            .iter()
+            .map(|recipient| {
+                let share = mul_div_floor(amount.into(), recipient.bps.into(), 10_000)?;
+                Some((recipient.recipient, share.try_into().ok()?))
+            })
+            .collect::<Option<Vec<(Pubkey, u64)>>>()?;
+
+        let distributed = split
+            .iter()
+            .try_fold(0u64, |total, (_, share)| total.checked_add(*share))?;
+        let (_, first_share) = split.first_mut()?;
+        *first_share = first_share.checked_add(amount.checked_sub(distributed)?)?;
+
+        Some(split)
+    }
+
+    /// Whether the whole amount goes to the recipient, as without fee split.
+    pub fn pays_only(&self, recipient: &Pubkey) -> bool {
+        self.recipients.iter().all(|split_recipient| {
+            split_recipient.bps == 0 || split_recipient.recipient == *recipient
+        })
+    }
+}
+
+/// Loads the fee split, the account being empty if none was configured.
+pub fn load_fee_split<'info>(
+    fee_split_info: &AccountInfo<'info>,
+) -> Result<Option<Account<'info, FeeSplit>>> {
+    if fee_split_info.data_is_empty() {
+        return Ok(None);
+    }
+
+    Ok(Some(Account::try_from(fee_split_info)?))
+}
+
+#[derive(Accounts)]
+pub struct InitFeeSplit<'info> {
+    #[account(init, seeds = [b"fee_split".as_ref()], bump, payer = fee_collector, space = FeeSplit::space())]
+    pub fee_split: Account<'info, FeeSplit>,
+    #[account(mut, address = fee_collector::ID)]
+    pub fee_collector: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateFeeSplit<'info> {
+    #[account(mut, seeds = [b"fee_split".as_ref()], bump = fee_split.bump)]
+    pub fee_split: Account<'info, FeeSplit>,
+    #[account(address = fee_collector::ID)]
+    pub fee_collector: Signer<'info>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn recipient(bps: u64) -> FeeSplitRecipient {
+        FeeSplitRecipient {
+            recipient: Pubkey::new_unique(),
+            bps,
+        }
+    }
+
+    #[test]
+    fn validate_recipients_requires_full_split() {
+        assert!(FeeSplit::validate_recipients(&[recipient(10_000)]).is_ok());
+        assert!(FeeSplit::validate_recipients(&[recipient(6_000), recipient(4_000)]).is_ok());
+        assert!(FeeSplit::validate_recipients(&[recipient(6_000), recipient(3_999)]).is_err());
+        assert!(FeeSplit::validate_recipients(&[recipient(6_000), recipient(4_001)]).is_err());
+        assert!(FeeSplit::validate_recipients(&[recipient(u64::MAX), recipient(10_001)]).is_err());
+        assert!(FeeSplit::validate_recipients(&[]).is_err());
+        assert!(
+            FeeSplit::validate_recipients(&vec![recipient(0); MAX_FEE_SPLIT_RECIPIENTS + 1])
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn pays_only_ignores_recipients_without_share() {
+        let fee_collector = recipient(10_000);
+        let fee_split = FeeSplit {
+            bump: 255,
+            recipients: vec![fee_collector, recipient(0)],
+        };
+        assert!(fee_split.pays_only(&fee_collector.recipient));
+
+        let fee_split = FeeSplit {
+            bump: 255,
+            recipients: vec![recipient(9_999), recipient(1)],
+        };
+        assert!(!fee_split.pays_only(&fee_split.recipients[0].recipient));
+    }
+
+    #[test]
+    fn split_distributes_the_whole_amount() {
+        let fee_split = FeeSplit {
+            bump: 255,
+            recipients: vec![recipient(5_000), recipient(3_333), recipient(1_667)],
+        };
+
+        assert_eq!(
+            vec![501, 332, 166],
+            fee_split
+                .split(999)
+                .unwrap()
+                .iter()
+                .map(|(_, share)| *share)
+                .collect::<Vec<u64>>()
+        );
+
+        for amount in 0..10_000 {
+            let split = fee_split.split(amount).unwrap();
+            assert_eq!(amount, split.iter().map(|(_, share)| share).sum::<u64>());
+            // Dust is at most one unit per recipient
+            assert!(split[0].1 - amount / 2 < 3);
+        }
+    }
+}
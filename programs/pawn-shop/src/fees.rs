@@ -0,0 +1,401 @@
+//! Admin fee schedule: discounted admin fee tiers per loan mint, by principal size, duration and
+//! cumulative volume of the lender, origination fee taken from the principal and default fee paid
+//! by the lender seizing a pawn.
+
+use anchor_lang::prelude::*;
+use vipers::prelude::*;
+
+use crate::{
+    compute_interest_due, fee_collector, load_or_create_fee_ledger, math::mul_div_floor,
+    transfer_payment, ErrorCode, LoanTerms, ADMIN_FEE_BPS,
+};
+
+pub const MAX_FEE_TIERS: usize = 8;
+pub const MAX_ORIGINATION_FEE_BPS: u64 = 1_000; // 10%
+pub const MAX_DEFAULT_FEE_BPS: u64 = 1_000; // 10%
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq)]
+pub struct FeeTier {
+    pub min_principal_amount: u64,
+    pub min_duration: i64,
+    /// Principal underwritten by the lender in the mint before the loan
+    pub min_lender_volume: u64,
+    pub admin_fee_bps: u64,
+}
+
+impl FeeTier {
+    pub fn space() -> usize {
+        8 + 8 + 8 + 8
+    }
+
+    fn applies(&self, principal_amount: u64, duration: i64, lender_volume: u64) -> bool {
+        principal_amount >= self.min_principal_amount
+            && duration >= self.min_duration
+            && lender_volume >= self.min_lender_volume
+    }
+}
+
+#[account]
+pub struct FeeSchedule {
+    pub mint: Pubkey,
+    pub bump: u8,
+    pub tiers: Vec<FeeTier>,
+    /// Share of the principal taken from the lender when underwriting
+    pub origination_fee_bps: u64,
+    /// Share of the amount due at maturity paid by the lender when seizing the pawn
+    pub default_fee_bps: u64,
+}
+
+impl FeeSchedule {
+    pub fn space() -> usize {
+        8 + 32 + 1 + 4 + MAX_FEE_TIERS * FeeTier::space() + 8 + 8
+    }
+
+    /// Tiers are discounts on the flat admin fee
+    pub fn validate_tiers(tiers: &[FeeTier]) -> Result<()> {
+        invariant!(tiers.len() <= MAX_FEE_TIERS, InvalidFeeSchedule);
+        for tier in tiers {
+            invariant!(tier.admin_fee_bps <= ADMIN_FEE_BPS, InvalidFeeSchedule);
+        }
+
+        Ok(())
+    }
+
+    /// Lowest admin fee among the tiers the loan qualifies for, flat admin fee otherwise.
+    pub fn resolve_admin_fee_bps(
+        &self,
+        principal_amount: u64,
+        duration: i64,
+        lender_volume: u64,
+    ) -> u64 {
+        self.tiers
+            .iter()
+            .filter(|tier| tier.applies(principal_amount, duration, lender_volume))
+            .map(|tier| tier.admin_fee_bps)
+            .fold(ADMIN_FEE_BPS, u64::min)
+    }
+}
+
+/// Loads the fee schedule of the loan mint, the account being empty if the mint has none.
+/// The address is expected to be checked by the caller.
+pub fn load_fee_schedule<'info>(
+    fee_schedule_info: &AccountInfo<'info>,
+) -> Result<Option<Account<'info, FeeSchedule>>> {
+    if fee_schedule_info.data_is_empty() {
+        return Ok(None);
+    }
+
+    Ok(Some(Account::try_from(fee_schedule_info)?))
+}
+
+pub fn find_fee_schedule_address(mint: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[mint.as_ref(), b"fee_schedule".as_ref()], &crate::ID).0
+}
+
+/// Default fee on the principal and the interest accrued until the maturity of the loan.
+pub fn compute_default_fee(
+    terms: &LoanTerms,
+    start_time: i64,
+    default_fee_bps: u64,
+) -> Result<u64> {
+    let maturity_time = unwrap_int!(start_time.checked_add(terms.duration));
+    let interest_due = compute_interest_due(terms, start_time, maturity_time)?;
+    let amount_due = u128::from(terms.principal_amount) + u128::from(interest_due);
+    let default_fee = unwrap_opt!(
+        mul_div_floor(amount_due, default_fee_bps.into(), 10_000),
+        CalculationError
+    );
+
+    Ok(unwrap_int!(default_fee.try_into().ok()))
+}
+
+pub fn compute_origination_fee(principal_amount: u64, origination_fee_bps: u64) -> Option<u64> {
+    mul_div_floor(principal_amount.into(), origination_fee_bps.into(), 10_000)?
+        .try_into()
+        .ok()
+}
+
+/// Fees of a loan settled when it is underwritten.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct UnderwritingFees {
+    pub admin_fee_bps: u64,
+    /// Taken from the principal sent to the borrower
+    pub origination_fee: u64,
+}
+
+impl UnderwritingFees {
+    /// Fees under the fee schedule of the loan mint, the flat admin fee without schedule.
+    pub fn compute(
+        fee_schedule: Option<&FeeSchedule>,
+        terms: &LoanTerms,
+        lender_volume: u64,
+    ) -> Option<Self> {
+        let fee_schedule = match fee_schedule {
+            Some(fee_schedule) => fee_schedule,
+            None => {
+                return Some(Self {
+                    admin_fee_bps: ADMIN_FEE_BPS,
+                    origination_fee: 0,
+                })
+            }
+        };
+
+        Some(Self {
+            admin_fee_bps: fee_schedule.resolve_admin_fee_bps(
+                terms.principal_amount,
+                terms.duration,
+                lender_volume,
+            ),
+            origination_fee: compute_origination_fee(
+                terms.principal_amount,
+                fee_schedule.origination_fee_bps,
+            )?,
+        })
+    }
+}
+
+/// Resolves the fees of a loan being underwritten, whichever way it is funded. The lender volume
+/// account, if any, qualifies the lender for volume tiers and accrues the principal. The fee
+/// schedule address is expected to be checked by the caller.
+pub(crate) fn resolve_underwriting_fees<'info>(
+    fee_schedule_info: &AccountInfo<'info>,
+    lender_volume_info: Option<&AccountInfo<'info>>,
+    lender: &Pubkey,
+    terms: &LoanTerms,
+) -> Result<UnderwritingFees> {
+    let fee_schedule = load_fee_schedule(fee_schedule_info)?;
+    let lender_volume = match (&fee_schedule, lender_volume_info) {
+        (Some(_), Some(lender_volume_info)) => {
+            let mut lender_volume_account: Account<LenderVolume> =
+                Account::try_from(lender_volume_info)?;
+            assert_keys_eq!(lender_volume_account.lender, *lender, InvalidLenderVolume);
+            assert_keys_eq!(lender_volume_account.mint, terms.mint, InvalidLenderVolume);
+
+            let lender_volume = lender_volume_account.total_principal_underwritten;
+            lender_volume_account.total_principal_underwritten =
+                unwrap_int!(lender_volume.checked_add(terms.principal_amount));
+            lender_volume_account.exit(&crate::ID)?;
+            lender_volume
+        }
+        _ => 0,
+    };
+
+    Ok(unwrap_opt!(
+        UnderwritingFees::compute(fee_schedule.as_deref(), terms, lender_volume),
+        CalculationError
+    ))
+}
+
+/// Pays the origination fee to the admin from the account funding the loan, recording it in the
+/// fee ledger of the loan mint.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn collect_origination_fee<'info>(
+    origination_fee: u64,
+    mint: &Pubkey,
+    admin: &AccountInfo<'info>,
+    admin_payment_account: &AccountInfo<'info>,
+    payer: &AccountInfo<'info>,
+    payer_payment_account: &AccountInfo<'info>,
+    fee_ledger_info: &AccountInfo<'info>,
+    rent_payer: &AccountInfo<'info>,
+    token_program: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+    signer_seeds: &[&[&[u8]]],
+) -> Result<()> {
+    if origination_fee == 0 {
+        return Ok(());
+    }
+
+    transfer_payment(
+        origination_fee,
+        mint,
+        admin.key,
+        admin_payment_account,
+        payer,
+        payer_payment_account,
+        token_program,
+        system_program,
+        signer_seeds,
+    )?;
+
+    let mut fee_ledger =
+        load_or_create_fee_ledger(fee_ledger_info, mint, rent_payer, system_program)?;
+    fee_ledger.record_collected(origination_fee)?;
+    fee_ledger.exit(&crate::ID)
+}
+
+#[account]
+pub struct LenderVolume {
+    pub lender: Pubkey,
+    pub mint: Pubkey,
+    pub bump: u8,
+    pub total_principal_underwritten: u64,
+}
+
+impl LenderVolume {
+    pub fn space() -> usize {
+        8 + 32 + 32 + 1 + 8
+    }
+}
+
+#[derive(Accounts)]
+#[instruction(mint: Pubkey)]
+pub struct CreateFeeSchedule<'info> {
+    #[account(init, seeds = [mint.as_ref(), b"fee_schedule".as_ref()], bump, payer = fee_collector, space = FeeSchedule::space())]
+    pub fee_schedule: Account<'info, FeeSchedule>,
+    #[account(mut, address = fee_collector::ID)]
+    pub fee_collector: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateFeeSchedule<'info> {
+    #[account(mut, seeds = [fee_schedule.mint.as_ref(), b"fee_schedule".as_ref()], bump = fee_schedule.bump)]
+    pub fee_schedule: Account<'info, FeeSchedule>,
+    #[account(address = fee_collector::ID)]
+    pub fee_collector: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(mint: Pubkey)]
+pub struct CreateLenderVolume<'info> {
+    #[account(init, seeds = [lender.key().as_ref(), mint.as_ref(), b"lender_volume".as_ref()], bump, payer = lender, space = LenderVolume::space())]
+    pub lender_volume: Account<'info, LenderVolume>,
+    #[account(mut)]
+    pub lender: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::InterestModel;
+
+    fn fee_schedule(tiers: Vec<FeeTier>) -> FeeSchedule {
+        FeeSchedule {
+            mint: Pubkey::new_unique(),
+            bump: 255,
+            tiers,
+            origination_fee_bps: 0,
+            default_fee_bps: 0,
+        }
+    }
+
+    fn tier(min_principal_amount: u64, min_lender_volume: u64, admin_fee_bps: u64) -> FeeTier {
+        FeeTier {
+            min_principal_amount,
+            min_duration: 0,
+            min_lender_volume,
+            admin_fee_bps,
+        }
+    }
+
+    #[test]
+    fn resolve_admin_fee_bps_picks_the_lowest_applicable_tier() {
+        let fee_schedule = fee_schedule(vec![
+            tier(1_000, 0, 150),
+            tier(0, 1_000_000, 100),
+            tier(10_000, 1_000_000, 50),
+        ]);
+
+        assert_eq!(ADMIN_FEE_BPS, fee_schedule.resolve_admin_fee_bps(999, 0, 0));
+        assert_eq!(150, fee_schedule.resolve_admin_fee_bps(1_000, 0, 999_999));
+        assert_eq!(100, fee_schedule.resolve_admin_fee_bps(1, 0, 1_000_000));
+        assert_eq!(100, fee_schedule.resolve_admin_fee_bps(9_999, 0, 1_000_000));
+        assert_eq!(50, fee_schedule.resolve_admin_fee_bps(10_000, 0, 1_000_000));
+    }
+
+    #[test]
+    fn resolve_admin_fee_bps_checks_duration() {
+        let fee_schedule = fee_schedule(vec![FeeTier {
+            min_principal_amount: 0,
+            min_duration: 30 * 24 * 60 * 60,
+            min_lender_volume: 0,
+            admin_fee_bps: 100,
+        }]);
+
+        assert_eq!(
+            ADMIN_FEE_BPS,
+            fee_schedule.resolve_admin_fee_bps(1, 7 * 24 * 60 * 60, 0)
+        );
+        assert_eq!(
+            100,
+            fee_schedule.resolve_admin_fee_bps(1, 30 * 24 * 60 * 60, 0)
+        );
+    }
+
+    #[test]
+    fn validate_tiers_only_allows_discounts() {
+        assert!(FeeSchedule::validate_tiers(&[tier(0, 0, 0), tier(0, 0, ADMIN_FEE_BPS)]).is_ok());
+        assert!(FeeSchedule::validate_tiers(&[tier(0, 0, ADMIN_FEE_BPS + 1)]).is_err());
+        assert!(FeeSchedule::validate_tiers(&vec![tier(0, 0, 0); MAX_FEE_TIERS + 1]).is_err());
+    }
+
+    #[test]
+    fn compute_origination_fee_rounds_down() {
+        assert_eq!(Some(0), compute_origination_fee(99, 100));
+        assert_eq!(Some(1), compute_origination_fee(100, 100));
+        assert_eq!(Some(100), compute_origination_fee(10_000, 100));
+        assert_eq!(
+            Some(u64::MAX / 10),
+            compute_origination_fee(u64::MAX, MAX_ORIGINATION_FEE_BPS)
+        );
+    }
+
+    #[test]
+    fn underwriting_fees_follow_the_fee_schedule() {
+        let terms = LoanTerms {
+            principal_amount: 10_000,
+            mint: Pubkey::default(),
+            interest_model: InterestModel::AnnualPercentageRate {
+                annual_percentage_rate_bps: 3500, // 35%
+            },
+            duration: 7 * 24 * 60 * 60, // 7 days
+            minimum_period_ratio_bps: None,
+        };
+        let mut fee_schedule = fee_schedule(vec![tier(0, 1_000_000, 100)]);
+        fee_schedule.origination_fee_bps = 250;
+
+        assert_eq!(
+            Some(UnderwritingFees {
+                admin_fee_bps: ADMIN_FEE_BPS,
+                origination_fee: 0,
+            }),
+            UnderwritingFees::compute(None, &terms, 1_000_000)
+        );
+        assert_eq!(
+            Some(UnderwritingFees {
+                admin_fee_bps: ADMIN_FEE_BPS,
+                origination_fee: 250,
+            }),
+            UnderwritingFees::compute(Some(&fee_schedule), &terms, 0)
+        );
+        assert_eq!(
+            Some(UnderwritingFees {
+                admin_fee_bps: 100,
+                origination_fee: 250,
+            }),
+            UnderwritingFees::compute(Some(&fee_schedule), &terms, 1_000_000)
+        );
+    }
+
+    #[test]
+    fn compute_default_fee_includes_interest_until_maturity() {
+        let terms = LoanTerms {
+            principal_amount: 5_000_000_000,
+            mint: Pubkey::default(),
+            interest_model: InterestModel::AnnualPercentageRate {
+                annual_percentage_rate_bps: 3500, // 35%
+            },
+            duration: 7 * 24 * 60 * 60, // 7 days
+            minimum_period_ratio_bps: None,
+        };
+
+        // 5% of the principal plus 33_561_644 interest over the whole duration
+        assert_eq!(
+            251_678_082,
+            compute_default_fee(&terms, 123456789, 500).unwrap()
+        );
+        assert_eq!(0, compute_default_fee(&terms, 123456789, 0).unwrap());
+    }
+}
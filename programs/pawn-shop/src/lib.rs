@@ -1,24 +1,73 @@
 use std::{cmp, convert::TryInto};
 
-use anchor_lang::{prelude::*, solana_program::program::invoke_signed, system_program};
+use anchor_lang::{
+    prelude::*,
+    solana_program::program::{invoke_signed, set_return_data},
+    system_program,
+};
 use anchor_spl::token::{self, Mint, Token, TokenAccount};
-use mpl_token_metadata::instruction::{freeze_delegated_account, thaw_delegated_account};
+use mpl_token_metadata::{
+    instruction::{freeze_delegated_account, thaw_delegated_account},
+    state::{Creator, Metadata},
+};
 use vipers::prelude::*;
 
 mod macros;
-use macros::{freeze_pawn_token_account, thaw_pawn_token_account};
+use macros::thaw_pawn_token_account;
+
+pub mod math;
+use math::{mul_div_ceil, mul_div_floor, wad_mul_ceil, wad_pow_ceil, WAD};
+
+mod pool;
+pub use pool::*;
+
+mod delegate;
+pub use delegate::*;
+
+mod oracle;
+pub use oracle::*;
+
+mod registry;
+pub use registry::*;
+
+mod referral;
+pub use referral::*;
+
+mod fees;
+pub use fees::*;
+
+mod fee_split;
+pub use fee_split::*;
+
+mod fee_ledger;
+pub use fee_ledger::*;
+
+mod stats;
+pub use stats::*;
+
+mod reputation;
+pub use reputation::*;
+
+mod pawn_loan;
+pub use pawn_loan::*;
+
+mod migration;
+pub use migration::*;
 
 const ADMIN_FEE_BPS: u64 = 200; // 2%
 const SECONDS_PER_YEAR: u64 = 31_536_000;
 const MINIMUM_PERIOD_RATIO_BPS: u64 = 2_500; // 25%
+const MAXIMUM_MINIMUM_PERIOD_RATIO_BPS: u64 = 5_000; // 50%
+/// Time after the end of a loan during which compounding interest keeps growing
+pub const COMPOUNDING_GRACE_PERIOD: i64 = 30 * 24 * 60 * 60; // 30 days
 
-mod native_mint {
+pub mod native_mint {
     use super::*;
     declare_id!("So11111111111111111111111111111111111111112");
 }
 
 /// The authority allowed to withdraw admin fees
-mod fee_collector {
+pub mod fee_collector {
     use super::*;
     #[cfg(feature = "mainnet")]
     declare_id!("BUX7s2ef2htTGb2KKoPHWkmzxPj4nTWMWRgs5CSbQxf9"); // Raccoons multisig
@@ -40,76 +89,135 @@ pub mod pawn_shop {
     use super::*;
 
     /// Borrower opens a loan request. Pawn is frozen
-    pub fn request_loan(ctx: Context<RequestLoan>, desired_terms: Option<LoanTerms>) -> Result<()> {
-        {
-            let unix_timestamp = Clock::get()?.unix_timestamp;
-            let pawn_loan = &mut ctx.accounts.pawn_loan;
-
-            pawn_loan.base = ctx.accounts.base.key();
-            pawn_loan.bump = unwrap_bump!(ctx, "pawn_loan");
-            pawn_loan.status = LoanStatus::Open;
-            pawn_loan.borrower = ctx.accounts.borrower.key();
-            pawn_loan.pawn_token_account = ctx.accounts.pawn_token_account.key();
-            pawn_loan.pawn_mint = ctx.accounts.pawn_mint.key();
-            match &desired_terms {
-                Some(terms) => {
-                    invariant!(terms.principal_amount != 0, InvalidLoanTerms);
-                    invariant!(terms.annual_percentage_rate_bps != 0, InvalidLoanTerms);
-                    invariant!(terms.duration > 0, InvalidLoanTerms);
-                }
-                _ => (),
-            }
-            pawn_loan.desired_terms = desired_terms;
-            pawn_loan.creation_time = unix_timestamp;
-
-            // Freeze the pawn token account
-            token::approve(
-                CpiContext::new(
-                    ctx.accounts.token_program.to_account_info(),
-                    token::Approve {
-                        to: ctx.accounts.pawn_token_account.to_account_info(),
-                        delegate: ctx.accounts.pawn_loan.to_account_info(),
-                        authority: ctx.accounts.borrower.to_account_info(),
-                    },
-                ),
-                1,
-            )?;
+    /// When the collection registry is enabled, the pawn metadata and the registered collection
+    /// are expected as remaining accounts. A referrer must be registered, its registered referrer
+    /// account being the last remaining account.
+    pub fn request_loan(
+        ctx: Context<RequestLoan>,
+        desired_terms: Option<LoanTerms>,
+        referrer: Option<Pubkey>,
+    ) -> Result<()> {
+        let seeds = PawnLoanSeeds::Base {
+            base: ctx.accounts.base.key(),
+            bump: [unwrap_bump!(ctx, "pawn_loan")],
+        };
+        open_loan_request(
+            ctx.accounts.loan_request_accounts(),
+            ctx.remaining_accounts,
+            seeds,
+            desired_terms,
+            referrer,
+        )?;
 
-            freeze_pawn_token_account!(ctx);
-        }
+        Ok(())
+    }
 
-        emit!(LoanRequested {
-            pawn_loan_address: ctx.accounts.pawn_loan.key(),
-            pawn_loan: *ctx.accounts.pawn_loan,
-        });
+    /// Borrower opens a loan request at an address derived from the pawn, the borrower and a
+    /// nonce, without a base keypair. The nonce tells apart the successive loans of the borrower
+    /// on the pawn. Remaining accounts are the ones of `request_loan`.
+    pub fn request_loan_for_pawn(
+        ctx: Context<RequestLoanForPawn>,
+        nonce: u64,
+        desired_terms: Option<LoanTerms>,
+        referrer: Option<Pubkey>,
+    ) -> Result<()> {
+        let seeds = PawnLoanSeeds::PawnMint {
+            pawn_mint: ctx.accounts.pawn_mint.key(),
+            borrower: ctx.accounts.borrower.key(),
+            nonce: nonce.to_le_bytes(),
+            bump: [unwrap_bump!(ctx, "pawn_loan")],
+        };
+        open_loan_request(
+            ctx.accounts.loan_request_accounts(),
+            ctx.remaining_accounts,
+            seeds,
+            desired_terms,
+            referrer,
+        )?;
 
         Ok(())
     }
 
     /// Lender funds the loan request and the loan starts. Funds are transferred to Borrower wallet.
+    /// With a loan-to-value guard, the collection price feed and the pawn metadata are expected
+    /// as remaining accounts. They may be followed by the lender volume account, to track the
+    /// volume of the lender for the fee tiers of the mint. A referrer must be registered, its
+    /// registered referrer account being the last remaining account.
     pub fn underwrite_loan(
         ctx: Context<UnderwriteLoan>,
         expected_terms: LoanTerms,
         expected_pawn_mint: Pubkey,
+        loan_to_value_guard: Option<LoanToValueGuard>,
+        referrer: Option<Pubkey>,
     ) -> Result<()> {
-        {
+        let origination_fee = {
             let unix_timestamp = Clock::get()?.unix_timestamp;
-            let pawn_loan = &mut ctx.accounts.pawn_loan;
+            let mut pawn_loan = ctx.accounts.pawn_loan.load_mut()?;
 
-            invariant!(pawn_loan.status == LoanStatus::Open, InvalidLoanStatus);
+            invariant!(pawn_loan.status()? == LoanStatus::Open, InvalidLoanStatus);
 
-            let terms = unwrap_opt!(pawn_loan.desired_terms.clone());
-            pawn_loan.status = LoanStatus::Active;
+            let terms = unwrap_opt!(pawn_loan.desired_terms.get()?);
+            pawn_loan.set_status(LoanStatus::Active);
             pawn_loan.start_time = unix_timestamp;
             pawn_loan.lender = ctx.accounts.lender.key();
+            pawn_loan.lender_referrer = referrer.into();
 
             // Verify loan matches lender expectation
             invariant!(expected_terms == terms, UnexpectedDesiredTerms);
             assert_keys_eq!(expected_pawn_mint, pawn_loan.pawn_mint, UnexpectedPawnMint);
 
-            let principal_amount = terms.principal_amount;
+            let mut remaining_accounts =
+                validate_referrer(referrer.as_ref(), &pawn_loan.lender, ctx.remaining_accounts)?;
+            if let Some(loan_to_value_guard) = loan_to_value_guard {
+                let (price_feed_info, pawn_metadata_info) = match remaining_accounts {
+                    [price_feed_info, pawn_metadata_info, rest @ ..] => {
+                        remaining_accounts = rest;
+                        (price_feed_info, pawn_metadata_info)
+                    }
+                    _ => return Err(error!(ErrorCode::InvalidPriceFeed)),
+                };
+                let collection =
+                    load_verified_collection(pawn_metadata_info, &pawn_loan.pawn_mint)?;
+                let collection_price = load_collection_price(price_feed_info)?;
+                assert_keys_eq!(collection_price.collection, collection, InvalidPriceFeed);
+                assert_keys_eq!(collection_price.quote_mint, terms.mint, InvalidPriceFeed);
+                loan_to_value_guard.validate(
+                    &collection_price,
+                    terms.principal_amount,
+                    unix_timestamp,
+                )?;
+            }
+
+            let fees = resolve_underwriting_fees(
+                &ctx.accounts.fee_schedule,
+                remaining_accounts.first(),
+                &pawn_loan.lender,
+                &terms,
+            )?;
+            pawn_loan.admin_fee_bps = fees.admin_fee_bps;
+            let origination_fee = fees.origination_fee;
+
+            // The origination fee is taken from the principal transferred by the lender
+            let principal_amount = unwrap_int!(terms.principal_amount.checked_sub(origination_fee));
             let loan_mint = terms.mint;
-            pawn_loan.terms = Some(terms.clone());
+            pawn_loan.terms = Some(terms).into();
+            update_loan_statistics(
+                &ctx.accounts.protocol_stats,
+                &ctx.accounts.mint_stats,
+                &loan_mint,
+                |stats| stats.record_underwriting(terms.principal_amount),
+            )?;
+
+            record_underwritten_loan(
+                &ctx.accounts.borrower_stats,
+                &pawn_loan.borrower,
+                unwrap_bump!(ctx, "borrower_stats"),
+                &ctx.accounts.lender_stats,
+                &pawn_loan.lender,
+                unwrap_bump!(ctx, "lender_stats"),
+                &ctx.accounts.lender.to_account_info(),
+                &ctx.accounts.system_program.to_account_info(),
+            )?;
 
             if loan_mint == native_mint::ID {
                 assert_keys_eq!(pawn_loan.borrower, ctx.accounts.borrower_payment_account);
@@ -142,11 +250,404 @@ pub mod pawn_shop {
                     principal_amount,
                 )?;
             }
-        }
 
+            collect_origination_fee(
+                origination_fee,
+                &loan_mint,
+                &ctx.accounts.admin.to_account_info(),
+                &ctx.accounts.admin_payment_account.to_account_info(),
+                &ctx.accounts.lender.to_account_info(),
+                &ctx.accounts.lender_payment_account.to_account_info(),
+                &ctx.accounts.fee_ledger,
+                &ctx.accounts.lender.to_account_info(),
+                &ctx.accounts.token_program.to_account_info(),
+                &ctx.accounts.system_program.to_account_info(),
+                &[],
+            )?;
+
+            origination_fee
+        };
+
+        let pawn_loan = ctx.accounts.pawn_loan.load()?.view()?;
+        emit!(LoanUnderwritten {
+            schema_version: EVENT_SCHEMA_VERSION,
+            pawn_loan_address: ctx.accounts.pawn_loan.key(),
+            pawn_loan,
+            payer: ctx.accounts.lender.key(),
+            payer_payment_account: ctx.accounts.lender_payment_account.key(),
+            origination_fee,
+            timestamp: pawn_loan.start_time,
+        });
+
+        Ok(())
+    }
+
+    /// Lender authorizes a delegate to underwrite loans on their behalf within limits.
+    /// The lender must approve the lender delegate as spl delegate of their payment accounts.
+    pub fn approve_lender_delegate(
+        ctx: Context<ApproveLenderDelegate>,
+        limits: LenderDelegateLimits,
+    ) -> Result<()> {
+        limits.validate()?;
+
+        let lender_delegate = &mut ctx.accounts.lender_delegate;
+        lender_delegate.lender = ctx.accounts.lender.key();
+        lender_delegate.delegate = ctx.accounts.delegate.key();
+        lender_delegate.bump = unwrap_bump!(ctx, "lender_delegate");
+        lender_delegate.limits = limits;
+        lender_delegate.total_principal_underwritten = 0;
+
+        Ok(())
+    }
+
+    /// Lender revokes the delegate. The spl approvals should be revoked alongside.
+    pub fn revoke_lender_delegate(_ctx: Context<RevokeLenderDelegate>) -> Result<()> {
+        Ok(())
+    }
+
+    /// Delegate funds the loan request from the lender payment account, within the delegate limits.
+    /// The lender volume account may be given as remaining account, to track the volume of the
+    /// lender for the fee tiers of the mint.
+    pub fn underwrite_loan_with_delegate(
+        ctx: Context<UnderwriteLoanWithDelegate>,
+        expected_terms: LoanTerms,
+        expected_pawn_mint: Pubkey,
+    ) -> Result<()> {
+        let origination_fee = {
+            let unix_timestamp = Clock::get()?.unix_timestamp;
+            let mut pawn_loan = ctx.accounts.pawn_loan.load_mut()?;
+
+            invariant!(pawn_loan.status()? == LoanStatus::Open, InvalidLoanStatus);
+
+            let terms = unwrap_opt!(pawn_loan.desired_terms.get()?);
+            pawn_loan.set_status(LoanStatus::Active);
+            pawn_loan.start_time = unix_timestamp;
+            pawn_loan.lender = ctx.accounts.lender.key();
+
+            // Verify loan matches delegate expectation and lender limits
+            invariant!(expected_terms == terms, UnexpectedDesiredTerms);
+            assert_keys_eq!(expected_pawn_mint, pawn_loan.pawn_mint, UnexpectedPawnMint);
+            ctx.accounts
+                .lender_delegate
+                .record_underwriting(&terms, unix_timestamp)?;
+
+            let lender_payment_account = &ctx.accounts.lender_payment_account;
+            assert_keys_eq!(ctx.accounts.lender, lender_payment_account.owner);
+            assert_keys_eq!(terms.mint, lender_payment_account.mint);
+            let borrower_payment_account = &ctx.accounts.borrower_payment_account;
+            assert_keys_eq!(pawn_loan.borrower, borrower_payment_account.owner);
+            assert_keys_eq!(terms.mint, borrower_payment_account.mint);
+
+            let fees = resolve_underwriting_fees(
+                &ctx.accounts.fee_schedule,
+                ctx.remaining_accounts.first(),
+                &pawn_loan.lender,
+                &terms,
+            )?;
+            pawn_loan.admin_fee_bps = fees.admin_fee_bps;
+            let origination_fee = fees.origination_fee;
+
+            pawn_loan.terms = Some(terms).into();
+            update_loan_statistics(
+                &ctx.accounts.protocol_stats,
+                &ctx.accounts.mint_stats,
+                &terms.mint,
+                |stats| stats.record_underwriting(terms.principal_amount),
+            )?;
+            record_underwritten_loan(
+                &ctx.accounts.borrower_stats,
+                &pawn_loan.borrower,
+                unwrap_bump!(ctx, "borrower_stats"),
+                &ctx.accounts.lender_stats,
+                &pawn_loan.lender,
+                unwrap_bump!(ctx, "lender_stats"),
+                &ctx.accounts.delegate.to_account_info(),
+                &ctx.accounts.system_program.to_account_info(),
+            )?;
+
+            // The origination fee is taken from the principal transferred from the lender
+            let lender_delegate = &ctx.accounts.lender_delegate;
+            let signer_seeds: &[&[&[u8]]] = &[&[
+                lender_delegate.lender.as_ref(),
+                lender_delegate.delegate.as_ref(),
+                b"lender_delegate".as_ref(),
+                &[lender_delegate.bump],
+            ]];
+            token::transfer(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    token::Transfer {
+                        from: ctx.accounts.lender_payment_account.to_account_info(),
+                        to: ctx.accounts.borrower_payment_account.to_account_info(),
+                        authority: ctx.accounts.lender_delegate.to_account_info(),
+                    },
+                    signer_seeds,
+                ),
+                unwrap_int!(terms.principal_amount.checked_sub(origination_fee)),
+            )?;
+            collect_origination_fee(
+                origination_fee,
+                &terms.mint,
+                &ctx.accounts.admin.to_account_info(),
+                &ctx.accounts.admin_payment_account.to_account_info(),
+                &ctx.accounts.lender_delegate.to_account_info(),
+                &ctx.accounts.lender_payment_account.to_account_info(),
+                &ctx.accounts.fee_ledger,
+                &ctx.accounts.delegate.to_account_info(),
+                &ctx.accounts.token_program.to_account_info(),
+                &ctx.accounts.system_program.to_account_info(),
+                signer_seeds,
+            )?;
+
+            origination_fee
+        };
+
+        let pawn_loan = ctx.accounts.pawn_loan.load()?.view()?;
         emit!(LoanUnderwritten {
+            schema_version: EVENT_SCHEMA_VERSION,
             pawn_loan_address: ctx.accounts.pawn_loan.key(),
-            pawn_loan: *ctx.accounts.pawn_loan,
+            pawn_loan,
+            payer: ctx.accounts.lender.key(),
+            payer_payment_account: ctx.accounts.lender_payment_account.key(),
+            origination_fee,
+            timestamp: pawn_loan.start_time,
+        });
+
+        Ok(())
+    }
+
+    /// Creates a collection floor price feed maintained by its authority.
+    pub fn create_price_feed(
+        ctx: Context<CreatePriceFeed>,
+        collection: Pubkey,
+        quote_mint: Pubkey,
+    ) -> Result<()> {
+        let price_feed = &mut ctx.accounts.price_feed;
+        price_feed.authority = ctx.accounts.authority.key();
+        price_feed.collection = collection;
+        price_feed.quote_mint = quote_mint;
+
+        Ok(())
+    }
+
+    /// Publishes a new price to the feed.
+    pub fn update_price_feed(
+        ctx: Context<UpdatePriceFeed>,
+        price: u64,
+        confidence: u64,
+    ) -> Result<()> {
+        let price_feed = &mut ctx.accounts.price_feed;
+        price_feed.price = price;
+        price_feed.confidence = confidence;
+        price_feed.publish_time = Clock::get()?.unix_timestamp;
+
+        Ok(())
+    }
+
+    /// Creates the collection registry, restricting loan requests to registered collections if enabled.
+    pub fn init_collection_registry(
+        ctx: Context<InitCollectionRegistry>,
+        enabled: bool,
+    ) -> Result<()> {
+        let collection_registry = &mut ctx.accounts.collection_registry;
+        collection_registry.bump = unwrap_bump!(ctx, "collection_registry");
+        collection_registry.enabled = enabled;
+
+        Ok(())
+    }
+
+    /// Switches between registered collections only and permissionless loan requests.
+    pub fn set_collection_registry_enabled(
+        ctx: Context<SetCollectionRegistryEnabled>,
+        enabled: bool,
+    ) -> Result<()> {
+        ctx.accounts.collection_registry.enabled = enabled;
+
+        Ok(())
+    }
+
+    /// Registers a verified collection along with the limits of loans on its pawns.
+    pub fn register_collection(
+        ctx: Context<RegisterCollection>,
+        collection: Pubkey,
+        mint: Pubkey,
+        max_principal_amount: u64,
+        max_duration: i64,
+    ) -> Result<()> {
+        let registered_collection = &mut ctx.accounts.registered_collection;
+        registered_collection.collection = collection;
+        registered_collection.mint = mint;
+        registered_collection.max_principal_amount = max_principal_amount;
+        registered_collection.max_duration = max_duration;
+        registered_collection.creator_royalty = None;
+
+        Ok(())
+    }
+
+    /// Sets the share of interest paid to the creators on loans requested on a registered collection.
+    pub fn set_collection_royalty(
+        ctx: Context<SetCollectionRoyalty>,
+        creator_royalty: Option<CreatorRoyalty>,
+    ) -> Result<()> {
+        if let Some(creator_royalty) = &creator_royalty {
+            creator_royalty.validate()?;
+        }
+        ctx.accounts.registered_collection.creator_royalty = creator_royalty;
+
+        Ok(())
+    }
+
+    /// Removes a collection from the registry. Existing loans are unaffected.
+    pub fn unregister_collection(_ctx: Context<UnregisterCollection>) -> Result<()> {
+        Ok(())
+    }
+
+    /// Creates the admin fee schedule of a mint.
+    pub fn create_fee_schedule(
+        ctx: Context<CreateFeeSchedule>,
+        mint: Pubkey,
+        tiers: Vec<FeeTier>,
+    ) -> Result<()> {
+        FeeSchedule::validate_tiers(&tiers)?;
+        let fee_schedule = &mut ctx.accounts.fee_schedule;
+        fee_schedule.mint = mint;
+        fee_schedule.bump = unwrap_bump!(ctx, "fee_schedule");
+        fee_schedule.tiers = tiers;
+        fee_schedule.origination_fee_bps = 0;
+        fee_schedule.default_fee_bps = 0;
+
+        Ok(())
+    }
+
+    /// Replaces the tiers of a fee schedule. Active loans keep the admin fee resolved when underwritten.
+    pub fn update_fee_schedule(ctx: Context<UpdateFeeSchedule>, tiers: Vec<FeeTier>) -> Result<()> {
+        FeeSchedule::validate_tiers(&tiers)?;
+        ctx.accounts.fee_schedule.tiers = tiers;
+
+        Ok(())
+    }
+
+    /// Sets the share of the principal taken from the lender as origination fee on loans in the mint.
+    pub fn set_origination_fee(
+        ctx: Context<UpdateFeeSchedule>,
+        origination_fee_bps: u64,
+    ) -> Result<()> {
+        invariant!(
+            origination_fee_bps <= MAX_ORIGINATION_FEE_BPS,
+            InvalidFeeSchedule
+        );
+        ctx.accounts.fee_schedule.origination_fee_bps = origination_fee_bps;
+
+        Ok(())
+    }
+
+    /// Sets the share of the amount due at maturity paid by lenders seizing pawns of loans in the mint.
+    pub fn set_default_fee(ctx: Context<UpdateFeeSchedule>, default_fee_bps: u64) -> Result<()> {
+        invariant!(default_fee_bps <= MAX_DEFAULT_FEE_BPS, InvalidFeeSchedule);
+        ctx.accounts.fee_schedule.default_fee_bps = default_fee_bps;
+
+        Ok(())
+    }
+
+    /// Creates the account tracking the volume the lender underwrote in a mint, for fee tiers.
+    pub fn create_lender_volume(ctx: Context<CreateLenderVolume>, mint: Pubkey) -> Result<()> {
+        let lender_volume = &mut ctx.accounts.lender_volume;
+        lender_volume.lender = ctx.accounts.lender.key();
+        lender_volume.mint = mint;
+        lender_volume.bump = unwrap_bump!(ctx, "lender_volume");
+
+        Ok(())
+    }
+
+    /// Reallocates a pawn loan created before account versioning to the current layout.
+    /// Anyone can migrate a loan, paying the additional rent.
+    pub fn migrate_loan(ctx: Context<MigrateLoan>) -> Result<()> {
+        migrate_pawn_loan(
+            &ctx.accounts.pawn_loan.to_account_info(),
+            &ctx.accounts.payer.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+        )?;
+
+        Ok(())
+    }
+
+    /// Creates the protocol statistics, maintained by every instruction changing a loan status.
+    /// The first loan request creates them otherwise.
+    pub fn init_protocol_stats(ctx: Context<InitProtocolStats>) -> Result<()> {
+        ctx.accounts.protocol_stats.bump = unwrap_bump!(ctx, "protocol_stats");
+
+        Ok(())
+    }
+
+    /// Creates the referral config holding the share of the admin fee paid to referrers.
+    pub fn init_referral_config(
+        ctx: Context<InitReferralConfig>,
+        referral_fee_share_bps: u64,
+    ) -> Result<()> {
+        invariant!(
+            referral_fee_share_bps <= MAX_REFERRAL_FEE_SHARE_BPS,
+            InvalidReferralFeeShare
+        );
+        let referral_config = &mut ctx.accounts.referral_config;
+        referral_config.bump = unwrap_bump!(ctx, "referral_config");
+        referral_config.referral_fee_share_bps = referral_fee_share_bps;
+
+        Ok(())
+    }
+
+    /// Updates the share of the admin fee paid to referrers on future repayments.
+    pub fn set_referral_fee_share(
+        ctx: Context<SetReferralFeeShare>,
+        referral_fee_share_bps: u64,
+    ) -> Result<()> {
+        invariant!(
+            referral_fee_share_bps <= MAX_REFERRAL_FEE_SHARE_BPS,
+            InvalidReferralFeeShare
+        );
+        ctx.accounts.referral_config.referral_fee_share_bps = referral_fee_share_bps;
+
+        Ok(())
+    }
+
+    /// Allows a front-end to refer the borrower or the lender of loans.
+    pub fn register_referrer(ctx: Context<RegisterReferrer>, referrer: Pubkey) -> Result<()> {
+        ctx.accounts.registered_referrer.referrer = referrer;
+
+        Ok(())
+    }
+
+    /// Stops new loans from being referred by the front-end. Existing loans keep crediting it.
+    pub fn unregister_referrer(_ctx: Context<UnregisterReferrer>) -> Result<()> {
+        Ok(())
+    }
+
+    /// Referrer claims the referral fees credited to them in a mint, paid out of the admin pda.
+    pub fn claim_referral_fees(ctx: Context<ClaimReferralFees>) -> Result<()> {
+        let admin_bump = unwrap_bump!(ctx, "admin");
+        let mint = ctx.accounts.referral_earnings.mint;
+        let amount = ctx.accounts.referral_earnings.unclaimed;
+        ctx.accounts.referral_earnings.unclaimed = 0;
+        ctx.accounts.fee_ledger.record_referral_claim(amount)?;
+
+        transfer_payment(
+            amount,
+            &mint,
+            &ctx.accounts.referrer.key(),
+            &ctx.accounts.referrer_payment_account.to_account_info(),
+            &ctx.accounts.admin.to_account_info(),
+            &ctx.accounts.admin_payment_account.to_account_info(),
+            &ctx.accounts.token_program.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+            &[&[b"admin".as_ref(), &[admin_bump]]],
+        )?;
+
+        emit!(ReferralFeesClaimed {
+            schema_version: EVENT_SCHEMA_VERSION,
+            referrer: ctx.accounts.referrer.key(),
+            mint,
+            amount,
+            recipient_payment_account: ctx.accounts.referrer_payment_account.key(),
+            timestamp: Clock::get()?.unix_timestamp,
         });
 
         Ok(())
@@ -154,87 +655,148 @@ pub mod pawn_shop {
 
     /// Borrower pays back loan amount plus interest and gets the pawn back.
     /// Lender gets back loan amount plus interest minus admin fee.
+    /// Referral earnings of the referrers are expected first in the remaining accounts, borrower
+    /// referrer then lender referrer, followed by the creator royalty accounts. The royalty of a
+    /// recipient without associated token account, or whose wallet it would not make rent exempt,
+    /// is paid to the lender instead.
     pub fn repay_loan(ctx: Context<RepayLoan>) -> Result<()> {
-        {
+        let (interest_due, payoff_amount, admin_fee, referral_fee, creator_royalty) = {
             let unix_timestamp = Clock::get()?.unix_timestamp;
-            let pawn_loan = &mut ctx.accounts.pawn_loan;
+            let mut pawn_loan = ctx.accounts.pawn_loan.load_mut()?;
 
-            invariant!(pawn_loan.status == LoanStatus::Active, InvalidLoanStatus);
+            invariant!(pawn_loan.status()? == LoanStatus::Active, InvalidLoanStatus);
 
-            pawn_loan.status = LoanStatus::Repaid;
+            pawn_loan.set_status(LoanStatus::Repaid);
 
-            let terms = unwrap_opt!(pawn_loan.terms.clone());
-            let interest_due = compute_interest_due(&terms, pawn_loan.start_time, unix_timestamp)?;
-            let admin_fee = compute_admin_fee(interest_due, ADMIN_FEE_BPS)
-                .ok_or(ErrorCode::CalculationError)?;
-            let payoff_amount =
-                compute_payoff_amount(terms.principal_amount, interest_due, admin_fee)
-                    .ok_or(ErrorCode::CalculationError)?;
+            let terms = unwrap_opt!(pawn_loan.terms.get()?);
+            let quote = compute_payoff_quote(
+                &terms,
+                pawn_loan.start_time,
+                unix_timestamp,
+                pawn_loan.admin_fee_bps,
+                pawn_loan.creator_royalty_bps(),
+            )?;
+            let PayoffQuote {
+                interest_due,
+                admin_fee,
+                ..
+            } = quote;
             pawn_loan.end_time = unix_timestamp;
+            update_loan_statistics(
+                &ctx.accounts.protocol_stats,
+                &ctx.accounts.mint_stats,
+                &terms.mint,
+                |stats| stats.record_repayment(interest_due),
+            )?;
+            backfill_loan_history(
+                &ctx.accounts.borrower_stats,
+                &pawn_loan.borrower,
+                unwrap_bump!(ctx, "borrower_stats"),
+                &ctx.accounts.lender_stats,
+                &pawn_loan.lender,
+                unwrap_bump!(ctx, "lender_stats"),
+                &ctx.accounts.borrower.to_account_info(),
+                &ctx.accounts.system_program.to_account_info(),
+            )?;
+            record_loan_outcome(
+                &ctx.accounts.borrower_stats,
+                &ctx.accounts.lender_stats,
+                LoanHistory::record_repayment,
+            )?;
 
-            // Transfer payoff to lender and admin fee.
-            if terms.mint == native_mint::ID {
-                assert_keys_eq!(pawn_loan.lender, ctx.accounts.lender_payment_account);
-
-                system_program::transfer(
-                    CpiContext::new(
-                        ctx.accounts.system_program.to_account_info(),
-                        system_program::Transfer {
-                            from: ctx.accounts.borrower_payment_account.to_account_info(),
-                            to: ctx.accounts.lender_payment_account.to_account_info(),
-                        },
-                    ),
-                    payoff_amount,
-                )?;
-
-                assert_keys_eq!(ctx.accounts.admin, ctx.accounts.admin_payment_account);
+            if ctx.accounts.lender.owner == ctx.program_id {
+                // The loan was funded by a lending pool, the payoff flows back into its vault
+                let mut lending_pool: Account<LendingPool> =
+                    Account::try_from(&ctx.accounts.lender)?;
+                assert_keys_eq!(lending_pool.vault, ctx.accounts.lender_payment_account);
+                lending_pool.outstanding_principal = unwrap_int!(lending_pool
+                    .outstanding_principal
+                    .checked_sub(terms.principal_amount));
+                lending_pool.exit(ctx.program_id)?;
+            }
 
-                system_program::transfer(
-                    CpiContext::new(
-                        ctx.accounts.system_program.to_account_info(),
-                        system_program::Transfer {
-                            from: ctx.accounts.borrower_payment_account.to_account_info(),
-                            to: ctx.accounts.admin_payment_account.to_account_info(),
-                        },
+            // Credit referral fees, then transfer creator royalty, payoff to lender and admin fee.
+            let mut fee_ledger = load_or_create_fee_ledger(
+                &ctx.accounts.fee_ledger,
+                &terms.mint,
+                &ctx.accounts.borrower.to_account_info(),
+                &ctx.accounts.system_program.to_account_info(),
+            )?;
+            let (referral_fee, remaining_accounts) = credit_referral_fees(
+                &[
+                    pawn_loan.borrower_referrer.get(),
+                    pawn_loan.lender_referrer.get(),
+                ],
+                unwrap_opt!(
+                    compute_referral_fee(
+                        admin_fee,
+                        load_referral_fee_share_bps(&ctx.accounts.referral_config)?
                     ),
-                    admin_fee,
-                )?;
-            } else {
-                let lender_payment_token_account: Account<TokenAccount> =
-                    Account::try_from(&ctx.accounts.lender_payment_account)?;
-                assert_keys_eq!(pawn_loan.lender, lender_payment_token_account.owner);
-                assert_keys_eq!(terms.mint, lender_payment_token_account.mint);
+                    CalculationError
+                ),
+                &terms.mint,
+                ctx.remaining_accounts,
+                &mut fee_ledger,
+                &ctx.accounts.borrower.to_account_info(),
+                &ctx.accounts.system_program.to_account_info(),
+            )?;
 
-                token::transfer(
-                    CpiContext::new(
-                        ctx.accounts.token_program.to_account_info(),
-                        token::Transfer {
-                            from: ctx.accounts.borrower_payment_account.to_account_info(),
-                            to: ctx.accounts.lender_payment_account.to_account_info(),
-                            authority: ctx.accounts.borrower.to_account_info(),
-                        },
-                    ),
+            let royalty = pawn_loan.creator_royalty.get();
+            let (creators, payment_accounts) = load_royalty_creators(
+                royalty.as_ref(),
+                quote.creator_royalty,
+                &pawn_loan.pawn_mint,
+                remaining_accounts,
+            )?;
+            let (
+                PayoffQuote {
                     payoff_amount,
-                )?;
-
-                let admin_payment_token_account: Account<TokenAccount> =
-                    Account::try_from(&ctx.accounts.admin_payment_account)?;
-                assert_keys_eq!(ctx.accounts.admin, admin_payment_token_account.owner);
-                assert_keys_eq!(terms.mint, admin_payment_token_account.mint);
-
-                token::transfer(
-                    CpiContext::new(
-                        ctx.accounts.token_program.to_account_info(),
-                        token::Transfer {
-                            from: ctx.accounts.borrower_payment_account.to_account_info(),
-                            to: ctx.accounts.admin_payment_account.to_account_info(),
-                            authority: ctx.accounts.borrower.to_account_info(),
-                        },
-                    ),
-                    admin_fee,
-                )?;
-            }
+                    creator_royalty,
+                    ..
+                },
+                royalty_recipients,
+            ) = split_payoff_quote(quote, royalty.as_ref(), &creators)?;
+            let unpaid_royalty = pay_creator_royalty(
+                &royalty_recipients,
+                &terms.mint,
+                payment_accounts,
+                &ctx.accounts.borrower.to_account_info(),
+                &ctx.accounts.borrower_payment_account.to_account_info(),
+                &ctx.accounts.token_program.to_account_info(),
+                &ctx.accounts.system_program.to_account_info(),
+            )?;
+            let payoff_amount = unwrap_int!(payoff_amount.checked_add(unpaid_royalty));
+            let creator_royalty = unwrap_int!(creator_royalty.checked_sub(unpaid_royalty));
+
+            transfer_payment(
+                payoff_amount,
+                &terms.mint,
+                &pawn_loan.lender,
+                &ctx.accounts.lender_payment_account.to_account_info(),
+                &ctx.accounts.borrower.to_account_info(),
+                &ctx.accounts.borrower_payment_account.to_account_info(),
+                &ctx.accounts.token_program.to_account_info(),
+                &ctx.accounts.system_program.to_account_info(),
+                &[],
+            )?;
+            // The admin pda holds the referral fees until the referrers claim them
+            transfer_payment(
+                admin_fee,
+                &terms.mint,
+                &ctx.accounts.admin.key(),
+                &ctx.accounts.admin_payment_account.to_account_info(),
+                &ctx.accounts.borrower.to_account_info(),
+                &ctx.accounts.borrower_payment_account.to_account_info(),
+                &ctx.accounts.token_program.to_account_info(),
+                &ctx.accounts.system_program.to_account_info(),
+                &[],
+            )?;
+            // Referral fees are a share of the admin fee
+            let admin_fee = unwrap_int!(admin_fee.checked_sub(referral_fee));
+            fee_ledger.record_repayment(admin_fee)?;
+            fee_ledger.exit(ctx.program_id)?;
 
+            drop(pawn_loan);
             thaw_pawn_token_account!(ctx);
             token::revoke(CpiContext::new(
                 ctx.accounts.token_program.to_account_info(),
@@ -243,11 +805,29 @@ pub mod pawn_shop {
                     authority: ctx.accounts.borrower.to_account_info(),
                 },
             ))?;
-        }
 
+            (
+                interest_due,
+                payoff_amount,
+                admin_fee,
+                referral_fee,
+                creator_royalty,
+            )
+        };
+
+        let pawn_loan = ctx.accounts.pawn_loan.load()?.view()?;
         emit!(LoanRepaid {
+            schema_version: EVENT_SCHEMA_VERSION,
             pawn_loan_address: ctx.accounts.pawn_loan.key(),
-            pawn_loan: *ctx.accounts.pawn_loan,
+            pawn_loan,
+            payer: ctx.accounts.borrower.key(),
+            payer_payment_account: ctx.accounts.borrower_payment_account.key(),
+            interest_due,
+            payoff_amount,
+            admin_fee,
+            referral_fee,
+            creator_royalty,
+            timestamp: pawn_loan.end_time,
         });
 
         Ok(())
@@ -255,9 +835,24 @@ pub mod pawn_shop {
 
     // Closes the loan request and thaw pawn.
     pub fn cancel_loan(ctx: Context<CancelLoan>) -> Result<()> {
-        let pawn_loan = &mut ctx.accounts.pawn_loan;
+        let desired_terms = {
+            let pawn_loan = ctx.accounts.pawn_loan.load()?;
+            invariant!(pawn_loan.status()? == LoanStatus::Open, InvalidLoanStatus);
+            pawn_loan.desired_terms.get()?
+        };
 
-        invariant!(pawn_loan.status == LoanStatus::Open, InvalidLoanStatus);
+        match &desired_terms {
+            Some(terms) => update_loan_statistics(
+                &ctx.accounts.protocol_stats,
+                &ctx.accounts.mint_stats,
+                &terms.mint,
+                LoanStatistics::record_cancellation,
+            )?,
+            None => update_protocol_statistics(
+                &ctx.accounts.protocol_stats,
+                LoanStatistics::record_cancellation,
+            )?,
+        }
 
         thaw_pawn_token_account!(ctx);
         token::revoke(CpiContext::new(
@@ -268,22 +863,92 @@ pub mod pawn_shop {
             },
         ))?;
 
+        let pawn_loan = ctx.accounts.pawn_loan.load()?.view()?;
+        emit!(LoanCancelled {
+            schema_version: EVENT_SCHEMA_VERSION,
+            pawn_loan_address: ctx.accounts.pawn_loan.key(),
+            pawn_loan,
+            borrower: ctx.accounts.borrower.key(),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
         Ok(())
     }
 
     /// Lender seizes pawn from program escrow when loan is overdue.
     pub fn seize_pawn(ctx: Context<SeizePawn>) -> Result<()> {
-        {
+        let default_fee = {
             let unix_timestamp = Clock::get()?.unix_timestamp;
-            let pawn_loan = &mut ctx.accounts.pawn_loan;
+            let mut pawn_loan = ctx.accounts.pawn_loan.load_mut()?;
 
-            invariant!(pawn_loan.status == LoanStatus::Active, InvalidLoanStatus);
+            invariant!(pawn_loan.status()? == LoanStatus::Active, InvalidLoanStatus);
 
-            let terms = unwrap_opt!(pawn_loan.terms.clone());
-            let overdue_time = unwrap_int!(pawn_loan.start_time.checked_add(terms.duration));
+            let terms = unwrap_opt!(pawn_loan.terms.get()?);
+            let start_time = pawn_loan.start_time;
+            let overdue_time = unwrap_int!(start_time.checked_add(terms.duration));
             invariant!(overdue_time < unix_timestamp, CannotSeizeBeforeExpiry);
-            pawn_loan.status = LoanStatus::Defaulted;
+            pawn_loan.set_status(LoanStatus::Defaulted);
             pawn_loan.end_time = unix_timestamp;
+            let seeds = pawn_loan.seeds();
+            let borrower = pawn_loan.borrower;
+            // The pawn loan signs the cpis below as delegate of the pawn
+            drop(pawn_loan);
+            update_loan_statistics(
+                &ctx.accounts.protocol_stats,
+                &ctx.accounts.mint_stats,
+                &terms.mint,
+                |stats| stats.record_default(terms.principal_amount),
+            )?;
+            backfill_loan_history(
+                &ctx.accounts.borrower_stats,
+                &borrower,
+                unwrap_bump!(ctx, "borrower_stats"),
+                &ctx.accounts.lender_stats,
+                ctx.accounts.lender.key,
+                unwrap_bump!(ctx, "lender_stats"),
+                &ctx.accounts.lender.to_account_info(),
+                &ctx.accounts.system_program.to_account_info(),
+            )?;
+            record_loan_outcome(
+                &ctx.accounts.borrower_stats,
+                &ctx.accounts.lender_stats,
+                LoanHistory::record_default,
+            )?;
+
+            // Lender pays the default fee before getting the pawn
+            assert_keys_eq!(
+                ctx.accounts.fee_schedule,
+                find_fee_schedule_address(&terms.mint),
+                InvalidFeeSchedule
+            );
+            let default_fee = match load_fee_schedule(&ctx.accounts.fee_schedule)? {
+                Some(fee_schedule) => {
+                    compute_default_fee(&terms, start_time, fee_schedule.default_fee_bps)?
+                }
+                None => 0,
+            };
+            if default_fee != 0 {
+                transfer_payment(
+                    default_fee,
+                    &terms.mint,
+                    &ctx.accounts.admin.key(),
+                    &ctx.accounts.admin_payment_account.to_account_info(),
+                    &ctx.accounts.lender.to_account_info(),
+                    &ctx.accounts.lender_payment_account.to_account_info(),
+                    &ctx.accounts.token_program.to_account_info(),
+                    &ctx.accounts.system_program.to_account_info(),
+                    &[],
+                )?;
+
+                let mut fee_ledger = load_or_create_fee_ledger(
+                    &ctx.accounts.fee_ledger,
+                    &terms.mint,
+                    &ctx.accounts.lender.to_account_info(),
+                    &ctx.accounts.system_program.to_account_info(),
+                )?;
+                fee_ledger.record_collected(default_fee)?;
+                fee_ledger.exit(ctx.program_id)?;
+            }
 
             // Thaw token account then transfer to lender
             thaw_pawn_token_account!(ctx);
@@ -295,67 +960,738 @@ pub mod pawn_shop {
                         to: ctx.accounts.lender_pawn_token_account.to_account_info(),
                         authority: ctx.accounts.pawn_loan.to_account_info(),
                     },
-                    &[&[
-                        ctx.accounts.pawn_loan.base.as_ref(),
-                        b"pawn_loan".as_ref(),
-                        &[ctx.accounts.pawn_loan.bump],
-                    ]],
+                    &[&seeds.signer_seeds()[..]],
                 ),
                 ctx.accounts.pawn_token_account.amount,
             )?;
-        }
 
+            default_fee
+        };
+
+        let pawn_loan = ctx.accounts.pawn_loan.load()?.view()?;
         emit!(PawnSeized {
+            schema_version: EVENT_SCHEMA_VERSION,
             pawn_loan_address: ctx.accounts.pawn_loan.key(),
-            pawn_loan: *ctx.accounts.pawn_loan,
+            pawn_loan,
+            lender: ctx.accounts.lender.key(),
+            default_fee,
+            timestamp: pawn_loan.end_time,
         });
 
         Ok(())
     }
 
-    /// Withdraw admin fees into the fee collector wallet.
-    pub fn withdraw_admin_fees(ctx: Context<WithdrawAdminFees>) -> Result<()> {
-        let admin_bump = unwrap_bump!(ctx, "admin");
-        let signer_seeds: &[&[&[u8]]] = &[&[b"admin".as_ref(), &[admin_bump]]];
+    /// Quotes the repayment of an active loan at the given timestamp, or now if none is given.
+    /// The quote is set as return data so that clients can simulate this instruction. The pawn
+    /// metadata is expected in the remaining accounts when the creator royalty goes to the creators.
+    pub fn quote_payoff(ctx: Context<QuotePayoff>, timestamp: Option<i64>) -> Result<()> {
+        let pawn_loan = ctx.accounts.pawn_loan.load()?;
 
-        if ctx.accounts.admin.key == ctx.accounts.admin_payment_account.key {
-            // Only withdraw what would leave the system program account rent exempt to avoid blocking repayments
-            let admin_account_info = ctx.accounts.admin.to_account_info();
-            let minimum_balance = Rent::get()?.minimum_balance(admin_account_info.data_len());
-            let amount = admin_account_info
-                .lamports()
-                .saturating_sub(minimum_balance);
+        invariant!(pawn_loan.status()? == LoanStatus::Active, InvalidLoanStatus);
 
-            system_program::transfer(
-                CpiContext::new_with_signer(
-                    ctx.accounts.system_program.to_account_info(),
-                    system_program::Transfer {
-                        from: ctx.accounts.admin_payment_account.to_account_info(),
-                        to: ctx.accounts.fee_collector_payment_account.to_account_info(),
-                    },
-                    signer_seeds,
-                ),
-                amount,
-            )?;
+        let timestamp = match timestamp {
+            Some(timestamp) => timestamp,
+            None => Clock::get()?.unix_timestamp,
+        };
+        invariant!(timestamp >= pawn_loan.start_time, InvalidQuoteTimestamp);
+        let terms = unwrap_opt!(pawn_loan.terms.get()?);
+        let quote = compute_payoff_quote(
+            &terms,
+            pawn_loan.start_time,
+            timestamp,
+            pawn_loan.admin_fee_bps,
+            pawn_loan.creator_royalty_bps(),
+        )?;
+        let royalty = pawn_loan.creator_royalty.get();
+        let (creators, _) = load_royalty_creators(
+            royalty.as_ref(),
+            quote.creator_royalty,
+            &pawn_loan.pawn_mint,
+            ctx.remaining_accounts,
+        )?;
+        let (quote, _) = split_payoff_quote(quote, royalty.as_ref(), &creators)?;
+
+        set_return_data(
+            &quote
+                .try_to_vec()
+                .map_err(|_| anchor_lang::error::ErrorCode::AccountDidNotSerialize)?,
+        );
+
+        Ok(())
+    }
+
+    /// Withdraw admin fees into the fee collector wallet. When a fee split is configured, the fees
+    /// are instead distributed to its recipients, whose payment accounts are the remaining accounts.
+    /// Referral fees not claimed yet stay in the admin pda.
+    pub fn withdraw_admin_fees<'info>(
+        ctx: Context<'_, '_, '_, 'info, WithdrawAdminFees<'info>>,
+    ) -> Result<()> {
+        let admin_bump = unwrap_bump!(ctx, "admin");
+        let signer_seeds: &[&[&[u8]]] = &[&[b"admin".as_ref(), &[admin_bump]]];
+
+        let (mint, balance) = if ctx.accounts.admin.key == ctx.accounts.admin_payment_account.key {
+            // Only withdraw what would leave the system program account rent exempt to avoid blocking repayments
+            let admin_account_info = ctx.accounts.admin.to_account_info();
+            let minimum_balance = Rent::get()?.minimum_balance(admin_account_info.data_len());
+            let balance = admin_account_info
+                .lamports()
+                .saturating_sub(minimum_balance);
+
+            (native_mint::ID, balance)
         } else {
             let admin_fee_token_account: Account<TokenAccount> =
                 Account::try_from(&ctx.accounts.admin_payment_account)?;
             assert_keys_eq!(ctx.accounts.admin, admin_fee_token_account.owner);
 
+            (admin_fee_token_account.mint, admin_fee_token_account.amount)
+        };
+        let mut fee_ledger = load_or_create_fee_ledger(
+            &ctx.accounts.fee_ledger,
+            &mint,
+            &ctx.accounts.fee_collector.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+        )?;
+        let amount = fee_ledger.withdrawable_amount(balance);
+
+        let payments = match load_fee_split(&ctx.accounts.fee_split)? {
+            Some(fee_split) => {
+                let split = unwrap_opt!(fee_split.split(amount));
+                invariant!(
+                    ctx.remaining_accounts.len() == split.len(),
+                    InvalidFeeSplitPaymentAccount
+                );
+                // Every recipient is checked, even those whose share is zero and skipped below
+                for ((recipient, _), recipient_payment_account) in
+                    split.iter().zip(ctx.remaining_accounts.iter())
+                {
+                    invariant!(
+                        is_payment_account_of(&mint, recipient, recipient_payment_account),
+                        InvalidFeeSplitPaymentAccount
+                    );
+                }
+                split
+                    .into_iter()
+                    .zip(ctx.remaining_accounts.iter())
+                    .collect::<Vec<((Pubkey, u64), &AccountInfo<'info>)>>()
+            }
+            None => vec![(
+                (fee_collector::ID, amount),
+                ctx.accounts.fee_collector_payment_account.as_ref(),
+            )],
+        };
+
+        let unix_timestamp = Clock::get()?.unix_timestamp;
+        for ((recipient, amount), recipient_payment_account) in payments {
+            if amount == 0 {
+                continue;
+            }
+
+            transfer_payment(
+                amount,
+                &mint,
+                &recipient,
+                recipient_payment_account,
+                &ctx.accounts.admin.to_account_info(),
+                &ctx.accounts.admin_payment_account.to_account_info(),
+                &ctx.accounts.token_program.to_account_info(),
+                &ctx.accounts.system_program.to_account_info(),
+                signer_seeds,
+            )?;
+
+            emit!(FeesWithdrawn {
+                schema_version: EVENT_SCHEMA_VERSION,
+                mint,
+                amount,
+                recipient,
+                recipient_payment_account: recipient_payment_account.key(),
+                timestamp: unix_timestamp,
+            });
+        }
+
+        fee_ledger.record_withdrawn(amount)?;
+        fee_ledger.exit(ctx.program_id)?;
+
+        Ok(())
+    }
+
+    /// Configures how withdrawn admin fees are split between treasury recipients.
+    pub fn init_fee_split(
+        ctx: Context<InitFeeSplit>,
+        recipients: Vec<FeeSplitRecipient>,
+    ) -> Result<()> {
+        FeeSplit::validate_recipients(&recipients)?;
+
+        let fee_split = &mut ctx.accounts.fee_split;
+        fee_split.bump = unwrap_bump!(ctx, "fee_split");
+        fee_split.recipients = recipients;
+
+        Ok(())
+    }
+
+    /// Replaces the recipients of the fee split.
+    pub fn update_fee_split(
+        ctx: Context<UpdateFeeSplit>,
+        recipients: Vec<FeeSplitRecipient>,
+    ) -> Result<()> {
+        FeeSplit::validate_recipients(&recipients)?;
+
+        ctx.accounts.fee_split.recipients = recipients;
+
+        Ok(())
+    }
+
+    /// Sweeps the admin fees of several spl mints at once. Remaining accounts are triples, one per
+    /// mint swept:
+    /// 0. `[writable]` admin token account, owned by the admin pda
+    /// 1. `[writable]` fee collector token account of the same mint, receiving the balance
+    /// 2. `[writable]` fee ledger of the mint, created if empty
+    ///
+    /// The fee ledger keeps the referral fees not claimed yet in the admin pda. The sweep is
+    /// rejected while the fee split pays other recipients, the fees being withdrawn per mint then.
+    pub fn sweep_admin_fees<'info>(
+        ctx: Context<'_, '_, '_, 'info, SweepAdminFees<'info>>,
+    ) -> Result<()> {
+        let admin_bump = unwrap_bump!(ctx, "admin");
+        let signer_seeds: &[&[&[u8]]] = &[&[b"admin".as_ref(), &[admin_bump]]];
+
+        if let Some(fee_split) = load_fee_split(&ctx.accounts.fee_split)? {
+            invariant!(fee_split.pays_only(&fee_collector::ID), FeeSplitConfigured);
+        }
+        invariant!(
+            ctx.remaining_accounts.len() % 3 == 0,
+            InvalidFeeSweepAccounts
+        );
+        let unix_timestamp = Clock::get()?.unix_timestamp;
+        for triple in ctx.remaining_accounts.chunks_exact(3) {
+            let (admin_token_account_info, fee_collector_token_account_info, fee_ledger_info) =
+                (&triple[0], &triple[1], &triple[2]);
+            let admin_token_account: Account<TokenAccount> =
+                Account::try_from(admin_token_account_info)?;
+            let fee_collector_token_account: Account<TokenAccount> =
+                Account::try_from(fee_collector_token_account_info)?;
+            assert_keys_eq!(
+                admin_token_account.owner,
+                ctx.accounts.admin,
+                InvalidFeeSweepAccounts
+            );
+            assert_keys_eq!(
+                fee_collector_token_account.owner,
+                fee_collector::ID,
+                InvalidFeeSweepAccounts
+            );
+            assert_keys_eq!(
+                fee_collector_token_account.mint,
+                admin_token_account.mint,
+                InvalidFeeSweepAccounts
+            );
+            let mut fee_ledger = load_or_create_fee_ledger(
+                fee_ledger_info,
+                &admin_token_account.mint,
+                &ctx.accounts.fee_collector.to_account_info(),
+                &ctx.accounts.system_program.to_account_info(),
+            )?;
+            let amount = fee_ledger.withdrawable_amount(admin_token_account.amount);
+
             token::transfer(
                 CpiContext::new_with_signer(
                     ctx.accounts.token_program.to_account_info(),
                     token::Transfer {
-                        from: ctx.accounts.admin_payment_account.to_account_info(),
-                        to: ctx.accounts.fee_collector_payment_account.to_account_info(),
+                        from: admin_token_account_info.clone(),
+                        to: fee_collector_token_account_info.clone(),
                         authority: ctx.accounts.admin.to_account_info(),
                     },
                     signer_seeds,
                 ),
-                admin_fee_token_account.amount,
+                amount,
+            )?;
+            fee_ledger.record_withdrawn(amount)?;
+            fee_ledger.exit(ctx.program_id)?;
+
+            emit!(FeesWithdrawn {
+                schema_version: EVENT_SCHEMA_VERSION,
+                mint: admin_token_account.mint,
+                amount,
+                recipient: fee_collector::ID,
+                recipient_payment_account: fee_collector_token_account_info.key(),
+                timestamp: unix_timestamp,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Creates a lending pool whose liquidity is denominated in the given mint.
+    pub fn create_lending_pool(ctx: Context<CreateLendingPool>) -> Result<()> {
+        let mint = ctx.accounts.mint.key();
+        // Repayments of native SOL loans are sent to the lender wallet, pools only hold spl tokens
+        invariant!(mint != native_mint::ID, InvalidPoolMint);
+
+        let lending_pool = &mut ctx.accounts.lending_pool;
+        lending_pool.base = ctx.accounts.base.key();
+        lending_pool.bump = unwrap_bump!(ctx, "lending_pool");
+        lending_pool.manager = ctx.accounts.manager.key();
+        lending_pool.mint = mint;
+        lending_pool.vault = ctx.accounts.vault.key();
+        lending_pool.lp_mint = ctx.accounts.lp_mint.key();
+        lending_pool.outstanding_principal = 0;
+
+        Ok(())
+    }
+
+    /// Depositor adds liquidity to the pool and receives LP shares. The first deposit locks part of
+    /// its shares in the pool, to protect later depositors against share price inflation.
+    pub fn deposit_pool_liquidity(ctx: Context<DepositPoolLiquidity>, amount: u64) -> Result<()> {
+        let lending_pool = &ctx.accounts.lending_pool;
+        let total_value = lending_pool
+            .total_value(ctx.accounts.vault.amount)
+            .ok_or(ErrorCode::CalculationError)?;
+        let (shares, locked_shares) =
+            compute_deposit_shares(amount, total_value, ctx.accounts.lp_mint.supply)
+                .ok_or(ErrorCode::CalculationError)?;
+        invariant!(shares != 0, InvalidPoolAmount);
+
+        token::transfer(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                token::Transfer {
+                    from: ctx.accounts.depositor_payment_account.to_account_info(),
+                    to: ctx.accounts.vault.to_account_info(),
+                    authority: ctx.accounts.depositor.to_account_info(),
+                },
+            ),
+            amount,
+        )?;
+
+        let signer_seeds: &[&[&[u8]]] = &[&[
+            lending_pool.base.as_ref(),
+            b"lending_pool".as_ref(),
+            &[lending_pool.bump],
+        ]];
+        token::mint_to(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                token::MintTo {
+                    mint: ctx.accounts.lp_mint.to_account_info(),
+                    to: ctx.accounts.depositor_lp_token_account.to_account_info(),
+                    authority: ctx.accounts.lending_pool.to_account_info(),
+                },
+                signer_seeds,
+            ),
+            shares,
+        )?;
+
+        if locked_shares != 0 {
+            // Nothing can move the shares out of a token account owned by the pool
+            let locked_shares_account: Account<TokenAccount> =
+                Account::try_from(&ctx.accounts.locked_shares_account)?;
+            assert_keys_eq!(
+                locked_shares_account.owner,
+                ctx.accounts.lending_pool,
+                InvalidLockedSharesAccount
+            );
+            assert_keys_eq!(
+                locked_shares_account.mint,
+                ctx.accounts.lp_mint,
+                InvalidLockedSharesAccount
+            );
+
+            token::mint_to(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    token::MintTo {
+                        mint: ctx.accounts.lp_mint.to_account_info(),
+                        to: ctx.accounts.locked_shares_account.to_account_info(),
+                        authority: ctx.accounts.lending_pool.to_account_info(),
+                    },
+                    signer_seeds,
+                ),
+                locked_shares,
+            )?;
+        }
+
+        emit!(PoolLiquidityDeposited {
+            schema_version: EVENT_SCHEMA_VERSION,
+            lending_pool_address: ctx.accounts.lending_pool.key(),
+            depositor: ctx.accounts.depositor.key(),
+            amount,
+            shares,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Depositor burns LP shares against their part of the pool. Only idle liquidity can be withdrawn.
+    pub fn withdraw_pool_liquidity(ctx: Context<WithdrawPoolLiquidity>, shares: u64) -> Result<()> {
+        let lending_pool = &ctx.accounts.lending_pool;
+        let total_value = lending_pool
+            .total_value(ctx.accounts.vault.amount)
+            .ok_or(ErrorCode::CalculationError)?;
+        let amount = compute_withdrawal_amount(shares, total_value, ctx.accounts.lp_mint.supply)
+            .ok_or(ErrorCode::CalculationError)?;
+        invariant!(amount != 0, InvalidPoolAmount);
+        invariant!(
+            amount <= ctx.accounts.vault.amount,
+            InsufficientIdleLiquidity
+        );
+
+        token::burn(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                token::Burn {
+                    mint: ctx.accounts.lp_mint.to_account_info(),
+                    to: ctx.accounts.depositor_lp_token_account.to_account_info(),
+                    authority: ctx.accounts.depositor.to_account_info(),
+                },
+            ),
+            shares,
+        )?;
+
+        token::transfer(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                token::Transfer {
+                    from: ctx.accounts.vault.to_account_info(),
+                    to: ctx.accounts.depositor_payment_account.to_account_info(),
+                    authority: ctx.accounts.lending_pool.to_account_info(),
+                },
+                &[&[
+                    lending_pool.base.as_ref(),
+                    b"lending_pool".as_ref(),
+                    &[lending_pool.bump],
+                ]],
+            ),
+            amount,
+        )?;
+
+        emit!(PoolLiquidityWithdrawn {
+            schema_version: EVENT_SCHEMA_VERSION,
+            lending_pool_address: ctx.accounts.lending_pool.key(),
+            depositor: ctx.accounts.depositor.key(),
+            amount,
+            shares,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Pool manager funds the loan request from the pool vault. The pool becomes the lender.
+    pub fn underwrite_loan_from_pool(
+        ctx: Context<UnderwriteLoanFromPool>,
+        expected_terms: LoanTerms,
+        expected_pawn_mint: Pubkey,
+    ) -> Result<()> {
+        let origination_fee = {
+            let mut pawn_loan = ctx.accounts.pawn_loan.load_mut()?;
+
+            // Verify loan matches manager expectation
+            let terms = unwrap_opt!(pawn_loan.desired_terms.get()?);
+            invariant!(expected_terms == terms, UnexpectedDesiredTerms);
+            assert_keys_eq!(expected_pawn_mint, pawn_loan.pawn_mint, UnexpectedPawnMint);
+
+            let fees = resolve_underwriting_fees(
+                &ctx.accounts.fee_schedule,
+                None,
+                &ctx.accounts.lending_pool.key(),
+                &terms,
+            )?;
+            fund_loan_from_pool(
+                &mut pawn_loan,
+                fees,
+                &mut ctx.accounts.lending_pool,
+                &ctx.accounts.vault,
+                &ctx.accounts.borrower_payment_account,
+                &ctx.accounts.token_program,
+            )?;
+            let lending_pool = &ctx.accounts.lending_pool;
+            collect_origination_fee(
+                fees.origination_fee,
+                &terms.mint,
+                &ctx.accounts.admin.to_account_info(),
+                &ctx.accounts.admin_payment_account.to_account_info(),
+                &lending_pool.to_account_info(),
+                &ctx.accounts.vault.to_account_info(),
+                &ctx.accounts.fee_ledger,
+                &ctx.accounts.manager.to_account_info(),
+                &ctx.accounts.token_program.to_account_info(),
+                &ctx.accounts.system_program.to_account_info(),
+                &[&[
+                    lending_pool.base.as_ref(),
+                    b"lending_pool".as_ref(),
+                    &[lending_pool.bump],
+                ]],
+            )?;
+            update_loan_statistics(
+                &ctx.accounts.protocol_stats,
+                &ctx.accounts.mint_stats,
+                &terms.mint,
+                |stats| stats.record_underwriting(terms.principal_amount),
+            )?;
+            record_underwritten_loan(
+                &ctx.accounts.borrower_stats,
+                &pawn_loan.borrower,
+                unwrap_bump!(ctx, "borrower_stats"),
+                &ctx.accounts.lender_stats,
+                &pawn_loan.lender,
+                unwrap_bump!(ctx, "lender_stats"),
+                &ctx.accounts.manager.to_account_info(),
+                &ctx.accounts.system_program.to_account_info(),
+            )?;
+
+            fees.origination_fee
+        };
+
+        let pawn_loan = ctx.accounts.pawn_loan.load()?.view()?;
+        emit!(LoanUnderwritten {
+            schema_version: EVENT_SCHEMA_VERSION,
+            pawn_loan_address: ctx.accounts.pawn_loan.key(),
+            pawn_loan,
+            payer: ctx.accounts.lending_pool.key(),
+            payer_payment_account: ctx.accounts.vault.key(),
+            origination_fee,
+            timestamp: pawn_loan.start_time,
+        });
+
+        Ok(())
+    }
+
+    /// Sets the policy under which anyone can underwrite matching loans from the pool.
+    /// No policy disables automatic underwriting.
+    pub fn set_pool_underwriting_policy(
+        ctx: Context<SetPoolUnderwritingPolicy>,
+        underwriting_policy: Option<UnderwritingPolicy>,
+    ) -> Result<()> {
+        if let Some(policy) = &underwriting_policy {
+            invariant!(
+                policy.min_annual_percentage_rate_bps <= policy.max_annual_percentage_rate_bps,
+                InvalidUnderwritingPolicy
+            );
+            invariant!(policy.max_duration > 0, InvalidUnderwritingPolicy);
+        }
+        ctx.accounts.lending_pool.underwriting_policy = underwriting_policy;
+
+        Ok(())
+    }
+
+    /// Allows loans on pawns of a verified collection to be underwritten automatically by the pool.
+    pub fn add_pool_collection(
+        ctx: Context<AddPoolCollection>,
+        collection: Pubkey,
+        max_principal_amount: u64,
+    ) -> Result<()> {
+        let pool_collection = &mut ctx.accounts.pool_collection;
+        pool_collection.lending_pool = ctx.accounts.lending_pool.key();
+        pool_collection.collection = collection;
+        pool_collection.max_principal_amount = max_principal_amount;
+
+        Ok(())
+    }
+
+    /// Stops automatic underwriting of loans on pawns of the collection.
+    pub fn remove_pool_collection(_ctx: Context<RemovePoolCollection>) -> Result<()> {
+        Ok(())
+    }
+
+    /// Anyone can underwrite an open loan from the pool when it matches the pool underwriting policy.
+    pub fn underwrite_from_pool(ctx: Context<UnderwriteFromPool>) -> Result<()> {
+        let origination_fee = {
+            let mut pawn_loan = ctx.accounts.pawn_loan.load_mut()?;
+            let pool_collection = &ctx.accounts.pool_collection;
+            let policy = unwrap_opt!(
+                ctx.accounts.lending_pool.underwriting_policy,
+                AutomaticUnderwritingDisabled
+            );
+
+            let terms = unwrap_opt!(pawn_loan.desired_terms.get()?);
+            policy.validate_terms(&terms)?;
+            invariant!(
+                terms.principal_amount <= pool_collection.max_principal_amount,
+                UnderwritingPolicyViolation
+            );
+
+            let collection =
+                load_verified_collection(&ctx.accounts.pawn_metadata, &pawn_loan.pawn_mint)?;
+            assert_keys_eq!(
+                collection,
+                pool_collection.collection,
+                UnderwritingPolicyViolation
+            );
+
+            assert_keys_eq!(
+                ctx.accounts.fee_schedule,
+                find_fee_schedule_address(&terms.mint),
+                InvalidFeeSchedule
+            );
+            let fees = resolve_underwriting_fees(
+                &ctx.accounts.fee_schedule,
+                None,
+                &ctx.accounts.lending_pool.key(),
+                &terms,
+            )?;
+            fund_loan_from_pool(
+                &mut pawn_loan,
+                fees,
+                &mut ctx.accounts.lending_pool,
+                &ctx.accounts.vault,
+                &ctx.accounts.borrower_payment_account,
+                &ctx.accounts.token_program,
+            )?;
+            let lending_pool = &ctx.accounts.lending_pool;
+            collect_origination_fee(
+                fees.origination_fee,
+                &terms.mint,
+                &ctx.accounts.admin.to_account_info(),
+                &ctx.accounts.admin_payment_account.to_account_info(),
+                &lending_pool.to_account_info(),
+                &ctx.accounts.vault.to_account_info(),
+                &ctx.accounts.fee_ledger,
+                &ctx.accounts.payer.to_account_info(),
+                &ctx.accounts.token_program.to_account_info(),
+                &ctx.accounts.system_program.to_account_info(),
+                &[&[
+                    lending_pool.base.as_ref(),
+                    b"lending_pool".as_ref(),
+                    &[lending_pool.bump],
+                ]],
+            )?;
+            update_loan_statistics(
+                &ctx.accounts.protocol_stats,
+                &ctx.accounts.mint_stats,
+                &terms.mint,
+                |stats| stats.record_underwriting(terms.principal_amount),
+            )?;
+            record_underwritten_loan(
+                &ctx.accounts.borrower_stats,
+                &pawn_loan.borrower,
+                unwrap_bump!(ctx, "borrower_stats"),
+                &ctx.accounts.lender_stats,
+                &pawn_loan.lender,
+                unwrap_bump!(ctx, "lender_stats"),
+                &ctx.accounts.payer.to_account_info(),
+                &ctx.accounts.system_program.to_account_info(),
+            )?;
+
+            fees.origination_fee
+        };
+
+        let pawn_loan = ctx.accounts.pawn_loan.load()?.view()?;
+        emit!(LoanUnderwritten {
+            schema_version: EVENT_SCHEMA_VERSION,
+            pawn_loan_address: ctx.accounts.pawn_loan.key(),
+            pawn_loan,
+            payer: ctx.accounts.lending_pool.key(),
+            payer_payment_account: ctx.accounts.vault.key(),
+            origination_fee,
+            timestamp: pawn_loan.start_time,
+        });
+
+        Ok(())
+    }
+
+    /// Seizes the pawn of an overdue pool loan into a token account owned by the pool.
+    pub fn seize_pawn_for_pool(ctx: Context<SeizePawnForPool>) -> Result<()> {
+        {
+            let unix_timestamp = Clock::get()?.unix_timestamp;
+            let mut pawn_loan = ctx.accounts.pawn_loan.load_mut()?;
+
+            invariant!(pawn_loan.status()? == LoanStatus::Active, InvalidLoanStatus);
+
+            let terms = unwrap_opt!(pawn_loan.terms.get()?);
+            let overdue_time = unwrap_int!(pawn_loan.start_time.checked_add(terms.duration));
+            invariant!(overdue_time < unix_timestamp, CannotSeizeBeforeExpiry);
+            pawn_loan.set_status(LoanStatus::Defaulted);
+            pawn_loan.end_time = unix_timestamp;
+            let seeds = pawn_loan.seeds();
+            let borrower = pawn_loan.borrower;
+            // The pawn loan signs the cpis below as delegate of the pawn
+            drop(pawn_loan);
+            update_loan_statistics(
+                &ctx.accounts.protocol_stats,
+                &ctx.accounts.mint_stats,
+                &terms.mint,
+                |stats| stats.record_default(terms.principal_amount),
+            )?;
+            backfill_loan_history(
+                &ctx.accounts.borrower_stats,
+                &borrower,
+                unwrap_bump!(ctx, "borrower_stats"),
+                &ctx.accounts.lender_stats,
+                &ctx.accounts.lending_pool.key(),
+                unwrap_bump!(ctx, "lender_stats"),
+                &ctx.accounts.payer.to_account_info(),
+                &ctx.accounts.system_program.to_account_info(),
+            )?;
+            record_loan_outcome(
+                &ctx.accounts.borrower_stats,
+                &ctx.accounts.lender_stats,
+                LoanHistory::record_default,
+            )?;
+
+            // The principal is lost for the depositors, the pool owns the pawn instead
+            let lending_pool = &mut ctx.accounts.lending_pool;
+            lending_pool.outstanding_principal = unwrap_int!(lending_pool
+                .outstanding_principal
+                .checked_sub(terms.principal_amount));
+
+            // Thaw token account then transfer to the pool
+            thaw_pawn_token_account!(ctx);
+            token::transfer(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    token::Transfer {
+                        from: ctx.accounts.pawn_token_account.to_account_info(),
+                        to: ctx.accounts.pool_pawn_token_account.to_account_info(),
+                        authority: ctx.accounts.pawn_loan.to_account_info(),
+                    },
+                    &[&seeds.signer_seeds()[..]],
+                ),
+                ctx.accounts.pawn_token_account.amount,
             )?;
         }
 
+        let pawn_loan = ctx.accounts.pawn_loan.load()?.view()?;
+        emit!(PawnSeized {
+            schema_version: EVENT_SCHEMA_VERSION,
+            pawn_loan_address: ctx.accounts.pawn_loan.key(),
+            pawn_loan,
+            lender: ctx.accounts.lending_pool.key(),
+            default_fee: 0,
+            timestamp: pawn_loan.end_time,
+        });
+
+        Ok(())
+    }
+
+    /// Pool manager withdraws a pawn seized by the pool to its associated token account, to sell it
+    /// and deposit the proceeds back into the vault for the depositors.
+    pub fn withdraw_pool_pawn(ctx: Context<WithdrawPoolPawn>) -> Result<()> {
+        let lending_pool = &ctx.accounts.lending_pool;
+        token::transfer(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                token::Transfer {
+                    from: ctx.accounts.pool_pawn_token_account.to_account_info(),
+                    to: ctx.accounts.manager_pawn_token_account.to_account_info(),
+                    authority: ctx.accounts.lending_pool.to_account_info(),
+                },
+                &[&[
+                    lending_pool.base.as_ref(),
+                    b"lending_pool".as_ref(),
+                    &[lending_pool.bump],
+                ]],
+            ),
+            ctx.accounts.pool_pawn_token_account.amount,
+        )?;
+
+        emit!(PoolPawnWithdrawn {
+            schema_version: EVENT_SCHEMA_VERSION,
+            lending_pool_address: ctx.accounts.lending_pool.key(),
+            pawn_mint: ctx.accounts.pool_pawn_token_account.mint,
+            manager_pawn_token_account: ctx.accounts.manager_pawn_token_account.key(),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
         Ok(())
     }
 }
@@ -365,7 +1701,33 @@ pub struct RequestLoan<'info> {
     #[account(mut)]
     pub base: Signer<'info>,
     #[account(init, seeds = [base.key.as_ref(), b"pawn_loan".as_ref()], bump, payer = borrower, space = PawnLoan::space())]
-    pub pawn_loan: Account<'info, PawnLoan>,
+    pub pawn_loan: AccountLoader<'info, PawnLoan>,
+    #[account(mut)]
+    pub borrower: Signer<'info>,
+    #[account(mut, token::mint = pawn_mint)]
+    pub pawn_token_account: Account<'info, TokenAccount>,
+    pub pawn_mint: Account<'info, Mint>,
+    /// CHECK: Validated by the cpi to mpl token metadata
+    pub edition: UncheckedAccount<'info>,
+    /// CHECK: Collection registry, loan requests are permissionless until it is created
+    #[account(seeds = [b"collection_registry".as_ref()], bump)]
+    pub collection_registry: UncheckedAccount<'info>,
+    /// CHECK: Protocol statistics, created if empty
+    #[account(mut, seeds = [b"protocol_stats".as_ref()], bump)]
+    pub protocol_stats: UncheckedAccount<'info>,
+    /// CHECK: Statistics of the loan mint, created if empty. Ignored without desired terms
+    #[account(mut)]
+    pub mint_stats: UncheckedAccount<'info>,
+    pub token_program: Program<'info, Token>,
+    pub mpl_token_metadata_program: Program<'info, MplTokenMetadata>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(nonce: u64)]
+pub struct RequestLoanForPawn<'info> {
+    #[account(init, seeds = [pawn_mint.key().as_ref(), borrower.key().as_ref(), nonce.to_le_bytes().as_ref(), b"pawn_loan".as_ref()], bump, payer = borrower, space = PawnLoan::space())]
+    pub pawn_loan: AccountLoader<'info, PawnLoan>,
     #[account(mut)]
     pub borrower: Signer<'info>,
     #[account(mut, token::mint = pawn_mint)]
@@ -373,15 +1735,222 @@ pub struct RequestLoan<'info> {
     pub pawn_mint: Account<'info, Mint>,
     /// CHECK: Validated by the cpi to mpl token metadata
     pub edition: UncheckedAccount<'info>,
+    /// CHECK: Collection registry, loan requests are permissionless until it is created
+    #[account(seeds = [b"collection_registry".as_ref()], bump)]
+    pub collection_registry: UncheckedAccount<'info>,
+    /// CHECK: Protocol statistics, created if empty
+    #[account(mut, seeds = [b"protocol_stats".as_ref()], bump)]
+    pub protocol_stats: UncheckedAccount<'info>,
+    /// CHECK: Statistics of the loan mint, created if empty. Ignored without desired terms
+    #[account(mut)]
+    pub mint_stats: UncheckedAccount<'info>,
     pub token_program: Program<'info, Token>,
     pub mpl_token_metadata_program: Program<'info, MplTokenMetadata>,
     pub system_program: Program<'info, System>,
 }
 
+/// Accounts opening a loan request, whatever the address scheme of the pawn loan.
+struct LoanRequestAccounts<'a, 'info> {
+    pawn_loan: &'a AccountLoader<'info, PawnLoan>,
+    borrower: &'a Signer<'info>,
+    pawn_token_account: &'a Account<'info, TokenAccount>,
+    pawn_mint: &'a Account<'info, Mint>,
+    edition: &'a UncheckedAccount<'info>,
+    collection_registry: &'a UncheckedAccount<'info>,
+    protocol_stats: &'a UncheckedAccount<'info>,
+    mint_stats: &'a UncheckedAccount<'info>,
+    token_program: &'a Program<'info, Token>,
+    mpl_token_metadata_program: &'a Program<'info, MplTokenMetadata>,
+    system_program: &'a Program<'info, System>,
+}
+
+impl<'info> RequestLoan<'info> {
+    fn loan_request_accounts(&self) -> LoanRequestAccounts<'_, 'info> {
+        LoanRequestAccounts {
+            pawn_loan: &self.pawn_loan,
+            borrower: &self.borrower,
+            pawn_token_account: &self.pawn_token_account,
+            pawn_mint: &self.pawn_mint,
+            edition: &self.edition,
+            collection_registry: &self.collection_registry,
+            protocol_stats: &self.protocol_stats,
+            mint_stats: &self.mint_stats,
+            token_program: &self.token_program,
+            mpl_token_metadata_program: &self.mpl_token_metadata_program,
+            system_program: &self.system_program,
+        }
+    }
+}
+
+impl<'info> RequestLoanForPawn<'info> {
+    fn loan_request_accounts(&self) -> LoanRequestAccounts<'_, 'info> {
+        LoanRequestAccounts {
+            pawn_loan: &self.pawn_loan,
+            borrower: &self.borrower,
+            pawn_token_account: &self.pawn_token_account,
+            pawn_mint: &self.pawn_mint,
+            edition: &self.edition,
+            collection_registry: &self.collection_registry,
+            protocol_stats: &self.protocol_stats,
+            mint_stats: &self.mint_stats,
+            token_program: &self.token_program,
+            mpl_token_metadata_program: &self.mpl_token_metadata_program,
+            system_program: &self.system_program,
+        }
+    }
+}
+
+/// Opens the loan request at the pawn loan address derived from the seeds, shared by the
+/// request instructions of each address scheme.
+fn open_loan_request<'info>(
+    accounts: LoanRequestAccounts<'_, 'info>,
+    remaining_accounts: &[AccountInfo<'info>],
+    seeds: PawnLoanSeeds,
+    desired_terms: Option<LoanTerms>,
+    referrer: Option<Pubkey>,
+) -> Result<()> {
+    {
+        let unix_timestamp = Clock::get()?.unix_timestamp;
+        let mut pawn_loan = accounts.pawn_loan.load_init()?;
+
+        pawn_loan.version = PAWN_LOAN_VERSION;
+        pawn_loan.set_seeds(&seeds);
+        pawn_loan.set_status(LoanStatus::Open);
+        pawn_loan.borrower = accounts.borrower.key();
+        pawn_loan.pawn_token_account = accounts.pawn_token_account.key();
+        pawn_loan.pawn_mint = accounts.pawn_mint.key();
+        let remaining_accounts =
+            validate_referrer(referrer.as_ref(), &pawn_loan.borrower, remaining_accounts)?;
+        pawn_loan.borrower_referrer = referrer.into();
+        pawn_loan.admin_fee_bps = ADMIN_FEE_BPS;
+        match &desired_terms {
+            Some(terms) => {
+                invariant!(terms.principal_amount != 0, InvalidLoanTerms);
+                match terms.interest_model {
+                    InterestModel::AnnualPercentageRate {
+                        annual_percentage_rate_bps,
+                    } => invariant!(annual_percentage_rate_bps != 0, InvalidLoanTerms),
+                    InterestModel::FixedInterest { interest_amount } => {
+                        invariant!(interest_amount != 0, InvalidLoanTerms)
+                    }
+                    InterestModel::CompoundingAnnualPercentageRate {
+                        annual_percentage_rate_bps,
+                        compounding_period,
+                    } => {
+                        invariant!(annual_percentage_rate_bps != 0, InvalidLoanTerms);
+                        invariant!(compounding_period > 0, InvalidLoanTerms);
+                    }
+                }
+                invariant!(terms.duration > 0, InvalidLoanTerms);
+                if let Some(minimum_period_ratio_bps) = terms.minimum_period_ratio_bps {
+                    invariant!(
+                        minimum_period_ratio_bps <= MAXIMUM_MINIMUM_PERIOD_RATIO_BPS,
+                        InvalidLoanTerms
+                    );
+                }
+                validate_compounding_bounds(terms)?;
+            }
+            _ => (),
+        }
+        if collection_registry_enabled(accounts.collection_registry)? {
+            let (pawn_metadata_info, registered_collection_info) = match remaining_accounts {
+                [pawn_metadata_info, registered_collection_info, ..] => {
+                    (pawn_metadata_info, registered_collection_info)
+                }
+                _ => return Err(error!(ErrorCode::CollectionNotRegistered)),
+            };
+            let collection = load_verified_collection(pawn_metadata_info, &pawn_loan.pawn_mint)?;
+            let registered_collection: Account<RegisteredCollection> =
+                Account::try_from(registered_collection_info)?;
+            assert_keys_eq!(
+                registered_collection.collection,
+                collection,
+                CollectionNotRegistered
+            );
+            if let Some(terms) = &desired_terms {
+                registered_collection.validate_terms(terms)?;
+            }
+            pawn_loan.creator_royalty = registered_collection.creator_royalty.into();
+        }
+        create_protocol_stats_if_needed(
+            accounts.protocol_stats,
+            &accounts.borrower.to_account_info(),
+            &accounts.system_program.to_account_info(),
+        )?;
+        match &desired_terms {
+            Some(terms) => {
+                create_mint_stats_if_needed(
+                    accounts.mint_stats,
+                    &terms.mint,
+                    &accounts.borrower.to_account_info(),
+                    &accounts.system_program.to_account_info(),
+                )?;
+                update_loan_statistics(
+                    accounts.protocol_stats,
+                    accounts.mint_stats,
+                    &terms.mint,
+                    LoanStatistics::record_request,
+                )?;
+            }
+            None => {
+                update_protocol_statistics(accounts.protocol_stats, LoanStatistics::record_request)?
+            }
+        }
+        pawn_loan.desired_terms = desired_terms.into();
+        pawn_loan.creation_time = unix_timestamp;
+        // The pawn loan signs the cpis below as delegate of the pawn
+        drop(pawn_loan);
+
+        // Freeze the pawn token account
+        token::approve(
+            CpiContext::new(
+                accounts.token_program.to_account_info(),
+                token::Approve {
+                    to: accounts.pawn_token_account.to_account_info(),
+                    delegate: accounts.pawn_loan.to_account_info(),
+                    authority: accounts.borrower.to_account_info(),
+                },
+            ),
+            1,
+        )?;
+
+        invoke_signed(
+            &freeze_delegated_account(
+                mpl_token_metadata::ID,
+                accounts.pawn_loan.key(),
+                accounts.pawn_token_account.key(),
+                accounts.edition.key(),
+                accounts.pawn_mint.key(),
+            ),
+            &[
+                accounts.mpl_token_metadata_program.to_account_info(),
+                accounts.pawn_loan.to_account_info(),
+                accounts.pawn_token_account.to_account_info(),
+                accounts.edition.to_account_info(),
+                accounts.pawn_mint.to_account_info(),
+            ],
+            &[&seeds.signer_seeds()[..]],
+        )?;
+    }
+
+    let pawn_loan = accounts.pawn_loan.load()?.view()?;
+    emit!(LoanRequested {
+        schema_version: EVENT_SCHEMA_VERSION,
+        pawn_loan_address: accounts.pawn_loan.key(),
+        pawn_loan,
+        borrower: accounts.borrower.key(),
+        timestamp: pawn_loan.creation_time,
+    });
+
+    Ok(())
+}
+
 #[derive(Accounts)]
+#[instruction(expected_terms: LoanTerms)]
 pub struct UnderwriteLoan<'info> {
+    #[account(mut, constraint = pawn_loan.to_account_info().data_len() == PawnLoan::space() @ ErrorCode::PawnLoanNotMigrated, constraint = pawn_loan.load()?.version == PAWN_LOAN_VERSION @ ErrorCode::PawnLoanNotMigrated)]
+    pub pawn_loan: AccountLoader<'info, PawnLoan>,
     #[account(mut)]
-    pub pawn_loan: Account<'info, PawnLoan>,
     pub lender: Signer<'info>,
     /// CHECK: Sends the principal, can be the lender wallet or his spl token account
     #[account(mut)]
@@ -389,23 +1958,50 @@ pub struct UnderwriteLoan<'info> {
     /// CHECK: Receives the principal, can be the borrower wallet or his spl token account
     #[account(mut)]
     pub borrower_payment_account: UncheckedAccount<'info>,
+    /// CHECK: Fee schedule of the loan mint, empty if the mint has none
+    #[account(seeds = [expected_terms.mint.as_ref(), b"fee_schedule".as_ref()], bump)]
+    pub fee_schedule: UncheckedAccount<'info>,
+    #[account(seeds = [b"admin"], bump)]
+    pub admin: SystemAccount<'info>,
+    /// CHECK: Receives the origination fee, can be the admin pda or a spl token account owned by the admin pda
+    #[account(mut)]
+    pub admin_payment_account: UncheckedAccount<'info>,
+    /// CHECK: Fee ledger of the loan mint, created if empty
+    #[account(mut, seeds = [expected_terms.mint.as_ref(), b"fee_ledger".as_ref()], bump)]
+    pub fee_ledger: UncheckedAccount<'info>,
+    /// CHECK: Loan history of the borrower, created if empty
+    #[account(mut, seeds = [pawn_loan.load()?.borrower.as_ref(), b"borrower_stats".as_ref()], bump)]
+    pub borrower_stats: UncheckedAccount<'info>,
+    /// CHECK: Loan history of the lender, created if empty
+    #[account(mut, seeds = [lender.key().as_ref(), b"lender_stats".as_ref()], bump)]
+    pub lender_stats: UncheckedAccount<'info>,
+    /// CHECK: Protocol statistics, skipped until created
+    #[account(mut, seeds = [b"protocol_stats".as_ref()], bump)]
+    pub protocol_stats: UncheckedAccount<'info>,
+    /// CHECK: Statistics of the loan mint, skipped if empty. Address checked in the handler
+    #[account(mut)]
+    pub mint_stats: UncheckedAccount<'info>,
     pub token_program: Program<'info, Token>,
     pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
 pub struct RepayLoan<'info> {
-    #[account(mut, has_one = pawn_token_account, has_one = pawn_mint, has_one = borrower)]
-    pub pawn_loan: Account<'info, PawnLoan>,
+    #[account(mut, constraint = pawn_loan.to_account_info().data_len() == PawnLoan::space() @ ErrorCode::PawnLoanNotMigrated, constraint = pawn_loan.load()?.pawn_token_account == pawn_token_account.key() @ anchor_lang::error::ErrorCode::ConstraintHasOne, constraint = pawn_loan.load()?.pawn_mint == pawn_mint.key() @ anchor_lang::error::ErrorCode::ConstraintHasOne, constraint = pawn_loan.load()?.borrower == borrower.key() @ anchor_lang::error::ErrorCode::ConstraintHasOne, constraint = pawn_loan.load()?.lender == lender.key() @ anchor_lang::error::ErrorCode::ConstraintHasOne, constraint = pawn_loan.load()?.version == PAWN_LOAN_VERSION @ ErrorCode::PawnLoanNotMigrated)]
+    pub pawn_loan: AccountLoader<'info, PawnLoan>,
     #[account(mut)]
     pub pawn_token_account: Account<'info, TokenAccount>,
     pub pawn_mint: Account<'info, Mint>,
     /// CHECK: Validated by the cpi to mpl token metadata
     pub edition: UncheckedAccount<'info>,
+    #[account(mut)]
     pub borrower: Signer<'info>,
     /// CHECK: Sends the payoff, can be the borrower wallet or his spl token account
     #[account(mut)]
     pub borrower_payment_account: UncheckedAccount<'info>,
+    /// CHECK: Lender wallet, or the lending pool that funded the loan
+    #[account(mut)]
+    pub lender: UncheckedAccount<'info>,
     /// CHECK: Receives the payoff, can be the lender wallet or his spl token account
     #[account(mut)]
     pub lender_payment_account: UncheckedAccount<'info>,
@@ -414,6 +2010,24 @@ pub struct RepayLoan<'info> {
     /// CHECK: Receives admin fee, can be the admin pda or a spl token account owned by the admin pda
     #[account(mut)]
     pub admin_payment_account: UncheckedAccount<'info>,
+    /// CHECK: Referral config, no referral fee is paid until it is created
+    #[account(seeds = [b"referral_config".as_ref()], bump)]
+    pub referral_config: UncheckedAccount<'info>,
+    /// CHECK: Fee ledger of the loan mint, created if empty. Address checked in the handler
+    #[account(mut)]
+    pub fee_ledger: UncheckedAccount<'info>,
+    /// CHECK: Protocol statistics, skipped until created
+    #[account(mut, seeds = [b"protocol_stats".as_ref()], bump)]
+    pub protocol_stats: UncheckedAccount<'info>,
+    /// CHECK: Statistics of the loan mint, skipped if empty. Address checked in the handler
+    #[account(mut)]
+    pub mint_stats: UncheckedAccount<'info>,
+    /// CHECK: Loan history of the borrower, created if empty for loans underwritten before it
+    #[account(mut, seeds = [borrower.key().as_ref(), b"borrower_stats".as_ref()], bump)]
+    pub borrower_stats: UncheckedAccount<'info>,
+    /// CHECK: Loan history of the lender, created if empty for loans underwritten before it
+    #[account(mut, seeds = [lender.key().as_ref(), b"lender_stats".as_ref()], bump)]
+    pub lender_stats: UncheckedAccount<'info>,
     pub token_program: Program<'info, Token>,
     pub mpl_token_metadata_program: Program<'info, MplTokenMetadata>,
     pub system_program: Program<'info, System>,
@@ -421,8 +2035,8 @@ pub struct RepayLoan<'info> {
 
 #[derive(Accounts)]
 pub struct CancelLoan<'info> {
-    #[account(mut, has_one = borrower, has_one = pawn_token_account, has_one = pawn_mint, close = borrower)]
-    pub pawn_loan: Account<'info, PawnLoan>,
+    #[account(mut, constraint = pawn_loan.to_account_info().data_len() == PawnLoan::space() @ ErrorCode::PawnLoanNotMigrated, constraint = pawn_loan.load()?.borrower == borrower.key() @ anchor_lang::error::ErrorCode::ConstraintHasOne, constraint = pawn_loan.load()?.pawn_token_account == pawn_token_account.key() @ anchor_lang::error::ErrorCode::ConstraintHasOne, constraint = pawn_loan.load()?.pawn_mint == pawn_mint.key() @ anchor_lang::error::ErrorCode::ConstraintHasOne, close = borrower, constraint = pawn_loan.load()?.version == PAWN_LOAN_VERSION @ ErrorCode::PawnLoanNotMigrated)]
+    pub pawn_loan: AccountLoader<'info, PawnLoan>,
     #[account(mut)]
     pub borrower: Signer<'info>,
     #[account(mut)]
@@ -430,14 +2044,20 @@ pub struct CancelLoan<'info> {
     pub pawn_mint: Account<'info, Mint>,
     /// CHECK: Validated by the cpi to mpl token metadata
     pub edition: UncheckedAccount<'info>,
+    /// CHECK: Protocol statistics, skipped until created
+    #[account(mut, seeds = [b"protocol_stats".as_ref()], bump)]
+    pub protocol_stats: UncheckedAccount<'info>,
+    /// CHECK: Statistics of the loan mint, skipped if empty. Address checked in the handler
+    #[account(mut)]
+    pub mint_stats: UncheckedAccount<'info>,
     pub token_program: Program<'info, Token>,
     pub mpl_token_metadata_program: Program<'info, MplTokenMetadata>,
 }
 
 #[derive(Accounts)]
 pub struct SeizePawn<'info> {
-    #[account(mut, has_one = lender, has_one = pawn_token_account, has_one = pawn_mint)]
-    pub pawn_loan: Account<'info, PawnLoan>,
+    #[account(mut, constraint = pawn_loan.to_account_info().data_len() == PawnLoan::space() @ ErrorCode::PawnLoanNotMigrated, constraint = pawn_loan.load()?.lender == lender.key() @ anchor_lang::error::ErrorCode::ConstraintHasOne, constraint = pawn_loan.load()?.pawn_token_account == pawn_token_account.key() @ anchor_lang::error::ErrorCode::ConstraintHasOne, constraint = pawn_loan.load()?.pawn_mint == pawn_mint.key() @ anchor_lang::error::ErrorCode::ConstraintHasOne, constraint = pawn_loan.load()?.version == PAWN_LOAN_VERSION @ ErrorCode::PawnLoanNotMigrated)]
+    pub pawn_loan: AccountLoader<'info, PawnLoan>,
     #[account(mut)]
     pub pawn_token_account: Account<'info, TokenAccount>,
     #[account(mut)]
@@ -448,13 +2068,58 @@ pub struct SeizePawn<'info> {
     pub lender: Signer<'info>,
     #[account(mut)]
     pub lender_pawn_token_account: Account<'info, TokenAccount>,
+    /// CHECK: Sends the default fee, can be the lender wallet or his spl token account
+    #[account(mut)]
+    pub lender_payment_account: UncheckedAccount<'info>,
+    /// CHECK: Fee schedule of the loan mint, empty if the mint has none. Address checked in the handler
+    pub fee_schedule: UncheckedAccount<'info>,
+    #[account(seeds = [b"admin"], bump)]
+    pub admin: SystemAccount<'info>,
+    /// CHECK: Receives the default fee, can be the admin pda or a spl token account owned by the admin pda
+    #[account(mut)]
+    pub admin_payment_account: UncheckedAccount<'info>,
+    /// CHECK: Fee ledger of the loan mint, created if empty. Address checked in the handler
+    #[account(mut)]
+    pub fee_ledger: UncheckedAccount<'info>,
+    /// CHECK: Protocol statistics, skipped until created
+    #[account(mut, seeds = [b"protocol_stats".as_ref()], bump)]
+    pub protocol_stats: UncheckedAccount<'info>,
+    /// CHECK: Statistics of the loan mint, skipped if empty. Address checked in the handler
+    #[account(mut)]
+    pub mint_stats: UncheckedAccount<'info>,
+    /// CHECK: Loan history of the borrower, created if empty for loans underwritten before it
+    #[account(mut, seeds = [pawn_loan.load()?.borrower.as_ref(), b"borrower_stats".as_ref()], bump)]
+    pub borrower_stats: UncheckedAccount<'info>,
+    /// CHECK: Loan history of the lender, created if empty for loans underwritten before it
+    #[account(mut, seeds = [lender.key().as_ref(), b"lender_stats".as_ref()], bump)]
+    pub lender_stats: UncheckedAccount<'info>,
     pub token_program: Program<'info, Token>,
     pub mpl_token_metadata_program: Program<'info, MplTokenMetadata>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct QuotePayoff<'info> {
+    #[account(constraint = pawn_loan.to_account_info().data_len() == PawnLoan::space() @ ErrorCode::PawnLoanNotMigrated, constraint = pawn_loan.load()?.version == PAWN_LOAN_VERSION @ ErrorCode::PawnLoanNotMigrated)]
+    pub pawn_loan: AccountLoader<'info, PawnLoan>,
+}
+
+#[derive(Accounts)]
+pub struct SweepAdminFees<'info> {
+    #[account(mut, address = fee_collector::ID)]
+    pub fee_collector: Signer<'info>,
+    #[account(seeds = [b"admin"], bump)]
+    pub admin: SystemAccount<'info>,
+    /// CHECK: Fee split pda, empty if the fees all go to the fee collector
+    #[account(seeds = [b"fee_split"], bump)]
+    pub fee_split: UncheckedAccount<'info>,
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
 pub struct WithdrawAdminFees<'info> {
-    #[account(address = fee_collector::ID)]
+    #[account(mut, address = fee_collector::ID)]
     pub fee_collector: Signer<'info>,
     /// CHECK: Receives the admin fees, can be the fee collector wallet or his spl token account
     #[account(mut)]
@@ -464,6 +2129,12 @@ pub struct WithdrawAdminFees<'info> {
     /// CHECK: Sends the admin fees, can be the admin pda or a spl token account owned by the admin pda
     #[account(mut)]
     pub admin_payment_account: UncheckedAccount<'info>,
+    /// CHECK: Fee split pda, empty if the fees all go to the fee collector
+    #[account(seeds = [b"fee_split"], bump)]
+    pub fee_split: UncheckedAccount<'info>,
+    /// CHECK: Fee ledger of the withdrawn mint, created if empty. Address checked in the handler
+    #[account(mut)]
+    pub fee_ledger: UncheckedAccount<'info>,
     pub token_program: Program<'info, Token>,
     pub system_program: Program<'info, System>,
 }
@@ -476,88 +2147,283 @@ pub enum LoanStatus {
     Defaulted,
 }
 
+/// How the interest owed by the borrower is computed.
+#[derive(Clone, Copy, AnchorSerialize, AnchorDeserialize, PartialEq)]
+pub enum InterestModel {
+    /// Interest accrues pro rata of the elapsed time, with a minimum chargeable duration.
+    AnnualPercentageRate { annual_percentage_rate_bps: u64 },
+    /// A fixed amount of interest is charged regardless of when the loan is repaid.
+    FixedInterest { interest_amount: u64 },
+    /// Interest compounds every `compounding_period` seconds, with a minimum chargeable duration.
+    /// The last incomplete period accrues pro rata.
+    CompoundingAnnualPercentageRate {
+        annual_percentage_rate_bps: u64,
+        compounding_period: i64,
+    },
+}
+
 #[derive(Clone, Copy, AnchorSerialize, AnchorDeserialize, PartialEq)]
 pub struct LoanTerms {
     pub principal_amount: u64,
     pub mint: Pubkey,
-    pub annual_percentage_rate_bps: u64,
+    pub interest_model: InterestModel,
     pub duration: i64,
+    /// Share of the duration charged at minimum when repaying early, defaults to `MINIMUM_PERIOD_RATIO_BPS`
+    pub minimum_period_ratio_bps: Option<u64>,
 }
 
-impl LoanTerms {
-    fn space() -> usize {
-        8 + 32 + 8 + 8
+/// Annual rate of the terms in bps. For fixed interest, the rate equivalent to charging the
+/// interest over the whole duration, rounded down.
+pub fn compute_nominal_annual_percentage_rate_bps(terms: &LoanTerms) -> Option<u64> {
+    match terms.interest_model {
+        InterestModel::AnnualPercentageRate {
+            annual_percentage_rate_bps,
+        }
+        | InterestModel::CompoundingAnnualPercentageRate {
+            annual_percentage_rate_bps,
+            ..
+        } => Some(annual_percentage_rate_bps),
+        InterestModel::FixedInterest { interest_amount } => mul_div_floor(
+            interest_amount.into(),
+            u128::from(SECONDS_PER_YEAR * 10_000),
+            u128::from(terms.principal_amount).checked_mul((terms.duration as u64).into())?,
+        )?
+        .try_into()
+        .ok(),
     }
 }
 
-#[account]
-#[derive(Copy)]
-pub struct PawnLoan {
-    pub base: Pubkey,
-    pub bump: u8,
-    pub borrower: Pubkey,
-    pub pawn_token_account: Pubkey,
-    pub pawn_mint: Pubkey,
-    pub status: LoanStatus,
-    pub lender: Pubkey,
-    pub desired_terms: Option<LoanTerms>,
-    pub terms: Option<LoanTerms>,
-    pub creation_time: i64,
-    pub start_time: i64,
-    pub end_time: i64,
+/// Breakdown of what is owed when repaying a loan at a given time.
+#[derive(Clone, Copy, Debug, AnchorSerialize, AnchorDeserialize, PartialEq)]
+pub struct PayoffQuote {
+    pub timestamp: i64,
+    pub interest_due: u64,
+    pub admin_fee: u64,
+    /// Share of the interest paid to the creators of the pawn, see `split_payoff_quote` for pawns
+    /// without creators
+    pub creator_royalty: u64,
+    /// Amount received by the lender
+    pub payoff_amount: u64,
+    /// Amount paid by the borrower, principal plus interest
+    pub total_repayment_amount: u64,
 }
 
-impl PawnLoan {
-    fn space() -> usize {
-        8 + 32 + 1 + 32 + 32 + 32 + 1 + 32 + 2 * (1 + LoanTerms::space()) + 8 + 8 + 8
-    }
+pub fn compute_payoff_quote(
+    terms: &LoanTerms,
+    start_time: i64,
+    timestamp: i64,
+    admin_fee_bps: u64,
+    creator_royalty_bps: u64,
+) -> Result<PayoffQuote> {
+    let interest_due = compute_interest_due(terms, start_time, timestamp)?;
+    let admin_fee =
+        compute_admin_fee(interest_due, admin_fee_bps).ok_or(ErrorCode::CalculationError)?;
+    let creator_royalty = compute_creator_royalty(interest_due, creator_royalty_bps)
+        .ok_or(ErrorCode::CalculationError)?;
+    let payoff_amount = compute_payoff_amount(
+        terms.principal_amount,
+        interest_due,
+        unwrap_int!(admin_fee.checked_add(creator_royalty)),
+    )
+    .ok_or(ErrorCode::CalculationError)?;
+    let total_repayment_amount = unwrap_int!(terms.principal_amount.checked_add(interest_due));
+
+    Ok(PayoffQuote {
+        timestamp,
+        interest_due,
+        admin_fee,
+        creator_royalty,
+        payoff_amount,
+        total_repayment_amount,
+    })
+}
+
+/// Splits the creator royalty of the quote between its recipients, the royalty without any
+/// recipient going to the lender as for pawns whose metadata lists no creators. Repayments pay the
+/// split that quotes return.
+pub fn split_payoff_quote(
+    quote: PayoffQuote,
+    creator_royalty: Option<&CreatorRoyalty>,
+    creators: &[Creator],
+) -> Result<(PayoffQuote, Vec<(Pubkey, u64)>)> {
+    let recipients = match creator_royalty {
+        Some(creator_royalty) if quote.creator_royalty != 0 => unwrap_opt!(
+            creator_royalty_recipients(creator_royalty, quote.creator_royalty, creators),
+            CalculationError
+        ),
+        _ => vec![],
+    };
+    let creator_royalty = unwrap_int!(recipients
+        .iter()
+        .try_fold(0u64, |total, (_, amount)| total.checked_add(*amount)));
+    let payoff_amount = unwrap_int!(quote.payoff_amount.checked_add(unwrap_int!(quote
+        .creator_royalty
+        .checked_sub(creator_royalty))));
+
+    Ok((
+        PayoffQuote {
+            creator_royalty,
+            payoff_amount,
+            ..quote
+        },
+        recipients,
+    ))
 }
 
+/// Admin fee taken on the interest, rounded down.
 pub fn compute_admin_fee(interest_due: u64, admin_fee_bps: u64) -> Option<u64> {
-    u128::from(interest_due)
-        .checked_mul(admin_fee_bps.into())?
-        .checked_div(10_000)?
+    mul_div_floor(interest_due.into(), admin_fee_bps.into(), 10_000)?
         .try_into()
         .ok()
 }
 
-pub fn compute_payoff_amount(
-    principal_amount: u64,
-    interest_due: u64,
-    admin_fee: u64,
-) -> Option<u64> {
+/// Amount received by the lender. The admin fee is subtracted from what the borrower pays,
+/// so that the lender and the admin always receive exactly the principal plus interest.
+pub fn compute_payoff_amount(principal_amount: u64, interest_due: u64, fees: u64) -> Option<u64> {
     u128::from(principal_amount)
         .checked_add(interest_due.into())?
-        .checked_sub(admin_fee.into())?
+        .checked_sub(fees.into())?
         .try_into()
         .ok()
 }
 
-fn compute_minimum_interest_duration(duration: u64) -> Option<i64> {
+fn compute_minimum_interest_duration(duration: u64, minimum_period_ratio_bps: u64) -> Option<i64> {
     u128::from(duration)
-        .checked_mul(MINIMUM_PERIOD_RATIO_BPS.into())?
+        .checked_mul(minimum_period_ratio_bps.into())?
         .checked_div(10_000)?
         .try_into()
         .ok()
 }
 
 pub fn compute_interest_due(terms: &LoanTerms, start_time: i64, timestamp: i64) -> Result<u64> {
+    match terms.interest_model {
+        InterestModel::AnnualPercentageRate {
+            annual_percentage_rate_bps,
+        } => {
+            compute_pro_rata_interest_due(terms, annual_percentage_rate_bps, start_time, timestamp)
+        }
+        InterestModel::FixedInterest { interest_amount } => Ok(interest_amount),
+        InterestModel::CompoundingAnnualPercentageRate {
+            annual_percentage_rate_bps,
+            compounding_period,
+        } => compute_compounding_interest_due(
+            terms,
+            annual_percentage_rate_bps,
+            compounding_period,
+            start_time,
+            timestamp,
+        ),
+    }
+}
+
+fn compute_effective_elapsed_time(
+    terms: &LoanTerms,
+    start_time: i64,
+    timestamp: i64,
+) -> Result<i64> {
     let elapsed_time = unwrap_int!(timestamp.checked_sub(start_time));
-    let minimum_interest_duration = compute_minimum_interest_duration(terms.duration as u64)
-        .ok_or(ErrorCode::CalculationError)?;
+    let minimum_interest_duration = compute_minimum_interest_duration(
+        terms.duration as u64,
+        terms
+            .minimum_period_ratio_bps
+            .unwrap_or(MINIMUM_PERIOD_RATIO_BPS),
+    )
+    .ok_or(ErrorCode::CalculationError)?;
 
     // The effective elapsed time will be at least the minimum interest duration.
-    let effective_elapsed_time = cmp::max(elapsed_time, minimum_interest_duration);
-
-    u128::from(terms.principal_amount)
-        .checked_mul(terms.annual_percentage_rate_bps.into())
-        .ok_or(ErrorCode::CalculationError)?
-        .checked_mul((effective_elapsed_time as u64).into())
-        .ok_or(ErrorCode::CalculationError)?
-        .checked_div(u128::from(SECONDS_PER_YEAR * 10_000))
-        .ok_or(ErrorCode::CalculationError)?
-        .try_into()
-        .map_err(|_| error!(ErrorCode::CalculationError))
+    Ok(cmp::max(elapsed_time, minimum_interest_duration))
+}
+
+fn compute_pro_rata_interest_due(
+    terms: &LoanTerms,
+    annual_percentage_rate_bps: u64,
+    start_time: i64,
+    timestamp: i64,
+) -> Result<u64> {
+    let effective_elapsed_time = compute_effective_elapsed_time(terms, start_time, timestamp)?;
+    let yearly_interest = u128::from(terms.principal_amount)
+        .checked_mul(annual_percentage_rate_bps.into())
+        .ok_or(ErrorCode::CalculationError)?;
+
+    // Interest is rounded up in favour of the lender.
+    mul_div_ceil(
+        yearly_interest,
+        (effective_elapsed_time as u64).into(),
+        u128::from(SECONDS_PER_YEAR * 10_000),
+    )
+    .ok_or(ErrorCode::CalculationError)?
+    .try_into()
+    .map_err(|_| error!(ErrorCode::CalculationError))
+}
+
+/// Rate accrued over `period` seconds, in fixed-point rounded up.
+fn compute_period_rate(annual_percentage_rate_bps: u64, period: u64) -> Option<u128> {
+    mul_div_ceil(
+        u128::from(annual_percentage_rate_bps).checked_mul(period.into())?,
+        WAD,
+        u128::from(SECONDS_PER_YEAR * 10_000),
+    )
+}
+
+/// Compounding interest grows until the end of the grace period after the end of the loan, and
+/// stays constant afterwards. The lender can seize the pawn by then, and requests are rejected
+/// when the interest at that time overflows, so that the loan can always be repaid.
+fn compute_compounding_interest_due(
+    terms: &LoanTerms,
+    annual_percentage_rate_bps: u64,
+    compounding_period: i64,
+    start_time: i64,
+    timestamp: i64,
+) -> Result<u64> {
+    let effective_elapsed_time = cmp::min(
+        compute_effective_elapsed_time(terms, start_time, timestamp)?,
+        unwrap_int!(terms.duration.checked_add(COMPOUNDING_GRACE_PERIOD)),
+    ) as u64;
+    let compounding_period = compounding_period as u64;
+    let compounded_periods = unwrap_int!(effective_elapsed_time.checked_div(compounding_period));
+    let remaining_time = unwrap_int!(effective_elapsed_time.checked_rem(compounding_period));
+
+    let period_rate = compute_period_rate(annual_percentage_rate_bps, compounding_period)
+        .ok_or(ErrorCode::CalculationError)?;
+    let remaining_rate = compute_period_rate(annual_percentage_rate_bps, remaining_time)
+        .ok_or(ErrorCode::CalculationError)?;
+
+    // (1 + period_rate) ^ compounded_periods * (1 + remaining_rate), every step rounded up so that
+    // the interest is never below the exact compounding.
+    let growth_factor = wad_pow_ceil(
+        unwrap_int!(WAD.checked_add(period_rate)),
+        compounded_periods,
+    )
+    .and_then(|compounded| wad_mul_ceil(compounded, WAD.checked_add(remaining_rate)?))
+    .ok_or(ErrorCode::CalculationError)?;
+
+    // Interest is rounded up in favour of the lender.
+    mul_div_ceil(
+        terms.principal_amount.into(),
+        unwrap_int!(growth_factor.checked_sub(WAD)),
+        WAD,
+    )
+    .ok_or(ErrorCode::CalculationError)?
+    .try_into()
+    .map_err(|_| error!(ErrorCode::CalculationError))
+}
+
+/// Checks that the payoff of compounding terms fits once the interest stops growing.
+fn validate_compounding_bounds(terms: &LoanTerms) -> Result<()> {
+    if let InterestModel::CompoundingAnnualPercentageRate { .. } = terms.interest_model {
+        let end_of_compounding = unwrap_opt!(
+            terms.duration.checked_add(COMPOUNDING_GRACE_PERIOD),
+            InvalidLoanTerms
+        );
+        let payoff_amount = compute_interest_due(terms, 0, end_of_compounding)
+            .ok()
+            .and_then(|interest_due| {
+                compute_payoff_amount(terms.principal_amount, interest_due, 0)
+            });
+        invariant!(payoff_amount.is_some(), InvalidLoanTerms);
+    }
+
+    Ok(())
 }
 
 #[error_code]
@@ -571,30 +2437,286 @@ pub enum ErrorCode {
     UnexpectedPawnAmount,
     CannotCancelLoanWithMoreThanZeroBids,
     CannotSeizeBeforeExpiry,
+    InvalidPoolMint,
+    InvalidPoolAmount,
+    InsufficientIdleLiquidity,
+    InvalidUnderwritingPolicy,
+    AutomaticUnderwritingDisabled,
+    UnderwritingPolicyViolation,
+    InvalidPawnMetadata,
+    UnverifiedCollection,
+    InvalidDelegateLimits,
+    DelegateExpired,
+    DelegateMintNotAllowed,
+    DelegateLimitExceeded,
+    InvalidPriceFeed,
+    StalePrice,
+    PriceConfidenceTooLow,
+    LoanToValueExceeded,
+    CollectionNotRegistered,
+    CollectionLimitExceeded,
+    InvalidCreatorRoyalty,
+    InvalidRoyaltyPaymentAccount,
+    InvalidReferralFeeShare,
+    InvalidReferralEarnings,
+    InvalidFeeSchedule,
+    InvalidLenderVolume,
+    InvalidFeeSweepAccounts,
+    InvalidFeeSplit,
+    InvalidFeeSplitPaymentAccount,
+    InvalidFeeLedger,
+    InvalidMintStats,
+    InvalidPawnLoanLayout,
+    PawnLoanAlreadyMigrated,
+    PawnLoanNotMigrated,
+    InvalidReferrer,
+    ReferrerNotRegistered,
+    LoanStatsNotFound,
+    FeeSplitConfigured,
+    InvalidLockedSharesAccount,
+    InvalidPoolPawnAccount,
+    InvalidQuoteTimestamp,
 }
 
+/// Version of the layout of the events, incremented whenever fields are added so that indexers
+/// can decode the events of every program version.
+pub const EVENT_SCHEMA_VERSION: u8 = 3;
+
 #[event]
 pub struct LoanRequested {
-    pawn_loan_address: Pubkey,
-    pawn_loan: PawnLoan,
+    pub schema_version: u8,
+    pub pawn_loan_address: Pubkey,
+    pub pawn_loan: PawnLoanView,
+    /// Borrower paying the rent of the pawn loan
+    pub borrower: Pubkey,
+    pub timestamp: i64,
 }
 
 #[event]
 pub struct LoanUnderwritten {
-    pawn_loan_address: Pubkey,
-    pawn_loan: PawnLoan,
+    pub schema_version: u8,
+    pub pawn_loan_address: Pubkey,
+    pub pawn_loan: PawnLoanView,
+    /// Lender wallet, delegate lender or lending pool funding the principal
+    pub payer: Pubkey,
+    /// Account the principal was paid from
+    pub payer_payment_account: Pubkey,
+    /// Share of the principal paid to the admin
+    pub origination_fee: u64,
+    pub timestamp: i64,
 }
 
 #[event]
 pub struct LoanRepaid {
-    pawn_loan_address: Pubkey,
-    pawn_loan: PawnLoan,
+    pub schema_version: u8,
+    pub pawn_loan_address: Pubkey,
+    pub pawn_loan: PawnLoanView,
+    /// Borrower paying the loan back
+    pub payer: Pubkey,
+    /// Account the repayment was paid from
+    pub payer_payment_account: Pubkey,
+    /// Interest accrued over the loan duration, before fees
+    pub interest_due: u64,
+    /// Amount received by the lender
+    pub payoff_amount: u64,
+    /// Admin fee net of the referral fees
+    pub admin_fee: u64,
+    /// Amount credited to the referrers of the loan, held by the admin pda until claimed
+    pub referral_fee: u64,
+    /// Amount paid to the creators of the pawn
+    pub creator_royalty: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct LoanCancelled {
+    pub schema_version: u8,
+    pub pawn_loan_address: Pubkey,
+    pub pawn_loan: PawnLoanView,
+    pub borrower: Pubkey,
+    pub timestamp: i64,
 }
 
 #[event]
 pub struct PawnSeized {
-    pawn_loan_address: Pubkey,
-    pawn_loan: PawnLoan,
+    pub schema_version: u8,
+    pub pawn_loan_address: Pubkey,
+    pub pawn_loan: PawnLoanView,
+    /// Lender wallet or lending pool receiving the pawn
+    pub lender: Pubkey,
+    /// Paid by the lender to the admin
+    pub default_fee: u64,
+    pub timestamp: i64,
+}
+
+/// Emitted for every payment out of the admin pda, once per recipient of a fee split.
+#[event]
+pub struct FeesWithdrawn {
+    pub schema_version: u8,
+    pub mint: Pubkey,
+    pub amount: u64,
+    pub recipient: Pubkey,
+    pub recipient_payment_account: Pubkey,
+    pub timestamp: i64,
+}
+
+/// Emitted when a referrer claims the referral fees earned in a mint.
+#[event]
+pub struct ReferralFeesClaimed {
+    pub schema_version: u8,
+    pub referrer: Pubkey,
+    pub mint: Pubkey,
+    pub amount: u64,
+    pub recipient_payment_account: Pubkey,
+    pub timestamp: i64,
+}
+
+/// Loads the metaplex metadata of the pawn mint.
+pub fn load_pawn_metadata(metadata_info: &AccountInfo, pawn_mint: &Pubkey) -> Result<Metadata> {
+    assert_keys_eq!(
+        *metadata_info.owner,
+        mpl_token_metadata::ID,
+        InvalidPawnMetadata
+    );
+    let metadata = Metadata::from_account_info(metadata_info)?;
+    // Only the metadata program can create its accounts, and only at the pda of the mint
+    assert_keys_eq!(metadata.mint, *pawn_mint, InvalidPawnMetadata);
+
+    Ok(metadata)
+}
+
+/// Transfers from a payer to a recipient, either sol to the recipient wallet or spl tokens
+/// to a token account owned by the recipient. Signer seeds are needed when the payer is a pda.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn transfer_payment<'info>(
+    amount: u64,
+    mint: &Pubkey,
+    recipient: &Pubkey,
+    recipient_payment_account: &AccountInfo<'info>,
+    payer: &AccountInfo<'info>,
+    payer_payment_account: &AccountInfo<'info>,
+    token_program: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+    signer_seeds: &[&[&[u8]]],
+) -> Result<()> {
+    if *mint == native_mint::ID {
+        assert_keys_eq!(*recipient, *recipient_payment_account.key);
+
+        system_program::transfer(
+            CpiContext::new_with_signer(
+                system_program.clone(),
+                system_program::Transfer {
+                    from: payer_payment_account.clone(),
+                    to: recipient_payment_account.clone(),
+                },
+                signer_seeds,
+            ),
+            amount,
+        )
+    } else {
+        let recipient_payment_token_account: Account<TokenAccount> =
+            Account::try_from(recipient_payment_account)?;
+        assert_keys_eq!(*recipient, recipient_payment_token_account.owner);
+        assert_keys_eq!(*mint, recipient_payment_token_account.mint);
+
+        token::transfer(
+            CpiContext::new_with_signer(
+                token_program.clone(),
+                token::Transfer {
+                    from: payer_payment_account.clone(),
+                    to: recipient_payment_account.clone(),
+                    authority: payer.clone(),
+                },
+                signer_seeds,
+            ),
+            amount,
+        )
+    }
+}
+
+/// Whether the account can receive payments of the mint for the recipient: the recipient wallet for
+/// SOL, or a token account of the mint owned by the recipient.
+fn is_payment_account_of(mint: &Pubkey, recipient: &Pubkey, payment_account: &AccountInfo) -> bool {
+    if *mint == native_mint::ID {
+        return payment_account.key == recipient;
+    }
+
+    match Account::<TokenAccount>::try_from(payment_account) {
+        Ok(token_account) => token_account.owner == *recipient && token_account.mint == *mint,
+        Err(_) => false,
+    }
+}
+
+/// Creates an account owned by the program at a pda, the payer funding its rent exemption.
+/// Lamports sent to the address beforehand must not prevent the creation of the account.
+pub(crate) fn create_pda_account<'info>(
+    account_info: &AccountInfo<'info>,
+    space: usize,
+    signer_seeds: &[&[&[u8]]],
+    payer: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+) -> Result<()> {
+    let rent_exempt_lamports = Rent::get()?.minimum_balance(space);
+
+    let lamports = account_info.lamports();
+    if lamports == 0 {
+        return system_program::create_account(
+            CpiContext::new_with_signer(
+                system_program.clone(),
+                system_program::CreateAccount {
+                    from: payer.clone(),
+                    to: account_info.clone(),
+                },
+                signer_seeds,
+            ),
+            rent_exempt_lamports,
+            space as u64,
+            &crate::ID,
+        );
+    }
+
+    let top_up = rent_exempt_lamports.saturating_sub(lamports);
+    if top_up != 0 {
+        system_program::transfer(
+            CpiContext::new(
+                system_program.clone(),
+                system_program::Transfer {
+                    from: payer.clone(),
+                    to: account_info.clone(),
+                },
+            ),
+            top_up,
+        )?;
+    }
+    system_program::allocate(
+        CpiContext::new_with_signer(
+            system_program.clone(),
+            system_program::Allocate {
+                account_to_allocate: account_info.clone(),
+            },
+            signer_seeds,
+        ),
+        space as u64,
+    )?;
+    system_program::assign(
+        CpiContext::new_with_signer(
+            system_program.clone(),
+            system_program::Assign {
+                account_to_assign: account_info.clone(),
+            },
+            signer_seeds,
+        ),
+        &crate::ID,
+    )
+}
+
+/// Loads the verified collection of the pawn from its metaplex metadata.
+pub fn load_verified_collection(metadata_info: &AccountInfo, pawn_mint: &Pubkey) -> Result<Pubkey> {
+    let pawn_metadata = load_pawn_metadata(metadata_info, pawn_mint)?;
+    let collection = unwrap_opt!(pawn_metadata.collection, UnverifiedCollection);
+    invariant!(collection.verified, UnverifiedCollection);
+
+    Ok(collection.key)
 }
 
 #[derive(Debug, Clone)]
@@ -615,29 +2737,371 @@ mod tests {
         let terms = LoanTerms {
             principal_amount: 5_000_000_000,
             mint: Pubkey::default(),
-            annual_percentage_rate_bps: 3500, // 35%
-            duration: 7 * 24 * 60 * 60,       // 7 days
+            interest_model: InterestModel::AnnualPercentageRate {
+                annual_percentage_rate_bps: 3500, // 35%
+            },
+            duration: 7 * 24 * 60 * 60, // 7 days
+            minimum_period_ratio_bps: None,
         };
         // Entire duration
         assert_eq!(
-            33_561_643,
+            33_561_644,
             compute_interest_due(&terms, 123456789, 123456789 + terms.duration as i64).unwrap()
         );
 
         // Half duration
         assert_eq!(
-            16_780_821,
+            16_780_822,
             compute_interest_due(&terms, 123456789, 123456789 + terms.duration as i64 / 2).unwrap()
         );
 
         // 10% of the duration should be brought back to 25% of duration as it is the minimum chargeable duration
         assert_eq!(
-            8_390_410,
+            8_390_411,
+            compute_interest_due(&terms, 123456789, 123456789 + terms.duration as i64 / 10)
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn compute_interest_due_custom_minimum_period() {
+        let mut terms = LoanTerms {
+            principal_amount: 5_000_000_000,
+            mint: Pubkey::default(),
+            interest_model: InterestModel::AnnualPercentageRate {
+                annual_percentage_rate_bps: 3500, // 35%
+            },
+            duration: 7 * 24 * 60 * 60, // 7 days
+            minimum_period_ratio_bps: Some(0),
+        };
+
+        // Without minimum period, 10% of the duration is charged as is
+        assert_eq!(
+            3_356_165,
+            compute_interest_due(&terms, 123456789, 123456789 + terms.duration as i64 / 10)
+                .unwrap()
+        );
+
+        // 10% of the duration should be brought back to the 50% minimum chargeable duration
+        terms.minimum_period_ratio_bps = Some(5_000);
+        assert_eq!(
+            16_780_822,
             compute_interest_due(&terms, 123456789, 123456789 + terms.duration as i64 / 10)
                 .unwrap()
         );
     }
 
+    #[test]
+    fn compute_interest_due_fixed_interest_is_time_independent() {
+        let terms = LoanTerms {
+            principal_amount: 5_000_000_000,
+            mint: Pubkey::default(),
+            interest_model: InterestModel::FixedInterest {
+                interest_amount: 250_000_000,
+            },
+            duration: 7 * 24 * 60 * 60, // 7 days
+            minimum_period_ratio_bps: None,
+        };
+
+        // Repaid right away, halfway and after the end of the duration
+        for elapsed_time in [0, terms.duration / 2, terms.duration * 2] {
+            assert_eq!(
+                250_000_000,
+                compute_interest_due(&terms, 123456789, 123456789 + elapsed_time).unwrap()
+            );
+        }
+    }
+
+    /// Deterministic xorshift generator to draw property test inputs.
+    struct XorShift(u64);
+
+    impl XorShift {
+        fn next_in(&mut self, low: u64, high: u64) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            low + self.0 % (high - low + 1)
+        }
+    }
+
+    /// Unsigned integer of any size, little endian 64 bits limbs, for exact references.
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    struct BigUint(Vec<u64>);
+
+    impl BigUint {
+        fn new(value: u64) -> Self {
+            BigUint(vec![value])
+        }
+
+        fn mul(&self, factor: u64) -> Self {
+            let mut carry = 0u128;
+            let mut limbs = self
+                .0
+                .iter()
+                .map(|limb| {
+                    let product = u128::from(*limb) * u128::from(factor) + carry;
+                    carry = product >> 64;
+                    product as u64
+                })
+                .collect::<Vec<u64>>();
+            limbs.push(carry as u64);
+            BigUint(limbs).trimmed()
+        }
+
+        fn sub(&self, other: &Self) -> Self {
+            let mut borrow = false;
+            let limbs = self
+                .0
+                .iter()
+                .enumerate()
+                .map(|(i, limb)| {
+                    let (difference, underflow) =
+                        limb.overflowing_sub(*other.0.get(i).unwrap_or(&0));
+                    let (difference, borrowed) = difference.overflowing_sub(borrow as u64);
+                    borrow = underflow || borrowed;
+                    difference
+                })
+                .collect::<Vec<u64>>();
+            assert!(!borrow && other.0.len() <= self.0.len());
+            BigUint(limbs).trimmed()
+        }
+
+        fn trimmed(mut self) -> Self {
+            while self.0.len() > 1 && self.0.last() == Some(&0) {
+                self.0.pop();
+            }
+            self
+        }
+    }
+
+    impl PartialOrd for BigUint {
+        fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
+            Some(
+                self.0
+                    .len()
+                    .cmp(&other.0.len())
+                    .then_with(|| self.0.iter().rev().cmp(other.0.iter().rev())),
+            )
+        }
+    }
+
+    /// Exact compounding interest `principal * ((1 + period_rate) ^ periods * (1 + remaining_rate)
+    /// - 1)`, as a numerator and a denominator.
+    fn reference_compounding_interest(
+        principal_amount: u64,
+        annual_percentage_rate_bps: u64,
+        compounding_period: u64,
+        elapsed_time: u64,
+    ) -> (BigUint, BigUint) {
+        let scale = SECONDS_PER_YEAR * 10_000;
+        let mut growth =
+            BigUint::new(scale + annual_percentage_rate_bps * (elapsed_time % compounding_period));
+        let mut denominator = BigUint::new(scale);
+        for _ in 0..elapsed_time / compounding_period {
+            growth = growth.mul(scale + annual_percentage_rate_bps * compounding_period);
+            denominator = denominator.mul(scale);
+        }
+
+        (growth.sub(&denominator).mul(principal_amount), denominator)
+    }
+
+    #[test]
+    fn compute_interest_due_compounding_is_correct() {
+        let mut terms = LoanTerms {
+            principal_amount: 5_000_000_000,
+            mint: Pubkey::default(),
+            interest_model: InterestModel::CompoundingAnnualPercentageRate {
+                annual_percentage_rate_bps: 3500, // 35%
+                compounding_period: 24 * 60 * 60, // daily
+            },
+            duration: 365 * 24 * 60 * 60, // 1 year
+            minimum_period_ratio_bps: Some(0),
+        };
+
+        // Over a year, daily compounding at 35% yields (1 + 0.35 / 365) ^ 365 - 1 ~= 41.88%
+        assert_eq!(
+            2_094_147_948,
+            compute_interest_due(&terms, 123456789, 123456789 + terms.duration).unwrap()
+        );
+
+        // Less than a compounding period is charged pro rata
+        assert_eq!(
+            2_397_261,
+            compute_interest_due(&terms, 123456789, 123456789 + 12 * 60 * 60).unwrap()
+        );
+
+        // Per-second compounding converges to continuous compounding: e^0.35 - 1 ~= 41.91%
+        terms.interest_model = InterestModel::CompoundingAnnualPercentageRate {
+            annual_percentage_rate_bps: 3500,
+            compounding_period: 1,
+        };
+        assert_eq!(
+            2_095_337_730,
+            compute_interest_due(&terms, 123456789, 123456789 + terms.duration).unwrap()
+        );
+
+        // Every fixed-point step rounds up, truncating them charged one unit less than the exact
+        // interest rounded up
+        terms.principal_amount = 132_852_266_797_165_126;
+        terms.interest_model = InterestModel::CompoundingAnnualPercentageRate {
+            annual_percentage_rate_bps: 5_266,
+            compounding_period: 49,
+        };
+        assert_eq!(
+            394_878_690_895,
+            compute_interest_due(&terms, 123456789, 123456789 + 178).unwrap()
+        );
+    }
+
+    #[test]
+    fn compute_interest_due_compounding_matches_reference() {
+        let mut rng = XorShift(0x9E37_79B9_7F4A_7C15);
+
+        for _ in 0..2_000 {
+            // Bounded so that the fixed-point rounding stays below one unit of interest
+            let principal_amount = rng.next_in(1, 1_000_000_000_000);
+            let annual_percentage_rate_bps = rng.next_in(1, 20_000);
+            let compounding_period = rng.next_in(1, 30 * 24 * 60 * 60);
+            let elapsed_time =
+                rng.next_in(0, 24) * compounding_period + rng.next_in(0, compounding_period - 1);
+            let terms = LoanTerms {
+                principal_amount,
+                mint: Pubkey::default(),
+                interest_model: InterestModel::CompoundingAnnualPercentageRate {
+                    annual_percentage_rate_bps,
+                    compounding_period: compounding_period as i64,
+                },
+                duration: 2 * SECONDS_PER_YEAR as i64,
+                minimum_period_ratio_bps: Some(0),
+            };
+
+            let interest_due =
+                compute_interest_due(&terms, 123456789, 123456789 + elapsed_time as i64).unwrap();
+            let (numerator, denominator) = reference_compounding_interest(
+                principal_amount,
+                annual_percentage_rate_bps,
+                compounding_period,
+                elapsed_time,
+            );
+
+            // Owed interest is rounded up: never below the exact interest, and at most one unit
+            // above the exact interest rounded up
+            assert!(denominator.mul(interest_due) >= numerator);
+            assert!(interest_due < 2 || denominator.mul(interest_due - 2) < numerator);
+        }
+    }
+
+    #[test]
+    fn interest_rounds_up_and_fees_round_down() {
+        let mut rng = XorShift(0x2545_F491_4F6C_DD1D);
+
+        for _ in 0..2_000 {
+            let principal_amount = rng.next_in(1, 1_000_000_000_000_000);
+            let annual_percentage_rate_bps = rng.next_in(1, 20_000);
+            let elapsed_time = rng.next_in(0, 2 * SECONDS_PER_YEAR);
+            let terms = LoanTerms {
+                principal_amount,
+                mint: Pubkey::default(),
+                interest_model: InterestModel::AnnualPercentageRate {
+                    annual_percentage_rate_bps,
+                },
+                duration: 2 * SECONDS_PER_YEAR as i64,
+                minimum_period_ratio_bps: Some(0),
+            };
+
+            // Owed interest is the exact interest rounded up
+            let interest_due = u128::from(
+                compute_interest_due(&terms, 123456789, 123456789 + elapsed_time as i64).unwrap(),
+            );
+            let exact_numerator = u128::from(principal_amount)
+                * u128::from(annual_percentage_rate_bps)
+                * u128::from(elapsed_time);
+            let exact_denominator = u128::from(SECONDS_PER_YEAR * 10_000);
+            assert!(interest_due * exact_denominator >= exact_numerator);
+            assert!(interest_due == 0 || (interest_due - 1) * exact_denominator < exact_numerator);
+
+            // Fees and royalties paid out of the interest are the exact share rounded down
+            let interest_due = interest_due as u64;
+            let bps = rng.next_in(0, 10_000);
+            for share in [
+                compute_admin_fee(interest_due, bps).unwrap(),
+                compute_creator_royalty(interest_due, bps).unwrap(),
+                compute_referral_fee(interest_due, bps).unwrap(),
+            ] {
+                let exact_numerator = u128::from(interest_due) * u128::from(bps);
+                assert!(u128::from(share) * 10_000 <= exact_numerator);
+                assert!(u128::from(share + 1) * 10_000 > exact_numerator);
+            }
+        }
+    }
+
+    #[test]
+    fn compute_interest_due_compounding_overflow() {
+        let terms = LoanTerms {
+            principal_amount: u64::MAX,
+            mint: Pubkey::default(),
+            interest_model: InterestModel::CompoundingAnnualPercentageRate {
+                annual_percentage_rate_bps: 100_000, // 1000%
+                compounding_period: 1,
+            },
+            duration: 10 * SECONDS_PER_YEAR as i64,
+            minimum_period_ratio_bps: None,
+        };
+
+        assert!(compute_interest_due(&terms, 0, terms.duration).is_err());
+    }
+
+    #[test]
+    fn compounding_stops_after_the_grace_period() {
+        let terms = LoanTerms {
+            principal_amount: 1_000_000_000,
+            mint: Pubkey::default(),
+            interest_model: InterestModel::CompoundingAnnualPercentageRate {
+                annual_percentage_rate_bps: 50_000, // 500%
+                compounding_period: 60,
+            },
+            duration: 30 * 24 * 60 * 60,
+            minimum_period_ratio_bps: None,
+        };
+        let end_of_compounding = terms.duration + COMPOUNDING_GRACE_PERIOD;
+
+        assert!(
+            compute_interest_due(&terms, 0, end_of_compounding - 60).unwrap()
+                < compute_interest_due(&terms, 0, end_of_compounding).unwrap()
+        );
+        assert_eq!(
+            compute_interest_due(&terms, 0, end_of_compounding).unwrap(),
+            compute_interest_due(&terms, 0, i64::MAX).unwrap()
+        );
+        assert!(validate_compounding_bounds(&terms).is_ok());
+    }
+
+    #[test]
+    fn compounding_terms_overflowing_before_the_end_of_compounding_are_rejected() {
+        let mut terms = LoanTerms {
+            principal_amount: 1_000_000_000,
+            mint: Pubkey::default(),
+            interest_model: InterestModel::CompoundingAnnualPercentageRate {
+                annual_percentage_rate_bps: 1_000_000, // 10000%
+                compounding_period: 1,
+            },
+            duration: 7 * 24 * 60 * 60,
+            minimum_period_ratio_bps: None,
+        };
+        // The interest fits at the end of the loan, not at the end of the grace period
+        assert!(compute_interest_due(&terms, 0, terms.duration).is_ok());
+        assert!(validate_compounding_bounds(&terms).is_err());
+
+        terms.duration = i64::MAX;
+        assert!(validate_compounding_bounds(&terms).is_err());
+
+        // Other interest models are not bounded by the compounding
+        terms.interest_model = InterestModel::FixedInterest {
+            interest_amount: u64::MAX,
+        };
+        assert!(validate_compounding_bounds(&terms).is_ok());
+    }
+
     #[test]
     fn compute_admin_fee_positive_interest() {
         const POSITIVE_INTEREST: u64 = 100;
@@ -654,6 +3118,178 @@ mod tests {
         assert_eq!(0, compute_admin_fee(0, ADMIN_FEE_BPS).unwrap());
     }
 
+    #[test]
+    fn compute_admin_fee_rounds_down() {
+        // 2% of 149 is 2.98
+        assert_eq!(2, compute_admin_fee(149, ADMIN_FEE_BPS).unwrap());
+        assert_eq!(0, compute_admin_fee(49, ADMIN_FEE_BPS).unwrap());
+    }
+
+    #[test]
+    fn compute_interest_due_rounds_up() {
+        let terms = LoanTerms {
+            principal_amount: 1,
+            mint: Pubkey::default(),
+            interest_model: InterestModel::AnnualPercentageRate {
+                annual_percentage_rate_bps: 1, // 0.01%
+            },
+            duration: 1,
+            minimum_period_ratio_bps: None,
+        };
+
+        // Any elapsed time yields at least one unit of interest
+        assert_eq!(1, compute_interest_due(&terms, 0, 1).unwrap());
+        // Only no time elapsed at all yields no interest
+        assert_eq!(0, compute_interest_due(&terms, 0, 0).unwrap());
+    }
+
+    #[test]
+    fn repayment_split_sums_to_borrower_payment() {
+        let principal_amount = 1_000;
+        for interest_due in 0..10_000 {
+            for admin_fee_bps in [0, 1, 33, ADMIN_FEE_BPS, 2_500, 9_999, 10_000] {
+                let admin_fee = compute_admin_fee(interest_due, admin_fee_bps).unwrap();
+                let payoff_amount =
+                    compute_payoff_amount(principal_amount, interest_due, admin_fee).unwrap();
+
+                assert!(admin_fee * 10_000 <= interest_due * admin_fee_bps);
+                assert!(interest_due * admin_fee_bps < (admin_fee + 1) * 10_000);
+                assert_eq!(principal_amount + interest_due, payoff_amount + admin_fee);
+            }
+        }
+    }
+
+    #[test]
+    fn compute_payoff_quote_is_consistent() {
+        let terms = LoanTerms {
+            principal_amount: 5_000_000_000,
+            mint: Pubkey::default(),
+            interest_model: InterestModel::AnnualPercentageRate {
+                annual_percentage_rate_bps: 3500, // 35%
+            },
+            duration: 7 * 24 * 60 * 60, // 7 days
+            minimum_period_ratio_bps: None,
+        };
+        let timestamp = 123456789 + terms.duration;
+
+        assert_eq!(
+            PayoffQuote {
+                timestamp,
+                interest_due: 33_561_644,
+                admin_fee: 671_232,
+                creator_royalty: 0,
+                payoff_amount: 5_000_000_000 + 33_561_644 - 671_232,
+                total_repayment_amount: 5_000_000_000 + 33_561_644,
+            },
+            compute_payoff_quote(&terms, 123456789, timestamp, ADMIN_FEE_BPS, 0).unwrap()
+        );
+
+        // 5% creator royalty is taken from the lender's share of the interest
+        assert_eq!(
+            PayoffQuote {
+                timestamp,
+                interest_due: 33_561_644,
+                admin_fee: 671_232,
+                creator_royalty: 1_678_082,
+                payoff_amount: 5_000_000_000 + 33_561_644 - 671_232 - 1_678_082,
+                total_repayment_amount: 5_000_000_000 + 33_561_644,
+            },
+            compute_payoff_quote(&terms, 123456789, timestamp, ADMIN_FEE_BPS, 500).unwrap()
+        );
+    }
+
+    #[test]
+    fn split_payoff_quote_pays_the_lender_the_royalty_without_recipient() {
+        let quote = PayoffQuote {
+            timestamp: 123456789,
+            interest_due: 1_000,
+            admin_fee: 20,
+            creator_royalty: 50,
+            payoff_amount: 10_000 + 1_000 - 20 - 50,
+            total_repayment_amount: 10_000 + 1_000,
+        };
+        let creator_royalty = CreatorRoyalty {
+            royalty_bps: 500,
+            payout_address: None,
+        };
+        let creators = [Creator {
+            address: Pubkey::new_unique(),
+            verified: true,
+            share: 100,
+        }];
+
+        assert_eq!(
+            (quote, vec![(creators[0].address, 50)]),
+            split_payoff_quote(quote, Some(&creator_royalty), &creators).unwrap()
+        );
+        // Pawn metadata without creators
+        assert_eq!(
+            (
+                PayoffQuote {
+                    creator_royalty: 0,
+                    payoff_amount: 10_000 + 1_000 - 20,
+                    ..quote
+                },
+                vec![]
+            ),
+            split_payoff_quote(quote, Some(&creator_royalty), &[]).unwrap()
+        );
+        // The payout address takes precedence over the creators
+        let payout_address = Pubkey::new_unique();
+        assert_eq!(
+            (quote, vec![(payout_address, 50)]),
+            split_payoff_quote(
+                quote,
+                Some(&CreatorRoyalty {
+                    payout_address: Some(payout_address),
+                    ..creator_royalty
+                }),
+                &[]
+            )
+            .unwrap()
+        );
+        // No royalty is due without interest
+        let quote = PayoffQuote {
+            interest_due: 0,
+            admin_fee: 0,
+            creator_royalty: 0,
+            payoff_amount: 10_000,
+            total_repayment_amount: 10_000,
+            ..quote
+        };
+        assert_eq!(
+            (quote, vec![]),
+            split_payoff_quote(quote, Some(&creator_royalty), &creators).unwrap()
+        );
+    }
+
+    #[test]
+    fn compute_nominal_annual_percentage_rate_bps_is_correct() {
+        let mut terms = LoanTerms {
+            principal_amount: 5_000_000_000,
+            mint: Pubkey::default(),
+            interest_model: InterestModel::CompoundingAnnualPercentageRate {
+                annual_percentage_rate_bps: 3500, // 35%
+                compounding_period: 1,
+            },
+            duration: 365 * 24 * 60 * 60 / 2, // Half a year
+            minimum_period_ratio_bps: None,
+        };
+        assert_eq!(
+            Some(3500),
+            compute_nominal_annual_percentage_rate_bps(&terms)
+        );
+
+        // 10% of the principal over half a year
+        terms.interest_model = InterestModel::FixedInterest {
+            interest_amount: 500_000_000,
+        };
+        assert_eq!(
+            Some(2000),
+            compute_nominal_annual_percentage_rate_bps(&terms)
+        );
+    }
+
     #[test]
     fn compute_payoff_amount_is_correct() {
         assert_eq!(
@@ -665,4 +3301,21 @@ mod tests {
         assert_eq!(None, compute_payoff_amount(u64::MAX, 1, 0));
         assert_eq!(None, compute_payoff_amount(u64::MAX, 2, 1));
     }
+
+    #[test]
+    fn event_data_starts_with_schema_version() {
+        let event = FeesWithdrawn {
+            schema_version: EVENT_SCHEMA_VERSION,
+            mint: Pubkey::new_unique(),
+            amount: 1_000,
+            recipient: fee_collector::ID,
+            recipient_payment_account: Pubkey::new_unique(),
+            timestamp: 1_650_000_000,
+        };
+
+        // Indexers read the version right after the discriminator to pick the layout
+        let data = anchor_lang::Event::data(&event);
+        assert_eq!(EVENT_SCHEMA_VERSION, data[8]);
+        assert_eq!(8 + 1 + 32 + 8 + 32 + 32 + 8, data.len());
+    }
 }
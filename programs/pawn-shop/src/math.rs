@@ -0,0 +1,109 @@
+//! Deterministic fixed-point arithmetic used by the interest and fee computations.
+//!
+//! Rounding policy:
+//! - Interest is rounded up, in favour of the lender.
+//! - Fees are rounded down, in favour of the payer.
+//! - Lender payoff is derived by subtraction so that the lender payoff and the admin fee
+//!   always sum to exactly what the borrower pays, leaving no dust behind.
+
+/// One in fixed-point representation, numbers carry 18 decimals.
+pub const WAD: u128 = 1_000_000_000_000_000_000;
+
+/// Computes `a * b / denominator` rounded toward zero.
+pub fn mul_div_floor(a: u128, b: u128, denominator: u128) -> Option<u128> {
+    a.checked_mul(b)?.checked_div(denominator)
+}
+
+/// Computes `a * b / denominator` rounded away from zero.
+pub fn mul_div_ceil(a: u128, b: u128, denominator: u128) -> Option<u128> {
+    let product = a.checked_mul(b)?;
+    let quotient = product.checked_div(denominator)?;
+    if product % denominator == 0 {
+        Some(quotient)
+    } else {
+        quotient.checked_add(1)
+    }
+}
+
+/// Multiplies two fixed-point numbers, rounding the result up.
+pub fn wad_mul_ceil(a: u128, b: u128) -> Option<u128> {
+    mul_div_ceil(a, b, WAD)
+}
+
+/// Raises a fixed-point number to an integer power using exponentiation by squaring, every
+/// product rounded up so that the result is never below the exact power.
+pub fn wad_pow_ceil(base: u128, exponent: u64) -> Option<u128> {
+    let mut result = WAD;
+    let mut base = base;
+    let mut exponent = exponent;
+
+    while exponent > 0 {
+        if exponent & 1 == 1 {
+            result = wad_mul_ceil(result, base)?;
+        }
+        exponent >>= 1;
+        // Skip the last squaring as it is unused and could overflow needlessly
+        if exponent > 0 {
+            base = wad_mul_ceil(base, base)?;
+        }
+    }
+
+    Some(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mul_div_rounding_is_exhaustively_correct() {
+        for a in 0..64u128 {
+            for b in 0..64u128 {
+                for denominator in 1..64u128 {
+                    let floor = mul_div_floor(a, b, denominator).unwrap();
+                    let ceil = mul_div_ceil(a, b, denominator).unwrap();
+
+                    assert!(floor * denominator <= a * b);
+                    assert!(a * b < (floor + 1) * denominator);
+                    assert!(ceil * denominator >= a * b);
+                    assert!(a * b + denominator > ceil * denominator);
+                    assert_eq!((a * b) % denominator != 0, ceil == floor + 1);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn mul_div_overflow() {
+        assert_eq!(None, mul_div_floor(u128::MAX, 2, 2));
+        assert_eq!(None, mul_div_ceil(u128::MAX, 2, 2));
+        assert_eq!(None, mul_div_floor(1, 1, 0));
+        assert_eq!(None, mul_div_ceil(1, 1, 0));
+    }
+
+    #[test]
+    fn wad_pow_ceil_is_correct() {
+        assert_eq!(Some(WAD), wad_pow_ceil(0, 0));
+        assert_eq!(Some(WAD), wad_pow_ceil(5 * WAD, 0));
+        assert_eq!(Some(0), wad_pow_ceil(0, 3));
+        assert_eq!(Some(64 * WAD), wad_pow_ceil(2 * WAD, 6));
+        // 1.5^3 = 3.375
+        assert_eq!(Some(3_375 * WAD / 1_000), wad_pow_ceil(3 * WAD / 2, 3));
+        // 0.5^2 = 0.25
+        assert_eq!(Some(WAD / 4), wad_pow_ceil(WAD / 2, 2));
+    }
+
+    #[test]
+    fn wad_pow_ceil_rounds_up() {
+        // (1 + 1e-18) ^ 2 = 1 + 2e-18 + 1e-36
+        assert_eq!(Some(WAD + 3), wad_pow_ceil(WAD + 1, 2));
+        // 0.333...333 ^ 2 = 0.111...110888...889
+        assert_eq!(Some(WAD / 9), wad_pow_ceil(WAD / 3, 2));
+    }
+
+    #[test]
+    fn wad_pow_ceil_overflow() {
+        assert_eq!(None, wad_pow_ceil(1_000 * WAD, 10));
+        assert_eq!(None, wad_mul_ceil(u128::MAX, 2));
+    }
+}
@@ -0,0 +1,392 @@
+//! Pawn loan versioning: accounts of earlier layouts keep them until `migrate_loan` rewrites
+//! them in the current layout. Accounts created before the version byte have the layout of the
+//! deployed program, the first versioned layout is the borsh encoding of `PawnLoanView`.
+
+use anchor_lang::{prelude::*, system_program, Discriminator};
+use vipers::prelude::*;
+
+use crate::{
+    ErrorCode, InterestModel, LoanStatus, LoanTerms, PawnLoan, PawnLoanView, ADMIN_FEE_BPS,
+};
+
+/// Zero-copy layout
+pub const PAWN_LOAN_VERSION: u8 = 2;
+/// Borsh layout, followed by the reserved space
+pub const BORSH_PAWN_LOAN_VERSION: u8 = 1;
+pub const PAWN_LOAN_RESERVED_SPACE: usize = 119;
+
+/// Loan terms before interest models, interest always accrued at an annual rate.
+#[derive(Clone, Copy, AnchorSerialize, AnchorDeserialize, PartialEq)]
+pub struct LegacyLoanTerms {
+    pub principal_amount: u64,
+    pub mint: Pubkey,
+    pub annual_percentage_rate_bps: u64,
+    pub duration: i64,
+}
+
+impl LegacyLoanTerms {
+    fn space() -> usize {
+        8 + 32 + 8 + 8
+    }
+}
+
+impl From<LegacyLoanTerms> for LoanTerms {
+    fn from(terms: LegacyLoanTerms) -> Self {
+        LoanTerms {
+            principal_amount: terms.principal_amount,
+            mint: terms.mint,
+            interest_model: InterestModel::AnnualPercentageRate {
+                annual_percentage_rate_bps: terms.annual_percentage_rate_bps,
+            },
+            duration: terms.duration,
+            minimum_period_ratio_bps: None,
+        }
+    }
+}
+
+/// Pawn loan without version, as created by the deployed program.
+#[derive(Clone, Copy, AnchorSerialize, AnchorDeserialize)]
+pub struct LegacyPawnLoan {
+    pub base: Pubkey,
+    pub bump: u8,
+    pub borrower: Pubkey,
+    pub pawn_token_account: Pubkey,
+    pub pawn_mint: Pubkey,
+    pub status: LoanStatus,
+    pub lender: Pubkey,
+    pub desired_terms: Option<LegacyLoanTerms>,
+    pub terms: Option<LegacyLoanTerms>,
+    pub creation_time: i64,
+    pub start_time: i64,
+    pub end_time: i64,
+}
+
+impl LegacyPawnLoan {
+    pub fn space() -> usize {
+        8 + 32 + 1 + 32 + 32 + 32 + 1 + 32 + 2 * (1 + LegacyLoanTerms::space()) + 8 + 8 + 8
+    }
+
+    /// Decodes the account data of a legacy pawn loan, discriminator included.
+    pub fn try_from_account_data(data: &[u8]) -> Result<Self> {
+        invariant!(data.len() == Self::space(), InvalidPawnLoanLayout);
+        invariant!(
+            data[..8] == PawnLoan::discriminator(),
+            InvalidPawnLoanLayout
+        );
+
+        Self::deserialize(&mut &data[8..])
+            .map_err(|_| error!(anchor_lang::error::ErrorCode::AccountDidNotDeserialize))
+    }
+
+    /// The loan in the current version. Legacy loans were charged the default admin fee and had
+    /// neither referrers nor creator royalties.
+    pub fn migrate(self) -> PawnLoanView {
+        PawnLoanView {
+            version: PAWN_LOAN_VERSION,
+            base: self.base,
+            bump: self.bump,
+            borrower: self.borrower,
+            pawn_token_account: self.pawn_token_account,
+            pawn_mint: self.pawn_mint,
+            status: self.status,
+            lender: self.lender,
+            desired_terms: self.desired_terms.map(LoanTerms::from),
+            terms: self.terms.map(LoanTerms::from),
+            creation_time: self.creation_time,
+            start_time: self.start_time,
+            end_time: self.end_time,
+            creator_royalty: None,
+            borrower_referrer: None,
+            lender_referrer: None,
+            admin_fee_bps: ADMIN_FEE_BPS,
+            seed_nonce: None,
+        }
+    }
+}
+
+/// Decodes the account data of a pawn loan of an earlier layout, in the current version.
+pub fn decode_outdated_pawn_loan(data: &[u8]) -> Result<PawnLoanView> {
+    if data.len() == LegacyPawnLoan::space() {
+        return Ok(LegacyPawnLoan::try_from_account_data(data)?.migrate());
+    }
+
+    invariant!(data.len() == PawnLoan::space(), InvalidPawnLoanLayout);
+    invariant!(
+        data[..8] == PawnLoan::discriminator(),
+        InvalidPawnLoanLayout
+    );
+    match data[8] {
+        BORSH_PAWN_LOAN_VERSION => {
+            let pawn_loan = PawnLoanView::deserialize(&mut &data[8..])
+                .map_err(|_| error!(anchor_lang::error::ErrorCode::AccountDidNotDeserialize))?;
+            Ok(PawnLoanView {
+                version: PAWN_LOAN_VERSION,
+                ..pawn_loan
+            })
+        }
+        PAWN_LOAN_VERSION => Err(error!(ErrorCode::PawnLoanAlreadyMigrated)),
+        _ => Err(error!(ErrorCode::InvalidPawnLoanLayout)),
+    }
+}
+
+/// Rewrites a pawn loan of an earlier layout in the current one, the payer funding the
+/// additional rent.
+pub fn migrate_pawn_loan<'info>(
+    pawn_loan_info: &AccountInfo<'info>,
+    payer: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+) -> Result<PawnLoanView> {
+    let pawn_loan = decode_outdated_pawn_loan(&pawn_loan_info.try_borrow_data()?)?;
+
+    let space = PawnLoan::space();
+    let top_up = Rent::get()?
+        .minimum_balance(space)
+        .saturating_sub(pawn_loan_info.lamports());
+    if top_up != 0 {
+        system_program::transfer(
+            CpiContext::new(
+                system_program.clone(),
+                system_program::Transfer {
+                    from: payer.clone(),
+                    to: pawn_loan_info.clone(),
+                },
+            ),
+            top_up,
+        )?;
+    }
+    pawn_loan_info.realloc(space, true)?;
+
+    let loader: AccountLoader<PawnLoan> = AccountLoader::try_from(pawn_loan_info)?;
+    *loader.load_mut()? = PawnLoan::from(&pawn_loan);
+
+    Ok(pawn_loan)
+}
+
+#[derive(Accounts)]
+pub struct MigrateLoan<'info> {
+    /// CHECK: Pawn loan of an earlier layout, checked in the handler
+    #[account(mut, owner = crate::ID)]
+    pub pawn_loan: UncheckedAccount<'info>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    const OPEN_LOAN: &str = include_str!("../fixtures/legacy_pawn_loan_open.hex");
+    const ACTIVE_LOAN: &str = include_str!("../fixtures/legacy_pawn_loan_active.hex");
+    const REPAID_LOAN: &str = include_str!("../fixtures/legacy_pawn_loan_repaid.hex");
+
+    /// Account data of a fixture, hex encoded.
+    fn account_data(fixture: &str) -> Vec<u8> {
+        let fixture = fixture.trim();
+        (0..fixture.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&fixture[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    fn key(address: &str) -> Pubkey {
+        Pubkey::from_str(address).unwrap()
+    }
+
+    fn annual_rate_terms(
+        principal_amount: u64,
+        mint: Pubkey,
+        bps: u64,
+        duration: i64,
+    ) -> LoanTerms {
+        LoanTerms {
+            principal_amount,
+            mint,
+            interest_model: InterestModel::AnnualPercentageRate {
+                annual_percentage_rate_bps: bps,
+            },
+            duration,
+            minimum_period_ratio_bps: None,
+        }
+    }
+
+    /// Decodes the fixture as `migrate_loan` does, checking the zero-copy account it writes.
+    fn migrate_fixture(fixture: &str) -> PawnLoanView {
+        let data = account_data(fixture);
+        assert_eq!(LegacyPawnLoan::space(), data.len());
+
+        let pawn_loan = decode_outdated_pawn_loan(&data).unwrap();
+        assert!(PawnLoan::from(&pawn_loan).view().unwrap() == pawn_loan);
+        pawn_loan
+    }
+
+    /// Fields legacy loans did not have.
+    fn assert_migration_defaults(pawn_loan: &PawnLoanView) {
+        assert_eq!(PAWN_LOAN_VERSION, pawn_loan.version);
+        assert_eq!(ADMIN_FEE_BPS, pawn_loan.admin_fee_bps);
+        assert!(pawn_loan.creator_royalty.is_none());
+        assert!(pawn_loan.borrower_referrer.is_none());
+        assert!(pawn_loan.lender_referrer.is_none());
+        assert!(pawn_loan.seed_nonce.is_none());
+    }
+
+    #[test]
+    fn legacy_open_loan_is_migrated() {
+        let pawn_loan = migrate_fixture(OPEN_LOAN);
+
+        assert_migration_defaults(&pawn_loan);
+        assert_eq!(
+            key("AVmiZGA2BRd4QXXddhaEPDR6SgVsVnYXQxM8LeAoa4fW"),
+            pawn_loan.base
+        );
+        assert_eq!(255, pawn_loan.bump);
+        assert_eq!(
+            key("CXUmGwgtVPWb5soPiaE5VJYWKz8MeJ9WHL6iAbmKPhvP"),
+            pawn_loan.borrower
+        );
+        assert_eq!(
+            key("FGyiAR7wi6ezEYwTnNThjeUV1HVMfZHJsSZv5QL4W92d"),
+            pawn_loan.pawn_token_account
+        );
+        assert_eq!(
+            key("FuctfjFbSK7kW6JQFJGqh9BvMrjoHpCNwnfzCxxhT22F"),
+            pawn_loan.pawn_mint
+        );
+        assert!(pawn_loan.status == LoanStatus::Open);
+        assert_eq!(Pubkey::default(), pawn_loan.lender);
+        assert!(
+            pawn_loan.desired_terms
+                == Some(annual_rate_terms(
+                    1_500_000_000,
+                    crate::native_mint::ID,
+                    3_500,
+                    1_209_600
+                ))
+        );
+        assert!(pawn_loan.terms.is_none());
+        assert_eq!(1_651_234_567, pawn_loan.creation_time);
+        assert_eq!(0, pawn_loan.start_time);
+        assert_eq!(0, pawn_loan.end_time);
+    }
+
+    #[test]
+    fn legacy_active_loan_is_migrated() {
+        let pawn_loan = migrate_fixture(ACTIVE_LOAN);
+
+        assert_migration_defaults(&pawn_loan);
+        assert_eq!(
+            key("2CDaLyN7MWDRLiuMkwZpGqwC59LcS1JaZWrxPBvQYyfU"),
+            pawn_loan.base
+        );
+        assert_eq!(254, pawn_loan.bump);
+        assert_eq!(
+            key("CXUmGwgtVPWb5soPiaE5VJYWKz8MeJ9WHL6iAbmKPhvP"),
+            pawn_loan.borrower
+        );
+        assert_eq!(
+            key("F7QGR1Gb658DfHRuCwfpN4TLxDCdTrN7iSX57U6Pr7oZ"),
+            pawn_loan.pawn_token_account
+        );
+        assert_eq!(
+            key("CstPmd2NpQ4NZEm9n2t8nypcDWGcGhUTRAm674od4Lm7"),
+            pawn_loan.pawn_mint
+        );
+        assert!(pawn_loan.status == LoanStatus::Active);
+        assert_eq!(
+            key("AaKp4239hqK45EwYm4gUVXevFzE5JC7iKNdxGhZWvHvG"),
+            pawn_loan.lender
+        );
+        let terms = annual_rate_terms(
+            250_000_000,
+            key("EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v"),
+            12_000,
+            2_592_000,
+        );
+        assert!(pawn_loan.desired_terms == Some(terms));
+        assert!(pawn_loan.terms == Some(terms));
+        assert_eq!(1_651_500_000, pawn_loan.creation_time);
+        assert_eq!(1_651_503_600, pawn_loan.start_time);
+        assert_eq!(0, pawn_loan.end_time);
+    }
+
+    #[test]
+    fn legacy_repaid_loan_is_migrated() {
+        let pawn_loan = migrate_fixture(REPAID_LOAN);
+
+        assert_migration_defaults(&pawn_loan);
+        assert_eq!(
+            key("26HuF6xptvu8JPBu4kwx4NZhu9mPQNiaZNxkWTSUobbL"),
+            pawn_loan.base
+        );
+        assert_eq!(253, pawn_loan.bump);
+        assert_eq!(
+            key("CXUmGwgtVPWb5soPiaE5VJYWKz8MeJ9WHL6iAbmKPhvP"),
+            pawn_loan.borrower
+        );
+        assert_eq!(
+            key("5NFDPaNy2Hswfrq56xESsYjdg5MBuo2PRpfQk4KNeHMA"),
+            pawn_loan.pawn_token_account
+        );
+        assert_eq!(
+            key("5LmLnDEwhAPth265SVHq487ERvERy2w6WprZcUuiNJcC"),
+            pawn_loan.pawn_mint
+        );
+        assert!(pawn_loan.status == LoanStatus::Repaid);
+        assert_eq!(
+            key("AaKp4239hqK45EwYm4gUVXevFzE5JC7iKNdxGhZWvHvG"),
+            pawn_loan.lender
+        );
+        let terms = annual_rate_terms(1_500_000_000, crate::native_mint::ID, 3_500, 1_209_600);
+        assert!(pawn_loan.desired_terms == Some(terms));
+        assert!(pawn_loan.terms == Some(terms));
+        assert_eq!(1_650_100_000, pawn_loan.creation_time);
+        assert_eq!(1_650_103_600, pawn_loan.start_time);
+        assert_eq!(1_650_700_000, pawn_loan.end_time);
+    }
+
+    #[test]
+    fn borsh_layout_is_migrated() {
+        let mut pawn_loan = migrate_fixture(ACTIVE_LOAN);
+        pawn_loan.version = BORSH_PAWN_LOAN_VERSION;
+        pawn_loan.borrower_referrer = Some(Pubkey::new_unique());
+
+        // The borsh layout is padded with zeros up to the reserved space
+        let mut data = PawnLoan::discriminator().to_vec();
+        data.extend_from_slice(&pawn_loan.try_to_vec().unwrap());
+        data.resize(PawnLoan::space(), 0);
+
+        let migrated = decode_outdated_pawn_loan(&data).unwrap();
+        assert_eq!(PAWN_LOAN_VERSION, migrated.version);
+        assert!(
+            migrated
+                == PawnLoanView {
+                    version: PAWN_LOAN_VERSION,
+                    ..pawn_loan
+                }
+        );
+    }
+
+    #[test]
+    fn current_layout_is_not_migrated() {
+        let mut data = PawnLoan::discriminator().to_vec();
+        data.resize(PawnLoan::space(), 0);
+
+        data[8] = PAWN_LOAN_VERSION;
+        assert!(decode_outdated_pawn_loan(&data).is_err());
+        data[8] = PAWN_LOAN_VERSION + 1;
+        assert!(decode_outdated_pawn_loan(&data).is_err());
+    }
+
+    #[test]
+    fn only_legacy_layouts_are_migrated() {
+        let mut data = account_data(OPEN_LOAN);
+        data.push(0);
+        assert!(LegacyPawnLoan::try_from_account_data(&data).is_err());
+        assert!(decode_outdated_pawn_loan(&data).is_err());
+
+        let mut data = account_data(OPEN_LOAN);
+        data[0] ^= 1;
+        assert!(LegacyPawnLoan::try_from_account_data(&data).is_err());
+    }
+}
@@ -0,0 +1,180 @@
+//! Collection floor price feeds backing the loan-to-value guard of underwriting.
+//!
+//! Feeds are pluggable: `load_collection_price` dispatches on the feed account owner.
+//! The program owned `PriceFeed`, written by its authority, is the only source for now.
+
+use std::convert::TryInto;
+
+use anchor_lang::prelude::*;
+use vipers::prelude::*;
+
+use crate::math::mul_div_floor;
+use crate::ErrorCode;
+
+/// Price of a collection, in the smallest unit of the quote mint.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CollectionPrice {
+    pub collection: Pubkey,
+    pub quote_mint: Pubkey,
+    pub price: u64,
+    /// Uncertainty of the price, in the same unit
+    pub confidence: u64,
+    pub publish_time: i64,
+}
+
+/// Loan-to-value requirements a lender underwrites with.
+#[derive(Clone, Copy, AnchorSerialize, AnchorDeserialize, PartialEq)]
+pub struct LoanToValueGuard {
+    pub max_loan_to_value_bps: u64,
+    /// Maximum age of the price in seconds
+    pub max_price_age: i64,
+    /// Maximum confidence interval relative to the price
+    pub max_confidence_bps: u64,
+}
+
+impl LoanToValueGuard {
+    /// Rejects stale or uncertain prices, and principals above the loan-to-value of the
+    /// lower bound of the price.
+    pub fn validate(
+        &self,
+        collection_price: &CollectionPrice,
+        principal_amount: u64,
+        unix_timestamp: i64,
+    ) -> Result<()> {
+        let price_age = unwrap_int!(unix_timestamp.checked_sub(collection_price.publish_time));
+        invariant!(price_age <= self.max_price_age, StalePrice);
+
+        let max_confidence = mul_div_floor(
+            collection_price.price.into(),
+            self.max_confidence_bps.into(),
+            10_000,
+        )
+        .ok_or(ErrorCode::CalculationError)?;
+        invariant!(
+            u128::from(collection_price.confidence) <= max_confidence,
+            PriceConfidenceTooLow
+        );
+
+        let conservative_price = collection_price
+            .price
+            .saturating_sub(collection_price.confidence);
+        let max_principal_amount: u64 = mul_div_floor(
+            conservative_price.into(),
+            self.max_loan_to_value_bps.into(),
+            10_000,
+        )
+        .and_then(|max_principal_amount| max_principal_amount.try_into().ok())
+        .ok_or(ErrorCode::CalculationError)?;
+        invariant!(
+            principal_amount <= max_principal_amount,
+            LoanToValueExceeded
+        );
+
+        Ok(())
+    }
+}
+
+#[account]
+pub struct PriceFeed {
+    pub authority: Pubkey,
+    pub collection: Pubkey,
+    pub quote_mint: Pubkey,
+    pub price: u64,
+    pub confidence: u64,
+    pub publish_time: i64,
+}
+
+impl PriceFeed {
+    pub fn space() -> usize {
+        8 + 32 + 32 + 32 + 8 + 8 + 8
+    }
+}
+
+/// Reads the collection price from a supported feed account.
+pub fn load_collection_price(feed_info: &AccountInfo) -> Result<CollectionPrice> {
+    if feed_info.owner == &crate::ID {
+        let price_feed: Account<PriceFeed> = Account::try_from(feed_info)?;
+        return Ok(CollectionPrice {
+            collection: price_feed.collection,
+            quote_mint: price_feed.quote_mint,
+            price: price_feed.price,
+            confidence: price_feed.confidence,
+            publish_time: price_feed.publish_time,
+        });
+    }
+
+    Err(error!(ErrorCode::InvalidPriceFeed))
+}
+
+#[derive(Accounts)]
+#[instruction(collection: Pubkey, quote_mint: Pubkey)]
+pub struct CreatePriceFeed<'info> {
+    #[account(init, seeds = [authority.key.as_ref(), collection.as_ref(), quote_mint.as_ref(), b"price_feed".as_ref()], bump, payer = authority, space = PriceFeed::space())]
+    pub price_feed: Account<'info, PriceFeed>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdatePriceFeed<'info> {
+    #[account(mut, has_one = authority)]
+    pub price_feed: Account<'info, PriceFeed>,
+    pub authority: Signer<'info>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GUARD: LoanToValueGuard = LoanToValueGuard {
+        max_loan_to_value_bps: 5_000,
+        max_price_age: 60,
+        max_confidence_bps: 1_000,
+    };
+
+    fn collection_price(price: u64, confidence: u64) -> CollectionPrice {
+        CollectionPrice {
+            collection: Pubkey::default(),
+            quote_mint: Pubkey::default(),
+            price,
+            confidence,
+            publish_time: 1_000,
+        }
+    }
+
+    #[test]
+    fn validate_loan_to_value() {
+        // 50% of the 1_000 price lower bound
+        assert!(GUARD
+            .validate(&collection_price(1_000, 0), 500, 1_000)
+            .is_ok());
+        assert!(GUARD
+            .validate(&collection_price(1_000, 0), 501, 1_000)
+            .is_err());
+        // 50% of the 900 price lower bound
+        assert!(GUARD
+            .validate(&collection_price(1_000, 100), 450, 1_000)
+            .is_ok());
+        assert!(GUARD
+            .validate(&collection_price(1_000, 100), 451, 1_000)
+            .is_err());
+    }
+
+    #[test]
+    fn validate_rejects_stale_price() {
+        assert!(GUARD
+            .validate(&collection_price(1_000, 0), 1, 1_060)
+            .is_ok());
+        assert!(GUARD
+            .validate(&collection_price(1_000, 0), 1, 1_061)
+            .is_err());
+    }
+
+    #[test]
+    fn validate_rejects_uncertain_price() {
+        assert!(GUARD
+            .validate(&collection_price(1_000, 101), 1, 1_000)
+            .is_err());
+    }
+}
@@ -0,0 +1,517 @@
+//! Zero-copy layout of the pawn loan. Optional fields are stored as a flag followed by a value
+//! of fixed size, so that instructions access the fields they use in place instead of
+//! deserializing the whole account. `PawnLoanView` decodes them for clients and events.
+
+use std::{
+    convert::{TryFrom, TryInto},
+    mem,
+};
+
+use anchor_lang::prelude::*;
+
+use crate::{
+    CreatorRoyalty, ErrorCode, InterestModel, LoanStatus, LoanTerms, PAWN_LOAN_RESERVED_SPACE,
+    PAWN_LOAN_VERSION,
+};
+
+#[zero_copy]
+#[derive(Default)]
+pub struct OptionalU64 {
+    pub is_some: u8,
+    pub value: u64,
+}
+
+impl OptionalU64 {
+    pub fn get(&self) -> Option<u64> {
+        (self.is_some != 0).then(|| self.value)
+    }
+}
+
+impl From<Option<u64>> for OptionalU64 {
+    fn from(value: Option<u64>) -> Self {
+        match value {
+            Some(value) => Self { is_some: 1, value },
+            None => Self::default(),
+        }
+    }
+}
+
+#[zero_copy]
+#[derive(Default)]
+pub struct OptionalPubkey {
+    pub is_some: u8,
+    pub value: Pubkey,
+}
+
+impl OptionalPubkey {
+    pub fn get(&self) -> Option<Pubkey> {
+        (self.is_some != 0).then(|| self.value)
+    }
+}
+
+impl From<Option<Pubkey>> for OptionalPubkey {
+    fn from(value: Option<Pubkey>) -> Self {
+        match value {
+            Some(value) => Self { is_some: 1, value },
+            None => Self::default(),
+        }
+    }
+}
+
+/// Loan terms with the interest model flattened into its variant and parameters.
+#[zero_copy]
+#[derive(Default)]
+pub struct PackedLoanTerms {
+    pub principal_amount: u64,
+    pub mint: Pubkey,
+    /// Variant of the interest model: 0 annual percentage rate, 1 fixed interest, 2 compounding
+    pub interest_model: u8,
+    /// Annual percentage rate in bps, or interest amount of the fixed interest model
+    pub interest_parameter: u64,
+    /// Compounding period of the compounding model
+    pub compounding_period: i64,
+    pub duration: i64,
+    pub minimum_period_ratio_bps: OptionalU64,
+}
+
+impl From<LoanTerms> for PackedLoanTerms {
+    fn from(terms: LoanTerms) -> Self {
+        let (interest_model, interest_parameter, compounding_period) = match terms.interest_model {
+            InterestModel::AnnualPercentageRate {
+                annual_percentage_rate_bps,
+            } => (0, annual_percentage_rate_bps, 0),
+            InterestModel::FixedInterest { interest_amount } => (1, interest_amount, 0),
+            InterestModel::CompoundingAnnualPercentageRate {
+                annual_percentage_rate_bps,
+                compounding_period,
+            } => (2, annual_percentage_rate_bps, compounding_period),
+        };
+
+        Self {
+            principal_amount: terms.principal_amount,
+            mint: terms.mint,
+            interest_model,
+            interest_parameter,
+            compounding_period,
+            duration: terms.duration,
+            minimum_period_ratio_bps: terms.minimum_period_ratio_bps.into(),
+        }
+    }
+}
+
+impl TryFrom<PackedLoanTerms> for LoanTerms {
+    type Error = anchor_lang::error::Error;
+
+    fn try_from(terms: PackedLoanTerms) -> Result<Self> {
+        let interest_model = match terms.interest_model {
+            0 => InterestModel::AnnualPercentageRate {
+                annual_percentage_rate_bps: terms.interest_parameter,
+            },
+            1 => InterestModel::FixedInterest {
+                interest_amount: terms.interest_parameter,
+            },
+            2 => InterestModel::CompoundingAnnualPercentageRate {
+                annual_percentage_rate_bps: terms.interest_parameter,
+                compounding_period: terms.compounding_period,
+            },
+            _ => return Err(error!(ErrorCode::InvalidPawnLoanLayout)),
+        };
+
+        Ok(Self {
+            principal_amount: terms.principal_amount,
+            mint: terms.mint,
+            interest_model,
+            duration: terms.duration,
+            minimum_period_ratio_bps: terms.minimum_period_ratio_bps.get(),
+        })
+    }
+}
+
+#[zero_copy]
+#[derive(Default)]
+pub struct OptionalLoanTerms {
+    pub is_some: u8,
+    pub value: PackedLoanTerms,
+}
+
+impl OptionalLoanTerms {
+    pub fn get(&self) -> Result<Option<LoanTerms>> {
+        match self.is_some {
+            0 => Ok(None),
+            _ => Ok(Some(self.value.try_into()?)),
+        }
+    }
+}
+
+impl From<Option<LoanTerms>> for OptionalLoanTerms {
+    fn from(terms: Option<LoanTerms>) -> Self {
+        match terms {
+            Some(terms) => Self {
+                is_some: 1,
+                value: terms.into(),
+            },
+            None => Self::default(),
+        }
+    }
+}
+
+#[zero_copy]
+#[derive(Default)]
+pub struct OptionalCreatorRoyalty {
+    pub is_some: u8,
+    pub royalty_bps: u64,
+    pub payout_address: OptionalPubkey,
+}
+
+impl OptionalCreatorRoyalty {
+    pub fn get(&self) -> Option<CreatorRoyalty> {
+        (self.is_some != 0).then(|| CreatorRoyalty {
+            royalty_bps: self.royalty_bps,
+            payout_address: self.payout_address.get(),
+        })
+    }
+}
+
+impl From<Option<CreatorRoyalty>> for OptionalCreatorRoyalty {
+    fn from(creator_royalty: Option<CreatorRoyalty>) -> Self {
+        match creator_royalty {
+            Some(creator_royalty) => Self {
+                is_some: 1,
+                royalty_bps: creator_royalty.royalty_bps,
+                payout_address: creator_royalty.payout_address.into(),
+            },
+            None => Self::default(),
+        }
+    }
+}
+
+/// The fixed fields keep the offsets of the borsh layout of the first version. Instructions load
+/// the account in place instead of deserializing it, `scripts/bench-compute-units.sh` measures
+/// the compute units against the borsh layout.
+#[account(zero_copy)]
+pub struct PawnLoan {
+    /// Layout of the account, `PAWN_LOAN_VERSION` once created or migrated
+    pub version: u8,
+    pub base: Pubkey,
+    pub bump: u8,
+    pub borrower: Pubkey,
+    pub pawn_token_account: Pubkey,
+    pub pawn_mint: Pubkey,
+    /// `LoanStatus` variant
+    pub status: u8,
+    pub lender: Pubkey,
+    pub desired_terms: OptionalLoanTerms,
+    pub terms: OptionalLoanTerms,
+    pub creation_time: i64,
+    pub start_time: i64,
+    pub end_time: i64,
+    /// Creator royalty of the registered collection at the time of the request
+    pub creator_royalty: OptionalCreatorRoyalty,
+    /// Front-end that referred the borrower
+    pub borrower_referrer: OptionalPubkey,
+    /// Front-end that referred the lender
+    pub lender_referrer: OptionalPubkey,
+    /// Admin fee tier resolved when the loan was underwritten
+    pub admin_fee_bps: u64,
+    /// Nonce of the address derived from the pawn, none for addresses derived from a base
+    pub seed_nonce: OptionalU64,
+    /// Room for new fields, which read as zero in accounts of earlier versions.
+    /// `PAWN_LOAN_RESERVED_SPACE` bytes, spelled out for the idl parser.
+    pub reserved: [u8; 119],
+}
+
+impl PawnLoan {
+    /// Accounts of earlier layouts are smaller and `load` panics on them, so contexts check the
+    /// size before any constraint loading the loan, has one constraints included.
+    pub fn space() -> usize {
+        8 + mem::size_of::<PawnLoan>()
+    }
+
+    pub fn status(&self) -> Result<LoanStatus> {
+        Ok(match self.status {
+            0 => LoanStatus::Open,
+            1 => LoanStatus::Active,
+            2 => LoanStatus::Repaid,
+            3 => LoanStatus::Defaulted,
+            _ => return Err(error!(ErrorCode::InvalidPawnLoanLayout)),
+        })
+    }
+
+    pub fn set_status(&mut self, status: LoanStatus) {
+        self.status = status as u8;
+    }
+
+    /// Seeds of the address, with which the pawn loan signs as delegate of the pawn.
+    pub fn seeds(&self) -> PawnLoanSeeds {
+        match self.seed_nonce.get() {
+            Some(nonce) => PawnLoanSeeds::PawnMint {
+                pawn_mint: self.pawn_mint,
+                borrower: self.borrower,
+                nonce: nonce.to_le_bytes(),
+                bump: [self.bump],
+            },
+            None => PawnLoanSeeds::Base {
+                base: self.base,
+                bump: [self.bump],
+            },
+        }
+    }
+
+    /// Records the seeds of the address, the pawn mint and borrower seeds being loan fields.
+    pub fn set_seeds(&mut self, seeds: &PawnLoanSeeds) {
+        match *seeds {
+            PawnLoanSeeds::Base { base, bump } => {
+                self.base = base;
+                self.bump = bump[0];
+                self.seed_nonce = None.into();
+            }
+            PawnLoanSeeds::PawnMint { nonce, bump, .. } => {
+                self.base = Pubkey::default();
+                self.bump = bump[0];
+                self.seed_nonce = Some(u64::from_le_bytes(nonce)).into();
+            }
+        }
+    }
+
+    pub fn creator_royalty_bps(&self) -> u64 {
+        self.creator_royalty
+            .get()
+            .map_or(0, |creator_royalty| creator_royalty.royalty_bps)
+    }
+
+    pub fn view(&self) -> Result<PawnLoanView> {
+        Ok(PawnLoanView {
+            version: self.version,
+            base: self.base,
+            bump: self.bump,
+            borrower: self.borrower,
+            pawn_token_account: self.pawn_token_account,
+            pawn_mint: self.pawn_mint,
+            status: self.status()?,
+            lender: self.lender,
+            desired_terms: self.desired_terms.get()?,
+            terms: self.terms.get()?,
+            creation_time: self.creation_time,
+            start_time: self.start_time,
+            end_time: self.end_time,
+            creator_royalty: self.creator_royalty.get(),
+            borrower_referrer: self.borrower_referrer.get(),
+            lender_referrer: self.lender_referrer.get(),
+            admin_fee_bps: self.admin_fee_bps,
+            seed_nonce: self.seed_nonce.get(),
+        })
+    }
+}
+
+impl From<&PawnLoanView> for PawnLoan {
+    fn from(view: &PawnLoanView) -> Self {
+        Self {
+            version: view.version,
+            base: view.base,
+            bump: view.bump,
+            borrower: view.borrower,
+            pawn_token_account: view.pawn_token_account,
+            pawn_mint: view.pawn_mint,
+            status: view.status as u8,
+            lender: view.lender,
+            desired_terms: view.desired_terms.into(),
+            terms: view.terms.into(),
+            creation_time: view.creation_time,
+            start_time: view.start_time,
+            end_time: view.end_time,
+            creator_royalty: view.creator_royalty.into(),
+            borrower_referrer: view.borrower_referrer.into(),
+            lender_referrer: view.lender_referrer.into(),
+            admin_fee_bps: view.admin_fee_bps,
+            seed_nonce: view.seed_nonce.into(),
+            reserved: [0; PAWN_LOAN_RESERVED_SPACE],
+        }
+    }
+}
+
+/// The pawn loan with its optional fields decoded, as emitted in the loan events. Its borsh
+/// layout is the one of the pawn loan account before the zero-copy layout, whose zeroed
+/// reserved space reads as no seed nonce.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq)]
+pub struct PawnLoanView {
+    pub version: u8,
+    pub base: Pubkey,
+    pub bump: u8,
+    pub borrower: Pubkey,
+    pub pawn_token_account: Pubkey,
+    pub pawn_mint: Pubkey,
+    pub status: LoanStatus,
+    pub lender: Pubkey,
+    pub desired_terms: Option<LoanTerms>,
+    pub terms: Option<LoanTerms>,
+    pub creation_time: i64,
+    pub start_time: i64,
+    pub end_time: i64,
+    pub creator_royalty: Option<CreatorRoyalty>,
+    pub borrower_referrer: Option<Pubkey>,
+    pub lender_referrer: Option<Pubkey>,
+    pub admin_fee_bps: u64,
+    pub seed_nonce: Option<u64>,
+}
+
+/// Seeds of a pawn loan address. Loans are addressed either from a base keypair signing the
+/// request, or from the pawn so that clients can look the loans of an nft up.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PawnLoanSeeds {
+    /// `[base, "pawn_loan"]`
+    Base { base: Pubkey, bump: [u8; 1] },
+    /// `[pawn_mint, borrower, nonce, "pawn_loan"]`, the nonce telling apart the successive
+    /// loans of the borrower on the pawn
+    PawnMint {
+        pawn_mint: Pubkey,
+        borrower: Pubkey,
+        nonce: [u8; 8],
+        bump: [u8; 1],
+    },
+}
+
+impl PawnLoanSeeds {
+    pub fn signer_seeds(&self) -> Vec<&[u8]> {
+        match self {
+            Self::Base { base, bump } => vec![base.as_ref(), b"pawn_loan".as_ref(), bump],
+            Self::PawnMint {
+                pawn_mint,
+                borrower,
+                nonce,
+                bump,
+            } => vec![
+                pawn_mint.as_ref(),
+                borrower.as_ref(),
+                nonce,
+                b"pawn_loan".as_ref(),
+                bump,
+            ],
+        }
+    }
+}
+
+pub fn find_pawn_loan_address(pawn_mint: &Pubkey, borrower: &Pubkey, nonce: u64) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[
+            pawn_mint.as_ref(),
+            borrower.as_ref(),
+            nonce.to_le_bytes().as_ref(),
+            b"pawn_loan".as_ref(),
+        ],
+        &crate::ID,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn view(status: LoanStatus) -> PawnLoanView {
+        let terms = LoanTerms {
+            principal_amount: 1_000_000_000,
+            mint: Pubkey::new_unique(),
+            interest_model: InterestModel::CompoundingAnnualPercentageRate {
+                annual_percentage_rate_bps: 3_500,
+                compounding_period: 24 * 60 * 60,
+            },
+            duration: 7 * 24 * 60 * 60,
+            minimum_period_ratio_bps: Some(1_000),
+        };
+
+        PawnLoanView {
+            version: PAWN_LOAN_VERSION,
+            base: Pubkey::new_unique(),
+            bump: 254,
+            borrower: Pubkey::new_unique(),
+            pawn_token_account: Pubkey::new_unique(),
+            pawn_mint: Pubkey::new_unique(),
+            status,
+            lender: Pubkey::new_unique(),
+            desired_terms: Some(terms),
+            terms: Some(LoanTerms {
+                interest_model: InterestModel::FixedInterest {
+                    interest_amount: 42,
+                },
+                minimum_period_ratio_bps: None,
+                ..terms
+            }),
+            creation_time: 1_650_000_000,
+            start_time: 1_650_000_100,
+            end_time: 0,
+            creator_royalty: Some(CreatorRoyalty {
+                royalty_bps: 500,
+                payout_address: Some(Pubkey::new_unique()),
+            }),
+            borrower_referrer: None,
+            lender_referrer: Some(Pubkey::new_unique()),
+            admin_fee_bps: 150,
+            seed_nonce: None,
+        }
+    }
+
+    #[test]
+    fn pawn_loan_round_trips_through_the_view() {
+        for status in [
+            LoanStatus::Open,
+            LoanStatus::Active,
+            LoanStatus::Repaid,
+            LoanStatus::Defaulted,
+        ] {
+            let view = view(status);
+            assert!(PawnLoan::from(&view).view().unwrap() == view);
+        }
+
+        let mut view = view(LoanStatus::Open);
+        view.terms = None;
+        view.creator_royalty = None;
+        view.seed_nonce = Some(3);
+        let pawn_loan = PawnLoan::from(&view);
+        assert!(pawn_loan.view().unwrap() == view);
+        assert_eq!(0, pawn_loan.creator_royalty_bps());
+    }
+
+    #[test]
+    fn pawn_loan_keeps_the_size_of_the_borsh_layout() {
+        // Largest encoding of the first versioned layout, options included
+        assert_eq!(589, PawnLoan::space());
+    }
+
+    #[test]
+    fn invalid_variants_are_rejected() {
+        let mut pawn_loan = PawnLoan::from(&view(LoanStatus::Active));
+
+        pawn_loan.terms.value.interest_model = 3;
+        assert!(pawn_loan.terms.get().is_err());
+
+        pawn_loan.status = 4;
+        assert!(pawn_loan.status().is_err());
+    }
+
+    #[test]
+    fn pawn_loan_signs_with_the_seeds_of_its_address() {
+        let mut pawn_loan = PawnLoan::from(&view(LoanStatus::Open));
+        let (address, bump) = find_pawn_loan_address(&pawn_loan.pawn_mint, &pawn_loan.borrower, 7);
+
+        pawn_loan.set_seeds(&PawnLoanSeeds::PawnMint {
+            pawn_mint: pawn_loan.pawn_mint,
+            borrower: pawn_loan.borrower,
+            nonce: 7u64.to_le_bytes(),
+            bump: [bump],
+        });
+        assert_eq!(Some(7), pawn_loan.view().unwrap().seed_nonce);
+        assert_eq!(
+            address,
+            Pubkey::create_program_address(&pawn_loan.seeds().signer_seeds(), &crate::ID).unwrap()
+        );
+
+        let base = Pubkey::new_unique();
+        let (address, bump) =
+            Pubkey::find_program_address(&[base.as_ref(), b"pawn_loan".as_ref()], &crate::ID);
+        pawn_loan.set_seeds(&PawnLoanSeeds::Base { base, bump: [bump] });
+        assert_eq!(None, pawn_loan.seed_nonce.get());
+        assert_eq!(
+            address,
+            Pubkey::create_program_address(&pawn_loan.seeds().signer_seeds(), &crate::ID).unwrap()
+        );
+    }
+}
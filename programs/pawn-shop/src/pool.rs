@@ -0,0 +1,509 @@
+//! Lending pools: depositors provide liquidity in the pool mint against LP shares,
+//! the pool manager underwrites loans from the pool vault, or anyone does when
+//! the loan matches the pool underwriting policy.
+//!
+//! Depositors trust the manager with the pawns seized by the pool: the manager
+//! withdraws them to its associated token account to sell them, and is expected
+//! to deposit the proceeds back into the vault. Each withdrawal emits
+//! `PoolPawnWithdrawn` for depositors to follow.
+
+use std::convert::TryInto;
+
+use anchor_lang::prelude::*;
+use anchor_spl::{
+    associated_token::get_associated_token_address,
+    token::{self, Mint, Token, TokenAccount},
+};
+use vipers::prelude::*;
+
+use crate::math::mul_div_floor;
+use crate::{
+    compute_nominal_annual_percentage_rate_bps, ErrorCode, LoanStatus, LoanTerms, MplTokenMetadata,
+    PawnLoan, UnderwritingFees, PAWN_LOAN_VERSION,
+};
+
+#[account]
+#[derive(Copy)]
+pub struct LendingPool {
+    pub base: Pubkey,
+    pub bump: u8,
+    pub manager: Pubkey,
+    /// Mint of the liquidity, loans funded by the pool must be denominated in this mint
+    pub mint: Pubkey,
+    /// Token account holding the idle liquidity
+    pub vault: Pubkey,
+    /// Mint of the LP shares
+    pub lp_mint: Pubkey,
+    /// Principal lent out by the pool and not yet repaid or defaulted
+    pub outstanding_principal: u64,
+    /// Terms under which anyone can underwrite loans from the pool
+    pub underwriting_policy: Option<UnderwritingPolicy>,
+}
+
+impl LendingPool {
+    pub fn space() -> usize {
+        8 + 32 + 1 + 32 + 32 + 32 + 32 + 8 + 1 + UnderwritingPolicy::space()
+    }
+
+    /// Value of the pool, idle liquidity plus the principal of active loans.
+    pub fn total_value(&self, idle_liquidity: u64) -> Option<u64> {
+        idle_liquidity.checked_add(self.outstanding_principal)
+    }
+}
+
+#[derive(Clone, Copy, AnchorSerialize, AnchorDeserialize, PartialEq)]
+pub struct UnderwritingPolicy {
+    pub min_annual_percentage_rate_bps: u64,
+    pub max_annual_percentage_rate_bps: u64,
+    pub max_duration: i64,
+}
+
+impl UnderwritingPolicy {
+    fn space() -> usize {
+        8 + 8 + 8
+    }
+
+    pub fn validate_terms(&self, terms: &LoanTerms) -> Result<()> {
+        let annual_percentage_rate_bps =
+            compute_nominal_annual_percentage_rate_bps(terms).ok_or(ErrorCode::CalculationError)?;
+        invariant!(
+            annual_percentage_rate_bps >= self.min_annual_percentage_rate_bps,
+            UnderwritingPolicyViolation
+        );
+        invariant!(
+            annual_percentage_rate_bps <= self.max_annual_percentage_rate_bps,
+            UnderwritingPolicyViolation
+        );
+        invariant!(
+            terms.duration <= self.max_duration,
+            UnderwritingPolicyViolation
+        );
+
+        Ok(())
+    }
+}
+
+/// Verified collection whose pawns the pool underwrites automatically.
+#[account]
+pub struct PoolCollection {
+    pub lending_pool: Pubkey,
+    pub collection: Pubkey,
+    pub max_principal_amount: u64,
+}
+
+impl PoolCollection {
+    pub fn space() -> usize {
+        8 + 32 + 32 + 8
+    }
+}
+
+/// Starts the loan with the pool as the lender and sends the principal from the pool vault.
+pub(crate) fn fund_loan_from_pool<'info>(
+    pawn_loan: &mut PawnLoan,
+    fees: UnderwritingFees,
+    lending_pool: &mut Account<'info, LendingPool>,
+    vault: &Account<'info, TokenAccount>,
+    borrower_payment_account: &Account<'info, TokenAccount>,
+    token_program: &Program<'info, Token>,
+) -> Result<()> {
+    let unix_timestamp = Clock::get()?.unix_timestamp;
+
+    invariant!(pawn_loan.status()? == LoanStatus::Open, InvalidLoanStatus);
+
+    let terms = unwrap_opt!(pawn_loan.desired_terms.get()?);
+    assert_keys_eq!(terms.mint, lending_pool.mint, InvalidPoolMint);
+    assert_keys_eq!(pawn_loan.borrower, borrower_payment_account.owner);
+
+    pawn_loan.set_status(LoanStatus::Active);
+    pawn_loan.start_time = unix_timestamp;
+    pawn_loan.lender = lending_pool.key();
+    pawn_loan.admin_fee_bps = fees.admin_fee_bps;
+    pawn_loan.terms = Some(terms).into();
+
+    lending_pool.outstanding_principal = unwrap_int!(lending_pool
+        .outstanding_principal
+        .checked_add(terms.principal_amount));
+
+    token::transfer(
+        CpiContext::new_with_signer(
+            token_program.to_account_info(),
+            token::Transfer {
+                from: vault.to_account_info(),
+                to: borrower_payment_account.to_account_info(),
+                authority: lending_pool.to_account_info(),
+            },
+            &[&[
+                lending_pool.base.as_ref(),
+                b"lending_pool".as_ref(),
+                &[lending_pool.bump],
+            ]],
+        ),
+        // The origination fee is taken from the principal, the caller collecting it from the vault
+        unwrap_int!(terms.principal_amount.checked_sub(fees.origination_fee)),
+    )
+}
+
+/// Shares locked in the pool by the first deposit. The share supply never falls below them, so that
+/// donations to the vault cannot raise the share price until later deposits round down to nothing.
+pub const LOCKED_POOL_SHARES: u64 = 1_000;
+
+/// Shares minted to the depositor and shares locked in the pool for a deposit. The first deposit
+/// locks `LOCKED_POOL_SHARES` out of its shares.
+pub fn compute_deposit_shares(
+    amount: u64,
+    total_value: u64,
+    share_supply: u64,
+) -> Option<(u64, u64)> {
+    let shares = compute_shares_for_deposit(amount, total_value, share_supply)?;
+    if share_supply == 0 {
+        return Some((
+            shares.saturating_sub(LOCKED_POOL_SHARES),
+            LOCKED_POOL_SHARES,
+        ));
+    }
+
+    Some((shares, 0))
+}
+
+/// Shares minted for a deposit, rounded down in favour of the pool.
+pub fn compute_shares_for_deposit(amount: u64, total_value: u64, share_supply: u64) -> Option<u64> {
+    if share_supply == 0 {
+        return Some(amount);
+    }
+    mul_div_floor(amount.into(), share_supply.into(), total_value.into())?
+        .try_into()
+        .ok()
+}
+
+/// Liquidity returned when burning shares, rounded down in favour of the pool.
+pub fn compute_withdrawal_amount(shares: u64, total_value: u64, share_supply: u64) -> Option<u64> {
+    mul_div_floor(shares.into(), total_value.into(), share_supply.into())?
+        .try_into()
+        .ok()
+}
+
+#[derive(Accounts)]
+pub struct CreateLendingPool<'info> {
+    pub base: Signer<'info>,
+    #[account(init, seeds = [base.key.as_ref(), b"lending_pool".as_ref()], bump, payer = manager, space = LendingPool::space())]
+    pub lending_pool: Account<'info, LendingPool>,
+    #[account(mut)]
+    pub manager: Signer<'info>,
+    pub mint: Account<'info, Mint>,
+    #[account(init, seeds = [lending_pool.key().as_ref(), b"vault".as_ref()], bump, payer = manager, token::mint = mint, token::authority = lending_pool)]
+    pub vault: Account<'info, TokenAccount>,
+    #[account(init, seeds = [lending_pool.key().as_ref(), b"lp_mint".as_ref()], bump, payer = manager, mint::decimals = mint.decimals, mint::authority = lending_pool)]
+    pub lp_mint: Account<'info, Mint>,
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct DepositPoolLiquidity<'info> {
+    #[account(has_one = vault, has_one = lp_mint)]
+    pub lending_pool: Account<'info, LendingPool>,
+    #[account(mut)]
+    pub vault: Account<'info, TokenAccount>,
+    #[account(mut)]
+    pub lp_mint: Account<'info, Mint>,
+    pub depositor: Signer<'info>,
+    /// Sends the liquidity, the token program enforces it matches the vault mint
+    #[account(mut)]
+    pub depositor_payment_account: Account<'info, TokenAccount>,
+    /// Receives the LP shares, the token program enforces it matches the LP mint
+    #[account(mut)]
+    pub depositor_lp_token_account: Account<'info, TokenAccount>,
+    /// CHECK: LP token account owned by the pool, receiving the shares locked by the first deposit.
+    /// Only checked on the first deposit
+    #[account(mut)]
+    pub locked_shares_account: UncheckedAccount<'info>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawPoolLiquidity<'info> {
+    #[account(has_one = vault, has_one = lp_mint)]
+    pub lending_pool: Account<'info, LendingPool>,
+    #[account(mut)]
+    pub vault: Account<'info, TokenAccount>,
+    #[account(mut)]
+    pub lp_mint: Account<'info, Mint>,
+    pub depositor: Signer<'info>,
+    /// Receives the liquidity, the token program enforces it matches the vault mint
+    #[account(mut)]
+    pub depositor_payment_account: Account<'info, TokenAccount>,
+    /// Burns the LP shares, the token program enforces it matches the LP mint
+    #[account(mut)]
+    pub depositor_lp_token_account: Account<'info, TokenAccount>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+#[instruction(expected_terms: LoanTerms)]
+pub struct UnderwriteLoanFromPool<'info> {
+    #[account(mut, constraint = pawn_loan.to_account_info().data_len() == PawnLoan::space() @ ErrorCode::PawnLoanNotMigrated, constraint = pawn_loan.load()?.version == PAWN_LOAN_VERSION @ ErrorCode::PawnLoanNotMigrated)]
+    pub pawn_loan: AccountLoader<'info, PawnLoan>,
+    #[account(mut, has_one = manager, has_one = vault)]
+    pub lending_pool: Account<'info, LendingPool>,
+    /// Pays the rent of the fee ledger if created
+    #[account(mut)]
+    pub manager: Signer<'info>,
+    #[account(mut)]
+    pub vault: Account<'info, TokenAccount>,
+    /// Receives the principal, the token program enforces it matches the vault mint
+    #[account(mut)]
+    pub borrower_payment_account: Account<'info, TokenAccount>,
+    /// CHECK: Fee schedule of the loan mint, empty if the mint has none
+    #[account(seeds = [expected_terms.mint.as_ref(), b"fee_schedule".as_ref()], bump)]
+    pub fee_schedule: UncheckedAccount<'info>,
+    #[account(seeds = [b"admin"], bump)]
+    pub admin: SystemAccount<'info>,
+    /// CHECK: Receives the origination fee, a spl token account owned by the admin pda
+    #[account(mut)]
+    pub admin_payment_account: UncheckedAccount<'info>,
+    /// CHECK: Fee ledger of the loan mint, created if empty
+    #[account(mut, seeds = [expected_terms.mint.as_ref(), b"fee_ledger".as_ref()], bump)]
+    pub fee_ledger: UncheckedAccount<'info>,
+    /// CHECK: Loan history of the borrower, created if empty
+    #[account(mut, seeds = [pawn_loan.load()?.borrower.as_ref(), b"borrower_stats".as_ref()], bump)]
+    pub borrower_stats: UncheckedAccount<'info>,
+    /// CHECK: Loan history of the lender, created if empty
+    #[account(mut, seeds = [lending_pool.key().as_ref(), b"lender_stats".as_ref()], bump)]
+    pub lender_stats: UncheckedAccount<'info>,
+    /// CHECK: Protocol statistics, skipped until created
+    #[account(mut, seeds = [b"protocol_stats".as_ref()], bump)]
+    pub protocol_stats: UncheckedAccount<'info>,
+    /// CHECK: Statistics of the loan mint, skipped if empty. Address checked in the handler
+    #[account(mut)]
+    pub mint_stats: UncheckedAccount<'info>,
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetPoolUnderwritingPolicy<'info> {
+    #[account(mut, has_one = manager)]
+    pub lending_pool: Account<'info, LendingPool>,
+    pub manager: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(collection: Pubkey)]
+pub struct AddPoolCollection<'info> {
+    #[account(has_one = manager)]
+    pub lending_pool: Account<'info, LendingPool>,
+    #[account(init, seeds = [lending_pool.key().as_ref(), collection.as_ref(), b"pool_collection".as_ref()], bump, payer = manager, space = PoolCollection::space())]
+    pub pool_collection: Account<'info, PoolCollection>,
+    #[account(mut)]
+    pub manager: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RemovePoolCollection<'info> {
+    #[account(has_one = manager)]
+    pub lending_pool: Account<'info, LendingPool>,
+    #[account(mut, has_one = lending_pool, close = manager)]
+    pub pool_collection: Account<'info, PoolCollection>,
+    #[account(mut)]
+    pub manager: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct UnderwriteFromPool<'info> {
+    #[account(mut, constraint = pawn_loan.to_account_info().data_len() == PawnLoan::space() @ ErrorCode::PawnLoanNotMigrated, constraint = pawn_loan.load()?.version == PAWN_LOAN_VERSION @ ErrorCode::PawnLoanNotMigrated)]
+    pub pawn_loan: AccountLoader<'info, PawnLoan>,
+    #[account(mut, has_one = vault)]
+    pub lending_pool: Account<'info, LendingPool>,
+    #[account(has_one = lending_pool)]
+    pub pool_collection: Account<'info, PoolCollection>,
+    /// CHECK: Validated against the pawn mint when loaded
+    pub pawn_metadata: UncheckedAccount<'info>,
+    #[account(mut)]
+    pub vault: Account<'info, TokenAccount>,
+    /// Receives the principal, the token program enforces it matches the vault mint
+    #[account(mut)]
+    pub borrower_payment_account: Account<'info, TokenAccount>,
+    /// CHECK: Fee schedule of the loan mint, empty if the mint has none. Address checked in the handler
+    pub fee_schedule: UncheckedAccount<'info>,
+    #[account(seeds = [b"admin"], bump)]
+    pub admin: SystemAccount<'info>,
+    /// CHECK: Receives the origination fee, a spl token account owned by the admin pda
+    #[account(mut)]
+    pub admin_payment_account: UncheckedAccount<'info>,
+    /// CHECK: Fee ledger of the loan mint, created if empty
+    #[account(mut)]
+    pub fee_ledger: UncheckedAccount<'info>,
+    /// Pays the rent of the fee ledger if created
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    /// CHECK: Loan history of the borrower, created if empty
+    #[account(mut, seeds = [pawn_loan.load()?.borrower.as_ref(), b"borrower_stats".as_ref()], bump)]
+    pub borrower_stats: UncheckedAccount<'info>,
+    /// CHECK: Loan history of the lender, created if empty
+    #[account(mut, seeds = [lending_pool.key().as_ref(), b"lender_stats".as_ref()], bump)]
+    pub lender_stats: UncheckedAccount<'info>,
+    /// CHECK: Protocol statistics, skipped until created
+    #[account(mut, seeds = [b"protocol_stats".as_ref()], bump)]
+    pub protocol_stats: UncheckedAccount<'info>,
+    /// CHECK: Statistics of the loan mint, skipped if empty. Address checked in the handler
+    #[account(mut)]
+    pub mint_stats: UncheckedAccount<'info>,
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SeizePawnForPool<'info> {
+    #[account(mut, constraint = pawn_loan.to_account_info().data_len() == PawnLoan::space() @ ErrorCode::PawnLoanNotMigrated, constraint = pawn_loan.load()?.lender == lending_pool.key(), constraint = pawn_loan.load()?.pawn_token_account == pawn_token_account.key() @ anchor_lang::error::ErrorCode::ConstraintHasOne, constraint = pawn_loan.load()?.pawn_mint == pawn_mint.key() @ anchor_lang::error::ErrorCode::ConstraintHasOne, constraint = pawn_loan.load()?.version == PAWN_LOAN_VERSION @ ErrorCode::PawnLoanNotMigrated)]
+    pub pawn_loan: AccountLoader<'info, PawnLoan>,
+    #[account(mut)]
+    pub lending_pool: Account<'info, LendingPool>,
+    #[account(mut)]
+    pub pawn_token_account: Account<'info, TokenAccount>,
+    #[account(mut)]
+    pub pawn_mint: Account<'info, Mint>,
+    /// CHECK: Validated by the cpi to mpl token metadata
+    pub edition: UncheckedAccount<'info>,
+    #[account(mut, token::mint = pawn_mint, token::authority = lending_pool)]
+    pub pool_pawn_token_account: Account<'info, TokenAccount>,
+    /// CHECK: Protocol statistics, skipped until created
+    #[account(mut, seeds = [b"protocol_stats".as_ref()], bump)]
+    pub protocol_stats: UncheckedAccount<'info>,
+    /// CHECK: Statistics of the loan mint, skipped if empty. Address checked in the handler
+    #[account(mut)]
+    pub mint_stats: UncheckedAccount<'info>,
+    /// Pays the rent of the loan history if created
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    /// CHECK: Loan history of the borrower, created if empty for loans underwritten before it
+    #[account(mut, seeds = [pawn_loan.load()?.borrower.as_ref(), b"borrower_stats".as_ref()], bump)]
+    pub borrower_stats: UncheckedAccount<'info>,
+    /// CHECK: Loan history of the lender, created if empty for loans underwritten before it
+    #[account(mut, seeds = [lending_pool.key().as_ref(), b"lender_stats".as_ref()], bump)]
+    pub lender_stats: UncheckedAccount<'info>,
+    pub token_program: Program<'info, Token>,
+    pub mpl_token_metadata_program: Program<'info, MplTokenMetadata>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawPoolPawn<'info> {
+    #[account(has_one = manager)]
+    pub lending_pool: Account<'info, LendingPool>,
+    pub manager: Signer<'info>,
+    /// Holds a pawn seized by the pool, neither the liquidity nor the LP shares of the pool
+    #[account(mut, token::authority = lending_pool, constraint = pool_pawn_token_account.mint != lending_pool.mint @ ErrorCode::InvalidPoolPawnAccount, constraint = pool_pawn_token_account.mint != lending_pool.lp_mint @ ErrorCode::InvalidPoolPawnAccount)]
+    pub pool_pawn_token_account: Account<'info, TokenAccount>,
+    /// Receives the pawn, only the associated token account of the manager
+    #[account(mut, address = get_associated_token_address(manager.key, &pool_pawn_token_account.mint))]
+    pub manager_pawn_token_account: Account<'info, TokenAccount>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[event]
+pub struct PoolLiquidityDeposited {
+    pub schema_version: u8,
+    pub lending_pool_address: Pubkey,
+    pub depositor: Pubkey,
+    pub amount: u64,
+    pub shares: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct PoolLiquidityWithdrawn {
+    pub schema_version: u8,
+    pub lending_pool_address: Pubkey,
+    pub depositor: Pubkey,
+    pub amount: u64,
+    pub shares: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct PoolPawnWithdrawn {
+    pub schema_version: u8,
+    pub lending_pool_address: Pubkey,
+    pub pawn_mint: Pubkey,
+    pub manager_pawn_token_account: Pubkey,
+    pub timestamp: i64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_deposit_mints_shares_one_to_one() {
+        assert_eq!(Some(1_000), compute_shares_for_deposit(1_000, 0, 0));
+    }
+
+    #[test]
+    fn first_deposit_locks_shares_in_the_pool() {
+        assert_eq!(
+            Some((9_000, LOCKED_POOL_SHARES)),
+            compute_deposit_shares(10_000, 0, 0)
+        );
+        assert_eq!(
+            Some((0, LOCKED_POOL_SHARES)),
+            compute_deposit_shares(1_000, 0, 0)
+        );
+        assert_eq!(Some((200, 0)), compute_deposit_shares(300, 1_500, 1_000));
+
+        // Attacker holding all but the locked shares donates to the vault: the next deposit still
+        // gets shares until the donation reaches a thousand times the deposit
+        let share_supply = LOCKED_POOL_SHARES + 1;
+        let donation = 999_999;
+        assert_eq!(
+            Some((1, 0)),
+            compute_deposit_shares(1_000, 1_001 + donation, share_supply)
+        );
+    }
+
+    #[test]
+    fn deposit_and_withdrawal_follow_share_price() {
+        // Pool earned 50% interest: 1_000 shares worth 1_500
+        assert_eq!(Some(200), compute_shares_for_deposit(300, 1_500, 1_000));
+        assert_eq!(Some(300), compute_withdrawal_amount(200, 1_800, 1_200));
+
+        // Rounding favours the pool
+        assert_eq!(Some(0), compute_shares_for_deposit(1, 1_500, 1_000));
+        assert_eq!(Some(1), compute_withdrawal_amount(1, 1_500, 1_000));
+    }
+
+    #[test]
+    fn underwriting_policy_validates_terms() {
+        let policy = UnderwritingPolicy {
+            min_annual_percentage_rate_bps: 1_000,
+            max_annual_percentage_rate_bps: 5_000,
+            max_duration: 30 * 24 * 60 * 60,
+        };
+        let terms = |annual_percentage_rate_bps, duration| LoanTerms {
+            principal_amount: 1_000_000,
+            mint: Pubkey::default(),
+            interest_model: crate::InterestModel::AnnualPercentageRate {
+                annual_percentage_rate_bps,
+            },
+            duration,
+            minimum_period_ratio_bps: None,
+        };
+
+        assert!(policy.validate_terms(&terms(1_000, 24 * 60 * 60)).is_ok());
+        assert!(policy
+            .validate_terms(&terms(5_000, 30 * 24 * 60 * 60))
+            .is_ok());
+        assert!(policy.validate_terms(&terms(999, 24 * 60 * 60)).is_err());
+        assert!(policy.validate_terms(&terms(5_001, 24 * 60 * 60)).is_err());
+        assert!(policy
+            .validate_terms(&terms(1_000, 30 * 24 * 60 * 60 + 1))
+            .is_err());
+    }
+
+    #[test]
+    fn deposit_into_insolvent_pool_fails() {
+        // All the liquidity was lost to defaults
+        assert_eq!(None, compute_shares_for_deposit(1_000, 0, 1_000));
+    }
+}
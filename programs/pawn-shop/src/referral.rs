@@ -0,0 +1,269 @@
+//! Referral fees: front-ends registered by the admin and referring the borrower or the lender of a
+//! loan earn a share of the admin fee when the loan is repaid. The fees stay in the admin pda,
+//! credited to the referrer until claimed, so that repayments never pay an account chosen by a
+//! party of the loan.
+
+use anchor_lang::prelude::*;
+use anchor_spl::token::Token;
+use vipers::prelude::*;
+
+use crate::{create_pda_account, fee_collector, math::mul_div_floor, ErrorCode, FeeLedger};
+
+/// Each referrer gets at most half of the admin fee, so that both together never exceed it
+pub const MAX_REFERRAL_FEE_SHARE_BPS: u64 = 5_000; // 50%
+
+#[account]
+pub struct ReferralConfig {
+    pub bump: u8,
+    /// Share of the admin fee paid to each referrer of a loan
+    pub referral_fee_share_bps: u64,
+}
+
+impl ReferralConfig {
+    pub fn space() -> usize {
+        8 + 1 + 8
+    }
+}
+
+/// Share of the admin fee paid to each referrer, none until the referral config is created.
+pub fn load_referral_fee_share_bps(referral_config_info: &AccountInfo) -> Result<u64> {
+    if referral_config_info.data_is_empty() {
+        return Ok(0);
+    }
+    let referral_config: Account<ReferralConfig> = Account::try_from(referral_config_info)?;
+
+    Ok(referral_config.referral_fee_share_bps)
+}
+
+/// Front-end allowed to refer loans.
+#[account]
+pub struct RegisteredReferrer {
+    pub referrer: Pubkey,
+}
+
+impl RegisteredReferrer {
+    pub fn space() -> usize {
+        8 + 32
+    }
+}
+
+/// Referral fees earned by a referrer in a mint, held by the admin pda until claimed.
+#[account]
+#[derive(Default)]
+pub struct ReferralEarnings {
+    pub referrer: Pubkey,
+    pub mint: Pubkey,
+    pub bump: u8,
+    pub unclaimed: u64,
+    pub total_earned: u64,
+}
+
+impl ReferralEarnings {
+    pub fn space() -> usize {
+        8 + 32 + 32 + 1 + 8 + 8
+    }
+
+    pub fn record_earned(&mut self, amount: u64) -> Result<()> {
+        self.unclaimed = unwrap_int!(self.unclaimed.checked_add(amount));
+        self.total_earned = unwrap_int!(self.total_earned.checked_add(amount));
+        Ok(())
+    }
+}
+
+pub fn find_referral_earnings_address(referrer: &Pubkey, mint: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[
+            referrer.as_ref(),
+            mint.as_ref(),
+            b"referral_earnings".as_ref(),
+        ],
+        &crate::ID,
+    )
+}
+
+pub fn compute_referral_fee(admin_fee: u64, referral_fee_share_bps: u64) -> Option<u64> {
+    mul_div_floor(admin_fee.into(), referral_fee_share_bps.into(), 10_000)?
+        .try_into()
+        .ok()
+}
+
+/// Checks that the referrer is registered and is not the party it refers. The registered referrer
+/// is expected as the last remaining account when there is a referrer. Returns the remaining
+/// accounts left.
+pub(crate) fn validate_referrer<'a, 'info>(
+    referrer: Option<&Pubkey>,
+    party: &Pubkey,
+    remaining_accounts: &'a [AccountInfo<'info>],
+) -> Result<&'a [AccountInfo<'info>]> {
+    let referrer = match referrer {
+        Some(referrer) => referrer,
+        None => return Ok(remaining_accounts),
+    };
+    assert_keys_neq!(*referrer, *party, InvalidReferrer);
+
+    let (registered_referrer_info, remaining_accounts) =
+        unwrap_opt!(remaining_accounts.split_last(), ReferrerNotRegistered);
+    let registered_referrer: Account<RegisteredReferrer> =
+        Account::try_from(registered_referrer_info)?;
+    assert_keys_eq!(
+        registered_referrer.referrer,
+        *referrer,
+        ReferrerNotRegistered
+    );
+
+    Ok(remaining_accounts)
+}
+
+/// Credits the referral fee to the earnings of each referrer, created at the expense of the payer.
+/// The earnings accounts are expected first in the remaining accounts, in the order of the
+/// referrers. The fee ledger keeps the credited fees in the admin pda until claimed. Returns the
+/// total amount credited and the remaining accounts left.
+pub(crate) fn credit_referral_fees<'a, 'info>(
+    referrers: &[Option<Pubkey>],
+    referral_fee: u64,
+    mint: &Pubkey,
+    remaining_accounts: &'a [AccountInfo<'info>],
+    fee_ledger: &mut FeeLedger,
+    payer: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+) -> Result<(u64, &'a [AccountInfo<'info>])> {
+    let mut remaining_accounts = remaining_accounts;
+    let mut credited = 0u64;
+
+    for referrer in referrers.iter().flatten() {
+        let (referral_earnings_info, rest) =
+            unwrap_opt!(remaining_accounts.split_first(), InvalidReferralEarnings);
+        remaining_accounts = rest;
+
+        let mut referral_earnings = load_or_create_referral_earnings(
+            referral_earnings_info,
+            referrer,
+            mint,
+            payer,
+            system_program,
+        )?;
+        referral_earnings.record_earned(referral_fee)?;
+        referral_earnings.exit(&crate::ID)?;
+        credited = unwrap_int!(credited.checked_add(referral_fee));
+    }
+    fee_ledger.record_referral_fees(credited)?;
+
+    Ok((credited, remaining_accounts))
+}
+
+fn load_or_create_referral_earnings<'info>(
+    referral_earnings_info: &AccountInfo<'info>,
+    referrer: &Pubkey,
+    mint: &Pubkey,
+    payer: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+) -> Result<Account<'info, ReferralEarnings>> {
+    let (address, bump) = find_referral_earnings_address(referrer, mint);
+    assert_keys_eq!(
+        address,
+        *referral_earnings_info.key,
+        InvalidReferralEarnings
+    );
+
+    if !referral_earnings_info.data_is_empty() {
+        return Account::try_from(referral_earnings_info);
+    }
+
+    create_pda_account(
+        referral_earnings_info,
+        ReferralEarnings::space(),
+        &[&[
+            referrer.as_ref(),
+            mint.as_ref(),
+            b"referral_earnings".as_ref(),
+            &[bump],
+        ]],
+        payer,
+        system_program,
+    )?;
+    let mut referral_earnings: Account<ReferralEarnings> =
+        Account::try_from_unchecked(referral_earnings_info)?;
+    referral_earnings.referrer = *referrer;
+    referral_earnings.mint = *mint;
+    referral_earnings.bump = bump;
+
+    Ok(referral_earnings)
+}
+
+#[derive(Accounts)]
+pub struct InitReferralConfig<'info> {
+    #[account(init, seeds = [b"referral_config".as_ref()], bump, payer = fee_collector, space = ReferralConfig::space())]
+    pub referral_config: Account<'info, ReferralConfig>,
+    #[account(mut, address = fee_collector::ID)]
+    pub fee_collector: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetReferralFeeShare<'info> {
+    #[account(mut, seeds = [b"referral_config".as_ref()], bump = referral_config.bump)]
+    pub referral_config: Account<'info, ReferralConfig>,
+    #[account(address = fee_collector::ID)]
+    pub fee_collector: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(referrer: Pubkey)]
+pub struct RegisterReferrer<'info> {
+    #[account(init, seeds = [b"registered_referrer".as_ref(), referrer.as_ref()], bump, payer = fee_collector, space = RegisteredReferrer::space())]
+    pub registered_referrer: Account<'info, RegisteredReferrer>,
+    #[account(mut, address = fee_collector::ID)]
+    pub fee_collector: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UnregisterReferrer<'info> {
+    #[account(mut, close = fee_collector)]
+    pub registered_referrer: Account<'info, RegisteredReferrer>,
+    #[account(mut, address = fee_collector::ID)]
+    pub fee_collector: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimReferralFees<'info> {
+    #[account(mut, has_one = referrer, seeds = [referrer.key().as_ref(), referral_earnings.mint.as_ref(), b"referral_earnings".as_ref()], bump = referral_earnings.bump)]
+    pub referral_earnings: Account<'info, ReferralEarnings>,
+    pub referrer: Signer<'info>,
+    /// CHECK: Receives the referral fees, can be the referrer wallet or his spl token account
+    #[account(mut)]
+    pub referrer_payment_account: UncheckedAccount<'info>,
+    #[account(mut, seeds = [b"admin"], bump)]
+    pub admin: SystemAccount<'info>,
+    /// CHECK: Sends the referral fees, can be the admin pda or a spl token account owned by the admin pda
+    #[account(mut)]
+    pub admin_payment_account: UncheckedAccount<'info>,
+    #[account(mut, seeds = [referral_earnings.mint.as_ref(), b"fee_ledger".as_ref()], bump = fee_ledger.bump)]
+    pub fee_ledger: Account<'info, FeeLedger>,
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{compute_admin_fee, ADMIN_FEE_BPS};
+
+    #[test]
+    fn referral_fees_never_exceed_admin_fee() {
+        for interest_due in 0..10_000 {
+            let admin_fee = compute_admin_fee(interest_due, ADMIN_FEE_BPS).unwrap();
+            for referral_fee_share_bps in [0, 1, 2_500, MAX_REFERRAL_FEE_SHARE_BPS] {
+                let referral_fee = compute_referral_fee(admin_fee, referral_fee_share_bps).unwrap();
+                assert!(2 * referral_fee <= admin_fee);
+            }
+        }
+    }
+
+    #[test]
+    fn compute_referral_fee_rounds_down() {
+        assert_eq!(Some(0), compute_referral_fee(1, MAX_REFERRAL_FEE_SHARE_BPS));
+        assert_eq!(Some(1), compute_referral_fee(3, MAX_REFERRAL_FEE_SHARE_BPS));
+        assert_eq!(Some(25), compute_referral_fee(100, 2_500));
+    }
+}
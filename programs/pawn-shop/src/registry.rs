@@ -0,0 +1,372 @@
+//! Collection registry: when enabled, loans can only be requested on pawns of verified
+//! collections registered by the protocol admin, within the collection limits.
+
+use anchor_lang::prelude::*;
+use anchor_spl::{associated_token::get_associated_token_address, token::TokenAccount};
+use mpl_token_metadata::state::Creator;
+use vipers::prelude::*;
+
+use crate::{
+    fee_collector, load_pawn_metadata, math::mul_div_floor, native_mint, transfer_payment,
+    ErrorCode, LoanTerms,
+};
+
+pub const MAX_CREATOR_ROYALTY_BPS: u64 = 5_000; // 50%
+
+#[account]
+pub struct CollectionRegistry {
+    pub bump: u8,
+    /// Whether loan requests are restricted to registered collections
+    pub enabled: bool,
+}
+
+impl CollectionRegistry {
+    pub fn space() -> usize {
+        8 + 1 + 1
+    }
+}
+
+/// Whether loan requests are restricted to registered collections, not until the collection
+/// registry is created.
+pub fn collection_registry_enabled(collection_registry_info: &AccountInfo) -> Result<bool> {
+    if collection_registry_info.data_is_empty() {
+        return Ok(false);
+    }
+    let collection_registry: Account<CollectionRegistry> =
+        Account::try_from(collection_registry_info)?;
+
+    Ok(collection_registry.enabled)
+}
+
+#[account]
+pub struct RegisteredCollection {
+    pub collection: Pubkey,
+    /// Mint loans on the collection must be denominated in
+    pub mint: Pubkey,
+    pub max_principal_amount: u64,
+    pub max_duration: i64,
+    /// Share of the interest of loans requested on the collection paid to its creators
+    pub creator_royalty: Option<CreatorRoyalty>,
+}
+
+impl RegisteredCollection {
+    pub fn space() -> usize {
+        8 + 32 + 32 + 8 + 8 + 1 + CreatorRoyalty::space()
+    }
+
+    pub fn validate_terms(&self, terms: &LoanTerms) -> Result<()> {
+        assert_keys_eq!(terms.mint, self.mint, CollectionLimitExceeded);
+        invariant!(
+            terms.principal_amount <= self.max_principal_amount,
+            CollectionLimitExceeded
+        );
+        invariant!(terms.duration <= self.max_duration, CollectionLimitExceeded);
+
+        Ok(())
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq)]
+pub struct CreatorRoyalty {
+    pub royalty_bps: u64,
+    /// Receives the whole royalty instead of the creators listed in the pawn metadata
+    pub payout_address: Option<Pubkey>,
+}
+
+impl CreatorRoyalty {
+    pub fn space() -> usize {
+        8 + (1 + 32)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        invariant!(
+            self.royalty_bps != 0 && self.royalty_bps <= MAX_CREATOR_ROYALTY_BPS,
+            InvalidCreatorRoyalty
+        );
+
+        Ok(())
+    }
+}
+
+pub fn compute_creator_royalty(interest_due: u64, royalty_bps: u64) -> Option<u64> {
+    mul_div_floor(interest_due.into(), royalty_bps.into(), 10_000)?
+        .try_into()
+        .ok()
+}
+
+/// Splits the royalty between the creators according to their shares.
+/// The rounding dust goes to the first creator so that the whole royalty is paid out.
+pub fn split_creator_royalty(royalty: u64, creators: &[Creator]) -> Option<Vec<(Pubkey, u64)>> {
+    let mut split = creators
+        .iter()
+        .filter(|creator| creator.share != 0)
+        .map(|creator| {
+            let amount = mul_div_floor(royalty.into(), creator.share.into(), 100)?;
+            Some((creator.address, amount.try_into().ok()?))
+        })
+        .collect::<Option<Vec<(Pubkey, u64)>>>()?;
+
+    let distributed = split
+        .iter()
+        .try_fold(0u64, |total, (_, amount)| total.checked_add(*amount))?;
+    if let Some((_, amount)) = split.first_mut() {
+        *amount = amount.checked_add(royalty.checked_sub(distributed)?)?;
+    }
+
+    Some(split)
+}
+
+/// Recipients of the creator royalty, the payout address or else the creators of the pawn. Empty
+/// when the royalty goes to creators and the pawn metadata lists none.
+pub fn creator_royalty_recipients(
+    creator_royalty: &CreatorRoyalty,
+    royalty: u64,
+    creators: &[Creator],
+) -> Option<Vec<(Pubkey, u64)>> {
+    match creator_royalty.payout_address {
+        Some(payout_address) => Some(vec![(payout_address, royalty)]),
+        None => split_creator_royalty(royalty, creators),
+    }
+}
+
+/// Creators listed in the pawn metadata, expected first in the remaining accounts when a royalty
+/// is due to them. Returns the remaining accounts after the metadata.
+pub(crate) fn load_royalty_creators<'a, 'info>(
+    creator_royalty: Option<&CreatorRoyalty>,
+    royalty: u64,
+    pawn_mint: &Pubkey,
+    remaining_accounts: &'a [AccountInfo<'info>],
+) -> Result<(Vec<Creator>, &'a [AccountInfo<'info>])> {
+    match creator_royalty {
+        Some(CreatorRoyalty {
+            payout_address: None,
+            ..
+        }) if royalty != 0 => {
+            let (pawn_metadata_info, remaining_accounts) = unwrap_opt!(
+                remaining_accounts.split_first(),
+                InvalidRoyaltyPaymentAccount
+            );
+            let pawn_metadata = load_pawn_metadata(pawn_metadata_info, pawn_mint)?;
+            Ok((
+                pawn_metadata.data.creators.unwrap_or_default(),
+                remaining_accounts,
+            ))
+        }
+        _ => Ok((vec![], remaining_accounts)),
+    }
+}
+
+/// Pays the creator royalty recipients from the borrower, their payment accounts expected in the
+/// same order. Returns the royalty the recipients cannot receive, paid to the lender instead so
+/// that the royalty never blocks a repayment.
+pub(crate) fn pay_creator_royalty<'info>(
+    recipients: &[(Pubkey, u64)],
+    mint: &Pubkey,
+    payment_accounts: &[AccountInfo<'info>],
+    borrower: &AccountInfo<'info>,
+    borrower_payment_account: &AccountInfo<'info>,
+    token_program: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+) -> Result<u64> {
+    invariant!(
+        payment_accounts.len() >= recipients.len(),
+        InvalidRoyaltyPaymentAccount
+    );
+
+    let mut unpaid = 0u64;
+    for ((recipient, amount), payment_account) in recipients.iter().zip(payment_accounts) {
+        if !can_receive_royalty(*amount, mint, recipient, payment_account)? {
+            unpaid = unwrap_int!(unpaid.checked_add(*amount));
+            continue;
+        }
+        transfer_payment(
+            *amount,
+            mint,
+            recipient,
+            payment_account,
+            borrower,
+            borrower_payment_account,
+            token_program,
+            system_program,
+            &[],
+        )?;
+    }
+
+    Ok(unpaid)
+}
+
+/// Whether the payment account of the recipient can receive the royalty: a wallet left rent
+/// exempt by the amount for sol, a token account of the mint that is not frozen for spl mints.
+/// Only the associated token account of the recipient may be missing, so that the borrower cannot
+/// pass another account to withhold the royalty.
+fn can_receive_royalty(
+    amount: u64,
+    mint: &Pubkey,
+    recipient: &Pubkey,
+    payment_account: &AccountInfo,
+) -> Result<bool> {
+    if *mint == native_mint::ID {
+        assert_keys_eq!(
+            *recipient,
+            *payment_account.key,
+            InvalidRoyaltyPaymentAccount
+        );
+        let balance = unwrap_int!(payment_account.lamports().checked_add(amount));
+        return Ok(!payment_account.executable
+            && balance >= Rent::get()?.minimum_balance(payment_account.data_len()));
+    }
+
+    if payment_account.data_is_empty() {
+        assert_keys_eq!(
+            get_associated_token_address(recipient, mint),
+            *payment_account.key,
+            InvalidRoyaltyPaymentAccount
+        );
+        return Ok(false);
+    }
+    let payment_token_account: Account<TokenAccount> = Account::try_from(payment_account)?;
+    assert_keys_eq!(
+        *recipient,
+        payment_token_account.owner,
+        InvalidRoyaltyPaymentAccount
+    );
+    assert_keys_eq!(
+        *mint,
+        payment_token_account.mint,
+        InvalidRoyaltyPaymentAccount
+    );
+
+    Ok(!payment_token_account.is_frozen())
+}
+
+#[derive(Accounts)]
+pub struct InitCollectionRegistry<'info> {
+    #[account(init, seeds = [b"collection_registry".as_ref()], bump, payer = fee_collector, space = CollectionRegistry::space())]
+    pub collection_registry: Account<'info, CollectionRegistry>,
+    #[account(mut, address = fee_collector::ID)]
+    pub fee_collector: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetCollectionRegistryEnabled<'info> {
+    #[account(mut, seeds = [b"collection_registry".as_ref()], bump = collection_registry.bump)]
+    pub collection_registry: Account<'info, CollectionRegistry>,
+    #[account(address = fee_collector::ID)]
+    pub fee_collector: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(collection: Pubkey)]
+pub struct RegisterCollection<'info> {
+    #[account(init, seeds = [b"registered_collection".as_ref(), collection.as_ref()], bump, payer = fee_collector, space = RegisteredCollection::space())]
+    pub registered_collection: Account<'info, RegisteredCollection>,
+    #[account(mut, address = fee_collector::ID)]
+    pub fee_collector: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetCollectionRoyalty<'info> {
+    #[account(mut)]
+    pub registered_collection: Account<'info, RegisteredCollection>,
+    #[account(address = fee_collector::ID)]
+    pub fee_collector: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct UnregisterCollection<'info> {
+    #[account(mut, close = fee_collector)]
+    pub registered_collection: Account<'info, RegisteredCollection>,
+    #[account(mut, address = fee_collector::ID)]
+    pub fee_collector: Signer<'info>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::InterestModel;
+
+    #[test]
+    fn validate_terms_enforces_collection_limits() {
+        let mint = Pubkey::new_unique();
+        let registered_collection = RegisteredCollection {
+            collection: Pubkey::new_unique(),
+            mint,
+            max_principal_amount: 1_000,
+            max_duration: 7 * 24 * 60 * 60,
+            creator_royalty: None,
+        };
+        let terms = |mint, principal_amount, duration| LoanTerms {
+            principal_amount,
+            mint,
+            interest_model: InterestModel::AnnualPercentageRate {
+                annual_percentage_rate_bps: 1_000,
+            },
+            duration,
+            minimum_period_ratio_bps: None,
+        };
+
+        assert!(registered_collection
+            .validate_terms(&terms(mint, 1_000, 7 * 24 * 60 * 60))
+            .is_ok());
+        assert!(registered_collection
+            .validate_terms(&terms(mint, 1_001, 24 * 60 * 60))
+            .is_err());
+        assert!(registered_collection
+            .validate_terms(&terms(mint, 1_000, 7 * 24 * 60 * 60 + 1))
+            .is_err());
+        assert!(registered_collection
+            .validate_terms(&terms(Pubkey::new_unique(), 1, 1))
+            .is_err());
+    }
+
+    #[test]
+    fn split_creator_royalty_pays_out_the_whole_royalty() {
+        let creator = |share| Creator {
+            address: Pubkey::new_unique(),
+            verified: true,
+            share,
+        };
+        let creators = [creator(33), creator(0), creator(67)];
+
+        let split = split_creator_royalty(1_000, &creators).unwrap();
+        assert_eq!(
+            vec![(creators[0].address, 330), (creators[2].address, 670)],
+            split
+        );
+
+        for royalty in 0..1_000 {
+            let split = split_creator_royalty(royalty, &creators).unwrap();
+            assert_eq!(royalty, split.iter().map(|(_, amount)| amount).sum::<u64>());
+            // Dust is at most one unit per creator
+            assert!(split[0].1 - royalty * 33 / 100 < 2);
+        }
+
+        assert!(split_creator_royalty(1_000, &[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn compute_creator_royalty_rounds_down() {
+        assert_eq!(Some(0), compute_creator_royalty(9, 1_000));
+        assert_eq!(Some(1), compute_creator_royalty(10, 1_000));
+        assert_eq!(
+            Some(50),
+            compute_creator_royalty(100, MAX_CREATOR_ROYALTY_BPS)
+        );
+    }
+
+    #[test]
+    fn creator_royalty_is_bounded() {
+        let creator_royalty = |royalty_bps| CreatorRoyalty {
+            royalty_bps,
+            payout_address: None,
+        };
+        assert!(creator_royalty(0).validate().is_err());
+        assert!(creator_royalty(1).validate().is_ok());
+        assert!(creator_royalty(MAX_CREATOR_ROYALTY_BPS).validate().is_ok());
+        assert!(creator_royalty(MAX_CREATOR_ROYALTY_BPS + 1)
+            .validate()
+            .is_err());
+    }
+}
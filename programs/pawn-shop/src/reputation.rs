@@ -0,0 +1,240 @@
+//! Reputation: loan history of each borrower and lender wallet, so that lenders can check whether
+//! a borrower has defaulted before underwriting.
+
+use anchor_lang::prelude::*;
+use vipers::prelude::*;
+
+use crate::{create_pda_account, ErrorCode};
+
+/// Loans are counted when underwritten, whether by the lender, its delegate or a lending pool,
+/// and their outcome when repaid or seized. Only loans are counted, not their principal, which has
+/// no common unit across loan mints.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct LoanHistory {
+    pub loan_count: u64,
+    pub repaid_count: u64,
+    pub default_count: u64,
+}
+
+impl LoanHistory {
+    pub fn space() -> usize {
+        3 * 8
+    }
+
+    pub fn record_loan(&mut self) -> Result<()> {
+        self.loan_count = unwrap_int!(self.loan_count.checked_add(1));
+        Ok(())
+    }
+
+    pub fn record_repayment(&mut self) -> Result<()> {
+        self.repaid_count = unwrap_int!(self.repaid_count.checked_add(1));
+        Ok(())
+    }
+
+    pub fn record_default(&mut self) -> Result<()> {
+        self.default_count = unwrap_int!(self.default_count.checked_add(1));
+        Ok(())
+    }
+}
+
+#[account]
+pub struct BorrowerStats {
+    pub borrower: Pubkey,
+    pub bump: u8,
+    pub history: LoanHistory,
+}
+
+impl BorrowerStats {
+    pub fn space() -> usize {
+        8 + 32 + 1 + LoanHistory::space()
+    }
+}
+
+#[account]
+pub struct LenderStats {
+    pub lender: Pubkey,
+    pub bump: u8,
+    pub history: LoanHistory,
+}
+
+impl LenderStats {
+    pub fn space() -> usize {
+        8 + 32 + 1 + LoanHistory::space()
+    }
+}
+
+/// Loads the borrower stats, creating them at the expense of the payer on the first loan.
+pub fn load_or_create_borrower_stats<'info>(
+    borrower_stats_info: &AccountInfo<'info>,
+    borrower: &Pubkey,
+    bump: u8,
+    payer: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+) -> Result<Account<'info, BorrowerStats>> {
+    if !borrower_stats_info.data_is_empty() {
+        return Account::try_from(borrower_stats_info);
+    }
+
+    create_pda_account(
+        borrower_stats_info,
+        BorrowerStats::space(),
+        &[&[borrower.as_ref(), b"borrower_stats".as_ref(), &[bump]]],
+        payer,
+        system_program,
+    )?;
+    let mut borrower_stats: Account<BorrowerStats> =
+        Account::try_from_unchecked(borrower_stats_info)?;
+    borrower_stats.borrower = *borrower;
+    borrower_stats.bump = bump;
+
+    Ok(borrower_stats)
+}
+
+/// Loads the lender stats, creating them at the expense of the payer on the first loan.
+pub fn load_or_create_lender_stats<'info>(
+    lender_stats_info: &AccountInfo<'info>,
+    lender: &Pubkey,
+    bump: u8,
+    payer: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+) -> Result<Account<'info, LenderStats>> {
+    if !lender_stats_info.data_is_empty() {
+        return Account::try_from(lender_stats_info);
+    }
+
+    create_pda_account(
+        lender_stats_info,
+        LenderStats::space(),
+        &[&[lender.as_ref(), b"lender_stats".as_ref(), &[bump]]],
+        payer,
+        system_program,
+    )?;
+    let mut lender_stats: Account<LenderStats> = Account::try_from_unchecked(lender_stats_info)?;
+    lender_stats.lender = *lender;
+    lender_stats.bump = bump;
+
+    Ok(lender_stats)
+}
+
+/// Records the loan in the history of both parties when it is underwritten, creating their stats
+/// at the expense of the payer on their first loan. The addresses are expected to be checked by
+/// the caller.
+#[allow(clippy::too_many_arguments)]
+pub fn record_underwritten_loan<'info>(
+    borrower_stats_info: &AccountInfo<'info>,
+    borrower: &Pubkey,
+    borrower_stats_bump: u8,
+    lender_stats_info: &AccountInfo<'info>,
+    lender: &Pubkey,
+    lender_stats_bump: u8,
+    payer: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+) -> Result<()> {
+    let mut borrower_stats = load_or_create_borrower_stats(
+        borrower_stats_info,
+        borrower,
+        borrower_stats_bump,
+        payer,
+        system_program,
+    )?;
+    borrower_stats.history.record_loan()?;
+    borrower_stats.exit(&crate::ID)?;
+
+    let mut lender_stats = load_or_create_lender_stats(
+        lender_stats_info,
+        lender,
+        lender_stats_bump,
+        payer,
+        system_program,
+    )?;
+    lender_stats.history.record_loan()?;
+    lender_stats.exit(&crate::ID)
+}
+
+/// Creates the missing stats of the parties of a loan underwritten before the loan history, at the
+/// expense of the payer, with the loan recorded so that outcomes never outnumber loans. Loans
+/// underwritten since then have both stats. The addresses are expected to be checked by the caller.
+#[allow(clippy::too_many_arguments)]
+pub fn backfill_loan_history<'info>(
+    borrower_stats_info: &AccountInfo<'info>,
+    borrower: &Pubkey,
+    borrower_stats_bump: u8,
+    lender_stats_info: &AccountInfo<'info>,
+    lender: &Pubkey,
+    lender_stats_bump: u8,
+    payer: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+) -> Result<()> {
+    if borrower_stats_info.data_is_empty() {
+        let mut borrower_stats = load_or_create_borrower_stats(
+            borrower_stats_info,
+            borrower,
+            borrower_stats_bump,
+            payer,
+            system_program,
+        )?;
+        borrower_stats.history.record_loan()?;
+        borrower_stats.exit(&crate::ID)?;
+    }
+
+    if lender_stats_info.data_is_empty() {
+        let mut lender_stats = load_or_create_lender_stats(
+            lender_stats_info,
+            lender,
+            lender_stats_bump,
+            payer,
+            system_program,
+        )?;
+        lender_stats.history.record_loan()?;
+        lender_stats.exit(&crate::ID)?;
+    }
+
+    Ok(())
+}
+
+/// Records the outcome of a loan in the history of both parties. Their stats exist since the loan
+/// was underwritten, or since `backfill_loan_history`. The addresses are expected to be checked by
+/// the caller.
+pub fn record_loan_outcome<'info>(
+    borrower_stats_info: &AccountInfo<'info>,
+    lender_stats_info: &AccountInfo<'info>,
+    update: impl Fn(&mut LoanHistory) -> Result<()>,
+) -> Result<()> {
+    invariant!(!borrower_stats_info.data_is_empty(), LoanStatsNotFound);
+    invariant!(!lender_stats_info.data_is_empty(), LoanStatsNotFound);
+
+    let mut borrower_stats: Account<BorrowerStats> = Account::try_from(borrower_stats_info)?;
+    update(&mut borrower_stats.history)?;
+    borrower_stats.exit(&crate::ID)?;
+
+    let mut lender_stats: Account<LenderStats> = Account::try_from(lender_stats_info)?;
+    update(&mut lender_stats.history)?;
+    lender_stats.exit(&crate::ID)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loan_history_counts_outcomes() {
+        let mut history = LoanHistory::default();
+
+        history.record_loan().unwrap();
+        history.record_loan().unwrap();
+        history.record_repayment().unwrap();
+        history.record_default().unwrap();
+
+        assert_eq!(
+            LoanHistory {
+                loan_count: 2,
+                repaid_count: 1,
+                default_count: 1,
+            },
+            history
+        );
+
+        history.loan_count = u64::MAX;
+        assert!(history.record_loan().is_err());
+    }
+}
@@ -0,0 +1,237 @@
+//! Loan statistics: protocol-wide and per loan mint counters maintained by every instruction
+//! changing the status of a loan, so that dashboards don't have to index every event.
+
+use anchor_lang::prelude::*;
+use vipers::prelude::*;
+
+use crate::{create_pda_account, fee_collector, ErrorCode};
+
+/// Counters of the loans, the principal and interest amounts being in the loan mint.
+/// Loans requested before the statistics existed are not counted, their transitions saturate
+/// the open requests and active loans at zero.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct LoanStatistics {
+    pub open_requests: u64,
+    pub active_loans: u64,
+    pub total_principal_originated: u64,
+    pub total_interest_paid: u64,
+    pub default_count: u64,
+    /// Principal of the defaulted loans
+    pub default_volume: u64,
+}
+
+impl LoanStatistics {
+    pub fn space() -> usize {
+        6 * 8
+    }
+
+    pub fn record_request(&mut self) -> Result<()> {
+        self.open_requests = unwrap_int!(self.open_requests.checked_add(1));
+        Ok(())
+    }
+
+    pub fn record_cancellation(&mut self) -> Result<()> {
+        self.open_requests = self.open_requests.saturating_sub(1);
+        Ok(())
+    }
+
+    pub fn record_underwriting(&mut self, principal_amount: u64) -> Result<()> {
+        self.open_requests = self.open_requests.saturating_sub(1);
+        self.active_loans = unwrap_int!(self.active_loans.checked_add(1));
+        self.total_principal_originated = unwrap_int!(self
+            .total_principal_originated
+            .checked_add(principal_amount));
+        Ok(())
+    }
+
+    pub fn record_repayment(&mut self, interest_paid: u64) -> Result<()> {
+        self.active_loans = self.active_loans.saturating_sub(1);
+        self.total_interest_paid = unwrap_int!(self.total_interest_paid.checked_add(interest_paid));
+        Ok(())
+    }
+
+    pub fn record_default(&mut self, principal_amount: u64) -> Result<()> {
+        self.active_loans = self.active_loans.saturating_sub(1);
+        self.default_count = unwrap_int!(self.default_count.checked_add(1));
+        self.default_volume = unwrap_int!(self.default_volume.checked_add(principal_amount));
+        Ok(())
+    }
+}
+
+/// Statistics of all loans. Amounts add up the raw units of every mint, the mint statistics
+/// should be read to value them.
+#[account]
+pub struct ProtocolStats {
+    pub bump: u8,
+    pub stats: LoanStatistics,
+}
+
+impl ProtocolStats {
+    pub fn space() -> usize {
+        8 + 1 + LoanStatistics::space()
+    }
+}
+
+#[account]
+pub struct MintStats {
+    pub mint: Pubkey,
+    pub bump: u8,
+    pub stats: LoanStatistics,
+}
+
+impl MintStats {
+    pub fn space() -> usize {
+        8 + 32 + 1 + LoanStatistics::space()
+    }
+}
+
+pub fn find_protocol_stats_address() -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"protocol_stats".as_ref()], &crate::ID)
+}
+
+pub fn find_mint_stats_address(mint: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[mint.as_ref(), b"mint_stats".as_ref()], &crate::ID)
+}
+
+/// Applies a loan transition to the protocol statistics, skipped until they are created so that
+/// live loans never depend on them.
+pub fn update_protocol_statistics<'info>(
+    protocol_stats_info: &AccountInfo<'info>,
+    update: impl Fn(&mut LoanStatistics) -> Result<()>,
+) -> Result<()> {
+    if protocol_stats_info.data_is_empty() {
+        return Ok(());
+    }
+    let mut protocol_stats: Account<ProtocolStats> = Account::try_from(protocol_stats_info)?;
+    update(&mut protocol_stats.stats)?;
+    protocol_stats.exit(&crate::ID)
+}
+
+/// Applies a loan transition to the protocol statistics and to the statistics of the loan mint.
+/// Mint statistics are created by loan requests, a mint without any is skipped.
+pub fn update_loan_statistics<'info>(
+    protocol_stats_info: &AccountInfo<'info>,
+    mint_stats_info: &AccountInfo<'info>,
+    mint: &Pubkey,
+    update: impl Fn(&mut LoanStatistics) -> Result<()>,
+) -> Result<()> {
+    update_protocol_statistics(protocol_stats_info, &update)?;
+
+    assert_keys_eq!(
+        find_mint_stats_address(mint).0,
+        *mint_stats_info.key,
+        InvalidMintStats
+    );
+    if mint_stats_info.data_is_empty() {
+        return Ok(());
+    }
+    let mut mint_stats: Account<MintStats> = Account::try_from(mint_stats_info)?;
+    update(&mut mint_stats.stats)?;
+    mint_stats.exit(&crate::ID)
+}
+
+/// Creates the protocol statistics on the first loan request if the admin has not, at the expense
+/// of the payer.
+pub fn create_protocol_stats_if_needed<'info>(
+    protocol_stats_info: &AccountInfo<'info>,
+    payer: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+) -> Result<()> {
+    let (address, bump) = find_protocol_stats_address();
+    assert_keys_eq!(address, *protocol_stats_info.key);
+    if !protocol_stats_info.data_is_empty() {
+        return Ok(());
+    }
+
+    create_pda_account(
+        protocol_stats_info,
+        ProtocolStats::space(),
+        &[&[b"protocol_stats".as_ref(), &[bump]]],
+        payer,
+        system_program,
+    )?;
+    let mut protocol_stats: Account<ProtocolStats> =
+        Account::try_from_unchecked(protocol_stats_info)?;
+    protocol_stats.bump = bump;
+    protocol_stats.exit(&crate::ID)
+}
+
+/// Creates the statistics of the loan mint on its first loan request, at the expense of the payer.
+pub fn create_mint_stats_if_needed<'info>(
+    mint_stats_info: &AccountInfo<'info>,
+    mint: &Pubkey,
+    payer: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+) -> Result<()> {
+    let (address, bump) = find_mint_stats_address(mint);
+    assert_keys_eq!(address, *mint_stats_info.key, InvalidMintStats);
+    if !mint_stats_info.data_is_empty() {
+        return Ok(());
+    }
+
+    create_pda_account(
+        mint_stats_info,
+        MintStats::space(),
+        &[&[mint.as_ref(), b"mint_stats".as_ref(), &[bump]]],
+        payer,
+        system_program,
+    )?;
+    let mut mint_stats: Account<MintStats> = Account::try_from_unchecked(mint_stats_info)?;
+    mint_stats.mint = *mint;
+    mint_stats.bump = bump;
+    mint_stats.exit(&crate::ID)
+}
+
+#[derive(Accounts)]
+pub struct InitProtocolStats<'info> {
+    #[account(init, seeds = [b"protocol_stats".as_ref()], bump, payer = fee_collector, space = ProtocolStats::space())]
+    pub protocol_stats: Account<'info, ProtocolStats>,
+    #[account(mut, address = fee_collector::ID)]
+    pub fee_collector: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loan_statistics_follow_transitions() {
+        let mut stats = LoanStatistics::default();
+
+        stats.record_request().unwrap();
+        stats.record_request().unwrap();
+        stats.record_request().unwrap();
+        stats.record_cancellation().unwrap();
+        stats.record_underwriting(1_000).unwrap();
+        stats.record_underwriting(500).unwrap();
+        stats.record_repayment(42).unwrap();
+        stats.record_default(500).unwrap();
+
+        assert_eq!(
+            LoanStatistics {
+                open_requests: 0,
+                active_loans: 0,
+                total_principal_originated: 1_500,
+                total_interest_paid: 42,
+                default_count: 1,
+                default_volume: 500,
+            },
+            stats
+        );
+    }
+
+    #[test]
+    fn loan_statistics_saturate_for_untracked_loans() {
+        let mut stats = LoanStatistics::default();
+
+        stats.record_underwriting(1_000).unwrap();
+        stats.record_repayment(10).unwrap();
+        stats.record_default(1_000).unwrap();
+        stats.record_cancellation().unwrap();
+
+        assert_eq!(0, stats.open_requests);
+        assert_eq!(0, stats.active_loans);
+        assert_eq!(1, stats.default_count);
+    }
+}
@@ -0,0 +1,43 @@
+#!/usr/bin/env bash
+# Compares the compute units of the loan lifecycle (request, underwrite, repay, seize) between the
+# last revision storing pawn loans with Borsh and the working tree, storing them zero-copy.
+#
+# Usage: scripts/bench-compute-units.sh [borsh revision]
+#
+# The Borsh revision gets the same "Compute units" tests from scripts/borsh-compute-units.patch,
+# adapted to the sdk of that revision. Both runs use `anchor test` and its local validator.
+set -euo pipefail
+
+BORSH_REVISION="${1:-2ebf283}"
+ROOT="$(git rev-parse --show-toplevel)"
+OUTPUT="$ROOT/bench_output.txt"
+WORKTREE="$(mktemp -d)"
+
+cleanup() {
+  git -C "$ROOT" worktree remove --force "$WORKTREE"
+}
+
+git -C "$ROOT" worktree add --detach "$WORKTREE" "$BORSH_REVISION"
+trap cleanup EXIT
+git -C "$WORKTREE" apply "$ROOT/scripts/borsh-compute-units.patch"
+ln -s "$ROOT/node_modules" "$WORKTREE/node_modules"
+cp "$ROOT/external-programs/"*.so "$WORKTREE/external-programs/"
+
+(cd "$WORKTREE" && COMPUTE_UNITS_OUTPUT="$WORKTREE/borsh.json" anchor test)
+(cd "$ROOT" && COMPUTE_UNITS_OUTPUT="$WORKTREE/zero-copy.json" anchor test)
+
+node - "$WORKTREE/borsh.json" "$WORKTREE/zero-copy.json" "$BORSH_REVISION" <<'EOF' | tee "$OUTPUT"
+const fs = require("fs");
+const [borshPath, zeroCopyPath, revision] = process.argv.slice(2);
+const borsh = JSON.parse(fs.readFileSync(borshPath, "utf8"));
+const zeroCopy = JSON.parse(fs.readFileSync(zeroCopyPath, "utf8"));
+
+console.log(`| Instruction | Borsh (${revision}) | Zero-copy | Difference |`);
+console.log("| --- | --- | --- | --- |");
+for (const instruction of Object.keys(zeroCopy)) {
+  const difference = zeroCopy[instruction] - borsh[instruction];
+  console.log(
+    `| ${instruction} | ${borsh[instruction]} | ${zeroCopy[instruction]} | ${difference} |`
+  );
+}
+EOF
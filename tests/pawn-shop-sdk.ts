@@ -1,6 +1,7 @@
 import { findProgramAddressSync } from "@project-serum/anchor/dist/cjs/utils/pubkey";
 import { BN, IdlAccounts, IdlTypes, Program } from "@project-serum/anchor";
 import { PublicKey, Keypair, SystemProgram } from "@solana/web3.js";
+import { Token, TOKEN_PROGRAM_ID } from "@solana/spl-token";
 import { PawnShop } from "../target/types/pawn_shop";
 import { assert } from "chai";
 import { PROGRAM_ID as METAPLEX_PROGRAM_ID } from "@metaplex-foundation/mpl-token-metadata";
@@ -14,14 +15,104 @@ const pawnShopIdl = JSON.parse(
 delete pawnShopIdl["events"];
 fs.writeFileSync("./target/idl/pawn_shop.json", JSON.stringify(pawnShopIdl));
 
+// Pawn loan with its optional fields decoded, as emitted in the loan events
 export type PawnLoan = Omit<
-  IdlAccounts<PawnShop>["pawnLoan"],
+  IdlTypes<PawnShop>["PawnLoanView"],
   "desiredTerms" | "terms"
 > & {
   desiredTerms: LoanTerms | null;
   terms: LoanTerms | null;
 };
 export type LoanTerms = IdlTypes<PawnShop>["LoanTerms"];
+export type PayoffQuote = IdlTypes<PawnShop>["PayoffQuote"];
+export type LenderDelegateLimits = IdlTypes<PawnShop>["LenderDelegateLimits"];
+export type CreatorRoyalty = IdlTypes<PawnShop>["CreatorRoyalty"];
+type PawnLoanAccount = IdlAccounts<PawnShop>["pawnLoan"];
+type PackedLoanTerms = IdlTypes<PawnShop>["PackedLoanTerms"];
+
+const LOAN_STATUSES = [
+  { open: {} },
+  { active: {} },
+  { repaid: {} },
+  { defaulted: {} },
+];
+
+function unpackLoanTerms(packed: {
+  isSome: number;
+  value: PackedLoanTerms;
+}): LoanTerms | null {
+  if (!packed.isSome) {
+    return null;
+  }
+  const terms = packed.value;
+  const interestModel = [
+    {
+      annualPercentageRate: {
+        annualPercentageRateBps: terms.interestParameter,
+      },
+    },
+    { fixedInterest: { interestAmount: terms.interestParameter } },
+    {
+      compoundingAnnualPercentageRate: {
+        annualPercentageRateBps: terms.interestParameter,
+        compoundingPeriod: terms.compoundingPeriod,
+      },
+    },
+  ][terms.interestModel];
+  return {
+    principalAmount: terms.principalAmount,
+    mint: terms.mint,
+    interestModel,
+    duration: terms.duration,
+    minimumPeriodRatioBps: terms.minimumPeriodRatioBps.isSome
+      ? terms.minimumPeriodRatioBps.value
+      : null,
+  } as LoanTerms;
+}
+
+function unpackPubkey(packed: {
+  isSome: number;
+  value: PublicKey;
+}): PublicKey | null {
+  return packed.isSome ? packed.value : null;
+}
+
+// Decodes the zero-copy layout of the pawn loan into the view of the events.
+export function toPawnLoanView(account: PawnLoanAccount): PawnLoan {
+  const creatorRoyalty = account.creatorRoyalty;
+  return {
+    version: account.version,
+    base: account.base,
+    bump: account.bump,
+    borrower: account.borrower,
+    pawnTokenAccount: account.pawnTokenAccount,
+    pawnMint: account.pawnMint,
+    status: LOAN_STATUSES[account.status],
+    lender: account.lender,
+    desiredTerms: unpackLoanTerms(account.desiredTerms),
+    terms: unpackLoanTerms(account.terms),
+    creationTime: account.creationTime,
+    startTime: account.startTime,
+    endTime: account.endTime,
+    creatorRoyalty: creatorRoyalty.isSome
+      ? {
+          royaltyBps: creatorRoyalty.royaltyBps,
+          payoutAddress: unpackPubkey(creatorRoyalty.payoutAddress),
+        }
+      : null,
+    borrowerReferrer: unpackPubkey(account.borrowerReferrer),
+    lenderReferrer: unpackPubkey(account.lenderReferrer),
+    adminFeeBps: account.adminFeeBps,
+    seedNonce: account.seedNonce.isSome ? account.seedNonce.value : null,
+  } as PawnLoan;
+}
+
+export async function fetchPawnLoan(
+  program: Program<PawnShop>,
+  pawnLoanAddress: PublicKey
+): Promise<PawnLoan> {
+  return toPawnLoanView(await program.account.pawnLoan.fetch(pawnLoanAddress));
+}
 
 export async function requestLoan(
   program: Program<PawnShop>,
@@ -29,7 +120,10 @@ export async function requestLoan(
   borrowerKeypair: Keypair,
   borrowerPawnTokenAccount: PublicKey,
   pawnMint: PublicKey,
-  desiredTerms: LoanTerms
+  desiredTerms: LoanTerms,
+  // Verified collection of the pawn, required while the collection registry is enabled
+  collection: PublicKey | null = null,
+  referrer: PublicKey | null = null
 ) {
   const pawnLoan = findProgramAddressSync(
     [baseKeypair.publicKey.toBuffer(), Buffer.from("pawn_loan")],
@@ -37,7 +131,7 @@ export async function requestLoan(
   )[0];
 
   const signature = await program.methods
-    .requestLoan(desiredTerms)
+    .requestLoan(desiredTerms, referrer)
     .accounts({
       base: baseKeypair.publicKey,
       pawnLoan,
@@ -45,8 +139,28 @@ export async function requestLoan(
       pawnTokenAccount: borrowerPawnTokenAccount,
       pawnMint,
       edition: findMasterEditionPda(pawnMint),
+      collectionRegistry: findCollectionRegistryPda(program),
+      // Mint statistics are ignored for requests without desired terms
+      ...loanStatsAccounts(program, desiredTerms?.mint ?? null),
       mplTokenMetadataProgram: METAPLEX_PROGRAM_ID,
     })
+    .remainingAccounts([
+      ...(collection
+        ? [
+            {
+              pubkey: findMetadataPda(pawnMint),
+              isSigner: false,
+              isWritable: false,
+            },
+            {
+              pubkey: findRegisteredCollectionPda(program, collection),
+              isSigner: false,
+              isWritable: false,
+            },
+          ]
+        : []),
+      ...registeredReferrerAccounts(program, referrer),
+    ])
     .signers([baseKeypair, borrowerKeypair])
     .rpc();
 
@@ -57,13 +171,83 @@ export async function requestLoan(
   };
 }
 
+// Requests a loan at the address derived from the pawn, without base keypair
+export async function requestLoanForPawn(
+  program: Program<PawnShop>,
+  borrowerKeypair: Keypair,
+  borrowerPawnTokenAccount: PublicKey,
+  pawnMint: PublicKey,
+  nonce: BN,
+  desiredTerms: LoanTerms,
+  // Verified collection of the pawn, required while the collection registry is enabled
+  collection: PublicKey | null = null,
+  referrer: PublicKey | null = null
+) {
+  const pawnLoan = findPawnLoanPda(
+    program,
+    pawnMint,
+    borrowerKeypair.publicKey,
+    nonce
+  );
+
+  const signature = await program.methods
+    .requestLoanForPawn(nonce, desiredTerms, referrer)
+    .accounts({
+      pawnLoan,
+      borrower: borrowerKeypair.publicKey,
+      pawnTokenAccount: borrowerPawnTokenAccount,
+      pawnMint,
+      edition: findMasterEditionPda(pawnMint),
+      collectionRegistry: findCollectionRegistryPda(program),
+      // Mint statistics are ignored for requests without desired terms
+      ...loanStatsAccounts(program, desiredTerms?.mint ?? null),
+      mplTokenMetadataProgram: METAPLEX_PROGRAM_ID,
+    })
+    .remainingAccounts([
+      ...(collection
+        ? [
+            {
+              pubkey: findMetadataPda(pawnMint),
+              isSigner: false,
+              isWritable: false,
+            },
+            {
+              pubkey: findRegisteredCollectionPda(program, collection),
+              isSigner: false,
+              isWritable: false,
+            },
+          ]
+        : []),
+      ...registeredReferrerAccounts(program, referrer),
+    ])
+    .signers([borrowerKeypair])
+    .rpc();
+
+  return {
+    signature,
+    pawnLoan,
+    pawnTokenAccount: borrowerPawnTokenAccount,
+  };
+}
+
+export type LoanToValueGuard = IdlTypes<PawnShop>["LoanToValueGuard"];
+
 export async function underwriteLoan(
   program: Program<PawnShop>,
   pawnLoanAddress: PublicKey,
   pawnLoanState: PawnLoan,
   lenderKeypair: Keypair,
   lenderPaymentAccount: PublicKey,
-  borrowerPaymentAccount: PublicKey
+  borrowerPaymentAccount: PublicKey,
+  loanToValueGuard: {
+    guard: LoanToValueGuard;
+    priceFeed: PublicKey;
+  } | null = null,
+  referrer: PublicKey | null = null,
+  // Tracks the volume of the lender for the fee tiers of the loan mint
+  lenderVolume: PublicKey | null = null,
+  // Receives the origination fee, defaults to the admin pda for sol loans
+  adminPaymentAccount: PublicKey | null = null
 ) {
   const expectedDesiredTerms = pawnLoanState.desiredTerms;
   assert.isNotNull(expectedDesiredTerms);
@@ -73,16 +257,151 @@ export async function underwriteLoan(
     return;
   }
 
-  const tx = await program.methods
-    .underwriteLoan(expectedDesiredTerms, pawnLoanState.pawnMint)
+  return await program.methods
+    .underwriteLoan(
+      expectedDesiredTerms,
+      pawnLoanState.pawnMint,
+      loanToValueGuard?.guard ?? null,
+      referrer
+    )
     .accounts({
       pawnLoan: pawnLoanAddress,
       lender: lenderKeypair.publicKey,
       lenderPaymentAccount: lenderPaymentAccount,
       borrowerPaymentAccount: borrowerPaymentAccount,
+      feeSchedule: findFeeSchedulePda(program, expectedDesiredTerms.mint),
+      admin: findAdminPda(program),
+      adminPaymentAccount: adminPaymentAccount ?? findAdminPda(program),
+      feeLedger: findFeeLedgerPda(program, expectedDesiredTerms.mint),
+      ...loanStatsAccounts(program, expectedDesiredTerms.mint),
+      borrowerStats: findBorrowerStatsPda(program, pawnLoanState.borrower),
+      lenderStats: findLenderStatsPda(program, lenderKeypair.publicKey),
+    })
+    .remainingAccounts([
+      ...(loanToValueGuard
+        ? [
+            {
+              pubkey: loanToValueGuard.priceFeed,
+              isSigner: false,
+              isWritable: false,
+            },
+            {
+              pubkey: findMetadataPda(pawnLoanState.pawnMint),
+              isSigner: false,
+              isWritable: false,
+            },
+          ]
+        : []),
+      ...(lenderVolume
+        ? [{ pubkey: lenderVolume, isSigner: false, isWritable: true }]
+        : []),
+      ...registeredReferrerAccounts(program, referrer),
+    ])
+    .signers([lenderKeypair])
+    .rpc();
+}
+
+// Creates the lender delegate and approves it as spl delegate of the lender payment account
+export async function approveLenderDelegate(
+  program: Program<PawnShop>,
+  lenderKeypair: Keypair,
+  delegate: PublicKey,
+  limits: LenderDelegateLimits,
+  lenderPaymentAccount: PublicKey
+) {
+  const lenderDelegate = findProgramAddressSync(
+    [
+      lenderKeypair.publicKey.toBuffer(),
+      delegate.toBuffer(),
+      Buffer.from("lender_delegate"),
+    ],
+    program.programId
+  )[0];
+
+  const signature = await program.methods
+    .approveLenderDelegate(limits)
+    .accounts({
+      lenderDelegate,
+      lender: lenderKeypair.publicKey,
+      delegate,
     })
+    .preInstructions([
+      Token.createApproveInstruction(
+        TOKEN_PROGRAM_ID,
+        lenderPaymentAccount,
+        lenderDelegate,
+        lenderKeypair.publicKey,
+        [],
+        limits.maxTotalPrincipal.toNumber()
+      ),
+    ])
     .signers([lenderKeypair])
     .rpc();
+
+  return { signature, lenderDelegate };
+}
+
+export async function underwriteLoanWithDelegate(
+  program: Program<PawnShop>,
+  pawnLoanAddress: PublicKey,
+  pawnLoanState: PawnLoan,
+  lenderDelegate: PublicKey,
+  delegateKeypair: Keypair,
+  lender: PublicKey,
+  lenderPaymentAccount: PublicKey,
+  borrowerPaymentAccount: PublicKey,
+  // Receives the origination fee, a token account owned by the admin pda
+  adminPaymentAccount: PublicKey,
+  // Tracks the volume of the lender for the fee tiers of the loan mint
+  lenderVolume: PublicKey | null = null
+) {
+  const expectedDesiredTerms = pawnLoanState.desiredTerms;
+  assert.isNotNull(expectedDesiredTerms);
+
+  // To silence typescript null warning. nulls should still throw instead of exiting.
+  if (!expectedDesiredTerms) {
+    return;
+  }
+
+  return await program.methods
+    .underwriteLoanWithDelegate(expectedDesiredTerms, pawnLoanState.pawnMint)
+    .accounts({
+      pawnLoan: pawnLoanAddress,
+      lenderDelegate,
+      delegate: delegateKeypair.publicKey,
+      lender,
+      lenderPaymentAccount,
+      borrowerPaymentAccount,
+      ...underwritingFeeAccounts(
+        program,
+        expectedDesiredTerms.mint,
+        adminPaymentAccount
+      ),
+      borrowerStats: findBorrowerStatsPda(program, pawnLoanState.borrower),
+      lenderStats: findLenderStatsPda(program, lender),
+      ...loanStatsAccounts(program, expectedDesiredTerms.mint),
+    })
+    .remainingAccounts(
+      lenderVolume
+        ? [{ pubkey: lenderVolume, isSigner: false, isWritable: true }]
+        : []
+    )
+    .signers([delegateKeypair])
+    .rpc();
+}
+
+// Accounts charging the fees of the loan mint when underwriting
+export function underwritingFeeAccounts(
+  program: Program<PawnShop>,
+  mint: PublicKey,
+  adminPaymentAccount: PublicKey
+) {
+  return {
+    feeSchedule: findFeeSchedulePda(program, mint),
+    admin: findAdminPda(program),
+    adminPaymentAccount,
+    feeLedger: findFeeLedgerPda(program, mint),
+  };
 }
 
 // Borrower, lender and admin payment accounts are the wallet pk
@@ -113,8 +432,43 @@ export async function repayLoan(
   borrowerPaymentAccount: PublicKey,
   lenderPaymentAccount: PublicKey,
   adminPda: PublicKey,
-  adminPaymentAccount: PublicKey
+  adminPaymentAccount: PublicKey,
+  // Payout address or creators payment accounts, for loans paying a creator royalty
+  creatorRoyaltyPaymentAccounts: PublicKey[] = []
 ) {
+  // Desired terms of open loans still tell the mint of the fee ledger
+  const terms = pawnLoanState.terms ?? pawnLoanState.desiredTerms;
+  assert.isNotNull(terms);
+
+  // To silence typescript null warning. nulls should still throw instead of exiting.
+  if (!terms) {
+    return;
+  }
+
+  const creatorRoyalty = pawnLoanState.creatorRoyalty as CreatorRoyalty | null;
+  const remainingAccounts = creatorRoyaltyPaymentAccounts.map((pubkey) => ({
+    pubkey,
+    isSigner: false,
+    isWritable: true,
+  }));
+  if (creatorRoyalty && !creatorRoyalty.payoutAddress) {
+    remainingAccounts.unshift({
+      pubkey: findMetadataPda(pawnLoanState.pawnMint),
+      isSigner: false,
+      isWritable: false,
+    });
+  }
+  // Referral earnings of the borrower referrer then of the lender referrer
+  remainingAccounts.unshift(
+    ...[pawnLoanState.borrowerReferrer, pawnLoanState.lenderReferrer]
+      .filter((referrer): referrer is PublicKey => referrer !== null)
+      .map((referrer) => ({
+        pubkey: findReferralEarningsPda(program, referrer, terms.mint),
+        isSigner: false,
+        isWritable: true,
+      }))
+  );
+
   return await program.methods
     .repayLoan()
     .accounts({
@@ -124,11 +478,18 @@ export async function repayLoan(
       edition: findMasterEditionPda(pawnLoanState.pawnMint),
       borrower: borrowerKeypair.publicKey,
       borrowerPaymentAccount,
+      lender: pawnLoanState.lender,
       lenderPaymentAccount,
       admin: adminPda,
       adminPaymentAccount,
+      referralConfig: findReferralConfigPda(program),
+      feeLedger: findFeeLedgerPda(program, terms.mint),
+      ...loanStatsAccounts(program, terms.mint),
+      borrowerStats: findBorrowerStatsPda(program, borrowerKeypair.publicKey),
+      lenderStats: findLenderStatsPda(program, pawnLoanState.lender),
       mplTokenMetadataProgram: METAPLEX_PROGRAM_ID,
     })
+    .remainingAccounts(remainingAccounts)
     .signers([borrowerKeypair])
     .rpc();
 }
@@ -138,8 +499,19 @@ export async function seizePawn(
   pawnLoanAddress: PublicKey,
   pawnLoanState: PawnLoan,
   lenderKeypair: Keypair,
-  lenderPawnTokenAccount: PublicKey
+  lenderPawnTokenAccount: PublicKey,
+  // Pays and receives the default fee, default to the lender wallet and admin pda for sol loans
+  lenderPaymentAccount: PublicKey | null = null,
+  adminPaymentAccount: PublicKey | null = null
 ) {
+  const terms = pawnLoanState.terms;
+  assert.isNotNull(terms);
+
+  // To silence typescript null warning. nulls should still throw instead of exiting.
+  if (!terms) {
+    return;
+  }
+
   return await program.methods
     .seizePawn()
     .accounts({
@@ -149,12 +521,838 @@ export async function seizePawn(
       edition: findMasterEditionPda(pawnLoanState.pawnMint),
       lender: lenderKeypair.publicKey,
       lenderPawnTokenAccount,
+      lenderPaymentAccount: lenderPaymentAccount ?? lenderKeypair.publicKey,
+      feeSchedule: findFeeSchedulePda(program, terms.mint),
+      admin: findAdminPda(program),
+      adminPaymentAccount: adminPaymentAccount ?? findAdminPda(program),
+      feeLedger: findFeeLedgerPda(program, terms.mint),
+      ...loanStatsAccounts(program, terms.mint),
+      borrowerStats: findBorrowerStatsPda(program, pawnLoanState.borrower),
+      lenderStats: findLenderStatsPda(program, lenderKeypair.publicKey),
       mplTokenMetadataProgram: METAPLEX_PROGRAM_ID,
     })
     .signers([lenderKeypair])
     .rpc();
 }
 
+// Simulates the quote instruction and decodes its return data, at the given timestamp or now.
+export async function quotePayoff(
+  program: Program<PawnShop>,
+  pawnLoanAddress: PublicKey,
+  timestamp: BN | null = null
+): Promise<PayoffQuote> {
+  const pawnLoanState = await fetchPawnLoan(program, pawnLoanAddress);
+  const creatorRoyalty = pawnLoanState.creatorRoyalty as CreatorRoyalty | null;
+  // The pawn metadata lists the creators paid the royalty
+  const remainingAccounts =
+    creatorRoyalty && !creatorRoyalty.payoutAddress
+      ? [
+          {
+            pubkey: findMetadataPda(pawnLoanState.pawnMint),
+            isSigner: false,
+            isWritable: false,
+          },
+        ]
+      : [];
+
+  const { raw } = await program.methods
+    .quotePayoff(timestamp)
+    .accounts({ pawnLoan: pawnLoanAddress })
+    .remainingAccounts(remainingAccounts)
+    .simulate();
+
+  const returnPrefix = `Program return: ${program.programId.toBase58()} `;
+  const returnLog = raw.find((log) => log.startsWith(returnPrefix));
+  assert.isDefined(returnLog, "quotePayoff did not set return data");
+
+  return program.coder.types.decode(
+    "PayoffQuote",
+    Buffer.from((returnLog as string).slice(returnPrefix.length), "base64")
+  );
+}
+
+// Reallocates a pawn loan created before account versioning to the current layout.
+export async function migrateLoan(
+  program: Program<PawnShop>,
+  pawnLoanAddress: PublicKey,
+  payerKeypair: Keypair
+) {
+  return await program.methods
+    .migrateLoan()
+    .accounts({
+      pawnLoan: pawnLoanAddress,
+      payer: payerKeypair.publicKey,
+    })
+    .signers([payerKeypair])
+    .rpc();
+}
+
+export async function createLendingPool(
+  program: Program<PawnShop>,
+  baseKeypair: Keypair,
+  managerKeypair: Keypair,
+  mint: PublicKey
+) {
+  const lendingPool = findProgramAddressSync(
+    [baseKeypair.publicKey.toBuffer(), Buffer.from("lending_pool")],
+    program.programId
+  )[0];
+  const vault = findProgramAddressSync(
+    [lendingPool.toBuffer(), Buffer.from("vault")],
+    program.programId
+  )[0];
+  const lpMint = findProgramAddressSync(
+    [lendingPool.toBuffer(), Buffer.from("lp_mint")],
+    program.programId
+  )[0];
+
+  const signature = await program.methods
+    .createLendingPool()
+    .accounts({
+      base: baseKeypair.publicKey,
+      lendingPool,
+      manager: managerKeypair.publicKey,
+      mint,
+      vault,
+      lpMint,
+    })
+    .signers([baseKeypair, managerKeypair])
+    .rpc();
+
+  return { signature, lendingPool, vault, lpMint };
+}
+
+export async function depositPoolLiquidity(
+  program: Program<PawnShop>,
+  lendingPool: PublicKey,
+  depositorKeypair: Keypair,
+  depositorPaymentAccount: PublicKey,
+  depositorLpTokenAccount: PublicKey,
+  // Pool-owned LP token account receiving the first deposit locked shares
+  lockedSharesAccount: PublicKey,
+  amount: BN
+) {
+  const lendingPoolState = await program.account.lendingPool.fetch(
+    lendingPool
+  );
+  return await program.methods
+    .depositPoolLiquidity(amount)
+    .accounts({
+      lendingPool,
+      vault: lendingPoolState.vault,
+      lpMint: lendingPoolState.lpMint,
+      depositor: depositorKeypair.publicKey,
+      depositorPaymentAccount,
+      depositorLpTokenAccount,
+      lockedSharesAccount,
+    })
+    .signers([depositorKeypair])
+    .rpc();
+}
+
+export async function withdrawPoolLiquidity(
+  program: Program<PawnShop>,
+  lendingPool: PublicKey,
+  depositorKeypair: Keypair,
+  depositorPaymentAccount: PublicKey,
+  depositorLpTokenAccount: PublicKey,
+  shares: BN
+) {
+  const lendingPoolState = await program.account.lendingPool.fetch(
+    lendingPool
+  );
+  return await program.methods
+    .withdrawPoolLiquidity(shares)
+    .accounts({
+      lendingPool,
+      vault: lendingPoolState.vault,
+      lpMint: lendingPoolState.lpMint,
+      depositor: depositorKeypair.publicKey,
+      depositorPaymentAccount,
+      depositorLpTokenAccount,
+    })
+    .signers([depositorKeypair])
+    .rpc();
+}
+
+export async function underwriteLoanFromPool(
+  program: Program<PawnShop>,
+  pawnLoanAddress: PublicKey,
+  pawnLoanState: PawnLoan,
+  lendingPool: PublicKey,
+  managerKeypair: Keypair,
+  borrowerPaymentAccount: PublicKey,
+  // Receives the origination fee, a token account owned by the admin pda
+  adminPaymentAccount: PublicKey
+) {
+  const expectedDesiredTerms = pawnLoanState.desiredTerms;
+  assert.isNotNull(expectedDesiredTerms);
+
+  // To silence typescript null warning. nulls should still throw instead of exiting.
+  if (!expectedDesiredTerms) {
+    return;
+  }
+
+  const lendingPoolState = await program.account.lendingPool.fetch(
+    lendingPool
+  );
+  return await program.methods
+    .underwriteLoanFromPool(expectedDesiredTerms, pawnLoanState.pawnMint)
+    .accounts({
+      pawnLoan: pawnLoanAddress,
+      lendingPool,
+      manager: managerKeypair.publicKey,
+      vault: lendingPoolState.vault,
+      borrowerPaymentAccount,
+      ...underwritingFeeAccounts(
+        program,
+        expectedDesiredTerms.mint,
+        adminPaymentAccount
+      ),
+      borrowerStats: findBorrowerStatsPda(program, pawnLoanState.borrower),
+      lenderStats: findLenderStatsPda(program, lendingPool),
+      ...loanStatsAccounts(program, expectedDesiredTerms.mint),
+    })
+    .signers([managerKeypair])
+    .rpc();
+}
+
+export async function underwriteFromPool(
+  program: Program<PawnShop>,
+  pawnLoanAddress: PublicKey,
+  pawnLoanState: PawnLoan,
+  lendingPool: PublicKey,
+  collection: PublicKey,
+  borrowerPaymentAccount: PublicKey,
+  // Receives the origination fee, a token account owned by the admin pda
+  adminPaymentAccount: PublicKey
+) {
+  const lendingPoolState = await program.account.lendingPool.fetch(
+    lendingPool
+  );
+  return await program.methods
+    .underwriteFromPool()
+    .accounts({
+      pawnLoan: pawnLoanAddress,
+      lendingPool,
+      poolCollection: findPoolCollectionPda(program, lendingPool, collection),
+      pawnMetadata: findMetadataPda(pawnLoanState.pawnMint),
+      vault: lendingPoolState.vault,
+      borrowerPaymentAccount,
+      ...underwritingFeeAccounts(
+        program,
+        lendingPoolState.mint,
+        adminPaymentAccount
+      ),
+      payer: program.provider.wallet.publicKey,
+      borrowerStats: findBorrowerStatsPda(program, pawnLoanState.borrower),
+      lenderStats: findLenderStatsPda(program, lendingPool),
+      ...loanStatsAccounts(program, lendingPoolState.mint),
+    })
+    .rpc();
+}
+
+export async function addPoolCollection(
+  program: Program<PawnShop>,
+  lendingPool: PublicKey,
+  managerKeypair: Keypair,
+  collection: PublicKey,
+  maxPrincipalAmount: BN
+) {
+  return await program.methods
+    .addPoolCollection(collection, maxPrincipalAmount)
+    .accounts({
+      lendingPool,
+      poolCollection: findPoolCollectionPda(program, lendingPool, collection),
+      manager: managerKeypair.publicKey,
+    })
+    .signers([managerKeypair])
+    .rpc();
+}
+
+export function findPoolCollectionPda(
+  program: Program<PawnShop>,
+  lendingPool: PublicKey,
+  collection: PublicKey
+): PublicKey {
+  return findProgramAddressSync(
+    [lendingPool.toBuffer(), collection.toBuffer(), Buffer.from("pool_collection")],
+    program.programId
+  )[0];
+}
+
+export async function seizePawnForPool(
+  program: Program<PawnShop>,
+  pawnLoanAddress: PublicKey,
+  pawnLoanState: PawnLoan,
+  poolPawnTokenAccount: PublicKey
+) {
+  return await program.methods
+    .seizePawnForPool()
+    .accounts({
+      pawnLoan: pawnLoanAddress,
+      lendingPool: pawnLoanState.lender,
+      pawnTokenAccount: pawnLoanState.pawnTokenAccount,
+      pawnMint: pawnLoanState.pawnMint,
+      edition: findMasterEditionPda(pawnLoanState.pawnMint),
+      poolPawnTokenAccount,
+      ...loanStatsAccounts(program, (pawnLoanState.terms as LoanTerms).mint),
+      payer: program.provider.wallet.publicKey,
+      borrowerStats: findBorrowerStatsPda(program, pawnLoanState.borrower),
+      lenderStats: findLenderStatsPda(program, pawnLoanState.lender),
+      mplTokenMetadataProgram: METAPLEX_PROGRAM_ID,
+    })
+    .rpc();
+}
+
+export async function withdrawPoolPawn(
+  program: Program<PawnShop>,
+  lendingPool: PublicKey,
+  managerKeypair: Keypair,
+  poolPawnTokenAccount: PublicKey,
+  managerPawnTokenAccount: PublicKey
+) {
+  return await program.methods
+    .withdrawPoolPawn()
+    .accounts({
+      lendingPool,
+      manager: managerKeypair.publicKey,
+      poolPawnTokenAccount,
+      managerPawnTokenAccount,
+    })
+    .signers([managerKeypair])
+    .rpc();
+}
+
+export async function createPriceFeed(
+  program: Program<PawnShop>,
+  authorityKeypair: Keypair,
+  collection: PublicKey,
+  quoteMint: PublicKey
+) {
+  const priceFeed = findProgramAddressSync(
+    [
+      authorityKeypair.publicKey.toBuffer(),
+      collection.toBuffer(),
+      quoteMint.toBuffer(),
+      Buffer.from("price_feed"),
+    ],
+    program.programId
+  )[0];
+
+  const signature = await program.methods
+    .createPriceFeed(collection, quoteMint)
+    .accounts({ priceFeed, authority: authorityKeypair.publicKey })
+    .signers([authorityKeypair])
+    .rpc();
+
+  return { signature, priceFeed };
+}
+
+export async function updatePriceFeed(
+  program: Program<PawnShop>,
+  priceFeed: PublicKey,
+  authorityKeypair: Keypair,
+  price: BN,
+  confidence: BN
+) {
+  return await program.methods
+    .updatePriceFeed(price, confidence)
+    .accounts({ priceFeed, authority: authorityKeypair.publicKey })
+    .signers([authorityKeypair])
+    .rpc();
+}
+
+export async function initCollectionRegistry(
+  program: Program<PawnShop>,
+  feeCollectorKeypair: Keypair,
+  enabled: boolean
+) {
+  return await program.methods
+    .initCollectionRegistry(enabled)
+    .accounts({
+      collectionRegistry: findCollectionRegistryPda(program),
+      feeCollector: feeCollectorKeypair.publicKey,
+    })
+    .signers([feeCollectorKeypair])
+    .rpc();
+}
+
+export async function setCollectionRegistryEnabled(
+  program: Program<PawnShop>,
+  feeCollectorKeypair: Keypair,
+  enabled: boolean
+) {
+  return await program.methods
+    .setCollectionRegistryEnabled(enabled)
+    .accounts({
+      collectionRegistry: findCollectionRegistryPda(program),
+      feeCollector: feeCollectorKeypair.publicKey,
+    })
+    .signers([feeCollectorKeypair])
+    .rpc();
+}
+
+export async function registerCollection(
+  program: Program<PawnShop>,
+  feeCollectorKeypair: Keypair,
+  collection: PublicKey,
+  mint: PublicKey,
+  maxPrincipalAmount: BN,
+  maxDuration: BN
+) {
+  return await program.methods
+    .registerCollection(collection, mint, maxPrincipalAmount, maxDuration)
+    .accounts({
+      registeredCollection: findRegisteredCollectionPda(program, collection),
+      feeCollector: feeCollectorKeypair.publicKey,
+    })
+    .signers([feeCollectorKeypair])
+    .rpc();
+}
+
+export async function setCollectionRoyalty(
+  program: Program<PawnShop>,
+  feeCollectorKeypair: Keypair,
+  collection: PublicKey,
+  creatorRoyalty: CreatorRoyalty | null
+) {
+  return await program.methods
+    .setCollectionRoyalty(creatorRoyalty)
+    .accounts({
+      registeredCollection: findRegisteredCollectionPda(program, collection),
+      feeCollector: feeCollectorKeypair.publicKey,
+    })
+    .signers([feeCollectorKeypair])
+    .rpc();
+}
+
+export function findCollectionRegistryPda(program: Program<PawnShop>): PublicKey {
+  return findProgramAddressSync(
+    [Buffer.from("collection_registry")],
+    program.programId
+  )[0];
+}
+
+export function findRegisteredCollectionPda(
+  program: Program<PawnShop>,
+  collection: PublicKey
+): PublicKey {
+  return findProgramAddressSync(
+    [Buffer.from("registered_collection"), collection.toBuffer()],
+    program.programId
+  )[0];
+}
+
+export type FeeTier = IdlTypes<PawnShop>["FeeTier"];
+
+export async function createFeeSchedule(
+  program: Program<PawnShop>,
+  feeCollectorKeypair: Keypair,
+  mint: PublicKey,
+  tiers: FeeTier[]
+) {
+  const feeSchedule = findFeeSchedulePda(program, mint);
+
+  const signature = await program.methods
+    .createFeeSchedule(mint, tiers)
+    .accounts({
+      feeSchedule,
+      feeCollector: feeCollectorKeypair.publicKey,
+    })
+    .signers([feeCollectorKeypair])
+    .rpc();
+
+  return { signature, feeSchedule };
+}
+
+export async function updateFeeSchedule(
+  program: Program<PawnShop>,
+  feeCollectorKeypair: Keypair,
+  mint: PublicKey,
+  tiers: FeeTier[]
+) {
+  return await program.methods
+    .updateFeeSchedule(tiers)
+    .accounts({
+      feeSchedule: findFeeSchedulePda(program, mint),
+      feeCollector: feeCollectorKeypair.publicKey,
+    })
+    .signers([feeCollectorKeypair])
+    .rpc();
+}
+
+export async function setOriginationFee(
+  program: Program<PawnShop>,
+  feeCollectorKeypair: Keypair,
+  mint: PublicKey,
+  originationFeeBps: BN
+) {
+  return await program.methods
+    .setOriginationFee(originationFeeBps)
+    .accounts({
+      feeSchedule: findFeeSchedulePda(program, mint),
+      feeCollector: feeCollectorKeypair.publicKey,
+    })
+    .signers([feeCollectorKeypair])
+    .rpc();
+}
+
+export async function setDefaultFee(
+  program: Program<PawnShop>,
+  feeCollectorKeypair: Keypair,
+  mint: PublicKey,
+  defaultFeeBps: BN
+) {
+  return await program.methods
+    .setDefaultFee(defaultFeeBps)
+    .accounts({
+      feeSchedule: findFeeSchedulePda(program, mint),
+      feeCollector: feeCollectorKeypair.publicKey,
+    })
+    .signers([feeCollectorKeypair])
+    .rpc();
+}
+
+export async function createLenderVolume(
+  program: Program<PawnShop>,
+  lenderKeypair: Keypair,
+  mint: PublicKey
+) {
+  const lenderVolume = findLenderVolumePda(program, lenderKeypair.publicKey, mint);
+
+  const signature = await program.methods
+    .createLenderVolume(mint)
+    .accounts({ lenderVolume, lender: lenderKeypair.publicKey })
+    .signers([lenderKeypair])
+    .rpc();
+
+  return { signature, lenderVolume };
+}
+
+// Sweeps each admin token account into the fee collector token account of the same mint
+export async function sweepAdminFees(
+  program: Program<PawnShop>,
+  feeCollectorKeypair: Keypair,
+  // Admin token account, fee collector token account and mint of the fees swept
+  sweeps: [PublicKey, PublicKey, PublicKey][]
+) {
+  return await program.methods
+    .sweepAdminFees()
+    .accounts({
+      feeCollector: feeCollectorKeypair.publicKey,
+      admin: findAdminPda(program),
+      feeSplit: findFeeSplitPda(program),
+    })
+    .remainingAccounts(
+      sweeps.flatMap(([adminTokenAccount, feeCollectorTokenAccount, mint]) => [
+        { pubkey: adminTokenAccount, isSigner: false, isWritable: true },
+        { pubkey: feeCollectorTokenAccount, isSigner: false, isWritable: true },
+        {
+          pubkey: findFeeLedgerPda(program, mint),
+          isSigner: false,
+          isWritable: true,
+        },
+      ])
+    )
+    .signers([feeCollectorKeypair])
+    .rpc();
+}
+
+export type FeeSplitRecipient = IdlTypes<PawnShop>["FeeSplitRecipient"];
+
+export async function initFeeSplit(
+  program: Program<PawnShop>,
+  feeCollectorKeypair: Keypair,
+  recipients: FeeSplitRecipient[]
+) {
+  return await program.methods
+    .initFeeSplit(recipients)
+    .accounts({
+      feeSplit: findFeeSplitPda(program),
+      feeCollector: feeCollectorKeypair.publicKey,
+    })
+    .signers([feeCollectorKeypair])
+    .rpc();
+}
+
+export async function updateFeeSplit(
+  program: Program<PawnShop>,
+  feeCollectorKeypair: Keypair,
+  recipients: FeeSplitRecipient[]
+) {
+  return await program.methods
+    .updateFeeSplit(recipients)
+    .accounts({
+      feeSplit: findFeeSplitPda(program),
+      feeCollector: feeCollectorKeypair.publicKey,
+    })
+    .signers([feeCollectorKeypair])
+    .rpc();
+}
+
+export async function withdrawAdminFees(
+  program: Program<PawnShop>,
+  feeCollectorKeypair: Keypair,
+  feeCollectorPaymentAccount: PublicKey,
+  adminPaymentAccount: PublicKey,
+  // Mint of the fees withdrawn, the native mint for sol
+  mint: PublicKey,
+  // Payment accounts of the fee split recipients, in order, if a fee split is configured
+  recipientPaymentAccounts: PublicKey[] = []
+) {
+  return await program.methods
+    .withdrawAdminFees()
+    .accounts({
+      feeCollector: feeCollectorKeypair.publicKey,
+      feeCollectorPaymentAccount,
+      admin: findAdminPda(program),
+      adminPaymentAccount,
+      feeSplit: findFeeSplitPda(program),
+      feeLedger: findFeeLedgerPda(program, mint),
+    })
+    .remainingAccounts(
+      recipientPaymentAccounts.map((pubkey) => ({
+        pubkey,
+        isSigner: false,
+        isWritable: true,
+      }))
+    )
+    .signers([feeCollectorKeypair])
+    .rpc();
+}
+
+export async function initProtocolStats(
+  program: Program<PawnShop>,
+  feeCollectorKeypair: Keypair
+) {
+  return await program.methods
+    .initProtocolStats()
+    .accounts({
+      protocolStats: findProtocolStatsPda(program),
+      feeCollector: feeCollectorKeypair.publicKey,
+    })
+    .signers([feeCollectorKeypair])
+    .rpc();
+}
+
+export function findProtocolStatsPda(program: Program<PawnShop>): PublicKey {
+  return findProgramAddressSync(
+    [Buffer.from("protocol_stats")],
+    program.programId
+  )[0];
+}
+
+export function findMintStatsPda(
+  program: Program<PawnShop>,
+  mint: PublicKey
+): PublicKey {
+  return findProgramAddressSync(
+    [mint.toBuffer(), Buffer.from("mint_stats")],
+    program.programId
+  )[0];
+}
+
+// Statistics maintained by the instructions changing the status of a loan of the mint
+export function loanStatsAccounts(
+  program: Program<PawnShop>,
+  mint: PublicKey | null
+) {
+  const protocolStats = findProtocolStatsPda(program);
+  return {
+    protocolStats,
+    mintStats: mint ? findMintStatsPda(program, mint) : protocolStats,
+  };
+}
+
+export function findBorrowerStatsPda(
+  program: Program<PawnShop>,
+  borrower: PublicKey
+): PublicKey {
+  return findProgramAddressSync(
+    [borrower.toBuffer(), Buffer.from("borrower_stats")],
+    program.programId
+  )[0];
+}
+
+export function findLenderStatsPda(
+  program: Program<PawnShop>,
+  lender: PublicKey
+): PublicKey {
+  return findProgramAddressSync(
+    [lender.toBuffer(), Buffer.from("lender_stats")],
+    program.programId
+  )[0];
+}
+
+export function findFeeLedgerPda(
+  program: Program<PawnShop>,
+  mint: PublicKey
+): PublicKey {
+  return findProgramAddressSync(
+    [mint.toBuffer(), Buffer.from("fee_ledger")],
+    program.programId
+  )[0];
+}
+
+export function findFeeSplitPda(program: Program<PawnShop>): PublicKey {
+  return findProgramAddressSync(
+    [Buffer.from("fee_split")],
+    program.programId
+  )[0];
+}
+
+// Loan of the borrower on the pawn, the nonce telling apart successive loans
+export function findPawnLoanPda(
+  program: Program<PawnShop>,
+  pawnMint: PublicKey,
+  borrower: PublicKey,
+  nonce: BN
+): PublicKey {
+  return findProgramAddressSync(
+    [
+      pawnMint.toBuffer(),
+      borrower.toBuffer(),
+      nonce.toArrayLike(Buffer, "le", 8),
+      Buffer.from("pawn_loan"),
+    ],
+    program.programId
+  )[0];
+}
+
+export function findAdminPda(program: Program<PawnShop>): PublicKey {
+  return findProgramAddressSync([Buffer.from("admin")], program.programId)[0];
+}
+
+export function findFeeSchedulePda(
+  program: Program<PawnShop>,
+  mint: PublicKey
+): PublicKey {
+  return findProgramAddressSync(
+    [mint.toBuffer(), Buffer.from("fee_schedule")],
+    program.programId
+  )[0];
+}
+
+export function findLenderVolumePda(
+  program: Program<PawnShop>,
+  lender: PublicKey,
+  mint: PublicKey
+): PublicKey {
+  return findProgramAddressSync(
+    [lender.toBuffer(), mint.toBuffer(), Buffer.from("lender_volume")],
+    program.programId
+  )[0];
+}
+
+export async function initReferralConfig(
+  program: Program<PawnShop>,
+  feeCollectorKeypair: Keypair,
+  referralFeeShareBps: BN
+) {
+  return await program.methods
+    .initReferralConfig(referralFeeShareBps)
+    .accounts({
+      referralConfig: findReferralConfigPda(program),
+      feeCollector: feeCollectorKeypair.publicKey,
+    })
+    .signers([feeCollectorKeypair])
+    .rpc();
+}
+
+export function findReferralConfigPda(program: Program<PawnShop>): PublicKey {
+  return findProgramAddressSync(
+    [Buffer.from("referral_config")],
+    program.programId
+  )[0];
+}
+
+export async function registerReferrer(
+  program: Program<PawnShop>,
+  feeCollectorKeypair: Keypair,
+  referrer: PublicKey
+) {
+  return await program.methods
+    .registerReferrer(referrer)
+    .accounts({
+      registeredReferrer: findRegisteredReferrerPda(program, referrer),
+      feeCollector: feeCollectorKeypair.publicKey,
+    })
+    .signers([feeCollectorKeypair])
+    .rpc();
+}
+
+export async function claimReferralFees(
+  program: Program<PawnShop>,
+  referrerKeypair: Keypair,
+  referrerPaymentAccount: PublicKey,
+  adminPaymentAccount: PublicKey,
+  // Mint of the fees claimed, the native mint for sol
+  mint: PublicKey
+) {
+  return await program.methods
+    .claimReferralFees()
+    .accounts({
+      referralEarnings: findReferralEarningsPda(
+        program,
+        referrerKeypair.publicKey,
+        mint
+      ),
+      referrer: referrerKeypair.publicKey,
+      referrerPaymentAccount,
+      admin: findAdminPda(program),
+      adminPaymentAccount,
+      feeLedger: findFeeLedgerPda(program, mint),
+    })
+    .signers([referrerKeypair])
+    .rpc();
+}
+
+export function findRegisteredReferrerPda(
+  program: Program<PawnShop>,
+  referrer: PublicKey
+): PublicKey {
+  return findProgramAddressSync(
+    [Buffer.from("registered_referrer"), referrer.toBuffer()],
+    program.programId
+  )[0];
+}
+
+// Registered referrer expected last in the remaining accounts of referred loans
+export function registeredReferrerAccounts(
+  program: Program<PawnShop>,
+  referrer: PublicKey | null
+) {
+  return referrer
+    ? [
+        {
+          pubkey: findRegisteredReferrerPda(program, referrer),
+          isSigner: false,
+          isWritable: false,
+        },
+      ]
+    : [];
+}
+
+export function findReferralEarningsPda(
+  program: Program<PawnShop>,
+  referrer: PublicKey,
+  mint: PublicKey
+): PublicKey {
+  return findProgramAddressSync(
+    [referrer.toBuffer(), mint.toBuffer(), Buffer.from("referral_earnings")],
+    program.programId
+  )[0];
+}
+
+export function findMetadataPda(mint: PublicKey): PublicKey {
+  const [metadata] = findProgramAddressSync(
+    [Buffer.from("metadata"), METAPLEX_PROGRAM_ID.toBuffer(), mint.toBuffer()],
+    METAPLEX_PROGRAM_ID
+  );
+  return metadata;
+}
+
 export function findMasterEditionPda(mint: PublicKey): PublicKey {
   const [masterEdition] = findProgramAddressSync(
     [
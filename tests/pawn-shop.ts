@@ -9,17 +9,65 @@ import {
 import { assert } from "chai";
 import { findProgramAddressSync } from "@project-serum/anchor/dist/cjs/utils/pubkey";
 import {
+  createNft,
+  createNftInVerifiedCollection,
   delay,
   deserializeTokenAccountInfo,
   getBorrowerAndLenderSolBalance,
   getBorrowerAndLenderTokenBalance,
+  getComputeUnitsConsumed,
+  reportComputeUnits,
 } from "./utils";
 import {
   LoanTerms,
+  LoanToValueGuard,
+  createPriceFeed,
+  updatePriceFeed,
+  approveLenderDelegate,
+  underwriteLoanWithDelegate,
+  createLendingPool,
+  depositPoolLiquidity,
+  withdrawPoolLiquidity,
+  underwriteLoanFromPool,
+  underwriteFromPool,
+  addPoolCollection,
+  initCollectionRegistry,
+  setCollectionRegistryEnabled,
+  registerCollection,
+  setCollectionRoyalty,
+  initReferralConfig,
+  registerReferrer,
+  claimReferralFees,
+  findReferralEarningsPda,
+  createFeeSchedule,
+  createLenderVolume,
+  updateFeeSchedule,
+  setOriginationFee,
+  setDefaultFee,
+  sweepAdminFees,
+  initFeeSplit,
+  findFeeSplitPda,
+  findFeeLedgerPda,
+  initProtocolStats,
+  loanStatsAccounts,
+  findProtocolStatsPda,
+  findMintStatsPda,
+  findBorrowerStatsPda,
+  findLenderStatsPda,
+  updateFeeSplit,
+  withdrawAdminFees,
+  migrateLoan,
+  fetchPawnLoan,
+  findFeeSchedulePda,
+  quotePayoff,
   repayLoanInSol,
   repayLoan,
   requestLoan,
+  requestLoanForPawn,
+  findPawnLoanPda,
   seizePawn,
+  seizePawnForPool,
+  withdrawPoolPawn,
   underwriteLoan,
   findMasterEditionPda,
 } from "./pawn-shop-sdk";
@@ -50,15 +98,29 @@ const DEFAULT_LOAN_AMOUNT = 10;
 const TERMS_VALID: LoanTerms = {
   principalAmount: new BN(DEFAULT_LOAN_AMOUNT),
   mint: NATIVE_MINT,
-  annualPercentageRateBps: new BN(1_000), // 10%
+  interestModel: {
+    annualPercentageRate: { annualPercentageRateBps: new BN(1_000) }, // 10%
+  },
   duration: new BN(7 * MILLISECONDS_PER_DAY),
+  minimumPeriodRatioBps: null,
+};
+
+const TERMS_FIXED_INTEREST: LoanTerms = {
+  principalAmount: new BN(DEFAULT_LOAN_AMOUNT),
+  mint: NATIVE_MINT,
+  interestModel: { fixedInterest: { interestAmount: new BN(1_000) } },
+  duration: new BN(7 * MILLISECONDS_PER_DAY),
+  minimumPeriodRatioBps: null,
 };
 
 const TERMS_SUPER_SHORT_LOAN: LoanTerms = {
   principalAmount: new BN(DEFAULT_LOAN_AMOUNT),
   mint: NATIVE_MINT,
-  annualPercentageRateBps: new BN(1_000), // 10%
+  interestModel: {
+    annualPercentageRate: { annualPercentageRateBps: new BN(1_000) }, // 10%
+  },
   duration: new BN(1),
+  minimumPeriodRatioBps: null,
 };
 
 describe("PawnHub", () => {
@@ -109,6 +171,14 @@ describe("PawnHub", () => {
       await provider.connection.requestAirdrop(ADMIN_PDA, 1_000_000_000),
       "confirmed"
     );
+    // Fee collector pays the rent of the protocol configuration accounts
+    await provider.connection.confirmTransaction(
+      await provider.connection.requestAirdrop(
+        FEE_COLLECTOR_KEYPAIR.publicKey,
+        1_000_000_000
+      ),
+      "confirmed"
+    );
 
     mintA = await Token.createMint(
       provider.connection,
@@ -126,6 +196,11 @@ describe("PawnHub", () => {
     );
     adminMintATokenAccount = await mintA.createAccount(ADMIN_PDA);
 
+    // Loan requests stay permissionless unless a test enables the registry.
+    await initCollectionRegistry(program, FEE_COLLECTOR_KEYPAIR, false);
+    await initReferralConfig(program, FEE_COLLECTOR_KEYPAIR, new BN(2_500));
+    await initProtocolStats(program, FEE_COLLECTOR_KEYPAIR);
+
     await mintA.mintTo(lenderMintATokenAccount, LENDER_KEYPAIR, [], 1_000_000);
     await mintA.mintTo(
       borrowerMintATokenAccount,
@@ -139,6 +214,7 @@ describe("PawnHub", () => {
       mint: mintA.publicKey,
       annualPercentageRateBps: new BN(1_000),
       duration: new BN(7 * MILLISECONDS_PER_DAY),
+      minimumPeriodRatioBps: null,
     };
   });
 
@@ -236,9 +312,7 @@ describe("PawnHub", () => {
         TERMS_VALID
       );
 
-      const pawnLoanState = await program.account.pawnLoan.fetch(
-        pawnLoanAddress
-      );
+      const pawnLoanState = await fetchPawnLoan(program, pawnLoanAddress);
       const desiredTerms = pawnLoanState.desiredTerms;
 
       assert.isTrue(pawnLoanState.borrower.equals(BORROWER_KEYPAIR.publicKey));
@@ -247,8 +321,8 @@ describe("PawnHub", () => {
       );
       assert.isTrue(desiredTerms?.mint.equals(TERMS_VALID.mint));
       assert.isTrue(
-        desiredTerms?.annualPercentageRateBps.eq(
-          TERMS_VALID.annualPercentageRateBps
+        desiredTerms?.interestModel.annualPercentageRate?.annualPercentageRateBps.eq(
+          TERMS_VALID.interestModel.annualPercentageRate?.annualPercentageRateBps
         )
       );
       assert.isTrue(desiredTerms?.duration.eq(TERMS_VALID.duration));
@@ -256,6 +330,78 @@ describe("PawnHub", () => {
       assert.isNull(pawnLoanState.terms);
     });
 
+    it("Creates loans in the current layout, which cannot be migrated", async () => {
+      const { pawnLoan: pawnLoanAddress } = await requestLoan(
+        program,
+        baseKeypair,
+        BORROWER_KEYPAIR,
+        borrowerPawnTokenAccount,
+        pawnMint.publicKey,
+        TERMS_VALID
+      );
+
+      const pawnLoanState = await fetchPawnLoan(program, pawnLoanAddress);
+      assert.strictEqual(pawnLoanState.version, 2);
+
+      try {
+        await migrateLoan(program, pawnLoanAddress, BORROWER_KEYPAIR);
+        assert.ok(false);
+      } catch (e) {
+        const err = e as AnchorError;
+        assert.strictEqual(err.error.errorMessage, "PawnLoanAlreadyMigrated");
+      }
+    });
+
+    it("Requests a loan at the address derived from the pawn", async () => {
+      const { pawnLoan: pawnLoanAddress } = await requestLoanForPawn(
+        program,
+        BORROWER_KEYPAIR,
+        borrowerPawnTokenAccount,
+        pawnMint.publicKey,
+        new BN(0),
+        TERMS_VALID
+      );
+      assert.isTrue(
+        pawnLoanAddress.equals(
+          findPawnLoanPda(
+            program,
+            pawnMint.publicKey,
+            BORROWER_KEYPAIR.publicKey,
+            new BN(0)
+          )
+        )
+      );
+
+      let pawnLoanState = await fetchPawnLoan(program, pawnLoanAddress);
+      assert.isTrue(pawnLoanState.seedNonce?.eq(new BN(0)));
+
+      // The pawn loan signs with the seeds of its address to thaw the pawn
+      await underwriteLoan(
+        program,
+        pawnLoanAddress,
+        pawnLoanState,
+        LENDER_KEYPAIR,
+        LENDER_KEYPAIR.publicKey,
+        BORROWER_KEYPAIR.publicKey
+      );
+      pawnLoanState = await fetchPawnLoan(program, pawnLoanAddress);
+      await repayLoanInSol(
+        program,
+        pawnLoanAddress,
+        pawnLoanState,
+        BORROWER_KEYPAIR,
+        ADMIN_PDA
+      );
+
+      const pawnTokenAccountInfo =
+        await program.provider.connection.getAccountInfo(
+          borrowerPawnTokenAccount
+        );
+      assert.isFalse(
+        deserializeTokenAccountInfo(pawnTokenAccountInfo?.data)?.isFrozen
+      );
+    });
+
     it("Freezes NFT into its original account under the pawn loan's control", async () => {
       const { pawnTokenAccount, pawnLoan } = await requestLoan(
         program,
@@ -295,7 +441,63 @@ describe("PawnHub", () => {
     it("Should throw if invalid APR requested", async () => {
       let termsInvalid = {} as LoanTerms;
       Object.assign(termsInvalid, TERMS_VALID);
-      termsInvalid.annualPercentageRateBps = new BN(0);
+      termsInvalid.interestModel = {
+        annualPercentageRate: { annualPercentageRateBps: new BN(0) },
+      };
+
+      await testInvalidTerms(
+        program,
+        baseKeypair,
+        BORROWER_KEYPAIR,
+        borrowerPawnTokenAccount,
+        pawnMint.publicKey,
+        termsInvalid
+      );
+    });
+
+    it("Should throw if zero fixed interest requested", async () => {
+      let termsInvalid = {} as LoanTerms;
+      Object.assign(termsInvalid, TERMS_FIXED_INTEREST);
+      termsInvalid.interestModel = {
+        fixedInterest: { interestAmount: new BN(0) },
+      };
+
+      await testInvalidTerms(
+        program,
+        baseKeypair,
+        BORROWER_KEYPAIR,
+        borrowerPawnTokenAccount,
+        pawnMint.publicKey,
+        termsInvalid
+      );
+    });
+
+    it("Should throw if compounding interest overflows before it stops growing", async () => {
+      let termsInvalid = {} as LoanTerms;
+      Object.assign(termsInvalid, TERMS_VALID);
+      // Fits at the end of the loan, not at the end of the grace period
+      termsInvalid.interestModel = {
+        compoundingAnnualPercentageRate: {
+          annualPercentageRateBps: new BN(1_000_000),
+          compoundingPeriod: new BN(1),
+        },
+      };
+      termsInvalid.duration = new BN(7 * 24 * 60 * 60);
+
+      await testInvalidTerms(
+        program,
+        baseKeypair,
+        BORROWER_KEYPAIR,
+        borrowerPawnTokenAccount,
+        pawnMint.publicKey,
+        termsInvalid
+      );
+    });
+
+    it("Should throw if minimum period ratio above protocol maximum requested", async () => {
+      let termsInvalid = {} as LoanTerms;
+      Object.assign(termsInvalid, TERMS_VALID);
+      termsInvalid.minimumPeriodRatioBps = new BN(5_001);
 
       await testInvalidTerms(
         program,
@@ -323,6 +525,95 @@ describe("PawnHub", () => {
     });
   });
 
+  describe("Request Loan - with collection registry", () => {
+    let collection: PublicKey;
+    let mint: Token;
+    let tokenAccount: PublicKey;
+
+    before(async () => {
+      await setCollectionRegistryEnabled(program, FEE_COLLECTOR_KEYPAIR, true);
+    });
+
+    after(async () => {
+      await setCollectionRegistryEnabled(program, FEE_COLLECTOR_KEYPAIR, false);
+    });
+
+    beforeEach(async () => {
+      ({ collection, mint, tokenAccount } = await createNftInVerifiedCollection(
+        provider,
+        BORROWER_KEYPAIR
+      ));
+    });
+
+    it("Requests a loan on a registered collection", async () => {
+      await registerCollection(
+        program,
+        FEE_COLLECTOR_KEYPAIR,
+        collection,
+        mintA.publicKey,
+        new BN(DEFAULT_LOAN_AMOUNT),
+        termsUsdc.duration
+      );
+
+      const { pawnLoan } = await requestLoan(
+        program,
+        baseKeypair,
+        BORROWER_KEYPAIR,
+        tokenAccount,
+        mint.publicKey,
+        termsUsdc,
+        collection
+      );
+
+      const pawnLoanState = await fetchPawnLoan(program, pawnLoan);
+      assert.strictEqual(Object.keys(pawnLoanState.status)[0], "open");
+    });
+
+    it("Throws error if the collection is not registered", async () => {
+      try {
+        await requestLoan(
+          program,
+          baseKeypair,
+          BORROWER_KEYPAIR,
+          tokenAccount,
+          mint.publicKey,
+          termsUsdc
+        );
+        assert.ok(false);
+      } catch (e) {
+        const err = e as AnchorError;
+        assert.strictEqual(err.error.errorMessage, "CollectionNotRegistered");
+      }
+    });
+
+    it("Throws error if the terms exceed the collection limits", async () => {
+      await registerCollection(
+        program,
+        FEE_COLLECTOR_KEYPAIR,
+        collection,
+        mintA.publicKey,
+        new BN(DEFAULT_LOAN_AMOUNT - 1),
+        termsUsdc.duration
+      );
+
+      try {
+        await requestLoan(
+          program,
+          baseKeypair,
+          BORROWER_KEYPAIR,
+          tokenAccount,
+          mint.publicKey,
+          termsUsdc,
+          collection
+        );
+        assert.ok(false);
+      } catch (e) {
+        const err = e as AnchorError;
+        assert.strictEqual(err.error.errorMessage, "CollectionLimitExceeded");
+      }
+    });
+  });
+
   describe("Underwrite Loan - in SOL", () => {
     let pawnLoanAddress: PublicKey;
     let pawnLoanState: any;
@@ -343,7 +634,7 @@ describe("PawnHub", () => {
         TERMS_VALID
       ));
 
-      pawnLoanState = await program.account.pawnLoan.fetch(pawnLoanAddress);
+      pawnLoanState = await fetchPawnLoan(program, pawnLoanAddress);
 
       pawnTokenAccountInfo = await program.provider.connection.getAccountInfo(
         pawnTokenAccount
@@ -365,9 +656,7 @@ describe("PawnHub", () => {
         BORROWER_KEYPAIR.publicKey
       );
 
-      const pawnLoanAfter = await program.account.pawnLoan.fetch(
-        pawnLoanAddress
-      );
+      const pawnLoanAfter = await fetchPawnLoan(program, pawnLoanAddress);
       const terms = pawnLoanAfter.terms;
 
       assert.isTrue(
@@ -448,12 +737,22 @@ describe("PawnHub", () => {
 
       try {
         await program.methods
-          .underwriteLoan(expectedDesiredTerms, expectedPawnMint)
+          .underwriteLoan(expectedDesiredTerms, expectedPawnMint, null, null)
           .accounts({
             pawnLoan: pawnLoanAddress,
             lender: LENDER_KEYPAIR.publicKey,
             lenderPaymentAccount: LENDER_KEYPAIR.publicKey,
             borrowerPaymentAccount: BORROWER_KEYPAIR.publicKey,
+            feeSchedule: findFeeSchedulePda(program, expectedDesiredTerms.mint),
+            admin: ADMIN_PDA,
+            adminPaymentAccount: ADMIN_PDA,
+            feeLedger: findFeeLedgerPda(program, expectedDesiredTerms.mint),
+            ...loanStatsAccounts(program, expectedDesiredTerms.mint),
+            borrowerStats: findBorrowerStatsPda(
+              program,
+              BORROWER_KEYPAIR.publicKey
+            ),
+            lenderStats: findLenderStatsPda(program, LENDER_KEYPAIR.publicKey),
           })
           .signers([LENDER_KEYPAIR])
           .rpc();
@@ -467,12 +766,22 @@ describe("PawnHub", () => {
     it("Throws error if desired loan mint not matched", async () => {
       try {
         await program.methods
-          .underwriteLoan(expectedDesiredTerms, mintA.publicKey)
+          .underwriteLoan(expectedDesiredTerms, mintA.publicKey, null, null)
           .accounts({
             pawnLoan: pawnLoanAddress,
             lender: LENDER_KEYPAIR.publicKey,
             lenderPaymentAccount: LENDER_KEYPAIR.publicKey,
             borrowerPaymentAccount: BORROWER_KEYPAIR.publicKey,
+            feeSchedule: findFeeSchedulePda(program, expectedDesiredTerms.mint),
+            admin: ADMIN_PDA,
+            adminPaymentAccount: ADMIN_PDA,
+            feeLedger: findFeeLedgerPda(program, expectedDesiredTerms.mint),
+            ...loanStatsAccounts(program, expectedDesiredTerms.mint),
+            borrowerStats: findBorrowerStatsPda(
+              program,
+              BORROWER_KEYPAIR.publicKey
+            ),
+            lenderStats: findLenderStatsPda(program, LENDER_KEYPAIR.publicKey),
           })
           .signers([LENDER_KEYPAIR])
           .rpc();
@@ -498,7 +807,7 @@ describe("PawnHub", () => {
         termsUsdc
       ));
 
-      pawnLoanState = await program.account.pawnLoan.fetch(pawnLoanAddress);
+      pawnLoanState = await fetchPawnLoan(program, pawnLoanAddress);
     });
 
     it("Transfers the loan amount from lender to the borrower -- in SPL token", async () => {
@@ -542,174 +851,451 @@ describe("PawnHub", () => {
     });
   });
 
-  describe("Repay Loan - in SOL", () => {
+  describe("Underwrite Loan - with loan-to-value guard", () => {
+    const COLLECTION_PRICE = 2 * DEFAULT_LOAN_AMOUNT;
+    const GUARD: LoanToValueGuard = {
+      maxLoanToValueBps: new BN(5_000), // 50%
+      maxPriceAge: new BN(60),
+      maxConfidenceBps: new BN(1_000), // 10%
+    };
+
+    let priceFeed: PublicKey;
     let pawnLoanAddress: PublicKey;
     let pawnLoanState: any;
 
     beforeEach(async () => {
-      ({ pawnLoan: pawnLoanAddress } = await requestLoan(
+      const { collection, mint, tokenAccount } =
+        await createNftInVerifiedCollection(provider, BORROWER_KEYPAIR);
+
+      ({ priceFeed } = await createPriceFeed(
         program,
-        baseKeypair,
-        BORROWER_KEYPAIR,
-        borrowerPawnTokenAccount,
-        pawnMint.publicKey,
-        TERMS_VALID
+        LENDER_KEYPAIR,
+        collection,
+        mintA.publicKey
       ));
-
-      pawnLoanState = await program.account.pawnLoan.fetch(pawnLoanAddress);
-
-      await underwriteLoan(
+      await updatePriceFeed(
         program,
-        pawnLoanAddress,
-        pawnLoanState,
+        priceFeed,
         LENDER_KEYPAIR,
-        LENDER_KEYPAIR.publicKey,
-        BORROWER_KEYPAIR.publicKey
+        new BN(COLLECTION_PRICE),
+        new BN(0)
       );
 
-      pawnLoanState = await program.account.pawnLoan.fetch(pawnLoanAddress);
+      ({ pawnLoan: pawnLoanAddress } = await requestLoan(
+        program,
+        baseKeypair,
+        BORROWER_KEYPAIR,
+        tokenAccount,
+        mint.publicKey,
+        termsUsdc
+      ));
+      pawnLoanState = await fetchPawnLoan(program, pawnLoanAddress);
     });
 
-    it("Repays loan amount from borrower to lender -- in SOL", async () => {
-      const [borrowerBalanceBefore, lenderBalanceBefore] =
-        await getBorrowerAndLenderSolBalance(
-          program,
-          BORROWER_KEYPAIR.publicKey,
-          LENDER_KEYPAIR.publicKey
-        );
-
-      await repayLoanInSol(
+    it("Underwrites a principal within the loan-to-value", async () => {
+      await underwriteLoan(
         program,
         pawnLoanAddress,
         pawnLoanState,
-        BORROWER_KEYPAIR,
-        ADMIN_PDA
-      );
-
-      const [borrowerBalanceAfter, lenderBalanceAfter] =
-        await getBorrowerAndLenderSolBalance(
-          program,
-          BORROWER_KEYPAIR.publicKey,
-          LENDER_KEYPAIR.publicKey
-        );
-
-      // To silence typescript null warning
-      if (borrowerBalanceBefore === null || lenderBalanceAfter == null) {
-        assert.ok(false);
-        return;
-      }
-
-      assert.strictEqual(
-        borrowerBalanceBefore - borrowerBalanceAfter,
-        lenderBalanceAfter - lenderBalanceBefore
-      );
-
-      const pawnTokenAccountInfo =
-        await program.provider.connection.getAccountInfo(
-          pawnLoanState.pawnTokenAccount
-        );
-
-      const decodedPawnTokenAccountInfo = deserializeTokenAccountInfo(
-        pawnTokenAccountInfo?.data
+        LENDER_KEYPAIR,
+        lenderMintATokenAccount,
+        borrowerMintATokenAccount,
+        { guard: GUARD, priceFeed }
       );
 
-      // Pawn token account no longer frozen and delegate is revoked
-      assert.isFalse(decodedPawnTokenAccountInfo?.isFrozen);
-      assert.isNull(decodedPawnTokenAccountInfo?.delegate);
+      pawnLoanState = await fetchPawnLoan(program, pawnLoanAddress);
+      assert.strictEqual(Object.keys(pawnLoanState.status)[0], "active");
     });
 
-    it("Throws error if non-borrower tries to repay", async () => {
+    it("Throws error if the principal exceeds the loan-to-value", async () => {
       try {
-        await repayLoanInSol(
+        await underwriteLoan(
           program,
           pawnLoanAddress,
           pawnLoanState,
           LENDER_KEYPAIR,
-          ADMIN_PDA
+          lenderMintATokenAccount,
+          borrowerMintATokenAccount,
+          {
+            guard: { ...GUARD, maxLoanToValueBps: new BN(4_000) },
+            priceFeed,
+          }
         );
         assert.ok(false);
-      } catch (_err) {
-        assert.isTrue(_err instanceof AnchorError);
-        const err = _err as AnchorError;
-        assert.strictEqual(err.error.errorCode.number, 2001);
-        assert.strictEqual(
-          err.error.errorMessage,
-          "A has one constraint was violated"
-        );
+      } catch (e) {
+        const err = e as AnchorError;
+        assert.strictEqual(err.error.errorMessage, "LoanToValueExceeded");
       }
     });
 
-    it("Throws if repayment happens when loan is not active", async () => {
-      // Status moves from active to repaid
-      await repayLoanInSol(
-        program,
-        pawnLoanAddress,
-        pawnLoanState,
-        BORROWER_KEYPAIR,
-        ADMIN_PDA
-      );
+    it("Throws error if the price is stale", async () => {
+      await delay(2000);
       try {
-        await repayLoanInSol(
+        await underwriteLoan(
           program,
           pawnLoanAddress,
           pawnLoanState,
-          BORROWER_KEYPAIR,
-          ADMIN_PDA
+          LENDER_KEYPAIR,
+          lenderMintATokenAccount,
+          borrowerMintATokenAccount,
+          { guard: { ...GUARD, maxPriceAge: new BN(1) }, priceFeed }
         );
         assert.ok(false);
-      } catch (_err) {
-        assert.isTrue(_err instanceof AnchorError);
-        const err = _err as AnchorError;
-        assert.strictEqual(err.error.errorCode.number, 6003);
-        assert.strictEqual(err.error.errorMessage, "InvalidLoanStatus");
+      } catch (e) {
+        const err = e as AnchorError;
+        assert.strictEqual(err.error.errorMessage, "StalePrice");
       }
     });
   });
 
-  describe("Repay Loan - in SPL Token", () => {
+  describe("Underwrite Loan - with fee schedule", () => {
+    const termsLarge = () => ({
+      ...termsUsdc,
+      principalAmount: new BN(10_000),
+    });
+
+    let lenderVolume: PublicKey;
     let pawnLoanAddress: PublicKey;
     let pawnLoanState: any;
 
-    beforeEach(async () => {
+    before(async () => {
+      await createFeeSchedule(program, FEE_COLLECTOR_KEYPAIR, mintA.publicKey, [
+        {
+          minPrincipalAmount: new BN(0),
+          minDuration: new BN(0),
+          minLenderVolume: new BN(0),
+          adminFeeBps: new BN(150),
+        },
+        {
+          minPrincipalAmount: new BN(0),
+          minDuration: new BN(0),
+          minLenderVolume: new BN(DEFAULT_LOAN_AMOUNT),
+          adminFeeBps: new BN(100),
+        },
+      ]);
+      ({ lenderVolume } = await createLenderVolume(
+        program,
+        LENDER_KEYPAIR,
+        mintA.publicKey
+      ));
+    });
+
+    after(async () => {
+      // Restore the flat admin fee for the tests below
+      await updateFeeSchedule(program, FEE_COLLECTOR_KEYPAIR, mintA.publicKey, []);
+      await setOriginationFee(
+        program,
+        FEE_COLLECTOR_KEYPAIR,
+        mintA.publicKey,
+        new BN(0)
+      );
+    });
+
+    it("Applies the flat admin fee to mints without fee schedule", async () => {
       ({ pawnLoan: pawnLoanAddress } = await requestLoan(
         program,
         baseKeypair,
         BORROWER_KEYPAIR,
         borrowerPawnTokenAccount,
         pawnMint.publicKey,
-        termsUsdc
+        TERMS_VALID
       ));
+      pawnLoanState = await fetchPawnLoan(program, pawnLoanAddress);
+
+      await underwriteLoan(
+        program,
+        pawnLoanAddress,
+        pawnLoanState,
+        LENDER_KEYPAIR,
+        LENDER_KEYPAIR.publicKey,
+        BORROWER_KEYPAIR.publicKey
+      );
 
-      pawnLoanState = await program.account.pawnLoan.fetch(pawnLoanAddress);
+      pawnLoanState = await fetchPawnLoan(program, pawnLoanAddress);
+      assert.strictEqual(pawnLoanState.adminFeeBps.toNumber(), 200);
+    });
 
+    it("Applies the volume discount once the lender volume is reached", async () => {
+      ({ pawnLoan: pawnLoanAddress } = await requestLoan(
+        program,
+        baseKeypair,
+        BORROWER_KEYPAIR,
+        borrowerPawnTokenAccount,
+        pawnMint.publicKey,
+        termsUsdc
+      ));
+      pawnLoanState = await fetchPawnLoan(program, pawnLoanAddress);
       await underwriteLoan(
         program,
         pawnLoanAddress,
         pawnLoanState,
         LENDER_KEYPAIR,
         lenderMintATokenAccount,
-        borrowerMintATokenAccount
+        borrowerMintATokenAccount,
+        null,
+        null,
+        lenderVolume
+      );
+
+      pawnLoanState = await fetchPawnLoan(program, pawnLoanAddress);
+      assert.strictEqual(pawnLoanState.adminFeeBps.toNumber(), 150);
+
+      const { mint, tokenAccount } = await createNft(provider, BORROWER_KEYPAIR);
+      const { pawnLoan: secondPawnLoanAddress } = await requestLoan(
+        program,
+        new Keypair(),
+        BORROWER_KEYPAIR,
+        tokenAccount,
+        mint.publicKey,
+        termsUsdc
+      );
+      let secondPawnLoanState = await fetchPawnLoan(
+        program,
+        secondPawnLoanAddress
+      );
+      await underwriteLoan(
+        program,
+        secondPawnLoanAddress,
+        secondPawnLoanState,
+        LENDER_KEYPAIR,
+        lenderMintATokenAccount,
+        borrowerMintATokenAccount,
+        null,
+        null,
+        lenderVolume
+      );
+
+      secondPawnLoanState = await fetchPawnLoan(program, secondPawnLoanAddress);
+      assert.strictEqual(secondPawnLoanState.adminFeeBps.toNumber(), 100);
+      const lenderVolumeState = await program.account.lenderVolume.fetch(
+        lenderVolume
+      );
+      assert.strictEqual(
+        lenderVolumeState.totalPrincipalUnderwritten.toNumber(),
+        2 * DEFAULT_LOAN_AMOUNT
       );
     });
 
-    it("Repays loan amount from borrower to lender -- in SPL Token", async () => {
+    it("Takes the origination fee from the principal sent by the lender", async () => {
+      await setOriginationFee(
+        program,
+        FEE_COLLECTOR_KEYPAIR,
+        mintA.publicKey,
+        new BN(100) // 1%
+      );
+      ({ pawnLoan: pawnLoanAddress } = await requestLoan(
+        program,
+        baseKeypair,
+        BORROWER_KEYPAIR,
+        borrowerPawnTokenAccount,
+        pawnMint.publicKey,
+        termsLarge()
+      ));
+      pawnLoanState = await fetchPawnLoan(program, pawnLoanAddress);
+
       const [borrowerBalanceBefore, lenderBalanceBefore] =
         await getBorrowerAndLenderTokenBalance(
           program,
           borrowerMintATokenAccount,
           lenderMintATokenAccount
         );
+      const adminBalanceBefore = (
+        await mintA.getAccountInfo(adminMintATokenAccount)
+      ).amount;
 
-      await repayLoan(
+      await underwriteLoan(
         program,
         pawnLoanAddress,
         pawnLoanState,
+        LENDER_KEYPAIR,
+        lenderMintATokenAccount,
+        borrowerMintATokenAccount,
+        null,
+        null,
+        null,
+        adminMintATokenAccount
+      );
+
+      const [borrowerBalanceAfter, lenderBalanceAfter] =
+        await getBorrowerAndLenderTokenBalance(
+          program,
+          borrowerMintATokenAccount,
+          lenderMintATokenAccount
+        );
+      const adminBalanceAfter = (
+        await mintA.getAccountInfo(adminMintATokenAccount)
+      ).amount;
+      // To silence typescript null warning
+      if (
+        borrowerBalanceBefore === null ||
+        borrowerBalanceAfter === null ||
+        lenderBalanceBefore === null ||
+        lenderBalanceAfter === null
+      ) {
+        assert.ok(false);
+        return;
+      }
+      assert.strictEqual(lenderBalanceBefore - lenderBalanceAfter, 10_000);
+      assert.strictEqual(borrowerBalanceAfter - borrowerBalanceBefore, 9_900);
+      assert.strictEqual(adminBalanceAfter.sub(adminBalanceBefore).toNumber(), 100);
+    });
+
+    it("Charges the same fees when a delegate underwrites", async () => {
+      const delegateKeypair = new Keypair();
+      await provider.connection.confirmTransaction(
+        await provider.connection.requestAirdrop(
+          delegateKeypair.publicKey,
+          1_000_000_000
+        ),
+        "confirmed"
+      );
+      const { lenderDelegate } = await approveLenderDelegate(
+        program,
+        LENDER_KEYPAIR,
+        delegateKeypair.publicKey,
+        {
+          maxPrincipalPerLoan: new BN(10_000),
+          maxTotalPrincipal: new BN(10_000),
+          allowedMints: [mintA.publicKey],
+          expiry: new BN(Math.floor(Date.now() / 1000) + 60 * 60),
+        },
+        lenderMintATokenAccount
+      );
+      await setOriginationFee(
+        program,
+        FEE_COLLECTOR_KEYPAIR,
+        mintA.publicKey,
+        new BN(100) // 1%
+      );
+      ({ pawnLoan: pawnLoanAddress } = await requestLoan(
+        program,
+        baseKeypair,
         BORROWER_KEYPAIR,
+        borrowerPawnTokenAccount,
+        pawnMint.publicKey,
+        termsLarge()
+      ));
+      pawnLoanState = await fetchPawnLoan(program, pawnLoanAddress);
+
+      const [borrowerBalanceBefore, lenderBalanceBefore] =
+        await getBorrowerAndLenderTokenBalance(
+          program,
+          borrowerMintATokenAccount,
+          lenderMintATokenAccount
+        );
+      const adminBalanceBefore = (
+        await mintA.getAccountInfo(adminMintATokenAccount)
+      ).amount;
+
+      await underwriteLoanWithDelegate(
+        program,
+        pawnLoanAddress,
+        pawnLoanState,
+        lenderDelegate,
+        delegateKeypair,
+        LENDER_KEYPAIR.publicKey,
+        lenderMintATokenAccount,
         borrowerMintATokenAccount,
+        adminMintATokenAccount,
+        lenderVolume
+      );
+
+      const [borrowerBalanceAfter, lenderBalanceAfter] =
+        await getBorrowerAndLenderTokenBalance(
+          program,
+          borrowerMintATokenAccount,
+          lenderMintATokenAccount
+        );
+      const adminBalanceAfter = (
+        await mintA.getAccountInfo(adminMintATokenAccount)
+      ).amount;
+      // To silence typescript null warning
+      if (
+        borrowerBalanceBefore === null ||
+        borrowerBalanceAfter === null ||
+        lenderBalanceBefore === null ||
+        lenderBalanceAfter === null
+      ) {
+        assert.ok(false);
+        return;
+      }
+      // Same split as the lender underwriting the loan itself
+      assert.strictEqual(lenderBalanceBefore - lenderBalanceAfter, 10_000);
+      assert.strictEqual(borrowerBalanceAfter - borrowerBalanceBefore, 9_900);
+      assert.strictEqual(adminBalanceAfter.sub(adminBalanceBefore).toNumber(), 100);
+
+      // The lender volume reached the discounted tier
+      pawnLoanState = await fetchPawnLoan(program, pawnLoanAddress);
+      assert.strictEqual(pawnLoanState.adminFeeBps.toNumber(), 100);
+
+      await program.methods
+        .revokeLenderDelegate()
+        .accounts({ lenderDelegate, lender: LENDER_KEYPAIR.publicKey })
+        .signers([LENDER_KEYPAIR])
+        .rpc();
+    });
+  });
+
+  describe("Underwrite Loan - with lender delegate", () => {
+    const DELEGATE_KEYPAIR = new Keypair();
+
+    let lenderDelegate: PublicKey;
+    let pawnLoanAddress: PublicKey;
+    let pawnLoanState: any;
+
+    beforeEach(async () => {
+      ({ lenderDelegate } = await approveLenderDelegate(
+        program,
+        LENDER_KEYPAIR,
+        DELEGATE_KEYPAIR.publicKey,
+        {
+          maxPrincipalPerLoan: new BN(DEFAULT_LOAN_AMOUNT),
+          maxTotalPrincipal: new BN(DEFAULT_LOAN_AMOUNT),
+          allowedMints: [mintA.publicKey],
+          expiry: new BN(Math.floor(Date.now() / 1000) + 60 * 60),
+        },
+        lenderMintATokenAccount
+      ));
+
+      ({ pawnLoan: pawnLoanAddress } = await requestLoan(
+        program,
+        baseKeypair,
+        BORROWER_KEYPAIR,
+        borrowerPawnTokenAccount,
+        pawnMint.publicKey,
+        termsUsdc
+      ));
+      pawnLoanState = await fetchPawnLoan(program, pawnLoanAddress);
+    });
+
+    afterEach(async () => {
+      await program.methods
+        .revokeLenderDelegate()
+        .accounts({ lenderDelegate, lender: LENDER_KEYPAIR.publicKey })
+        .signers([LENDER_KEYPAIR])
+        .rpc();
+    });
+
+    it("Delegate underwrites on behalf of the lender within limits", async () => {
+      const [borrowerBalanceBefore, lenderBalanceBefore] =
+        await getBorrowerAndLenderTokenBalance(
+          program,
+          borrowerMintATokenAccount,
+          lenderMintATokenAccount
+        );
+
+      await underwriteLoanWithDelegate(
+        program,
+        pawnLoanAddress,
+        pawnLoanState,
+        lenderDelegate,
+        DELEGATE_KEYPAIR,
+        LENDER_KEYPAIR.publicKey,
         lenderMintATokenAccount,
-        ADMIN_PDA,
+        borrowerMintATokenAccount,
         adminMintATokenAccount
       );
+
       const [borrowerBalanceAfter, lenderBalanceAfter] =
         await getBorrowerAndLenderTokenBalance(
           program,
@@ -726,149 +1312,1513 @@ describe("PawnHub", () => {
         assert.ok(false);
         return;
       }
-      assert.strictEqual(
-        borrowerBalanceBefore - borrowerBalanceAfter,
-        lenderBalanceAfter - lenderBalanceBefore
-      );
+      assert.strictEqual(
+        borrowerBalanceBefore + DEFAULT_LOAN_AMOUNT,
+        borrowerBalanceAfter
+      );
+      assert.strictEqual(
+        lenderBalanceBefore,
+        lenderBalanceAfter + DEFAULT_LOAN_AMOUNT
+      );
+
+      pawnLoanState = await fetchPawnLoan(program, pawnLoanAddress);
+      assert.isTrue(pawnLoanState.lender.equals(LENDER_KEYPAIR.publicKey));
+    });
+
+    it("Throws error when the delegate exceeds its limits", async () => {
+      const lowLimitDelegateKeypair = new Keypair();
+      const { lenderDelegate: lowLimitLenderDelegate } =
+        await approveLenderDelegate(
+          program,
+          LENDER_KEYPAIR,
+          lowLimitDelegateKeypair.publicKey,
+          {
+            maxPrincipalPerLoan: new BN(DEFAULT_LOAN_AMOUNT - 1),
+            maxTotalPrincipal: new BN(DEFAULT_LOAN_AMOUNT),
+            allowedMints: [mintA.publicKey],
+            expiry: new BN(Math.floor(Date.now() / 1000) + 60 * 60),
+          },
+          lenderMintATokenAccount
+        );
+
+      try {
+        await underwriteLoanWithDelegate(
+          program,
+          pawnLoanAddress,
+          pawnLoanState,
+          lowLimitLenderDelegate,
+          lowLimitDelegateKeypair,
+          LENDER_KEYPAIR.publicKey,
+          lenderMintATokenAccount,
+          borrowerMintATokenAccount,
+          adminMintATokenAccount
+        );
+        assert.ok(false);
+      } catch (e) {
+        const err = e as AnchorError;
+        assert.strictEqual(err.error.errorMessage, "DelegateLimitExceeded");
+      }
+    });
+  });
+
+  describe("Repay Loan - in SOL", () => {
+    let pawnLoanAddress: PublicKey;
+    let pawnLoanState: any;
+
+    beforeEach(async () => {
+      ({ pawnLoan: pawnLoanAddress } = await requestLoan(
+        program,
+        baseKeypair,
+        BORROWER_KEYPAIR,
+        borrowerPawnTokenAccount,
+        pawnMint.publicKey,
+        TERMS_VALID
+      ));
+
+      pawnLoanState = await fetchPawnLoan(program, pawnLoanAddress);
+
+      await underwriteLoan(
+        program,
+        pawnLoanAddress,
+        pawnLoanState,
+        LENDER_KEYPAIR,
+        LENDER_KEYPAIR.publicKey,
+        BORROWER_KEYPAIR.publicKey
+      );
+
+      pawnLoanState = await fetchPawnLoan(program, pawnLoanAddress);
+    });
+
+    it("Repays loan amount from borrower to lender -- in SOL", async () => {
+      const [borrowerBalanceBefore, lenderBalanceBefore] =
+        await getBorrowerAndLenderSolBalance(
+          program,
+          BORROWER_KEYPAIR.publicKey,
+          LENDER_KEYPAIR.publicKey
+        );
+
+      await repayLoanInSol(
+        program,
+        pawnLoanAddress,
+        pawnLoanState,
+        BORROWER_KEYPAIR,
+        ADMIN_PDA
+      );
+
+      const [borrowerBalanceAfter, lenderBalanceAfter] =
+        await getBorrowerAndLenderSolBalance(
+          program,
+          BORROWER_KEYPAIR.publicKey,
+          LENDER_KEYPAIR.publicKey
+        );
+
+      // To silence typescript null warning
+      if (borrowerBalanceBefore === null || lenderBalanceAfter == null) {
+        assert.ok(false);
+        return;
+      }
+
+      assert.strictEqual(
+        borrowerBalanceBefore - borrowerBalanceAfter,
+        lenderBalanceAfter - lenderBalanceBefore
+      );
+
+      const pawnTokenAccountInfo =
+        await program.provider.connection.getAccountInfo(
+          pawnLoanState.pawnTokenAccount
+        );
+
+      const decodedPawnTokenAccountInfo = deserializeTokenAccountInfo(
+        pawnTokenAccountInfo?.data
+      );
+
+      // Pawn token account no longer frozen and delegate is revoked
+      assert.isFalse(decodedPawnTokenAccountInfo?.isFrozen);
+      assert.isNull(decodedPawnTokenAccountInfo?.delegate);
+    });
+
+    it("Throws error if non-borrower tries to repay", async () => {
+      try {
+        await repayLoanInSol(
+          program,
+          pawnLoanAddress,
+          pawnLoanState,
+          LENDER_KEYPAIR,
+          ADMIN_PDA
+        );
+        assert.ok(false);
+      } catch (_err) {
+        assert.isTrue(_err instanceof AnchorError);
+        const err = _err as AnchorError;
+        assert.strictEqual(err.error.errorCode.number, 2001);
+        assert.strictEqual(
+          err.error.errorMessage,
+          "A has one constraint was violated"
+        );
+      }
+    });
+
+    it("Throws if repayment happens when loan is not active", async () => {
+      // Status moves from active to repaid
+      await repayLoanInSol(
+        program,
+        pawnLoanAddress,
+        pawnLoanState,
+        BORROWER_KEYPAIR,
+        ADMIN_PDA
+      );
+      try {
+        await repayLoanInSol(
+          program,
+          pawnLoanAddress,
+          pawnLoanState,
+          BORROWER_KEYPAIR,
+          ADMIN_PDA
+        );
+        assert.ok(false);
+      } catch (_err) {
+        assert.isTrue(_err instanceof AnchorError);
+        const err = _err as AnchorError;
+        assert.strictEqual(err.error.errorCode.number, 6003);
+        assert.strictEqual(err.error.errorMessage, "InvalidLoanStatus");
+      }
+    });
+  });
+
+  describe("Repay Loan - fixed interest", () => {
+    let pawnLoanAddress: PublicKey;
+    let pawnLoanState: any;
+
+    beforeEach(async () => {
+      ({ pawnLoan: pawnLoanAddress } = await requestLoan(
+        program,
+        baseKeypair,
+        BORROWER_KEYPAIR,
+        borrowerPawnTokenAccount,
+        pawnMint.publicKey,
+        TERMS_FIXED_INTEREST
+      ));
+
+      pawnLoanState = await fetchPawnLoan(program, pawnLoanAddress);
+
+      await underwriteLoan(
+        program,
+        pawnLoanAddress,
+        pawnLoanState,
+        LENDER_KEYPAIR,
+        LENDER_KEYPAIR.publicKey,
+        BORROWER_KEYPAIR.publicKey
+      );
+
+      pawnLoanState = await fetchPawnLoan(program, pawnLoanAddress);
+    });
+
+    it("Charges the fixed interest minus admin fee regardless of elapsed time", async () => {
+      const [borrowerBalanceBefore, lenderBalanceBefore] =
+        await getBorrowerAndLenderSolBalance(
+          program,
+          BORROWER_KEYPAIR.publicKey,
+          LENDER_KEYPAIR.publicKey
+        );
+
+      await repayLoanInSol(
+        program,
+        pawnLoanAddress,
+        pawnLoanState,
+        BORROWER_KEYPAIR,
+        ADMIN_PDA
+      );
+
+      const [borrowerBalanceAfter, lenderBalanceAfter] =
+        await getBorrowerAndLenderSolBalance(
+          program,
+          BORROWER_KEYPAIR.publicKey,
+          LENDER_KEYPAIR.publicKey
+        );
+
+      const interestAmount =
+        TERMS_FIXED_INTEREST.interestModel.fixedInterest?.interestAmount.toNumber() ??
+        0;
+      const adminFee = (interestAmount * 200) / 10_000; // 2%
+      assert.strictEqual(
+        borrowerBalanceBefore - borrowerBalanceAfter,
+        DEFAULT_LOAN_AMOUNT + interestAmount
+      );
+      assert.strictEqual(
+        lenderBalanceAfter - lenderBalanceBefore,
+        DEFAULT_LOAN_AMOUNT + interestAmount - adminFee
+      );
+    });
+
+    it("Quotes the amounts charged on repayment", async () => {
+      const quote = await quotePayoff(program, pawnLoanAddress);
+
+      const [borrowerBalanceBefore, lenderBalanceBefore] =
+        await getBorrowerAndLenderSolBalance(
+          program,
+          BORROWER_KEYPAIR.publicKey,
+          LENDER_KEYPAIR.publicKey
+        );
+
+      await repayLoanInSol(
+        program,
+        pawnLoanAddress,
+        pawnLoanState,
+        BORROWER_KEYPAIR,
+        ADMIN_PDA
+      );
+
+      const [borrowerBalanceAfter, lenderBalanceAfter] =
+        await getBorrowerAndLenderSolBalance(
+          program,
+          BORROWER_KEYPAIR.publicKey,
+          LENDER_KEYPAIR.publicKey
+        );
+
+      assert.strictEqual(
+        borrowerBalanceBefore - borrowerBalanceAfter,
+        quote.totalRepaymentAmount.toNumber()
+      );
+      assert.strictEqual(
+        lenderBalanceAfter - lenderBalanceBefore,
+        quote.payoffAmount.toNumber()
+      );
+      assert.isTrue(
+        quote.payoffAmount.add(quote.adminFee).eq(quote.totalRepaymentAmount)
+      );
+    });
+
+    it("Throws error if the quote is before the start of the loan", async () => {
+      let quoted = true;
+      try {
+        await quotePayoff(
+          program,
+          pawnLoanAddress,
+          pawnLoanState.startTime.subn(1)
+        );
+      } catch (_err) {
+        quoted = false;
+      }
+      assert.isFalse(quoted);
+    });
+  });
+
+  describe("Repay Loan - in SPL Token", () => {
+    let pawnLoanAddress: PublicKey;
+    let pawnLoanState: any;
+
+    beforeEach(async () => {
+      ({ pawnLoan: pawnLoanAddress } = await requestLoan(
+        program,
+        baseKeypair,
+        BORROWER_KEYPAIR,
+        borrowerPawnTokenAccount,
+        pawnMint.publicKey,
+        termsUsdc
+      ));
+
+      pawnLoanState = await fetchPawnLoan(program, pawnLoanAddress);
+
+      await underwriteLoan(
+        program,
+        pawnLoanAddress,
+        pawnLoanState,
+        LENDER_KEYPAIR,
+        lenderMintATokenAccount,
+        borrowerMintATokenAccount
+      );
+
+      pawnLoanState = await fetchPawnLoan(program, pawnLoanAddress);
+    });
+
+    it("Repays loan amount from borrower to lender -- in SPL Token", async () => {
+      const [borrowerBalanceBefore, lenderBalanceBefore] =
+        await getBorrowerAndLenderTokenBalance(
+          program,
+          borrowerMintATokenAccount,
+          lenderMintATokenAccount
+        );
+
+      await repayLoan(
+        program,
+        pawnLoanAddress,
+        pawnLoanState,
+        BORROWER_KEYPAIR,
+        borrowerMintATokenAccount,
+        lenderMintATokenAccount,
+        ADMIN_PDA,
+        adminMintATokenAccount
+      );
+      const [borrowerBalanceAfter, lenderBalanceAfter] =
+        await getBorrowerAndLenderTokenBalance(
+          program,
+          borrowerMintATokenAccount,
+          lenderMintATokenAccount
+        );
+      // To silence typescript null warning
+      if (
+        borrowerBalanceBefore === null ||
+        borrowerBalanceAfter === null ||
+        lenderBalanceBefore === null ||
+        lenderBalanceAfter === null
+      ) {
+        assert.ok(false);
+        return;
+      }
+      assert.strictEqual(
+        borrowerBalanceBefore - borrowerBalanceAfter,
+        lenderBalanceAfter - lenderBalanceBefore
+      );
+    });
+  });
+
+  describe("Repay Loan - with creator royalty", () => {
+    const PAYOUT_KEYPAIR = new Keypair();
+    const termsLarge = () => ({
+      ...termsUsdc,
+      principalAmount: new BN(100_000),
+    });
+
+    let payoutTokenAccount: PublicKey;
+    let pawnLoanAddress: PublicKey;
+    let pawnLoanState: any;
+
+    before(async () => {
+      await setCollectionRegistryEnabled(program, FEE_COLLECTOR_KEYPAIR, true);
+      payoutTokenAccount = await mintA.createAccount(PAYOUT_KEYPAIR.publicKey);
+    });
+
+    after(async () => {
+      await setCollectionRegistryEnabled(program, FEE_COLLECTOR_KEYPAIR, false);
+    });
+
+    beforeEach(async () => {
+      const { collection, mint, tokenAccount } =
+        await createNftInVerifiedCollection(provider, BORROWER_KEYPAIR);
+      await registerCollection(
+        program,
+        FEE_COLLECTOR_KEYPAIR,
+        collection,
+        mintA.publicKey,
+        termsLarge().principalAmount,
+        termsUsdc.duration
+      );
+      await setCollectionRoyalty(program, FEE_COLLECTOR_KEYPAIR, collection, {
+        royaltyBps: new BN(5_000),
+        payoutAddress: PAYOUT_KEYPAIR.publicKey,
+      });
+
+      ({ pawnLoan: pawnLoanAddress } = await requestLoan(
+        program,
+        baseKeypair,
+        BORROWER_KEYPAIR,
+        tokenAccount,
+        mint.publicKey,
+        termsLarge(),
+        collection
+      ));
+      pawnLoanState = await fetchPawnLoan(program, pawnLoanAddress);
+
+      await underwriteLoan(
+        program,
+        pawnLoanAddress,
+        pawnLoanState,
+        LENDER_KEYPAIR,
+        lenderMintATokenAccount,
+        borrowerMintATokenAccount
+      );
+      pawnLoanState = await fetchPawnLoan(program, pawnLoanAddress);
+    });
+
+    it("Pays the creator royalty out of the lender's interest", async () => {
+      // Interest is constant within the minimum period
+      const quote = await quotePayoff(program, pawnLoanAddress);
+      assert.isTrue(quote.creatorRoyalty.gtn(0));
+
+      const [borrowerBalanceBefore, lenderBalanceBefore] =
+        await getBorrowerAndLenderTokenBalance(
+          program,
+          borrowerMintATokenAccount,
+          lenderMintATokenAccount
+        );
+
+      await repayLoan(
+        program,
+        pawnLoanAddress,
+        pawnLoanState,
+        BORROWER_KEYPAIR,
+        borrowerMintATokenAccount,
+        lenderMintATokenAccount,
+        ADMIN_PDA,
+        adminMintATokenAccount,
+        [payoutTokenAccount]
+      );
+
+      const [borrowerBalanceAfter, lenderBalanceAfter] =
+        await getBorrowerAndLenderTokenBalance(
+          program,
+          borrowerMintATokenAccount,
+          lenderMintATokenAccount
+        );
+      const payoutBalance = (await mintA.getAccountInfo(payoutTokenAccount))
+        .amount;
+      // To silence typescript null warning
+      if (
+        borrowerBalanceBefore === null ||
+        borrowerBalanceAfter === null ||
+        lenderBalanceBefore === null ||
+        lenderBalanceAfter === null
+      ) {
+        assert.ok(false);
+        return;
+      }
+      assert.isTrue(payoutBalance.eq(quote.creatorRoyalty));
+      assert.strictEqual(
+        borrowerBalanceBefore - borrowerBalanceAfter,
+        quote.totalRepaymentAmount.toNumber()
+      );
+      assert.strictEqual(
+        lenderBalanceAfter - lenderBalanceBefore,
+        quote.payoffAmount.toNumber()
+      );
+    });
+
+    it("Pays the lender the royalty the payout address cannot receive", async () => {
+      const quote = await quotePayoff(program, pawnLoanAddress);
+      // The payout address has no associated token account of the loan mint
+      const payoutAssociatedTokenAccount =
+        await Token.getAssociatedTokenAddress(
+          ASSOCIATED_TOKEN_PROGRAM_ID,
+          TOKEN_PROGRAM_ID,
+          mintA.publicKey,
+          PAYOUT_KEYPAIR.publicKey
+        );
+
+      const [, lenderBalanceBefore] = await getBorrowerAndLenderTokenBalance(
+        program,
+        borrowerMintATokenAccount,
+        lenderMintATokenAccount
+      );
+      await repayLoan(
+        program,
+        pawnLoanAddress,
+        pawnLoanState,
+        BORROWER_KEYPAIR,
+        borrowerMintATokenAccount,
+        lenderMintATokenAccount,
+        ADMIN_PDA,
+        adminMintATokenAccount,
+        [payoutAssociatedTokenAccount]
+      );
+      const [, lenderBalanceAfter] = await getBorrowerAndLenderTokenBalance(
+        program,
+        borrowerMintATokenAccount,
+        lenderMintATokenAccount
+      );
+
+      assert.strictEqual(
+        (lenderBalanceAfter ?? 0) - (lenderBalanceBefore ?? 0),
+        quote.payoffAmount.add(quote.creatorRoyalty).toNumber()
+      );
+    });
+
+    it("Throws error if the royalty payment account is missing", async () => {
+      try {
+        await repayLoan(
+          program,
+          pawnLoanAddress,
+          pawnLoanState,
+          BORROWER_KEYPAIR,
+          borrowerMintATokenAccount,
+          lenderMintATokenAccount,
+          ADMIN_PDA,
+          adminMintATokenAccount
+        );
+        assert.ok(false);
+      } catch (e) {
+        const err = e as AnchorError;
+        assert.strictEqual(
+          err.error.errorMessage,
+          "InvalidRoyaltyPaymentAccount"
+        );
+      }
+    });
+  });
+
+  describe("Repay Loan - with referrers", () => {
+    const BORROWER_REFERRER_KEYPAIR = new Keypair();
+    const LENDER_REFERRER_KEYPAIR = new Keypair();
+    const termsReferred = () => ({
+      ...termsUsdc,
+      interestModel: { fixedInterest: { interestAmount: new BN(10_000) } },
+    });
+
+    let borrowerReferrerTokenAccount: PublicKey;
+    let pawnLoanAddress: PublicKey;
+    let pawnLoanState: any;
+
+    before(async () => {
+      borrowerReferrerTokenAccount = await mintA.createAccount(
+        BORROWER_REFERRER_KEYPAIR.publicKey
+      );
+      await registerReferrer(
+        program,
+        FEE_COLLECTOR_KEYPAIR,
+        BORROWER_REFERRER_KEYPAIR.publicKey
+      );
+      await registerReferrer(
+        program,
+        FEE_COLLECTOR_KEYPAIR,
+        LENDER_REFERRER_KEYPAIR.publicKey
+      );
+    });
+
+    beforeEach(async () => {
+      ({ pawnLoan: pawnLoanAddress } = await requestLoan(
+        program,
+        baseKeypair,
+        BORROWER_KEYPAIR,
+        borrowerPawnTokenAccount,
+        pawnMint.publicKey,
+        termsReferred(),
+        null,
+        BORROWER_REFERRER_KEYPAIR.publicKey
+      ));
+      pawnLoanState = await fetchPawnLoan(program, pawnLoanAddress);
+
+      await underwriteLoan(
+        program,
+        pawnLoanAddress,
+        pawnLoanState,
+        LENDER_KEYPAIR,
+        lenderMintATokenAccount,
+        borrowerMintATokenAccount,
+        null,
+        LENDER_REFERRER_KEYPAIR.publicKey
+      );
+      pawnLoanState = await fetchPawnLoan(program, pawnLoanAddress);
+    });
+
+    it("Saves the referrers in the pawn loan account", async () => {
+      assert.isTrue(
+        pawnLoanState.borrowerReferrer.equals(
+          BORROWER_REFERRER_KEYPAIR.publicKey
+        )
+      );
+      assert.isTrue(
+        pawnLoanState.lenderReferrer.equals(LENDER_REFERRER_KEYPAIR.publicKey)
+      );
+    });
+
+    it("Credits each referrer a share of the admin fee", async () => {
+      const referralEarnings = (referrer: PublicKey) =>
+        program.account.referralEarnings.fetch(
+          findReferralEarningsPda(program, referrer, mintA.publicKey)
+        );
+      const adminBalanceBefore = (
+        await mintA.getAccountInfo(adminMintATokenAccount)
+      ).amount;
+
+      await repayLoan(
+        program,
+        pawnLoanAddress,
+        pawnLoanState,
+        BORROWER_KEYPAIR,
+        borrowerMintATokenAccount,
+        lenderMintATokenAccount,
+        ADMIN_PDA,
+        adminMintATokenAccount
+      );
+
+      const adminBalanceAfter = (
+        await mintA.getAccountInfo(adminMintATokenAccount)
+      ).amount;
+      const borrowerReferrerEarnings = await referralEarnings(
+        BORROWER_REFERRER_KEYPAIR.publicKey
+      );
+      const lenderReferrerEarnings = await referralEarnings(
+        LENDER_REFERRER_KEYPAIR.publicKey
+      );
+      // 2% admin fee on 10_000 interest, 25% of it credited to each referrer
+      assert.strictEqual(borrowerReferrerEarnings.unclaimed.toNumber(), 50);
+      assert.strictEqual(lenderReferrerEarnings.unclaimed.toNumber(), 50);
+      // The admin pda holds the referral fees until claimed
+      assert.strictEqual(adminBalanceAfter.sub(adminBalanceBefore).toNumber(), 200);
+    });
+
+    it("Lets referrers claim their fees from the admin pda", async () => {
+      await repayLoan(
+        program,
+        pawnLoanAddress,
+        pawnLoanState,
+        BORROWER_KEYPAIR,
+        borrowerMintATokenAccount,
+        lenderMintATokenAccount,
+        ADMIN_PDA,
+        adminMintATokenAccount
+      );
+      const referralEarningsPda = findReferralEarningsPda(
+        program,
+        BORROWER_REFERRER_KEYPAIR.publicKey,
+        mintA.publicKey
+      );
+      const unclaimed = (
+        await program.account.referralEarnings.fetch(referralEarningsPda)
+      ).unclaimed;
+      const owedBefore = (
+        await program.account.feeLedger.fetch(
+          findFeeLedgerPda(program, mintA.publicKey)
+        )
+      ).referralFeesOwed;
+      const referrerBalanceBefore = (
+        await mintA.getAccountInfo(borrowerReferrerTokenAccount)
+      ).amount;
+
+      await claimReferralFees(
+        program,
+        BORROWER_REFERRER_KEYPAIR,
+        borrowerReferrerTokenAccount,
+        adminMintATokenAccount,
+        mintA.publicKey
+      );
+
+      const referrerBalanceAfter = (
+        await mintA.getAccountInfo(borrowerReferrerTokenAccount)
+      ).amount;
+      const owedAfter = (
+        await program.account.feeLedger.fetch(
+          findFeeLedgerPda(program, mintA.publicKey)
+        )
+      ).referralFeesOwed;
+      assert.isTrue(
+        referrerBalanceAfter.sub(referrerBalanceBefore).eq(unclaimed)
+      );
+      assert.isTrue(owedBefore.sub(owedAfter).eq(unclaimed));
+      assert.strictEqual(
+        (
+          await program.account.referralEarnings.fetch(referralEarningsPda)
+        ).unclaimed.toNumber(),
+        0
+      );
+    });
+
+    it("Throws error if the referrer is not registered", async () => {
+      try {
+        await requestLoan(
+          program,
+          new Keypair(),
+          BORROWER_KEYPAIR,
+          borrowerPawnTokenAccount,
+          pawnMint.publicKey,
+          termsReferred(),
+          null,
+          new Keypair().publicKey
+        );
+        assert.ok(false);
+      } catch (e) {
+        const err = e as AnchorError;
+        assert.strictEqual(err.error.errorMessage, "ReferrerNotRegistered");
+      }
+    });
+
+    it("Throws error if the borrower refers their own loan", async () => {
+      try {
+        await requestLoan(
+          program,
+          new Keypair(),
+          BORROWER_KEYPAIR,
+          borrowerPawnTokenAccount,
+          pawnMint.publicKey,
+          termsReferred(),
+          null,
+          BORROWER_KEYPAIR.publicKey
+        );
+        assert.ok(false);
+      } catch (e) {
+        const err = e as AnchorError;
+        assert.strictEqual(err.error.errorMessage, "InvalidReferrer");
+      }
+    });
+  });
+
+  describe("Seize Pawn", () => {
+    let pawnLoanAddress: PublicKey;
+    let pawnLoanState: any;
+
+    beforeEach(async () => {
+      ({ pawnLoan: pawnLoanAddress } = await requestLoan(
+        program,
+        baseKeypair,
+        BORROWER_KEYPAIR,
+        borrowerPawnTokenAccount,
+        pawnMint.publicKey,
+        TERMS_SUPER_SHORT_LOAN
+      ));
+
+      pawnLoanState = await fetchPawnLoan(program, pawnLoanAddress);
+
+      await underwriteLoan(
+        program,
+        pawnLoanAddress,
+        pawnLoanState,
+        LENDER_KEYPAIR,
+        LENDER_KEYPAIR.publicKey,
+        BORROWER_KEYPAIR.publicKey
+      );
+
+      pawnLoanState = await fetchPawnLoan(program, pawnLoanAddress);
+    });
+
+    it("Throws error if attempt to seize before due", async () => {
+      try {
+        await seizePawn(
+          program,
+          pawnLoanAddress,
+          pawnLoanState,
+          LENDER_KEYPAIR,
+          lenderPawnTokenAccount
+        );
+        assert.ok(false);
+      } catch (_err) {
+        assert.isTrue(_err instanceof AnchorError);
+        const err = _err as AnchorError;
+        assert.strictEqual(err.error.errorCode.number, 6008);
+        assert.strictEqual(err.error.errorMessage, "CannotSeizeBeforeExpiry");
+      }
+    });
+
+    it("Seizing should update status and transfer the pawn to the lender", async () => {
+      await delay(2000);
+      await seizePawn(
+        program,
+        pawnLoanAddress,
+        pawnLoanState,
+        LENDER_KEYPAIR,
+        lenderPawnTokenAccount
+      );
+      pawnLoanState = await fetchPawnLoan(program, pawnLoanAddress);
+
+      // Unsure why pawnLoan status enum shows up as {defaulted: {}}
+      assert.strictEqual(Object.keys(pawnLoanState.status)[0], "defaulted");
+
+      const lenderPawnTokenAccountInfo =
+        await program.provider.connection.getAccountInfo(
+          lenderPawnTokenAccount
+        );
+
+      const decodedlenderPawnTokenAccountInfo = deserializeTokenAccountInfo(
+        lenderPawnTokenAccountInfo?.data
+      );
+
+      if (!pawnLoanState.terms?.mint) {
+        assert.ok(false);
+        return;
+      }
+
+      // Pawn mint transferred to lender account
+      assert.isTrue(
+        decodedlenderPawnTokenAccountInfo?.mint.equals(pawnMint.publicKey)
+      );
+      assert.strictEqual(
+        decodedlenderPawnTokenAccountInfo?.amount.toNumber(),
+        1
+      );
+
+      const pawnTokenAccountInfo =
+        await program.provider.connection.getAccountInfo(
+          pawnLoanState.pawnTokenAccount
+        );
+
+      const decodedPawnTokenAccountInfo = deserializeTokenAccountInfo(
+        pawnTokenAccountInfo?.data
+      );
+
+      // Pawn token account no longer frozen and delegate is revoked
+      assert.isFalse(decodedPawnTokenAccountInfo?.isFrozen);
+      assert.isNull(decodedPawnTokenAccountInfo?.delegate);
+    });
+
+    it("Lender pays the default fee of the mint when seizing", async () => {
+      // The fee schedule of mint A is created by the fee schedule tests
+      await setDefaultFee(
+        program,
+        FEE_COLLECTOR_KEYPAIR,
+        mintA.publicKey,
+        new BN(1_000) // 10%
+      );
+      const { mint, tokenAccount } = await createNft(provider, BORROWER_KEYPAIR);
+      const lenderTokenAccount = await mint.createAccount(
+        LENDER_KEYPAIR.publicKey
+      );
+      const { pawnLoan } = await requestLoan(
+        program,
+        new Keypair(),
+        BORROWER_KEYPAIR,
+        tokenAccount,
+        mint.publicKey,
+        {
+          ...TERMS_SUPER_SHORT_LOAN,
+          principalAmount: new BN(1_000),
+          mint: mintA.publicKey,
+        }
+      );
+      let state = await fetchPawnLoan(program, pawnLoan);
+      await underwriteLoan(
+        program,
+        pawnLoan,
+        state,
+        LENDER_KEYPAIR,
+        lenderMintATokenAccount,
+        borrowerMintATokenAccount
+      );
+      state = await fetchPawnLoan(program, pawnLoan);
+      await delay(2000);
+
+      const adminBalanceBefore = (
+        await mintA.getAccountInfo(adminMintATokenAccount)
+      ).amount;
+      await seizePawn(
+        program,
+        pawnLoan,
+        state,
+        LENDER_KEYPAIR,
+        lenderTokenAccount,
+        lenderMintATokenAccount,
+        adminMintATokenAccount
+      );
+      await setDefaultFee(
+        program,
+        FEE_COLLECTOR_KEYPAIR,
+        mintA.publicKey,
+        new BN(0)
+      );
+
+      const adminBalanceAfter = (
+        await mintA.getAccountInfo(adminMintATokenAccount)
+      ).amount;
+      // 10% of the principal plus the one unit of interest accrued at maturity
+      assert.strictEqual(adminBalanceAfter.sub(adminBalanceBefore).toNumber(), 100);
+    });
+
+    it("Records the loan and its default in the borrower and lender history", async () => {
+      const borrowerStats = findBorrowerStatsPda(
+        program,
+        BORROWER_KEYPAIR.publicKey
+      );
+      const lenderStats = findLenderStatsPda(program, LENDER_KEYPAIR.publicKey);
+      const { mint, tokenAccount } = await createNft(provider, BORROWER_KEYPAIR);
+      const lenderTokenAccount = await mint.createAccount(
+        LENDER_KEYPAIR.publicKey
+      );
+      const { pawnLoan } = await requestLoan(
+        program,
+        new Keypair(),
+        BORROWER_KEYPAIR,
+        tokenAccount,
+        mint.publicKey,
+        TERMS_SUPER_SHORT_LOAN
+      );
+      let state = await fetchPawnLoan(program, pawnLoan);
+      await underwriteLoan(
+        program,
+        pawnLoan,
+        state,
+        LENDER_KEYPAIR,
+        LENDER_KEYPAIR.publicKey,
+        BORROWER_KEYPAIR.publicKey
+      );
+      const borrowerHistoryBefore = (
+        await program.account.borrowerStats.fetch(borrowerStats)
+      ).history;
+      const lenderHistoryBefore = (
+        await program.account.lenderStats.fetch(lenderStats)
+      ).history;
+      state = await fetchPawnLoan(program, pawnLoan);
+      await delay(2000);
+
+      await seizePawn(
+        program,
+        pawnLoan,
+        state,
+        LENDER_KEYPAIR,
+        lenderTokenAccount
+      );
+
+      const borrowerHistory = (
+        await program.account.borrowerStats.fetch(borrowerStats)
+      ).history;
+      const lenderHistory = (
+        await program.account.lenderStats.fetch(lenderStats)
+      ).history;
+      assert.isTrue(borrowerHistory.loanCount.gtn(0));
+      assert.isTrue(
+        borrowerHistory.defaultCount.eq(
+          borrowerHistoryBefore.defaultCount.addn(1)
+        )
+      );
+      assert.isTrue(
+        lenderHistory.defaultCount.eq(lenderHistoryBefore.defaultCount.addn(1))
+      );
+    });
+  });
+
+  describe("Cancel Loan", () => {
+    it("Cancel loan", async () => {
+      const { pawnLoan, pawnTokenAccount } = await requestLoan(
+        program,
+        baseKeypair,
+        BORROWER_KEYPAIR,
+        borrowerPawnTokenAccount,
+        pawnMint.publicKey,
+        TERMS_VALID
+      );
+
+      await program.methods
+        .cancelLoan()
+        .accounts({
+          pawnLoan,
+          borrower: BORROWER_KEYPAIR.publicKey,
+          pawnTokenAccount,
+          pawnMint: pawnMint.publicKey,
+          edition: findMasterEditionPda(pawnMint.publicKey),
+          ...loanStatsAccounts(program, TERMS_VALID.mint),
+          mplTokenMetadataProgram: METAPLEX_PROGRAM_ID,
+        })
+        .signers([BORROWER_KEYPAIR])
+        .rpc();
+
+      // Relevant accounts should be zeroed (back to system program and no lamports) and pawn freed in wallet
+      const pawnLoanAccountInfo =
+        await program.provider.connection.getAccountInfo(pawnLoan);
+      assert.isNull(pawnLoanAccountInfo);
+
+      const pawnTokenAccountInfo =
+        await program.provider.connection.getAccountInfo(pawnTokenAccount);
+
+      const decodedPawnTokenAccountInfo = deserializeTokenAccountInfo(
+        pawnTokenAccountInfo?.data
+      );
+      assert.isTrue(decodedPawnTokenAccountInfo?.amount.eq(new BN(1)));
+      assert.isFalse(decodedPawnTokenAccountInfo?.isFrozen);
+      assert.isNull(decodedPawnTokenAccountInfo?.delegate);
+    });
+
+    it("Statistics count the request until it is cancelled", async () => {
+      const mintStats = findMintStatsPda(program, TERMS_VALID.mint);
+      const openRequests = async () => ({
+        protocol: (
+          await program.account.protocolStats.fetch(
+            findProtocolStatsPda(program)
+          )
+        ).stats.openRequests.toNumber(),
+        mint: (
+          await program.account.mintStats.fetch(mintStats)
+        ).stats.openRequests.toNumber(),
+      });
+
+      const { pawnLoan, pawnTokenAccount } = await requestLoan(
+        program,
+        baseKeypair,
+        BORROWER_KEYPAIR,
+        borrowerPawnTokenAccount,
+        pawnMint.publicKey,
+        TERMS_VALID
+      );
+      const afterRequest = await openRequests();
+
+      await program.methods
+        .cancelLoan()
+        .accounts({
+          pawnLoan,
+          borrower: BORROWER_KEYPAIR.publicKey,
+          pawnTokenAccount,
+          pawnMint: pawnMint.publicKey,
+          edition: findMasterEditionPda(pawnMint.publicKey),
+          ...loanStatsAccounts(program, TERMS_VALID.mint),
+          mplTokenMetadataProgram: METAPLEX_PROGRAM_ID,
+        })
+        .signers([BORROWER_KEYPAIR])
+        .rpc();
+
+      const afterCancel = await openRequests();
+      assert.strictEqual(afterCancel.protocol, afterRequest.protocol - 1);
+      assert.strictEqual(afterCancel.mint, afterRequest.mint - 1);
+      assert.isTrue(
+        (await program.account.mintStats.fetch(mintStats)).mint.equals(
+          TERMS_VALID.mint
+        )
+      );
+    });
+  });
+
+  describe("Compute units", () => {
+    const computeUnits: Record<string, number> = {};
+
+    after(() => {
+      reportComputeUnits(computeUnits);
+    });
+
+    it("Keeps the loan lifecycle within the default compute budget", async () => {
+      const { signature: requestSignature, pawnLoan: pawnLoanAddress } =
+        await requestLoan(
+          program,
+          baseKeypair,
+          BORROWER_KEYPAIR,
+          borrowerPawnTokenAccount,
+          pawnMint.publicKey,
+          TERMS_VALID
+        );
+      const underwriteSignature = await underwriteLoan(
+        program,
+        pawnLoanAddress,
+        await fetchPawnLoan(program, pawnLoanAddress),
+        LENDER_KEYPAIR,
+        LENDER_KEYPAIR.publicKey,
+        BORROWER_KEYPAIR.publicKey
+      );
+      const repaySignature = await repayLoanInSol(
+        program,
+        pawnLoanAddress,
+        await fetchPawnLoan(program, pawnLoanAddress),
+        BORROWER_KEYPAIR,
+        ADMIN_PDA
+      );
+
+      computeUnits.requestLoan = await getComputeUnitsConsumed(
+        program,
+        requestSignature
+      );
+      computeUnits.underwriteLoan = await getComputeUnitsConsumed(
+        program,
+        underwriteSignature as string
+      );
+      computeUnits.repayLoan = await getComputeUnitsConsumed(
+        program,
+        repaySignature
+      );
+      for (const consumed of Object.values(computeUnits)) {
+        assert.isBelow(consumed, 200_000);
+      }
+    });
+
+    it("Keeps the seizure within the default compute budget", async () => {
+      const { pawnLoan: pawnLoanAddress } = await requestLoan(
+        program,
+        baseKeypair,
+        BORROWER_KEYPAIR,
+        borrowerPawnTokenAccount,
+        pawnMint.publicKey,
+        TERMS_SUPER_SHORT_LOAN
+      );
+      await underwriteLoan(
+        program,
+        pawnLoanAddress,
+        await fetchPawnLoan(program, pawnLoanAddress),
+        LENDER_KEYPAIR,
+        LENDER_KEYPAIR.publicKey,
+        BORROWER_KEYPAIR.publicKey
+      );
+      await delay(2000);
+      const seizeSignature = await seizePawn(
+        program,
+        pawnLoanAddress,
+        await fetchPawnLoan(program, pawnLoanAddress),
+        LENDER_KEYPAIR,
+        lenderPawnTokenAccount
+      );
+
+      computeUnits.seizePawn = await getComputeUnitsConsumed(
+        program,
+        seizeSignature as string
+      );
+      assert.isBelow(computeUnits.seizePawn, 200_000);
+    });
+  });
+
+  describe("Lending pool", () => {
+    const POOL_DEPOSIT_AMOUNT = 10_000;
+    const LOCKED_POOL_SHARES = 1_000;
+
+    let lendingPool: PublicKey;
+    let vault: PublicKey;
+    let lenderLpTokenAccount: PublicKey;
+    let lockedSharesAccount: PublicKey;
+
+    const getTokenAmount = async (tokenAccount: PublicKey) =>
+      deserializeTokenAccountInfo(
+        (await program.provider.connection.getAccountInfo(tokenAccount))?.data
+      )?.amount.toNumber();
+
+    beforeEach(async () => {
+      let lpMint: PublicKey;
+      ({ lendingPool, vault, lpMint } = await createLendingPool(
+        program,
+        new Keypair(),
+        LENDER_KEYPAIR,
+        mintA.publicKey
+      ));
+
+      const lpToken = new Token(
+        provider.connection,
+        lpMint,
+        TOKEN_PROGRAM_ID,
+        LENDER_KEYPAIR
+      );
+      lenderLpTokenAccount = await lpToken.createAccount(
+        LENDER_KEYPAIR.publicKey
+      );
+      lockedSharesAccount = await lpToken.createAccount(lendingPool);
+
+      await depositPoolLiquidity(
+        program,
+        lendingPool,
+        LENDER_KEYPAIR,
+        lenderMintATokenAccount,
+        lenderLpTokenAccount,
+        lockedSharesAccount,
+        new BN(POOL_DEPOSIT_AMOUNT)
+      );
+    });
+
+    it("Locks part of the shares of the first deposit in the pool", async () => {
+      assert.strictEqual(await getTokenAmount(vault), POOL_DEPOSIT_AMOUNT);
+      assert.strictEqual(
+        await getTokenAmount(lenderLpTokenAccount),
+        POOL_DEPOSIT_AMOUNT - LOCKED_POOL_SHARES
+      );
+      assert.strictEqual(
+        await getTokenAmount(lockedSharesAccount),
+        LOCKED_POOL_SHARES
+      );
+
+      // Later deposits mint all their shares to the depositor
+      await depositPoolLiquidity(
+        program,
+        lendingPool,
+        LENDER_KEYPAIR,
+        lenderMintATokenAccount,
+        lenderLpTokenAccount,
+        lockedSharesAccount,
+        new BN(POOL_DEPOSIT_AMOUNT)
+      );
+      assert.strictEqual(
+        await getTokenAmount(lenderLpTokenAccount),
+        2 * POOL_DEPOSIT_AMOUNT - LOCKED_POOL_SHARES
+      );
+      assert.strictEqual(
+        await getTokenAmount(lockedSharesAccount),
+        LOCKED_POOL_SHARES
+      );
+    });
+
+    it("Throws error if the first deposit does not exceed the locked shares", async () => {
+      const { lendingPool: emptyLendingPool, lpMint } = await createLendingPool(
+        program,
+        new Keypair(),
+        LENDER_KEYPAIR,
+        mintA.publicKey
+      );
+      const lpToken = new Token(
+        provider.connection,
+        lpMint,
+        TOKEN_PROGRAM_ID,
+        LENDER_KEYPAIR
+      );
+
+      try {
+        await depositPoolLiquidity(
+          program,
+          emptyLendingPool,
+          LENDER_KEYPAIR,
+          lenderMintATokenAccount,
+          await lpToken.createAccount(LENDER_KEYPAIR.publicKey),
+          await lpToken.createAccount(emptyLendingPool),
+          new BN(LOCKED_POOL_SHARES)
+        );
+        assert.ok(false);
+      } catch (e) {
+        const err = e as AnchorError;
+        assert.strictEqual(err.error.errorMessage, "InvalidPoolAmount");
+      }
+    });
+
+    it("Underwrites from the pool and repayments flow back to the pool", async () => {
+      const { pawnLoan: pawnLoanAddress } = await requestLoan(
+        program,
+        baseKeypair,
+        BORROWER_KEYPAIR,
+        borrowerPawnTokenAccount,
+        pawnMint.publicKey,
+        termsUsdc
+      );
+      let pawnLoanState = await fetchPawnLoan(program, pawnLoanAddress);
+
+      await underwriteLoanFromPool(
+        program,
+        pawnLoanAddress,
+        pawnLoanState,
+        lendingPool,
+        LENDER_KEYPAIR,
+        borrowerMintATokenAccount,
+        adminMintATokenAccount
+      );
+
+      pawnLoanState = await fetchPawnLoan(program, pawnLoanAddress);
+      assert.isTrue(pawnLoanState.lender.equals(lendingPool));
+      assert.strictEqual(
+        await getTokenAmount(vault),
+        POOL_DEPOSIT_AMOUNT - DEFAULT_LOAN_AMOUNT
+      );
+      let lendingPoolState = await program.account.lendingPool.fetch(
+        lendingPool
+      );
+      assert.strictEqual(
+        lendingPoolState.outstandingPrincipal.toNumber(),
+        DEFAULT_LOAN_AMOUNT
+      );
+
+      await repayLoan(
+        program,
+        pawnLoanAddress,
+        pawnLoanState,
+        BORROWER_KEYPAIR,
+        borrowerMintATokenAccount,
+        vault,
+        ADMIN_PDA,
+        adminMintATokenAccount
+      );
+
+      lendingPoolState = await program.account.lendingPool.fetch(lendingPool);
+      assert.strictEqual(lendingPoolState.outstandingPrincipal.toNumber(), 0);
+      assert.isAtLeast(
+        (await getTokenAmount(vault)) as number,
+        POOL_DEPOSIT_AMOUNT
+      );
+
+      // The pool history records the loan like any lender
+      const poolHistory = (
+        await program.account.lenderStats.fetch(
+          findLenderStatsPda(program, lendingPool)
+        )
+      ).history;
+      assert.strictEqual(poolHistory.loanCount.toNumber(), 1);
+      assert.strictEqual(poolHistory.repaidCount.toNumber(), 1);
+      assert.strictEqual(poolHistory.defaultCount.toNumber(), 0);
+    });
+
+    it("Refuses automatic underwriting without policy or verified collection", async () => {
+      const collection = new Keypair().publicKey;
+      await addPoolCollection(
+        program,
+        lendingPool,
+        LENDER_KEYPAIR,
+        collection,
+        new BN(DEFAULT_LOAN_AMOUNT)
+      );
+      const { pawnLoan: pawnLoanAddress } = await requestLoan(
+        program,
+        baseKeypair,
+        BORROWER_KEYPAIR,
+        borrowerPawnTokenAccount,
+        pawnMint.publicKey,
+        termsUsdc
+      );
+      const pawnLoanState = await fetchPawnLoan(program, pawnLoanAddress);
+
+      try {
+        await underwriteFromPool(
+          program,
+          pawnLoanAddress,
+          pawnLoanState,
+          lendingPool,
+          collection,
+          borrowerMintATokenAccount,
+          adminMintATokenAccount
+        );
+        assert.ok(false);
+      } catch (e) {
+        const err = e as AnchorError;
+        assert.strictEqual(
+          err.error.errorMessage,
+          "AutomaticUnderwritingDisabled"
+        );
+      }
+
+      await program.methods
+        .setPoolUnderwritingPolicy({
+          minAnnualPercentageRateBps: new BN(0),
+          maxAnnualPercentageRateBps: new BN(10_000),
+          maxDuration: new BN(30 * MILLISECONDS_PER_DAY),
+        })
+        .accounts({ lendingPool, manager: LENDER_KEYPAIR.publicKey })
+        .signers([LENDER_KEYPAIR])
+        .rpc();
+
+      // The pawn metadata has no collection
+      try {
+        await underwriteFromPool(
+          program,
+          pawnLoanAddress,
+          pawnLoanState,
+          lendingPool,
+          collection,
+          borrowerMintATokenAccount,
+          adminMintATokenAccount
+        );
+        assert.ok(false);
+      } catch (e) {
+        const err = e as AnchorError;
+        assert.strictEqual(err.error.errorMessage, "UnverifiedCollection");
+      }
     });
-  });
-
-  describe("Seize Pawn", () => {
-    let pawnLoanAddress: PublicKey;
-    let pawnLoanState: any;
 
-    beforeEach(async () => {
-      ({ pawnLoan: pawnLoanAddress } = await requestLoan(
+    it("Only allows withdrawing idle liquidity", async () => {
+      const idleLiquidity = 500;
+      const { pawnLoan: pawnLoanAddress } = await requestLoan(
         program,
         baseKeypair,
         BORROWER_KEYPAIR,
         borrowerPawnTokenAccount,
         pawnMint.publicKey,
-        TERMS_SUPER_SHORT_LOAN
-      ));
-
-      pawnLoanState = await program.account.pawnLoan.fetch(pawnLoanAddress);
-
-      await underwriteLoan(
+        {
+          ...termsUsdc,
+          principalAmount: new BN(POOL_DEPOSIT_AMOUNT - idleLiquidity),
+        }
+      );
+      const pawnLoanState = await fetchPawnLoan(program, pawnLoanAddress);
+      await underwriteLoanFromPool(
         program,
         pawnLoanAddress,
         pawnLoanState,
+        lendingPool,
         LENDER_KEYPAIR,
-        LENDER_KEYPAIR.publicKey,
-        BORROWER_KEYPAIR.publicKey
+        borrowerMintATokenAccount,
+        adminMintATokenAccount
       );
 
-      pawnLoanState = await program.account.pawnLoan.fetch(pawnLoanAddress);
-    });
-
-    it("Throws error if attempt to seize before due", async () => {
       try {
-        await seizePawn(
+        await withdrawPoolLiquidity(
           program,
-          pawnLoanAddress,
-          pawnLoanState,
+          lendingPool,
           LENDER_KEYPAIR,
-          lenderPawnTokenAccount
+          lenderMintATokenAccount,
+          lenderLpTokenAccount,
+          new BN(POOL_DEPOSIT_AMOUNT - LOCKED_POOL_SHARES)
         );
         assert.ok(false);
-      } catch (_err) {
-        assert.isTrue(_err instanceof AnchorError);
-        const err = _err as AnchorError;
-        assert.strictEqual(err.error.errorCode.number, 6008);
-        assert.strictEqual(err.error.errorMessage, "CannotSeizeBeforeExpiry");
+      } catch (e) {
+        const err = e as AnchorError;
+        assert.strictEqual(err.error.errorMessage, "InsufficientIdleLiquidity");
       }
-    });
 
-    it("Seizing should update status and transfer the pawn to the lender", async () => {
-      await delay(2000);
-      await seizePawn(
+      await withdrawPoolLiquidity(
         program,
-        pawnLoanAddress,
-        pawnLoanState,
+        lendingPool,
         LENDER_KEYPAIR,
-        lenderPawnTokenAccount
-      );
-      pawnLoanState = await program.account.pawnLoan.fetch(pawnLoanAddress);
-
-      // Unsure why pawnLoan status enum shows up as {defaulted: {}}
-      assert.strictEqual(Object.keys(pawnLoanState.status)[0], "defaulted");
-
-      const lenderPawnTokenAccountInfo =
-        await program.provider.connection.getAccountInfo(
-          lenderPawnTokenAccount
-        );
-
-      const decodedlenderPawnTokenAccountInfo = deserializeTokenAccountInfo(
-        lenderPawnTokenAccountInfo?.data
-      );
-
-      if (!pawnLoanState.terms?.mint) {
-        assert.ok(false);
-        return;
-      }
-
-      // Pawn mint transferred to lender account
-      assert.isTrue(
-        decodedlenderPawnTokenAccountInfo?.mint.equals(pawnMint.publicKey)
+        lenderMintATokenAccount,
+        lenderLpTokenAccount,
+        new BN(idleLiquidity)
       );
+      assert.strictEqual(await getTokenAmount(vault), 0);
       assert.strictEqual(
-        decodedlenderPawnTokenAccountInfo?.amount.toNumber(),
-        1
-      );
-
-      const pawnTokenAccountInfo =
-        await program.provider.connection.getAccountInfo(
-          pawnLoanState.pawnTokenAccount
-        );
-
-      const decodedPawnTokenAccountInfo = deserializeTokenAccountInfo(
-        pawnTokenAccountInfo?.data
+        await getTokenAmount(lenderLpTokenAccount),
+        POOL_DEPOSIT_AMOUNT - LOCKED_POOL_SHARES - idleLiquidity
       );
-
-      // Pawn token account no longer frozen and delegate is revoked
-      assert.isFalse(decodedPawnTokenAccountInfo?.isFrozen);
-      assert.isNull(decodedPawnTokenAccountInfo?.delegate);
     });
-  });
 
-  describe("Cancel Loan", () => {
-    it("Cancel loan", async () => {
-      const { pawnLoan, pawnTokenAccount } = await requestLoan(
+    it("Manager withdraws the pawns seized by the pool", async () => {
+      const { pawnLoan: pawnLoanAddress } = await requestLoan(
         program,
         baseKeypair,
         BORROWER_KEYPAIR,
         borrowerPawnTokenAccount,
         pawnMint.publicKey,
-        TERMS_VALID
+        { ...termsUsdc, duration: new BN(1) }
+      );
+      let pawnLoanState = await fetchPawnLoan(program, pawnLoanAddress);
+      await underwriteLoanFromPool(
+        program,
+        pawnLoanAddress,
+        pawnLoanState,
+        lendingPool,
+        LENDER_KEYPAIR,
+        borrowerMintATokenAccount,
+        adminMintATokenAccount
       );
+      pawnLoanState = await fetchPawnLoan(program, pawnLoanAddress);
+      await delay(2000);
 
-      await program.methods
-        .cancelLoan()
-        .accounts({
-          pawnLoan,
-          borrower: BORROWER_KEYPAIR.publicKey,
-          pawnTokenAccount,
-          pawnMint: pawnMint.publicKey,
-          edition: findMasterEditionPda(pawnMint.publicKey),
-          mplTokenMetadataProgram: METAPLEX_PROGRAM_ID,
-        })
-        .signers([BORROWER_KEYPAIR])
-        .rpc();
+      const poolPawnTokenAccount = await pawnMint.createAccount(lendingPool);
+      await seizePawnForPool(
+        program,
+        pawnLoanAddress,
+        pawnLoanState,
+        poolPawnTokenAccount
+      );
 
-      // Relevant accounts should be zeroed (back to system program and no lamports) and pawn freed in wallet
-      const pawnLoanAccountInfo =
-        await program.provider.connection.getAccountInfo(pawnLoan);
-      assert.isNull(pawnLoanAccountInfo);
+      // Only pawns can be withdrawn, not the pool liquidity
+      try {
+        await withdrawPoolPawn(
+          program,
+          lendingPool,
+          LENDER_KEYPAIR,
+          vault,
+          lenderMintATokenAccount
+        );
+        assert.ok(false);
+      } catch (e) {
+        const err = e as AnchorError;
+        assert.strictEqual(err.error.errorMessage, "InvalidPoolPawnAccount");
+      }
+      // Only by the manager
+      try {
+        await withdrawPoolPawn(
+          program,
+          lendingPool,
+          BORROWER_KEYPAIR,
+          poolPawnTokenAccount,
+          borrowerPawnTokenAccount
+        );
+        assert.ok(false);
+      } catch (e) {
+        const err = e as AnchorError;
+        assert.strictEqual(
+          err.error.errorMessage,
+          "A has one constraint was violated"
+        );
+      }
 
-      const pawnTokenAccountInfo =
-        await program.provider.connection.getAccountInfo(pawnTokenAccount);
+      // Only to the associated token account of the manager
+      try {
+        await withdrawPoolPawn(
+          program,
+          lendingPool,
+          LENDER_KEYPAIR,
+          poolPawnTokenAccount,
+          lenderPawnTokenAccount
+        );
+        assert.ok(false);
+      } catch (e) {
+        const err = e as AnchorError;
+        assert.strictEqual(
+          err.error.errorMessage,
+          "An address constraint was violated"
+        );
+      }
 
-      const decodedPawnTokenAccountInfo = deserializeTokenAccountInfo(
-        pawnTokenAccountInfo?.data
+      const { address: managerPawnTokenAccount } =
+        await pawnMint.getOrCreateAssociatedAccountInfo(
+          LENDER_KEYPAIR.publicKey
+        );
+      await withdrawPoolPawn(
+        program,
+        lendingPool,
+        LENDER_KEYPAIR,
+        poolPawnTokenAccount,
+        managerPawnTokenAccount
       );
-      assert.isTrue(decodedPawnTokenAccountInfo?.amount.eq(new BN(1)));
-      assert.isFalse(decodedPawnTokenAccountInfo?.isFrozen);
-      assert.isNull(decodedPawnTokenAccountInfo?.delegate);
+      assert.strictEqual(await getTokenAmount(poolPawnTokenAccount), 0);
+      assert.strictEqual(await getTokenAmount(managerPawnTokenAccount), 1);
     });
   });
 
@@ -878,6 +2828,15 @@ describe("PawnHub", () => {
       await mintA.mintTo(adminMintATokenAccount, LENDER_KEYPAIR, [], 1_000_000);
     });
 
+    it("Fee ledger records the collected fees", async () => {
+      const feeLedger = await program.account.feeLedger.fetch(
+        findFeeLedgerPda(program, mintA.publicKey)
+      );
+      assert.isTrue(feeLedger.mint.equals(mintA.publicKey));
+      assert.isTrue(feeLedger.totalCollected.gtn(0));
+      assert.isTrue(feeLedger.loanCount.gtn(0));
+    });
+
     it("Can withdraw - in SOL", async () => {
       const beforeAdminAccountInfo =
         await program.provider.connection.getAccountInfo(ADMIN_PDA);
@@ -885,6 +2844,10 @@ describe("PawnHub", () => {
         assert.isNotNull(beforeAdminAccountInfo);
         return;
       }
+      const beforeFeeCollectorLamports =
+        await program.provider.connection.getBalance(
+          FEE_COLLECTOR_KEYPAIR.publicKey
+        );
 
       await program.methods
         .withdrawAdminFees()
@@ -893,6 +2856,8 @@ describe("PawnHub", () => {
           feeCollectorPaymentAccount: FEE_COLLECTOR_KEYPAIR.publicKey,
           admin: ADMIN_PDA,
           adminPaymentAccount: ADMIN_PDA,
+          feeSplit: findFeeSplitPda(program),
+          feeLedger: findFeeLedgerPda(program, NATIVE_MINT),
         })
         .signers([FEE_COLLECTOR_KEYPAIR])
         .rpc();
@@ -911,13 +2876,16 @@ describe("PawnHub", () => {
       assert.isTrue(afterAdminAccountInfo.lamports === rentExemptThreshold);
 
       // fee collector received the SOL
-      const feeCollectorAccountInfo =
-        await program.provider.connection.getAccountInfo(
+      const afterFeeCollectorLamports =
+        await program.provider.connection.getBalance(
           FEE_COLLECTOR_KEYPAIR.publicKey
         );
       const expectedLamports =
         beforeAdminAccountInfo.lamports - afterAdminAccountInfo.lamports;
-      assert.isTrue(feeCollectorAccountInfo?.lamports === expectedLamports);
+      assert.strictEqual(
+        afterFeeCollectorLamports - beforeFeeCollectorLamports,
+        expectedLamports
+      );
     });
 
     it("Can withdraw - in SPL tokens", async () => {
@@ -944,6 +2912,12 @@ describe("PawnHub", () => {
         );
         return;
       }
+      // Referral fees not claimed yet stay in the admin pda
+      const referralFeesOwed = (
+        await program.account.feeLedger.fetch(
+          findFeeLedgerPda(program, mintA.publicKey)
+        )
+      ).referralFeesOwed;
 
       await program.methods
         .withdrawAdminFees()
@@ -952,6 +2926,8 @@ describe("PawnHub", () => {
           feeCollectorPaymentAccount,
           admin: ADMIN_PDA,
           adminPaymentAccount: adminMintATokenAccount,
+          feeSplit: findFeeSplitPda(program),
+          feeLedger: findFeeLedgerPda(program, mintA.publicKey),
         })
         .preInstructions([
           Token.createAssociatedTokenAccountInstruction(
@@ -975,8 +2951,219 @@ describe("PawnHub", () => {
         feeCollectorPaymentAccountInfo?.data
       );
       assert.isTrue(
-        decodedFeeCollectorTokenAccount?.amount.eq(availableAdminMintAFees)
+        decodedFeeCollectorTokenAccount?.amount.eq(
+          availableAdminMintAFees.sub(referralFeesOwed)
+        )
+      );
+      assert.isTrue(
+        (await mintA.getAccountInfo(adminMintATokenAccount)).amount.eq(
+          referralFeesOwed
+        )
+      );
+    });
+
+    it("Sweeps the admin fees of several mints at once", async () => {
+      const mintB = await Token.createMint(
+        provider.connection,
+        LENDER_KEYPAIR,
+        LENDER_KEYPAIR.publicKey,
+        null /** freeze authority */,
+        0 /** decimals */,
+        TOKEN_PROGRAM_ID
+      );
+      const adminMintBTokenAccount = await mintB.createAccount(ADMIN_PDA);
+      const feeCollectorMintATokenAccount = await mintA.createAccount(
+        FEE_COLLECTOR_KEYPAIR.publicKey
+      );
+      const feeCollectorMintBTokenAccount = await mintB.createAccount(
+        FEE_COLLECTOR_KEYPAIR.publicKey
+      );
+      await mintA.mintTo(adminMintATokenAccount, LENDER_KEYPAIR, [], 1_000);
+      await mintB.mintTo(adminMintBTokenAccount, LENDER_KEYPAIR, [], 500);
+      const adminMintABalance = (
+        await mintA.getAccountInfo(adminMintATokenAccount)
+      ).amount;
+      const referralFeesOwed = (
+        await program.account.feeLedger.fetch(
+          findFeeLedgerPda(program, mintA.publicKey)
+        )
+      ).referralFeesOwed;
+
+      await sweepAdminFees(program, FEE_COLLECTOR_KEYPAIR, [
+        [
+          adminMintATokenAccount,
+          feeCollectorMintATokenAccount,
+          mintA.publicKey,
+        ],
+        [
+          adminMintBTokenAccount,
+          feeCollectorMintBTokenAccount,
+          mintB.publicKey,
+        ],
+      ]);
+
+      // Referral fees owed are left for the referrers to claim
+      assert.isTrue(
+        (await mintA.getAccountInfo(adminMintATokenAccount)).amount.eq(
+          referralFeesOwed
+        )
+      );
+      assert.isTrue(
+        (await mintB.getAccountInfo(adminMintBTokenAccount)).amount.isZero()
+      );
+      assert.isTrue(
+        (
+          await mintA.getAccountInfo(feeCollectorMintATokenAccount)
+        ).amount.eq(adminMintABalance.sub(referralFeesOwed))
+      );
+      assert.strictEqual(
+        (
+          await mintB.getAccountInfo(feeCollectorMintBTokenAccount)
+        ).amount.toNumber(),
+        500
+      );
+
+      const mintBFeeLedger = await program.account.feeLedger.fetch(
+        findFeeLedgerPda(program, mintB.publicKey)
+      );
+      assert.isTrue(mintBFeeLedger.totalCollected.isZero());
+      assert.strictEqual(mintBFeeLedger.totalWithdrawn.toNumber(), 500);
+    });
+
+    it("Throws error if a sweep triple mixes mints", async () => {
+      const mintB = await Token.createMint(
+        provider.connection,
+        LENDER_KEYPAIR,
+        LENDER_KEYPAIR.publicKey,
+        null /** freeze authority */,
+        0 /** decimals */,
+        TOKEN_PROGRAM_ID
+      );
+      const feeCollectorMintBTokenAccount = await mintB.createAccount(
+        FEE_COLLECTOR_KEYPAIR.publicKey
+      );
+
+      try {
+        await sweepAdminFees(program, FEE_COLLECTOR_KEYPAIR, [
+          [
+            adminMintATokenAccount,
+            feeCollectorMintBTokenAccount,
+            mintA.publicKey,
+          ],
+        ]);
+        assert.ok(false);
+      } catch (e) {
+        const err = e as AnchorError;
+        assert.strictEqual(err.error.errorMessage, "InvalidFeeSweepAccounts");
+      }
+    });
+
+    it("Splits the withdrawn fees between treasury recipients", async () => {
+      const treasuryKeypair = Keypair.generate();
+      const treasuryMintATokenAccount = await mintA.createAccount(
+        treasuryKeypair.publicKey
+      );
+      const feeCollectorMintATokenAccount = await mintA.createAccount(
+        FEE_COLLECTOR_KEYPAIR.publicKey
+      );
+      await mintA.mintTo(adminMintATokenAccount, LENDER_KEYPAIR, [], 1_000);
+
+      try {
+        await initFeeSplit(program, FEE_COLLECTOR_KEYPAIR, [
+          { recipient: treasuryKeypair.publicKey, bps: new BN(7_000) },
+          { recipient: FEE_COLLECTOR_KEYPAIR.publicKey, bps: new BN(2_000) },
+        ]);
+        assert.ok(false);
+      } catch (e) {
+        const err = e as AnchorError;
+        assert.strictEqual(err.error.errorMessage, "InvalidFeeSplit");
+      }
+
+      await initFeeSplit(program, FEE_COLLECTOR_KEYPAIR, [
+        { recipient: treasuryKeypair.publicKey, bps: new BN(7_000) },
+        { recipient: FEE_COLLECTOR_KEYPAIR.publicKey, bps: new BN(3_000) },
+      ]);
+
+      const beforeFeeLedger = await program.account.feeLedger.fetch(
+        findFeeLedgerPda(program, mintA.publicKey)
+      );
+
+      await withdrawAdminFees(
+        program,
+        FEE_COLLECTOR_KEYPAIR,
+        feeCollectorMintATokenAccount,
+        adminMintATokenAccount,
+        mintA.publicKey,
+        [treasuryMintATokenAccount, feeCollectorMintATokenAccount]
+      );
+
+      assert.strictEqual(
+        (
+          await mintA.getAccountInfo(treasuryMintATokenAccount)
+        ).amount.toNumber(),
+        700
       );
+      assert.strictEqual(
+        (
+          await mintA.getAccountInfo(feeCollectorMintATokenAccount)
+        ).amount.toNumber(),
+        300
+      );
+      const afterFeeLedger = await program.account.feeLedger.fetch(
+        findFeeLedgerPda(program, mintA.publicKey)
+      );
+      assert.strictEqual(
+        afterFeeLedger.totalWithdrawn
+          .sub(beforeFeeLedger.totalWithdrawn)
+          .toNumber(),
+        1_000
+      );
+
+      // Recipients are checked even when their share is zero
+      try {
+        await withdrawAdminFees(
+          program,
+          FEE_COLLECTOR_KEYPAIR,
+          feeCollectorMintATokenAccount,
+          adminMintATokenAccount,
+          mintA.publicKey,
+          [feeCollectorMintATokenAccount, treasuryMintATokenAccount]
+        );
+        assert.ok(false);
+      } catch (e) {
+        const err = e as AnchorError;
+        assert.strictEqual(
+          err.error.errorMessage,
+          "InvalidFeeSplitPaymentAccount"
+        );
+      }
+
+      // Sweeping would bypass the split
+      try {
+        await sweepAdminFees(program, FEE_COLLECTOR_KEYPAIR, [
+          [
+            adminMintATokenAccount,
+            feeCollectorMintATokenAccount,
+            mintA.publicKey,
+          ],
+        ]);
+        assert.ok(false);
+      } catch (e) {
+        const err = e as AnchorError;
+        assert.strictEqual(err.error.errorMessage, "FeeSplitConfigured");
+      }
+
+      // Fees all go to the fee collector again
+      await updateFeeSplit(program, FEE_COLLECTOR_KEYPAIR, [
+        { recipient: FEE_COLLECTOR_KEYPAIR.publicKey, bps: new BN(10_000) },
+      ]);
+      await sweepAdminFees(program, FEE_COLLECTOR_KEYPAIR, [
+        [
+          adminMintATokenAccount,
+          feeCollectorMintATokenAccount,
+          mintA.publicKey,
+        ],
+      ]);
     });
   });
 });
@@ -1,14 +1,22 @@
-import { Program } from "@project-serum/anchor";
+import { Program, Provider } from "@project-serum/anchor";
+import fs from "fs";
 import { findProgramAddressSync } from "@project-serum/anchor/dist/cjs/utils/pubkey";
 import {
   AccountInfo as TokenAccountInfo,
   AccountLayout,
   ASSOCIATED_TOKEN_PROGRAM_ID,
+  Token,
   TOKEN_PROGRAM_ID,
   u64,
 } from "@solana/spl-token";
-import { PublicKey } from "@solana/web3.js";
+import { Keypair, PublicKey, Transaction } from "@solana/web3.js";
+import {
+  createCreateMasterEditionV3Instruction,
+  createCreateMetadataAccountV2Instruction,
+  createVerifyCollectionInstruction,
+} from "@metaplex-foundation/mpl-token-metadata";
 import { PawnShop } from "../target/types/pawn_shop";
+import { findMasterEditionPda, findMetadataPda } from "./pawn-shop-sdk";
 
 export const deserializeTokenAccountInfo = (
   data: Buffer | undefined
@@ -79,3 +87,127 @@ export const getBorrowerAndLenderTokenBalance = async (
 export const delay = async (timeInMS: number) => {
   return new Promise((_) => setTimeout(_, timeInMS));
 };
+
+// Compute units consumed by the program in a transaction, as logged by the runtime
+export const getComputeUnitsConsumed = async (
+  program: Program<PawnShop>,
+  signature: string
+): Promise<number> => {
+  const prefix = `Program ${program.programId.toBase58()} consumed `;
+  // The transaction is only returned once confirmed
+  for (let attempt = 0; attempt < 10; attempt++) {
+    const tx = await program.provider.connection.getTransaction(signature, {
+      commitment: "confirmed",
+    });
+    const log = tx?.meta?.logMessages?.find((log) => log.startsWith(prefix));
+    if (log) {
+      return parseInt(log.slice(prefix.length).split(" ")[0]);
+    }
+    await delay(500);
+  }
+  throw new Error(`No compute units logged for ${signature}`);
+};
+
+// Prints the compute units per instruction, and saves them as json to the file named by
+// COMPUTE_UNITS_OUTPUT for scripts/bench-compute-units.sh to compare layouts
+export const reportComputeUnits = (computeUnits: Record<string, number>) => {
+  console.table(computeUnits);
+  if (process.env.COMPUTE_UNITS_OUTPUT) {
+    fs.writeFileSync(
+      process.env.COMPUTE_UNITS_OUTPUT,
+      JSON.stringify(computeUnits, null, 2)
+    );
+  }
+};
+
+// Mints a master edition nft, optionally part of an unverified collection
+export const createNft = async (
+  provider: Provider,
+  ownerKeypair: Keypair,
+  collection: PublicKey | null = null
+): Promise<{ mint: Token; tokenAccount: PublicKey }> => {
+  const mint = await Token.createMint(
+    provider.connection,
+    ownerKeypair,
+    ownerKeypair.publicKey,
+    ownerKeypair.publicKey /** freeze authority */,
+    0 /** decimals */,
+    TOKEN_PROGRAM_ID
+  );
+  const tokenAccount = await mint.createAccount(ownerKeypair.publicKey);
+  await mint.mintTo(tokenAccount, ownerKeypair, [], 1);
+
+  const metadata = findMetadataPda(mint.publicKey);
+  const tx = new Transaction();
+  tx.instructions.push(
+    createCreateMetadataAccountV2Instruction(
+      {
+        metadata,
+        mint: mint.publicKey,
+        mintAuthority: ownerKeypair.publicKey,
+        payer: ownerKeypair.publicKey,
+        updateAuthority: ownerKeypair.publicKey,
+      },
+      {
+        createMetadataAccountArgsV2: {
+          data: {
+            name: "Test",
+            symbol: "TST",
+            uri: "https://google.com",
+            sellerFeeBasisPoints: 0,
+            creators: null,
+            collection: collection ? { key: collection, verified: false } : null,
+            uses: null,
+          },
+          isMutable: true,
+        },
+      }
+    )
+  );
+  tx.instructions.push(
+    createCreateMasterEditionV3Instruction(
+      {
+        edition: findMasterEditionPda(mint.publicKey),
+        mint: mint.publicKey,
+        updateAuthority: ownerKeypair.publicKey,
+        mintAuthority: ownerKeypair.publicKey,
+        payer: ownerKeypair.publicKey,
+        metadata,
+      },
+      { createMasterEditionArgs: { maxSupply: 1 } }
+    )
+  );
+  await provider.send(tx, [ownerKeypair]);
+
+  return { mint, tokenAccount };
+};
+
+// Mints a collection nft and an nft verified as part of it
+export const createNftInVerifiedCollection = async (
+  provider: Provider,
+  ownerKeypair: Keypair
+): Promise<{ collection: PublicKey; mint: Token; tokenAccount: PublicKey }> => {
+  const { mint: collectionMint } = await createNft(provider, ownerKeypair);
+  const { mint, tokenAccount } = await createNft(
+    provider,
+    ownerKeypair,
+    collectionMint.publicKey
+  );
+
+  const tx = new Transaction();
+  tx.instructions.push(
+    createVerifyCollectionInstruction({
+      metadata: findMetadataPda(mint.publicKey),
+      collectionAuthority: ownerKeypair.publicKey,
+      payer: ownerKeypair.publicKey,
+      collectionMint: collectionMint.publicKey,
+      collection: findMetadataPda(collectionMint.publicKey),
+      collectionMasterEditionAccount: findMasterEditionPda(
+        collectionMint.publicKey
+      ),
+    })
+  );
+  await provider.send(tx, [ownerKeypair]);
+
+  return { collection: collectionMint.publicKey, mint, tokenAccount };
+};